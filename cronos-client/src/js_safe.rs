@@ -0,0 +1,116 @@
+//! Serde helpers for Dlist's JS safe integers.
+//!
+//! Large integers such as snowflakes are sent as strings so they survive
+//! being parsed by JavaScript, these helpers accept either form.
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrInt {
+    String(String),
+    Int(i64),
+}
+
+impl StringOrInt {
+    fn into_i64<E: Error>(self) -> Result<i64, E> {
+        match self {
+            Self::Int(v) => Ok(v),
+            Self::String(v) => v.parse().map_err(E::custom),
+        }
+    }
+}
+
+pub fn serialize<S: Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string())
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    StringOrInt::deserialize(deserializer)?.into_i64()
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<i64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(v) => super::serialize(v, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<i64>, D::Error> {
+        Option::<StringOrInt>::deserialize(deserializer)?
+            .map(StringOrInt::into_i64)
+            .transpose()
+    }
+}
+
+pub mod vec {
+    use serde::ser::SerializeSeq;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        values: &[i64],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(&value.to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<i64>, D::Error> {
+        Vec::<StringOrInt>::deserialize(deserializer)?
+            .into_iter()
+            .map(StringOrInt::into_i64)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Example {
+        #[serde(with = "super")]
+        id: i64,
+
+        #[serde(with = "super::vec")]
+        ids: Vec<i64>,
+    }
+
+    #[test]
+    fn test_accepts_strings_and_numbers() {
+        let value: Example =
+            serde_json::from_str(r#"{"id": "123", "ids": [1, "2"]}"#).unwrap();
+        assert_eq!(
+            value,
+            Example {
+                id: 123,
+                ids: vec![1, 2]
+            }
+        );
+    }
+
+    #[test]
+    fn test_serializes_as_strings() {
+        let value = Example {
+            id: 123,
+            ids: vec![4],
+        };
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#"{"id":"123","ids":["4"]}"#);
+    }
+}
@@ -0,0 +1,259 @@
+//! A typed async client for the Cronos search API.
+//!
+//! The types mirror the server's OpenAPI definitions, if you change
+//! a payload or response in Cronos make sure to update them here as well.
+
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub mod js_safe;
+mod types;
+
+pub use types::*;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to send request: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("cronos responded with status {status}: {body}")]
+    Status { status: StatusCode, body: String },
+}
+
+#[derive(Clone)]
+pub struct CronosClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl CronosClient {
+    /// Creates a new client for the given base url, i.e `http://127.0.0.1:7700/v0`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_client(reqwest::Client::new(), base_url)
+    }
+
+    /// Creates a new client re-using an existing http client.
+    pub fn with_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self { http, base_url }
+    }
+
+    /// Search Bots
+    pub async fn search_bots(
+        &self,
+        payload: &BotSearchPayload,
+    ) -> Result<BotSearchResult> {
+        self.request(Method::POST, "/bots/search", Some(payload))
+            .await
+    }
+
+    /// Suggest Bots
+    ///
+    /// Autocompletes bot usernames by prefix, returning at most 10 bots.
+    pub async fn suggest_bots(
+        &self,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<BotSuggestion>> {
+        let mut params = vec![("q", prefix.to_string())];
+        if let Some(limit) = limit {
+            params.push(("limit", limit.to_string()));
+        }
+
+        let builder = self
+            .http
+            .get(format!("{}/bots/suggest", self.base_url))
+            .query(&params);
+
+        Ok(self.dispatch(builder).await?.json().await?)
+    }
+
+    /// Popular Searches
+    ///
+    /// The most frequent bot queries over the last 24 hours.
+    pub async fn popular_bot_searches(
+        &self,
+        limit: Option<usize>,
+    ) -> Result<Vec<PopularSearch>> {
+        let mut builder = self
+            .http
+            .get(format!("{}/bots/search/popular", self.base_url));
+
+        if let Some(limit) = limit {
+            builder = builder.query(&[("limit", limit)]);
+        }
+
+        Ok(self.dispatch(builder).await?.json().await?)
+    }
+
+    /// Similar Bots
+    ///
+    /// Returns `None` if the bot does not exist.
+    pub async fn similar_bots(
+        &self,
+        id: u64,
+        limit: Option<usize>,
+    ) -> Result<Option<Vec<BotHit>>> {
+        let mut builder = self
+            .http
+            .get(format!("{}/bots/{}/similar", self.base_url, id));
+
+        if let Some(limit) = limit {
+            builder = builder.query(&[("limit", limit)]);
+        }
+
+        match self.dispatch(builder).await {
+            Ok(resp) => Ok(Some(resp.json().await?)),
+            Err(Error::Status { status, .. }) if status == StatusCode::NOT_FOUND => {
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// List all bot ids.
+    pub async fn bot_ids(&self) -> Result<Vec<i64>> {
+        let ids: Vec<JsSafeId> = self
+            .request::<(), _>(Method::GET, "/bots/ids", None)
+            .await?;
+        Ok(ids.into_iter().map(|id| id.0).collect())
+    }
+
+    /// List Bot Tags
+    pub async fn bot_tags(&self) -> Result<Vec<TagCount>> {
+        self.request::<(), _>(Method::GET, "/bots/tags", None).await
+    }
+
+    /// Update Bot Data
+    ///
+    /// Cronos will pull the bot's latest data from the database.
+    pub async fn update_bot(&self, id: u64) -> Result<()> {
+        self.execute::<()>(Method::POST, &format!("/bots/{}", id), None)
+            .await
+    }
+
+    /// Remove Bot Data
+    pub async fn remove_bot(&self, id: u64) -> Result<()> {
+        self.execute::<()>(Method::DELETE, &format!("/bots/{}", id), None)
+            .await
+    }
+
+    /// Refresh Bot Data
+    pub async fn refresh_bots(&self) -> Result<RefreshSummary> {
+        self.request::<(), _>(Method::POST, "/bots/refresh", None)
+            .await
+    }
+
+    /// Refresh Bot Data
+    ///
+    /// Only re-indexes the bots updated after the given unix timestamp in seconds.
+    pub async fn refresh_bots_since(&self, since: i64) -> Result<RefreshSummary> {
+        let builder = self
+            .http
+            .post(format!("{}/bots/refresh", self.base_url))
+            .query(&[("since", since)]);
+
+        Ok(self.dispatch(builder).await?.json().await?)
+    }
+
+    /// Search Packs
+    pub async fn search_packs(
+        &self,
+        payload: &PackSearchPayload,
+    ) -> Result<PackSearchResult> {
+        self.request(Method::POST, "/packs/search", Some(payload))
+            .await
+    }
+
+    /// List all pack ids.
+    pub async fn pack_ids(&self) -> Result<Vec<i64>> {
+        let ids: Vec<JsSafeId> = self
+            .request::<(), _>(Method::GET, "/packs/ids", None)
+            .await?;
+        Ok(ids.into_iter().map(|id| id.0).collect())
+    }
+
+    /// List Pack Tags
+    pub async fn pack_tags(&self) -> Result<Vec<TagCount>> {
+        self.request::<(), _>(Method::GET, "/packs/tags", None)
+            .await
+    }
+
+    /// Update Pack Data
+    ///
+    /// Cronos will pull the pack's latest data from the database.
+    pub async fn update_pack(&self, id: u64) -> Result<()> {
+        self.execute::<()>(Method::POST, &format!("/packs/{}", id), None)
+            .await
+    }
+
+    /// Remove Pack Data
+    pub async fn remove_pack(&self, id: u64) -> Result<()> {
+        self.execute::<()>(Method::DELETE, &format!("/packs/{}", id), None)
+            .await
+    }
+
+    /// Refresh Packs
+    pub async fn refresh_packs(&self) -> Result<RefreshSummary> {
+        self.request::<(), _>(Method::POST, "/packs/refresh", None)
+            .await
+    }
+
+    async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<reqwest::Response> {
+        let mut builder = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+
+        if let Some(body) = body {
+            builder = builder.json(body);
+        }
+
+        self.dispatch(builder).await
+    }
+
+    async fn dispatch(
+        &self,
+        builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let resp = builder.send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(Error::Status { status, body });
+        }
+
+        Ok(resp)
+    }
+
+    async fn execute<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<()> {
+        self.send(method, path, body).await?;
+        Ok(())
+    }
+
+    async fn request<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T> {
+        let resp = self.send(method, path, body).await?;
+        Ok(resp.json().await?)
+    }
+}
+
+/// An entity ID sent as a JS safe integer.
+#[derive(serde::Deserialize)]
+struct JsSafeId(#[serde(with = "js_safe")] i64);
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Order in descending order.
+    Desc,
+
+    /// Order in ascending order.
+    Asc,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountMode {
+    /// Count every matching document.
+    Exact,
+
+    /// Extrapolate the counts once enough documents have matched.
+    Estimate,
+}
+
+/// A field of a bot hit, the `id` is always returned.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BotField {
+    Id,
+    Username,
+    Avatar,
+    Discriminator,
+    Prefix,
+    Flags,
+    Features,
+    Tags,
+    CreatedOn,
+    OwnerId,
+    CoOwnerIds,
+    GuildCount,
+    BriefDescription,
+    Votes,
+    AllTimeVotes,
+    Rating,
+    InviteUrl,
+    Highlights,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BotsSortBy {
+    /// Sort by relevance.
+    Relevancy,
+
+    /// Sort by votes.
+    Votes,
+
+    /// Sort by the votes of every month combined.
+    AllTimeVotes,
+
+    /// Sort by age.
+    Age,
+
+    /// Sort by the creation date.
+    Newest,
+
+    /// Sort alphabetically by name, `Asc` orders from A to Z.
+    Name,
+
+    /// Sort by the trending score.
+    Trending,
+
+    /// Sort by the average rating.
+    Rating,
+
+    /// Sort by how much the guild count grew over the last 7 days.
+    Growth,
+
+    /// How many servers the bot is in.
+    Popularity,
+
+    /// Premium Bots.
+    Premium,
+
+    /// Certified Bots.
+    Certified,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacksSortBy {
+    /// Sort by relevance.
+    Relevancy,
+
+    /// Sort by likes.
+    Likes,
+
+    /// The same as `Likes`, kept for existing clients.
+    Votes,
+
+    /// Sort by age.
+    Age,
+
+    /// Sort by the creation date.
+    Newest,
+
+    /// Sort alphabetically by name, `Asc` orders from A to Z.
+    Name,
+
+    /// Sort by the trending score.
+    Trending,
+
+    /// How many bots the pack is in.
+    NumBots,
+
+    /// Premium Packs.
+    Premium,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct BotsSortCriterion {
+    /// What to sort by.
+    pub by: BotsSortBy,
+
+    /// Order results Asc or Desc.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct PacksSortCriterion {
+    /// What to sort by.
+    pub by: PacksSortBy,
+
+    /// Order results Asc or Desc.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeaturesMatch {
+    /// Match bots with any of the given features.
+    Any,
+
+    /// Match bots with all of the given features.
+    All,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    Union,
+    Intersection,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BotFilter {
+    /// A set of tags to filter results by.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// A set of tags to remove from the results.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude_tags: Vec<String>,
+
+    /// The set of features to filter by.
+    #[serde(
+        with = "crate::js_safe::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub features: Option<i64>,
+
+    /// If bots must have any or all of the given features.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features_match: Option<FeaturesMatch>,
+
+    /// If the bot should be premium or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub premium: Option<bool>,
+
+    /// If the bot should be certified or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certified: Option<bool>,
+
+    /// Only return bots owned by this user.
+    #[serde(
+        with = "crate::js_safe::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub owner_id: Option<i64>,
+
+    /// Only return bots this user is a co-owner of.
+    #[serde(
+        with = "crate::js_safe::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub co_owner_id: Option<i64>,
+
+    /// Only return bots using this command prefix, ignoring case.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+
+    /// Only return bots created within this many days.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added_within_days: Option<u32>,
+
+    /// Include bots marked as NSFW in the results.
+    pub include_nsfw: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_mode: Option<FilterMode>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BotSearchPayload {
+    /// The query to be searched.
+    ///
+    /// If `None` this will be a wild card search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+
+    /// How many documents to return.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+
+    /// How many documents to skip first.
+    pub offset: usize,
+
+    /// A set of filter rules.
+    pub filter: BotFilter,
+
+    /// A structured query in a subset of the Elasticsearch query DSL which
+    /// results must also match, e.g. `{"range": {"votes": {"gte": 100}}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub es_query: Option<serde_json::Value>,
+
+    /// How to sort results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<BotsSortBy>,
+
+    /// Order results Asc or Desc.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+
+    /// Further sorts applied in order to break ties of the primary sort.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub then_by: Vec<BotsSortCriterion>,
+
+    /// The name of a ranking formula to sort by instead of `sort` and `then_by`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ranking: Option<String>,
+
+    /// Only match results containing the exact query terms.
+    pub exact: bool,
+
+    /// If the hit and facet counts should be exact or estimated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count_mode: Option<CountMode>,
+
+    /// Only return these fields of each hit, every field if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<BotField>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PackFilter {
+    /// A set of categories to filter results by.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+
+    /// If the pack should be premium or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub premium: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackSearchPayload {
+    /// The query to be searched.
+    ///
+    /// If `None` this will be a wild card search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+
+    /// How many documents to return.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+
+    /// How many documents to skip first.
+    pub offset: usize,
+
+    /// A set of filter rules.
+    pub filter: PackFilter,
+
+    /// A structured query in a subset of the Elasticsearch query DSL which
+    /// results must also match, e.g. `{"range": {"votes": {"gte": 100}}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub es_query: Option<serde_json::Value>,
+
+    /// How to sort results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<PacksSortBy>,
+
+    /// Order results Asc or Desc.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+
+    /// Further sorts applied in order to break ties of the primary sort.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub then_by: Vec<PacksSortCriterion>,
+
+    /// Only match results containing the exact query terms.
+    pub exact: bool,
+
+    /// If the hit and facet counts should be exact or estimated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count_mode: Option<CountMode>,
+
+    /// Only return the IDs of the bots in each pack, leaving `bots` empty.
+    pub bot_ids_only: bool,
+
+    /// Only return these fields of each bot in a pack, every field if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bot_fields: Vec<BotField>,
+}
+
+/// A bot hit, fields which were not requested are left empty.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BotHit {
+    /// The snowflake ID of the bot.
+    #[serde(with = "crate::js_safe")]
+    pub id: i64,
+
+    /// The bot's username.
+    pub username: String,
+
+    /// The bot's avatar hash if applicable.
+    #[serde(default)]
+    pub avatar: Option<String>,
+
+    /// The bot's discriminator i.e `0001`
+    pub discriminator: i32,
+
+    /// The bot's given prefix.
+    #[serde(default)]
+    pub prefix: Option<String>,
+
+    /// The given Dlist flags.
+    #[serde(with = "crate::js_safe")]
+    pub flags: i64,
+
+    /// The bot's given list of features.
+    #[serde(with = "crate::js_safe")]
+    pub features: i64,
+
+    /// The bot's associated tags.
+    pub tags: Vec<String>,
+
+    /// The timestamp that the bot was first created on.
+    pub created_on: serde_json::Value,
+
+    /// The bot's primary owner.
+    #[serde(with = "crate::js_safe")]
+    pub owner_id: i64,
+
+    /// The bot's secondary/co-owners
+    #[serde(with = "crate::js_safe::vec")]
+    pub co_owner_ids: Vec<i64>,
+
+    /// The amount of guilds the bot is in.
+    #[serde(default)]
+    pub guild_count: Option<i32>,
+
+    /// The short description of the bot.
+    pub brief_description: String,
+
+    /// The number of votes the bot currently has this month.
+    #[serde(with = "crate::js_safe")]
+    pub votes: i64,
+
+    /// The number of votes the bot has had across every month.
+    #[serde(with = "crate::js_safe", default)]
+    pub all_time_votes: i64,
+
+    /// The average number of stars the bot has been rated, `0` if unrated.
+    #[serde(default)]
+    pub rating: f64,
+
+    /// The invite url of the bot.
+    pub invite_url: String,
+
+    /// The matched fragments of the `username` and `description` fields.
+    #[serde(default)]
+    pub highlights: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagCount {
+    /// The name of the tag.
+    pub name: String,
+
+    /// The number of listed entities with the tag.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PopularSearch {
+    /// The normalized query.
+    pub query: String,
+
+    /// The number of times the query was searched within the window.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BotSuggestion {
+    /// The snowflake ID of the bot.
+    #[serde(with = "crate::js_safe")]
+    pub id: i64,
+
+    /// The bot's username.
+    pub username: String,
+
+    /// The bot's avatar hash if applicable.
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackHit {
+    /// The ID of the pack.
+    #[serde(with = "crate::js_safe")]
+    pub id: i64,
+
+    /// The name of the pack.
+    pub name: String,
+
+    /// The description of the pack.
+    pub description: String,
+
+    /// The timestamp of when the pack was created.
+    pub created_on: serde_json::Value,
+
+    /// The tag associated with this pack.
+    #[serde(rename = "category")]
+    pub tag: String,
+
+    /// The bots that this pack contains.
+    ///
+    /// This is empty if only the bot IDs were requested.
+    pub bots: Vec<BotHit>,
+
+    /// The IDs of the bots that this pack contains.
+    #[serde(default, with = "crate::js_safe::vec")]
+    pub bot_ids: Vec<i64>,
+
+    /// The primary owner of this pack.
+    #[serde(with = "crate::js_safe")]
+    pub owner_id: i64,
+
+    /// The number of likes the pack has.
+    #[serde(with = "crate::js_safe")]
+    pub likes: i64,
+
+    /// If the pack is being promoted.
+    #[serde(default)]
+    pub premium: bool,
+
+    /// The matched fragments of the `name` and `description` fields.
+    #[serde(default)]
+    pub highlights: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuildCountBucket {
+    /// The lowest guild count of the bucket.
+    pub start: u64,
+
+    /// The guild count the bucket ends before, the last bucket has no end.
+    #[serde(default)]
+    pub end: Option<u64>,
+
+    /// The number of results within the bucket.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult<T> {
+    /// The search results themselves.
+    pub hits: Vec<T>,
+
+    /// The maximum amount of docs that could get returned.
+    pub limit: usize,
+
+    /// The number of skipped documents.
+    pub offset: usize,
+
+    /// The original query used to search results.
+    pub query: String,
+
+    /// The total number of documents that matched the query.
+    pub nb_hits: usize,
+
+    /// If there are more results after this page.
+    #[serde(default)]
+    pub has_more: bool,
+
+    /// The number of pages of `limit` results which can be reached.
+    #[serde(default)]
+    pub total_pages: usize,
+
+    /// The number of results which can be paged through.
+    #[serde(default)]
+    pub max_window: usize,
+
+    /// The distribution of tags/categories across the results.
+    pub tag_distribution: HashMap<String, usize>,
+
+    /// The number of results which are premium, only set for bots.
+    #[serde(default)]
+    pub premium_count: Option<usize>,
+
+    /// The number of results which are not premium, only set for bots.
+    #[serde(default)]
+    pub non_premium_count: Option<usize>,
+
+    /// The number of results with each feature flag set, only set for bots.
+    #[serde(default)]
+    pub feature_distribution: HashMap<String, usize>,
+
+    /// The number of results within each range of guild counts, only set for bots.
+    #[serde(default)]
+    pub guild_count_histogram: Vec<GuildCountBucket>,
+
+    /// A corrected version of the query if it produced very few hits.
+    #[serde(default)]
+    pub suggestion: Option<String>,
+}
+
+pub type BotSearchResult = SearchResult<BotHit>;
+pub type PackSearchResult = SearchResult<PackHit>;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshSummary {
+    /// The number of documents which were indexed.
+    pub indexed: usize,
+
+    /// The number of rows which were intentionally not indexed.
+    pub skipped: usize,
+
+    /// The number of rows which failed to be loaded or indexed.
+    pub errored: usize,
+
+    /// A sample of the errors which occurred.
+    pub errors: Vec<String>,
+}
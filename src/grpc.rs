@@ -0,0 +1,225 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use poem_openapi::types::ParseFromJSON;
+use tantivy::schema::Field;
+use tantivy::Document;
+use tonic::{Request, Response, Status};
+
+use crate::search::readers::{CountMode, Order};
+use crate::search::{
+    index_impls,
+    readers,
+    replication,
+    FromTantivyDoc,
+    WriterSaturated,
+};
+
+pub mod proto {
+    tonic::include_proto!("cronos");
+}
+
+use proto::search_server::{Search, SearchServer};
+use proto::{Empty, EntityRequest, SearchRequest, SearchResponse};
+
+/// The default number of IDs returned by a search.
+const DEFAULT_LIMIT: u32 = 20;
+
+/// The most IDs which can be returned by a search.
+const MAX_LIMIT: u32 = 50;
+
+/// The furthest a search can page into the results.
+const MAX_OFFSET: u32 = 40_000;
+
+/// Serves the internal gRPC API until the server stops.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    info!("Serving gRPC API on {}", addr);
+
+    tonic::transport::Server::builder()
+        .add_service(SearchServer::new(SearchService))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+/// A hit which only loads the ID of the document.
+struct IdHit(i64);
+
+impl FromTantivyDoc for IdHit {
+    fn from_doc(id_field: Field, doc: Document) -> Option<Self> {
+        doc.get_first(id_field)?.as_i64().map(Self)
+    }
+}
+
+struct SearchService;
+
+#[tonic::async_trait]
+impl Search for SearchService {
+    async fn search_bots(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let req = request.into_inner();
+        let (limit, offset) = page(&req)?;
+
+        let result = readers::bots::reader()
+            .search::<IdHit>(
+                req.query,
+                parse_filter(&req.filter)?,
+                None,
+                limit,
+                offset,
+                parse_sort(&req.sort)?,
+                order(req.ascending),
+                Vec::new(),
+                None,
+                false,
+                CountMode::default(),
+            )
+            .await
+            .map_err(internal_error)?;
+
+        Ok(Response::new(SearchResponse {
+            ids: result.hits.into_iter().map(|hit| hit.0).collect(),
+            nb_hits: result.num_hits as u64,
+        }))
+    }
+
+    async fn search_packs(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let req = request.into_inner();
+        let (limit, offset) = page(&req)?;
+
+        let result = readers::packs::reader()
+            .search::<IdHit>(
+                req.query,
+                parse_filter(&req.filter)?,
+                None,
+                limit,
+                offset,
+                parse_sort(&req.sort)?,
+                order(req.ascending),
+                Vec::new(),
+                false,
+                CountMode::default(),
+            )
+            .await
+            .map_err(internal_error)?;
+
+        Ok(Response::new(SearchResponse {
+            ids: result.hits.into_iter().map(|hit| hit.0).collect(),
+            nb_hits: result.num_hits as u64,
+        }))
+    }
+
+    async fn upsert_bot(
+        &self,
+        request: Request<EntityRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        ensure_primary()?;
+
+        index_impls::bots::writer()
+            .upsert_bot(request.into_inner().id)
+            .await
+            .map_err(writer_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn remove_bot(
+        &self,
+        request: Request<EntityRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        ensure_primary()?;
+
+        index_impls::bots::writer()
+            .remove_bot(request.into_inner().id)
+            .await
+            .map_err(writer_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+}
+
+/// Validates the page of the search the same as the HTTP search payloads.
+fn page(req: &SearchRequest) -> Result<(usize, usize), Status> {
+    let limit = if req.limit == 0 {
+        DEFAULT_LIMIT
+    } else {
+        req.limit
+    };
+    if limit > MAX_LIMIT {
+        return Err(Status::invalid_argument(format!(
+            "limit must be at most {}",
+            MAX_LIMIT
+        )));
+    }
+
+    if req.offset > MAX_OFFSET {
+        return Err(Status::invalid_argument(format!(
+            "offset must be at most {}",
+            MAX_OFFSET
+        )));
+    }
+
+    Ok((limit as usize, req.offset as usize))
+}
+
+/// Parses the JSON filter, an empty filter matches everything.
+fn parse_filter<T: ParseFromJSON + Default>(filter: &str) -> Result<T, Status> {
+    if filter.is_empty() {
+        return Ok(T::default());
+    }
+
+    let value = serde_json::from_str(filter)
+        .map_err(|e| Status::invalid_argument(format!("invalid filter: {}", e)))?;
+
+    T::parse_from_json(Some(value)).map_err(|e| {
+        Status::invalid_argument(format!("invalid filter: {}", e.into_message()))
+    })
+}
+
+/// Parses the name of the sort, an empty name uses the default sort.
+fn parse_sort<T: ParseFromJSON + Default>(sort: &str) -> Result<T, Status> {
+    if sort.is_empty() {
+        return Ok(T::default());
+    }
+
+    T::parse_from_json(Some(serde_json::Value::String(sort.to_string()))).map_err(|e| {
+        Status::invalid_argument(format!("invalid sort: {}", e.into_message()))
+    })
+}
+
+fn order(ascending: bool) -> Order {
+    if ascending {
+        Order::Asc
+    } else {
+        Order::Desc
+    }
+}
+
+fn ensure_primary() -> Result<(), Status> {
+    if replication::is_replica() {
+        return Err(Status::failed_precondition(
+            "This instance is a replica and does not accept writes.",
+        ));
+    }
+
+    Ok(())
+}
+
+fn writer_error(e: anyhow::Error) -> Status {
+    if e.is::<WriterSaturated>() {
+        Status::unavailable(e.to_string())
+    } else {
+        internal_error(e)
+    }
+}
+
+fn internal_error(e: anyhow::Error) -> Status {
+    error!("gRPC request failed: {}", e);
+    Status::internal(e.to_string())
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+
+use crate::search::index_impls;
+
+pub mod nats;
+pub mod redis;
+
+/// A change made to an entity by the main backend.
+#[derive(Debug, Copy, Clone)]
+pub enum EntityChange {
+    Bot(i64),
+    Pack(i64),
+}
+
+impl EntityChange {
+    /// Parses a change from the name of the event and the ID of the entity.
+    pub fn parse(event: &str, id: &str) -> Option<Self> {
+        let id = id.trim().parse::<i64>().ok()?;
+
+        match event {
+            "bot.updated" => Some(Self::Bot(id)),
+            "pack.updated" => Some(Self::Pack(id)),
+            _ => None,
+        }
+    }
+
+    /// Re-indexes the entity from the database, removing it if it was
+    /// deleted or hidden.
+    pub async fn apply(self) -> Result<()> {
+        match self {
+            Self::Bot(id) => index_impls::bots::writer().apply_change(id).await?,
+            Self::Pack(id) => index_impls::packs::writer().apply_change(id).await?,
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use async_nats::jetstream::consumer::{pull, AckPolicy};
+use async_nats::jetstream::{self, AckKind, Message};
+use futures::StreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::ingest::EntityChange;
+
+/// The subject prefix entity changes are published under, i.e `cronos.bot.updated`.
+const SUBJECT_PREFIX: &str = "cronos.";
+
+/// The durable consumer name shared by every instance.
+const CONSUMER_NAME: &str = "cronos";
+
+/// How many times a change is delivered before it's written to the dead letter log.
+const MAX_DELIVERIES: i64 = 5;
+
+/// How long to wait before reconnecting after losing the connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub struct NatsIngest {
+    pub url: String,
+
+    /// The JetStream stream the changes are published to.
+    pub stream: String,
+
+    /// The file changes which could not be applied are appended to.
+    pub dead_letters: PathBuf,
+}
+
+impl NatsIngest {
+    /// Applies the entity changes consumed from the stream, reconnecting
+    /// whenever the connection is lost.
+    ///
+    /// Each change is only acknowledged once it has been applied, so changes
+    /// are redelivered until they succeed or [MAX_DELIVERIES] is reached.
+    pub async fn consume_loop(&self) {
+        loop {
+            if let Err(e) = self.consume().await {
+                error!("NATS ingestion failed due to error: {}", e);
+            }
+
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn consume(&self) -> Result<()> {
+        let client = async_nats::connect(&self.url)
+            .await
+            .map_err(|e| anyhow!(e))?;
+        let stream = jetstream::new(client)
+            .get_stream(&self.stream)
+            .await
+            .map_err(|e| anyhow!(e))?;
+
+        let consumer = stream
+            .get_or_create_consumer(
+                CONSUMER_NAME,
+                pull::Config {
+                    durable_name: Some(CONSUMER_NAME.to_string()),
+                    ack_policy: AckPolicy::Explicit,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| anyhow!(e))?;
+
+        info!("Consuming entity changes from NATS stream {}", self.stream);
+
+        let mut messages = consumer.messages().await.map_err(|e| anyhow!(e))?;
+        while let Some(msg) = messages.next().await {
+            let msg = msg.map_err(|e| anyhow!(e))?;
+            self.handle_message(msg).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_message(&self, msg: Message) -> Result<()> {
+        let event = msg.subject.trim_start_matches(SUBJECT_PREFIX);
+        let payload = String::from_utf8_lossy(&msg.payload);
+
+        let result = match EntityChange::parse(event, &payload) {
+            Some(change) => {
+                debug!("Applying change from NATS: {:?}", change);
+                change.apply().await
+            },
+            None => Err(anyhow!("invalid change event")),
+        };
+
+        let error = match result {
+            Ok(()) => return msg.ack().await.map_err(|e| anyhow!(e)),
+            Err(e) => e,
+        };
+
+        let delivered = msg
+            .info()
+            .map(|info| info.delivered)
+            .unwrap_or(MAX_DELIVERIES);
+        if delivered < MAX_DELIVERIES {
+            warn!(
+                "Failed to apply change on {} (attempt {}), it will be retried: {}",
+                msg.subject, delivered, error
+            );
+            return msg
+                .ack_with(AckKind::Nak(None))
+                .await
+                .map_err(|e| anyhow!(e));
+        }
+
+        error!(
+            "Failed to apply change on {} after {} attempts, writing it to the dead letter log: {}",
+            msg.subject, delivered, error
+        );
+        self.write_dead_letter(&msg.subject, &payload, &error)
+            .await?;
+
+        msg.ack_with(AckKind::Term).await.map_err(|e| anyhow!(e))
+    }
+
+    async fn write_dead_letter(
+        &self,
+        subject: &str,
+        payload: &str,
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        let failed_on = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut line = serde_json::to_vec(&serde_json::json!({
+            "subject": subject,
+            "payload": payload,
+            "error": error.to_string(),
+            "failedOn": failed_on,
+        }))?;
+        line.push(b'\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.dead_letters)
+            .await?;
+        file.write_all(&line).await?;
+
+        Ok(())
+    }
+}
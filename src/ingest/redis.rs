@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use crate::ingest::EntityChange;
+
+/// The channels the main backend publishes entity changes to.
+const CHANNELS: [&str; 2] = ["bot.updated", "pack.updated"];
+
+/// How long to wait before reconnecting after losing the connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Applies the entity changes published to Redis, reconnecting whenever
+/// the connection is lost.
+///
+/// Messages published while disconnected are missed, so the periodic
+/// refreshes are still needed to catch up.
+pub async fn subscribe_loop(url: String) {
+    loop {
+        if let Err(e) = subscribe(&url).await {
+            error!("Redis subscription failed due to error: {}", e);
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn subscribe(url: &str) -> Result<()> {
+    let client = redis::Client::open(url)?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    for channel in CHANNELS {
+        pubsub.subscribe(channel).await?;
+    }
+
+    info!("Subscribed to entity changes on Redis");
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let payload = msg.get_payload::<String>()?;
+        let change = match EntityChange::parse(msg.get_channel_name(), &payload) {
+            Some(change) => change,
+            None => {
+                warn!(
+                    "Ignoring invalid message on channel {}: {:?}",
+                    msg.get_channel_name(),
+                    payload
+                );
+                continue;
+            },
+        };
+
+        debug!("Applying change from Redis: {:?}", change);
+        if let Err(e) = change.apply().await {
+            error!("Failed to apply change {:?} due to error: {}", change, e);
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,13 @@
 #[macro_use]
 extern crate tracing;
 
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
 use std::num::NonZeroU32;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Result;
 use clap::Parser;
@@ -17,12 +20,19 @@ use poem::listener::TcpListener;
 use poem::middleware::Cors;
 use poem::{Endpoint, EndpointExt, IntoResponse, Request, Response, Route, Server};
 use poem_openapi::{OpenApiService, Tags};
-use tokio::sync::Semaphore;
 use tracing_subscriber::filter::LevelFilter;
 
+use crate::ingest::nats::NatsIngest;
+use crate::search::replication::IndexName;
+
+mod grpc;
+mod ingest;
 pub(crate) mod models;
+mod notify;
 mod routes;
+mod scores;
 pub(crate) mod search;
+mod supervisor;
 mod tasks;
 
 type Ratelimiter = governor::RateLimiter<
@@ -37,6 +47,8 @@ static GLOBAL_RATELIMITER: OnceCell<Ratelimiter> = OnceCell::new();
 pub enum ApiTags {
     Bots,
     Packs,
+    Admin,
+    Webhooks,
 }
 
 #[derive(Debug, Parser)]
@@ -46,6 +58,10 @@ pub struct Config {
     /// The address for the webserver to bind to.
     bind: String,
 
+    #[clap(long, env)]
+    /// The address for the internal gRPC server to bind to, it is disabled when not set.
+    grpc_bind: Option<SocketAddr>,
+
     #[clap(long, env, default_value = "info")]
     /// The level in which to display logs.
     log_level: LevelFilter,
@@ -57,12 +73,73 @@ pub struct Config {
     #[clap(long)]
     init_tables: bool,
 
+    #[clap(long, env)]
+    /// The username to authenticate with the cluster as, requires `scylla_password`.
+    scylla_username: Option<String>,
+
+    #[clap(long, env)]
+    /// The password to authenticate with the cluster with.
+    scylla_password: Option<String>,
+
+    #[clap(long, env)]
+    /// Connect to the cluster over TLS, implied by `scylla_ca_cert`.
+    scylla_tls: bool,
+
+    #[clap(long, env)]
+    /// The PEM file of the CA that issued the node certificates, defaults to the system roots.
+    scylla_ca_cert: Option<String>,
+
+    #[clap(long, env)]
+    /// The host name the node certificates are verified against, only the chain is verified when not set.
+    scylla_tls_hostname: Option<String>,
+
+    #[clap(long, env)]
+    /// The datacenter of this instance, queries are routed to the nodes owning the data in it.
+    scylla_local_dc: Option<String>,
+
+    #[clap(long, env, default_value_t = 500)]
+    /// The most prepared statements kept by the database session.
+    scylla_prepare_cache_size: usize,
+
+    #[clap(long, env, default_value_t = 5000)]
+    /// How long a query to the cluster can take in milliseconds, including retries.
+    scylla_request_timeout: u64,
+
+    #[clap(long, env, value_enum, default_value = "default")]
+    /// How queries which fail on a node are retried.
+    scylla_retry_policy: models::connection::RetryPolicy,
+
+    #[clap(long, env, default_value_t = 0)]
+    /// The most extra nodes a slow read is sent to, `0` disables speculative execution.
+    scylla_speculative_retries: usize,
+
+    #[clap(long, env, default_value_t = 100)]
+    /// How long to wait on a read in milliseconds before sending it to another node.
+    scylla_speculative_interval: u64,
+
     #[clap(long, env)]
     data_path: String,
 
+    #[clap(long, env)]
+    /// Delete and rebuild indexes built with an outdated schema instead of refusing to start.
+    recreate_outdated_indexes: bool,
+
     #[clap(long, env, default_value_t = 50)]
+    /// The maximum number of searches which can run concurrently.
     max_concurrency: usize,
 
+    #[clap(long, env, default_value_t = 4)]
+    /// The concurrency limit will never be reduced below this when under load.
+    min_concurrency: usize,
+
+    #[clap(long, env, default_value_t = 64)]
+    /// The maximum number of operations queued for each index writer.
+    writer_queue_size: usize,
+
+    #[clap(long, env, default_value_t = 0)]
+    /// The number of threads each index writer indexes with, `0` picks based on the number of cores.
+    writer_threads: usize,
+
     #[clap(short, long, env, default_value = "http://127.0.0.1:7700/v0")]
     /// The exposed address of the server.
     exposed_address: String,
@@ -74,6 +151,139 @@ pub struct Config {
     #[clap(long, env, default_value = "erghsudhfgadfpiughade")]
     /// The auth key of the a7s server.
     a7s_auth: String,
+
+    #[clap(long, env, default_value = "")]
+    /// The bearer token required by every admin route, these are disabled when empty.
+    admin_token: String,
+
+    #[clap(long, env, default_value = "")]
+    /// The secret vote webhook events are signed with, the webhook is disabled when empty.
+    vote_webhook_secret: String,
+
+    #[clap(long, env)]
+    /// A list of URLs seperated by a `,` which are sent index changes.
+    webhook_urls: Option<String>,
+
+    #[clap(long, env, default_value = "")]
+    /// The secret used to sign the events sent to the webhook URLs.
+    webhook_secret: String,
+
+    #[clap(long, env, value_enum, default_value = "a7s")]
+    /// Where trending scores are sourced from.
+    scores_provider: scores::ScoresSource,
+
+    #[clap(long, env, value_enum, default_value = "transliterate")]
+    /// How decorative symbols and emojis are handled when tokenizing text.
+    symbol_mode: search::tokenizer::SymbolMode,
+
+    #[clap(
+        long,
+        env,
+        default_value = "a,an,and,at,for,in,is,of,on,or,the,to,with"
+    )]
+    /// A list of words to ignore when tokenizing seperated by a `,`.
+    stopwords: String,
+
+    #[clap(long, env, default_value_t = 2.0)]
+    /// The score added to featured bots when sorting by relevancy, `0` disables it.
+    featured_boost: f32,
+
+    #[clap(long, env, default_value_t = 1.0)]
+    /// The factor premium bot scores are multiplied by when sorting by relevancy, `1` disables it.
+    premium_boost: f32,
+
+    #[clap(long, env, default_value_t = 0)]
+    /// How many days it takes the boost newer bots get when sorting by relevancy to halve, `0` disables it.
+    recency_half_life: u64,
+
+    #[clap(long, env)]
+    /// Index and search the full description of each bot as well as the brief description.
+    index_long_descriptions: bool,
+
+    #[clap(long, env)]
+    /// The address of a primary instance to replicate the indexes from.
+    ///
+    /// Replicas never build their own indexes and must not receive writes.
+    replicate_from: Option<String>,
+
+    #[clap(long, env)]
+    /// A list of instance URLs seperated by a `,` which replicas can be
+    /// verified against, the primary being replicated from is always included.
+    verify_peers: Option<String>,
+
+    #[clap(long, env, default_value_t = 10)]
+    /// How often replicas pull changes from the primary in seconds.
+    replication_interval: u64,
+
+    #[clap(long, env)]
+    /// Only run snapshot uploads and scheduled refreshes on one instance at a time.
+    leader_election: bool,
+
+    #[clap(long, env)]
+    /// The unique ID of this instance used for leader election, defaults to `HOSTNAME` if set.
+    instance_id: Option<String>,
+
+    #[clap(long, env)]
+    /// The Redis server to subscribe to `bot.updated` and `pack.updated` messages from.
+    ///
+    /// Each message is the ID of the entity, which is re-indexed from the database.
+    redis_url: Option<String>,
+
+    #[clap(long, env)]
+    /// A `nats://` server to consume entity changes from, as an alternative to the update routes.
+    ///
+    /// Changes are published to the ingest stream as `cronos.bot.updated` or
+    /// `cronos.pack.updated` with the ID of the entity as the payload.
+    ingest: Option<String>,
+
+    #[clap(long, env, default_value = "CRONOS")]
+    /// The JetStream stream entity changes are consumed from.
+    ingest_stream: String,
+
+    #[clap(long, env)]
+    /// A cron expression for when every index is fully refreshed in UTC, i.e `0 0 4 * * *` for 04:00 daily.
+    full_refresh_schedule: Option<cron::Schedule>,
+
+    #[clap(long, env)]
+    /// How often changes to the bots and packs tables are read from the CDC log in seconds.
+    ///
+    /// When set the tables are no longer fully reloaded every 20 minutes,
+    /// the tables must have CDC enabled.
+    cdc_interval: Option<u64>,
+
+    #[clap(long, env)]
+    /// The S3 bucket index snapshots are uploaded to and restored from.
+    ///
+    /// Snapshots are only restored when the local data path has no index.
+    snapshot_s3_bucket: Option<String>,
+
+    #[clap(long, env, default_value = "us-east-1")]
+    /// The region of the snapshot bucket.
+    snapshot_s3_region: String,
+
+    #[clap(long, env)]
+    /// A custom endpoint for S3 compatible stores, i.e `http://127.0.0.1:9000`.
+    snapshot_s3_endpoint: Option<String>,
+
+    #[clap(long, env, default_value_t = 3600)]
+    /// How often snapshots are uploaded in seconds.
+    snapshot_interval: u64,
+
+    #[clap(long, env)]
+    /// Record each search into the `search_analytics` table.
+    record_analytics: bool,
+
+    #[clap(long, env, default_value = "")]
+    /// The secret the IPs of recorded searches are hashed with, required when recording analytics.
+    analytics_ip_secret: String,
+
+    #[clap(long, env)]
+    /// Move the monthly bot votes into the vote history and reset them when each month ends.
+    vote_rollover: bool,
+
+    #[clap(long, env)]
+    /// Record a daily snapshot of each bot's guild count to measure its growth from.
+    record_growth: bool,
 }
 
 #[tokio::main]
@@ -91,15 +301,173 @@ async fn main() -> Result<()> {
     {
         let nodes = args.cluster_nodes.split(';').collect::<Vec<&str>>();
 
-        models::connection::connect(&nodes, args.init_tables).await?;
+        let tls = (args.scylla_tls || args.scylla_ca_cert.is_some()).then(|| {
+            models::connection::TlsOptions {
+                ca_cert: args.scylla_ca_cert.clone(),
+                hostname: args.scylla_tls_hostname.clone(),
+            }
+        });
+        let options = models::connection::ConnectionOptions {
+            username: args.scylla_username.clone(),
+            password: args.scylla_password.clone(),
+            tls,
+            request_timeout: Duration::from_millis(args.scylla_request_timeout),
+            local_datacenter: args.scylla_local_dc.clone(),
+            prepare_cache_size: args.scylla_prepare_cache_size,
+            retry_policy: args.scylla_retry_policy,
+            speculative_execution: (args.scylla_speculative_retries > 0).then(|| {
+                models::connection::SpeculativeExecution {
+                    max_retries: args.scylla_speculative_retries,
+                    interval: Duration::from_millis(args.scylla_speculative_interval),
+                }
+            }),
+        };
+
+        models::connection::connect(&nodes, args.init_tables, options).await?;
+    }
+
+    tasks::start_db_health_tasks();
+
+    if args.replicate_from.is_some() {
+        search::replication::set_replica();
+    }
+
+    if let Some(url) = args.ingest.as_deref() {
+        if !url.starts_with("nats://") {
+            anyhow::bail!("Only `nats://` ingest servers are supported, got {:?}", url);
+        }
+    }
+
+    if args.leader_election {
+        let instance_id = args
+            .instance_id
+            .clone()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .unwrap_or_else(random_instance_id);
+
+        info!("Taking part in leader election as {}", instance_id);
+        tasks::start_leader_election(instance_id);
+    }
+
+    if args.record_analytics {
+        if args.analytics_ip_secret.is_empty() {
+            anyhow::bail!("An analytics IP secret must be set to record analytics.");
+        }
+
+        models::analytics::set_ip_secret(args.analytics_ip_secret.clone());
+        tasks::start_analytics_tasks();
+        tasks::start_popular_searches_tasks();
+    }
+
+    // Replicas never write to their indexes so they always reload the tables.
+    let cdc_interval = args.cdc_interval.filter(|_| args.replicate_from.is_none());
+    tasks::start_live_data_tasks(
+        args.scores_provider
+            .into_provider(args.a7s_uri, args.a7s_auth),
+        cdc_interval.is_none(),
+    );
+
+    routes::set_admin_token(args.admin_token.clone());
+
+    let verify_peers = args
+        .verify_peers
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .chain(args.replicate_from.clone())
+        .collect();
+    routes::admin::set_verify_peers(verify_peers);
+    routes::webhooks::set_vote_secret(args.vote_webhook_secret.clone());
+
+    if let Some(urls) = args.webhook_urls.as_deref() {
+        let urls = urls
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect::<Vec<_>>();
+
+        if args.webhook_secret.is_empty() {
+            anyhow::bail!("A webhook secret must be set to send webhooks.");
+        }
+
+        notify::init(urls, args.webhook_secret.clone())?;
+    }
+    search::set_writer_queue_size(args.writer_queue_size);
+    search::set_recreate_outdated_indexes(args.recreate_outdated_indexes);
+    search::set_writer_threads(args.writer_threads);
+    search::tokenizer::set_symbol_mode(args.symbol_mode);
+    search::tokenizer::set_stopwords(args.stopwords.split(','));
+    search::readers::bots::set_featured_boost(args.featured_boost);
+    search::readers::bots::set_premium_boost(args.premium_boost);
+    search::index_impls::bots::set_index_long_descriptions(args.index_long_descriptions);
+    search::readers::bots::set_recency_half_life(Duration::from_secs(
+        args.recency_half_life * 24 * 60 * 60,
+    ));
+
+    // Synonyms are normalized with the tokenizer so it must be configured first.
+    if let Err(e) = models::synonyms::refresh_synonyms().await {
+        warn!("Failed to load synonyms, continuing without them: {}", e);
+    }
+
+    if let Err(e) = models::featured::refresh_featured_bots().await {
+        warn!(
+            "Failed to load featured bots, continuing without them: {}",
+            e
+        );
+    }
+
+    // Votes are indexed so they must be loaded before the first refresh.
+    if let Err(e) = models::bots::refresh_latest_votes().await {
+        warn!("Failed to load bot votes, continuing without them: {}", e);
+    }
+
+    if let Err(e) = models::ratings::refresh_latest_ratings().await {
+        warn!("Failed to load bot ratings, continuing without them: {}", e);
     }
 
-    tasks::start_vote_update_tasks();
-    tasks::start_live_data_tasks(args.a7s_uri, args.a7s_auth);
+    if let Err(e) = models::packs::refresh_latest_votes().await {
+        warn!("Failed to load pack votes, continuing without them: {}", e);
+    }
+
+    // Any changes made while the indexes are loaded are applied from the CDC log after.
+    let loading_started = SystemTime::now();
 
     {
-        let limiter = Arc::new(Semaphore::new(args.max_concurrency));
+        let limiter = Arc::new(search::limiter::AdaptiveLimiter::new(
+            args.min_concurrency,
+            args.max_concurrency,
+        ));
         let base_path = Path::new(&args.data_path);
+
+        let mut restored = false;
+        if let Some(bucket) = args.snapshot_s3_bucket.as_deref() {
+            search::snapshots::init(
+                bucket,
+                &args.snapshot_s3_region,
+                args.snapshot_s3_endpoint.clone(),
+            )?;
+
+            if args.replicate_from.is_none() {
+                for index in IndexName::ALL {
+                    let path = base_path.join(index.as_str());
+                    match search::snapshots::restore_if_empty(index, &path).await {
+                        Ok(true) => {
+                            info!("Restored {} index from snapshot", index.as_str());
+                            restored = true;
+                        },
+                        Ok(false) => {},
+                        Err(e) => warn!(
+                            "Failed to restore {} index from snapshot, rebuilding it instead: {}",
+                            index.as_str(),
+                            e
+                        ),
+                    }
+                }
+            }
+        }
+
         search::index_impls::bots::init_index(
             &base_path.join("bots"),
             limiter.clone(),
@@ -114,25 +482,133 @@ async fn main() -> Result<()> {
         )
         .await?;
 
-        search::index_impls::packs::writer().full_refresh().await?;
-        search::index_impls::bots::writer().full_refresh().await?;
+        if let Some(primary) = args.replicate_from {
+            models::packs::refresh_latest_data().await?;
+            models::bots::refresh_latest_data().await?;
+            search::autocomplete::rebuild();
+
+            tasks::start_vote_update_tasks(false);
+            tasks::start_replication_tasks(
+                primary,
+                Duration::from_secs(args.replication_interval),
+            );
+        } else {
+            // A snapshot built with an older schema is recreated empty when opened
+            // with `--recreate-outdated-indexes`, so it must be rebuilt before serving.
+            let restored = restored
+                && search::index_impls::packs::writer().is_current()
+                && search::index_impls::bots::writer().is_current();
+
+            if restored {
+                models::packs::refresh_latest_data().await?;
+                models::bots::refresh_latest_data().await?;
+                search::autocomplete::rebuild();
+
+                tasks::start_background_refresh();
+            } else {
+                search::index_impls::packs::writer().full_refresh().await?;
+                search::index_impls::bots::writer().full_refresh().await?;
+            }
+
+            tasks::start_vote_update_tasks(true);
+            tasks::start_featured_tasks();
+
+            if args.vote_rollover {
+                tasks::start_vote_rollover_tasks();
+            }
+
+            tasks::start_growth_tasks(args.record_growth);
+
+            if let Some(url) = args.redis_url {
+                tasks::start_redis_tasks(url);
+            }
+
+            if let Some(url) = args.ingest {
+                tasks::start_nats_tasks(NatsIngest {
+                    url,
+                    stream: args.ingest_stream,
+                    dead_letters: base_path.join("dead-letters.ndjson"),
+                });
+            }
+
+            if let Some(schedule) = args.full_refresh_schedule {
+                tasks::start_scheduled_refresh_tasks(schedule);
+            }
+
+            if let Some(every) = cdc_interval {
+                tasks::start_cdc_tasks(loading_started, Duration::from_secs(every));
+            }
+
+            if search::snapshots::is_enabled() {
+                tasks::start_snapshot_tasks(Duration::from_secs(args.snapshot_interval));
+            }
+        }
+    }
+
+    if let Some(addr) = args.grpc_bind {
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(addr).await {
+                error!("gRPC server stopped: {}", e);
+            }
+        });
     }
 
     let api_service = OpenApiService::new(
-        (routes::bots::BotApi, routes::packs::PackApi),
+        (
+            routes::bots::BotApi,
+            routes::bots::BotSearchApi,
+            routes::packs::PackApi,
+            routes::packs::PackSearchApi,
+            routes::admin::AdminApi,
+            routes::webhooks::WebhookApi,
+        ),
         "Cronos API",
         env!("CARGO_PKG_VERSION"),
     )
     .description("The Dlist api system.")
-    .server(args.exposed_address);
+    .server(args.exposed_address.clone());
 
     let ui = api_service.redoc();
     let spec = api_service.spec();
 
+    // The exposed address points at `/v0`, so `/v1` is served next to it.
+    let exposed_v1 = match args.exposed_address.strip_suffix("/v0") {
+        Some(base) => format!("{}/v1", base),
+        None => args.exposed_address.clone(),
+    };
+
+    let api_v1_service = OpenApiService::new(
+        (
+            routes::bots::BotApi,
+            routes::v1::BotSearchApi,
+            routes::packs::PackApi,
+            routes::v1::PackSearchApi,
+            routes::admin::AdminApi,
+            routes::webhooks::WebhookApi,
+        ),
+        "Cronos API",
+        env!("CARGO_PKG_VERSION"),
+    )
+    .description("The Dlist api system.")
+    .server(exposed_v1);
+
+    let ui_v1 = api_v1_service.redoc();
+    let spec_v1 = api_v1_service.spec();
+
     let app = Route::new()
         .nest("/v0", api_service)
+        .nest("/v1", api_v1_service)
+        .at("/ws", poem::get(routes::live::subscribe))
+        .at("/events", poem::get(routes::live::events))
+        .at("/health", poem::get(routes::health::health))
+        .at("/indexes/bots/search", poem::post(routes::meili::search))
         .nest("/ui", ui)
         .at("/spec", poem::endpoint::make_sync(move |_| spec.clone()))
+        .nest("/ui/v1", ui_v1)
+        .at(
+            "/spec/v1",
+            poem::endpoint::make_sync(move |_| spec_v1.clone()),
+        )
         .around(global_ratelimiter)
         .around(log)
         .with(
@@ -167,6 +643,11 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+fn random_instance_id() -> String {
+    let hash = RandomState::new().build_hasher().finish();
+    format!("cronos-{:016x}", hash)
+}
+
 macro_rules! get_limit {
     ($env_var:expr) => {{
         std::env::var_os($env_var)
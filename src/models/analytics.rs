@@ -0,0 +1,349 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use once_cell::sync::{Lazy, OnceCell};
+use sha2::Sha256;
+
+use crate::models::connection::{session, PageSize};
+
+/// The maximum number of searches buffered before new ones are dropped.
+const BUFFER_SIZE: usize = 10_000;
+
+/// The maximum number of searches written per flush.
+const MAX_FLUSH_SIZE: usize = 1_000;
+
+/// The number of searches written at once.
+const FLUSH_CONCURRENCY: usize = 32;
+
+/// The number of partitions each day of searches is split across.
+const DAY_BUCKETS: i32 = 16;
+
+static RECORDER: OnceCell<flume::Sender<(i64, SearchRecord)>> = OnceCell::new();
+static RECORDS: OnceCell<flume::Receiver<(i64, SearchRecord)>> = OnceCell::new();
+static IP_SECRET: OnceCell<String> = OnceCell::new();
+
+/// The period popular searches are counted over.
+const POPULAR_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The maximum number of popular searches kept.
+const MAX_POPULAR: usize = 50;
+
+/// The fewest distinct IPs a query must be searched from to be popular.
+const MIN_POPULAR_IPS: usize = 3;
+
+/// Longer queries are never shown as popular.
+const MAX_POPULAR_QUERY_CHARS: usize = 64;
+
+static POPULAR_ENTITIES: [&str; 2] = ["bots", "packs"];
+
+const DAY_MILLIS: i64 = 86_400_000;
+
+static POPULAR_SEARCHES: Lazy<ArcSwap<HashMap<&'static str, Vec<(String, usize)>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+/// A per-process sequence used to keep records in the same millisecond apart.
+static SEQUENCE: AtomicI32 = AtomicI32::new(0);
+
+#[derive(Debug)]
+pub struct SearchRecord {
+    /// The type of entity being searched, i.e `bots`.
+    pub entity: &'static str,
+
+    /// The original query, `*` for a wild card search.
+    pub query: String,
+
+    /// The filters used as JSON.
+    pub filters: String,
+
+    /// The total number of documents that matched the query.
+    pub nb_hits: usize,
+
+    /// How long the search took to run.
+    pub latency: Duration,
+
+    /// The truncated hash of the IP which ran the search.
+    pub ip_hash: i32,
+}
+
+/// Enables recording searches.
+///
+/// Searches are buffered in memory until they are written by [flush_searches].
+pub fn init() {
+    let (tx, rx) = flume::bounded(BUFFER_SIZE);
+    let _ = RECORDER.set(tx);
+    let _ = RECORDS.set(rx);
+}
+
+/// Buffers a search to be written to the analytics table.
+///
+/// This never blocks, the search is dropped if the buffer is full or
+/// recording has not been enabled.
+pub fn record_search(record: SearchRecord) {
+    if let Some(recorder) = RECORDER.get() {
+        let searched_on = now_millis();
+        if recorder.try_send((searched_on, record)).is_err() {
+            debug!("Dropping search record, the analytics buffer is full");
+        }
+    }
+}
+
+/// Sets the secret IPs are hashed with, changing it means the hashes of
+/// past searches no longer match new ones.
+pub fn set_ip_secret(secret: String) {
+    let _ = IP_SECRET.set(secret);
+}
+
+/// Hashes the given IP with HMAC-SHA256, only keeping the first 32 bits
+/// so the original address is never stored.
+///
+/// The current day is hashed along with the IP, so the same IP only hashes
+/// the same for a day and can't be followed across days.
+pub fn hash_ip(ip: &str) -> i32 {
+    let secret = IP_SECRET.get().map(String::as_str).unwrap_or_default();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(&(now_millis() / DAY_MILLIS).to_be_bytes());
+    mac.update(ip.as_bytes());
+
+    let hash = mac.finalize().into_bytes();
+    i32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+#[inline]
+/// Get up to `limit` of the most frequent queries for the given entity,
+/// most frequent first.
+pub fn popular_searches(entity: &str, limit: usize) -> Vec<(String, usize)> {
+    POPULAR_SEARCHES
+        .load()
+        .get(entity)
+        .map(|v| v.iter().take(limit).cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Recounts the most frequent queries of each entity over the rolling window
+/// and saves them for every other instance to load.
+///
+/// Each query is counted once per IP which searched it and only queries
+/// searched from at least [MIN_POPULAR_IPS] IPs are kept. Wild card searches,
+/// searches without any hits and queries which look like IDs or links are
+/// not counted.
+///
+/// This only needs to run on a single instance at a time.
+pub async fn refresh_popular_searches() -> Result<()> {
+    let now = now_millis();
+    let since = now - POPULAR_WINDOW.as_millis() as i64;
+
+    let partitions = ((since / DAY_MILLIS)..=(now / DAY_MILLIS))
+        .flat_map(|day| (0..DAY_BUCKETS).map(move |bucket| (day, bucket)));
+
+    let mut searched_by: HashMap<&'static str, HashMap<String, HashSet<i32>>> =
+        HashMap::new();
+    for (day, bucket) in partitions {
+        let mut iter = session()
+            .query_iter(
+                "SELECT entity, query, nb_hits, ip_hash FROM search_analytics WHERE day = ? AND bucket = ? AND searched_on >= ?;",
+                (day, bucket, since),
+                PageSize::Bulk,
+            )
+            .await?
+            .into_typed::<(String, String, i32, i32)>();
+
+        while let Some(row) = iter.next().await {
+            let (entity, query, nb_hits, ip_hash) = row?;
+            let entity = match entity.as_str() {
+                "bots" => "bots",
+                "packs" => "packs",
+                _ => continue,
+            };
+
+            if nb_hits == 0 {
+                continue;
+            }
+
+            if let Some(query) = normalize_query(&query) {
+                searched_by
+                    .entry(entity)
+                    .or_default()
+                    .entry(query)
+                    .or_default()
+                    .insert(ip_hash);
+            }
+        }
+    }
+
+    let mut popular = HashMap::new();
+    for (entity, queries) in searched_by {
+        let mut queries = queries
+            .into_iter()
+            .map(|(query, ips)| (query, ips.len()))
+            .filter(|(_, count)| *count >= MIN_POPULAR_IPS)
+            .collect::<Vec<_>>();
+        queries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        queries.truncate(MAX_POPULAR);
+
+        popular.insert(entity, queries);
+    }
+
+    for entity in POPULAR_ENTITIES {
+        let queries = popular.get(entity).map(Vec::as_slice).unwrap_or_default();
+        save_popular_searches(entity, queries).await?;
+    }
+
+    POPULAR_SEARCHES.store(Arc::new(popular));
+
+    Ok(())
+}
+
+/// Loads the popular searches last saved by [refresh_popular_searches].
+pub async fn load_popular_searches() -> Result<()> {
+    let mut popular = HashMap::new();
+    for entity in POPULAR_ENTITIES {
+        let mut iter = session()
+            .query_iter(
+                "SELECT query, searches FROM popular_searches WHERE entity = ?;",
+                (entity,),
+                PageSize::Interactive,
+            )
+            .await?
+            .into_typed::<(String, i32)>();
+
+        let mut queries = vec![];
+        while let Some(row) = iter.next().await {
+            let (query, searches) = row?;
+            queries.push((query, searches.max(0) as usize));
+        }
+
+        popular.insert(entity, queries);
+    }
+
+    POPULAR_SEARCHES.store(Arc::new(popular));
+
+    Ok(())
+}
+
+async fn save_popular_searches(entity: &str, queries: &[(String, usize)]) -> Result<()> {
+    for (rank, (query, searches)) in queries.iter().enumerate() {
+        session()
+            .query_prepared(
+                "INSERT INTO popular_searches (entity, rank, query, searches) VALUES (?, ?, ?, ?);",
+                (entity, rank as i32, query.as_str(), *searches as i32),
+            )
+            .await?;
+    }
+
+    // Any queries which dropped off the end of a longer list are removed.
+    session()
+        .query_prepared(
+            "DELETE FROM popular_searches WHERE entity = ? AND rank >= ?;",
+            (entity, queries.len() as i32),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Normalizes the query so the same search written differently is counted
+/// together, returning `None` if it should never be shown as popular.
+fn normalize_query(query: &str) -> Option<String> {
+    let query = query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    let is_shown = !query.is_empty()
+        && query != "*"
+        && query.chars().count() <= MAX_POPULAR_QUERY_CHARS
+        && !query.chars().any(char::is_control)
+        && !query.chars().all(|c| c.is_ascii_digit())
+        && !query.contains("://")
+        && !query.contains('@');
+
+    is_shown.then_some(query)
+}
+
+/// Writes up to [MAX_FLUSH_SIZE] of the buffered searches.
+///
+/// Searches which fail to be written are put back into the buffer to be
+/// retried by the next flush. Returns the number of searches written.
+pub async fn flush_searches() -> Result<usize> {
+    let records = match RECORDS.get() {
+        Some(records) => records,
+        None => return Ok(0),
+    };
+
+    let batch = records.try_iter().take(MAX_FLUSH_SIZE).collect::<Vec<_>>();
+    let mut writes = futures::stream::iter(batch)
+        .map(|(searched_on, record)| async move {
+            match write_search(searched_on, &record).await {
+                Ok(()) => Ok(()),
+                Err(e) => Err((e, searched_on, record)),
+            }
+        })
+        .buffer_unordered(FLUSH_CONCURRENCY);
+
+    let mut written = 0;
+    let mut last_error = None;
+    while let Some(result) = writes.next().await {
+        match result {
+            Ok(()) => written += 1,
+            Err((e, searched_on, record)) => {
+                if let Some(recorder) = RECORDER.get() {
+                    let _ = recorder.try_send((searched_on, record));
+                }
+                last_error = Some(e);
+            },
+        }
+    }
+
+    match last_error {
+        Some(e) if written == 0 => Err(e),
+        Some(e) => {
+            warn!(
+                "Failed to write some search analytics, retrying them later: {}",
+                e
+            );
+            Ok(written)
+        },
+        None => Ok(written),
+    }
+}
+
+async fn write_search(searched_on: i64, record: &SearchRecord) -> Result<()> {
+    let day = searched_on / DAY_MILLIS;
+    let bucket = record.ip_hash.rem_euclid(DAY_BUCKETS);
+    let seq = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+
+    session()
+        .query_prepared(
+            "INSERT INTO search_analytics (day, bucket, searched_on, ip_hash, seq, entity, query, filters, nb_hits, latency_us) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+            (
+                day,
+                bucket,
+                searched_on,
+                record.ip_hash,
+                seq,
+                record.entity,
+                record.query.as_str(),
+                record.filters.as_str(),
+                record.nb_hits as i32,
+                record.latency.as_micros() as i64,
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
@@ -1,35 +1,66 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use arc_swap::ArcSwap;
 use backend_common::types::{JsSafeBigInt, JsSafeInt, Set, Timestamp};
 use backend_common::FieldNamesAsArray;
+use chrono::Utc;
 use futures::StreamExt;
 use once_cell::sync::Lazy;
-use parking_lot::RwLock;
-use scylla::FromRow;
+use poem_openapi::types::{ParseFromJSON, ToJSON};
+use poem_openapi::Object;
+use scylla::{FromRow, IntoTypedRows};
 use tantivy::schema::Schema;
 
-use crate::models::bots::flags::PREMIUM;
-use crate::models::connection::session;
-use crate::models::utils::{process_rows, VoteStats};
+use crate::models::bots::flags::{CERTIFIED, NSFW, PREMIUM};
+use crate::models::connection::{session, PageSize};
+use crate::models::featured::is_featured;
+use crate::models::growth::{get_bot_growth, scale_growth};
+use crate::models::ratings::{get_bot_rating, scale_rating};
+use crate::models::utils::{is_applied, process_vote_rows, RefreshSummary, VoteStats};
 use crate::search::index_impls::bots::{
+    indexes_long_descriptions,
+    CERTIFIED_FIELD,
+    CO_OWNER_IDS_FIELD,
+    CREATED_ON_FIELD,
+    DATA_FIELD,
     DESCRIPTION_FIELD,
+    FEATURED_FIELD,
     FEATURES_FIELD,
+    GROWTH_FIELD,
+    GUILD_COUNT_FIELD,
+    HIDDEN_FIELD,
     ID_FIELD,
+    LONG_DESCRIPTION_FIELD,
+    NSFW_FIELD,
+    OWNER_ID_FIELD,
+    PREFIX_FIELD,
     PREMIUM_FIELD,
+    RATING_FIELD,
     TAGS_AGG_FIELD,
     TAGS_FIELD,
+    TRENDING_FIELD,
     USERNAME_FIELD,
+    USERNAME_SORT_FIELD,
+    VOTES_FIELD,
 };
+use crate::search::tokenizer::{prefix_key, sort_key};
 use crate::{derive_fetch_by_id, derive_fetch_iter};
 
+/// The bits of the Dlist `flags` bitfield.
+///
+/// These mirror the flag values defined by the site's backend, which is the
+/// only writer of the `bots.flags` column, and must be kept in sync with it.
 pub mod flags {
     pub const PREMIUM: i64 = 1 << 0;
+    pub const NSFW: i64 = 1 << 1;
+    pub const CERTIFIED: i64 = 1 << 2;
 }
 
-#[derive(FromRow, FieldNamesAsArray, Debug, Clone)]
+#[derive(FromRow, FieldNamesAsArray, Object, Debug, Clone)]
 pub struct Bot {
     /// The snowflake ID of the bot.
     pub id: JsSafeBigInt,
@@ -86,27 +117,104 @@ pub struct Bot {
 
     /// The short description of the bot.
     pub brief_description: String,
+
+    /// The full markdown description of the bot.
+    ///
+    /// This is only kept while long descriptions are indexed.
+    pub description: Option<String>,
+
+    /// The timestamp the bot was last updated on, if it has been updated.
+    pub updated_on: Option<Timestamp>,
 }
 derive_fetch_by_id!(Bot, table = "bots");
 derive_fetch_iter!(Bot, table = "bots");
 
 impl Bot {
+    /// The copy of the bot stored in its document which hits are built from.
+    ///
+    /// The full description is left out as it is only ever searched.
+    fn stored_data(&self) -> String {
+        let mut data = self.to_json().unwrap_or_default();
+        if let Some(fields) = data.as_object_mut() {
+            fields.remove("description");
+        }
+
+        data.to_string()
+    }
+
+    /// Loads the bot from the copy stored in its document.
+    pub fn from_stored(data: &str) -> Option<Self> {
+        let value = serde_json::from_str(data).ok()?;
+        Self::parse_from_json(Some(value)).ok()
+    }
+
     pub fn as_tantivy_doc(&self, schema: &Schema) -> tantivy::Document {
         let mut document = tantivy::Document::new();
 
         let id_field = schema.get_field(ID_FIELD).unwrap();
+        let created_on_field = schema.get_field(CREATED_ON_FIELD).unwrap();
+        let username_sort_field = schema.get_field(USERNAME_SORT_FIELD).unwrap();
         let premium_field = schema.get_field(PREMIUM_FIELD).unwrap();
+        let nsfw_field = schema.get_field(NSFW_FIELD).unwrap();
+        let hidden_field = schema.get_field(HIDDEN_FIELD).unwrap();
+        let certified_field = schema.get_field(CERTIFIED_FIELD).unwrap();
+        let votes_field = schema.get_field(VOTES_FIELD).unwrap();
+        let trending_field = schema.get_field(TRENDING_FIELD).unwrap();
+        let rating_field = schema.get_field(RATING_FIELD).unwrap();
+        let growth_field = schema.get_field(GROWTH_FIELD).unwrap();
+        let prefix_field = schema.get_field(PREFIX_FIELD).unwrap();
+        let guild_count_field = schema.get_field(GUILD_COUNT_FIELD).unwrap();
+        let owner_id_field = schema.get_field(OWNER_ID_FIELD).unwrap();
+        let co_owner_ids_field = schema.get_field(CO_OWNER_IDS_FIELD).unwrap();
         let username_field = schema.get_field(USERNAME_FIELD).unwrap();
         let description_field = schema.get_field(DESCRIPTION_FIELD).unwrap();
+        let long_description_field = schema.get_field(LONG_DESCRIPTION_FIELD).unwrap();
+        let data_field = schema.get_field(DATA_FIELD).unwrap();
         let features_field = schema.get_field(FEATURES_FIELD).unwrap();
+        let featured_field = schema.get_field(FEATURED_FIELD).unwrap();
         let tags_field = schema.get_field(TAGS_FIELD).unwrap();
         let tags_agg_field = schema.get_field(TAGS_AGG_FIELD).unwrap();
 
         document.add_i64(id_field, *self.id);
+        document.add_i64(created_on_field, self.created_on.timestamp());
+        document.add_i64(username_sort_field, sort_key(&self.username));
         document.add_u64(premium_field, ((*self.flags & PREMIUM) != 0) as u64);
+        document.add_u64(nsfw_field, ((*self.flags & NSFW) != 0) as u64);
+        document.add_u64(
+            hidden_field,
+            (self.is_hidden || self.is_forced_into_hiding) as u64,
+        );
+        document.add_u64(certified_field, ((*self.flags & CERTIFIED) != 0) as u64);
+        document.add_i64(votes_field, get_bot_votes(*self.id) as i64);
+        document.add_i64(
+            trending_field,
+            scale_trending_score(get_bot_trending_score(*self.id)),
+        );
+        document.add_i64(rating_field, scale_rating(get_bot_rating(*self.id)));
+        document.add_i64(growth_field, scale_growth(get_bot_growth(*self.id)));
+        document.add_u64(
+            guild_count_field,
+            self.guild_count.as_deref().copied().unwrap_or_default() as u64,
+        );
+        document.add_i64(owner_id_field, *self.owner_id);
         document.add_text(username_field, &self.username);
         document.add_text(description_field, &self.brief_description);
+        document.add_text(data_field, self.stored_data());
+        if let Some(description) = self.description.as_deref() {
+            if indexes_long_descriptions() {
+                document.add_text(long_description_field, description);
+            }
+        }
         document.add_u64(features_field, *self.features as u64);
+        document.add_u64(featured_field, is_featured(*self.id) as u64);
+
+        if let Some(prefix) = self.prefix.as_deref() {
+            document.add_text(prefix_field, prefix_key(prefix));
+        }
+
+        for co_owner_id in self.co_owner_ids.iter() {
+            document.add_i64(co_owner_ids_field, **co_owner_id);
+        }
 
         for tag in self.tags.iter() {
             document.add_text(tags_field, &tag);
@@ -117,42 +225,244 @@ impl Bot {
     }
 }
 
-static VOTE_INFO: Lazy<ArcSwap<HashMap<i64, VoteStats>>> =
+/// The precision trending scores are kept to when indexed.
+const TRENDING_SCALE: f64 = 1_000_000.0;
+
+/// The live votes of a bot, these are updated in place as votes come in.
+#[derive(Debug, Default)]
+struct VoteCounter {
+    votes: AtomicU64,
+    all_time_votes: AtomicU64,
+}
+
+impl VoteCounter {
+    fn new(stats: VoteStats) -> Self {
+        Self {
+            votes: AtomicU64::new(stats.votes()),
+            all_time_votes: AtomicU64::new(stats.all_time_votes()),
+        }
+    }
+
+    fn stats(&self) -> VoteStats {
+        VoteStats::new(self.votes.load(Ordering::Relaxed) as i64)
+            .with_all_time_votes(self.all_time_votes.load(Ordering::Relaxed) as i64)
+    }
+}
+
+static VOTE_INFO: Lazy<ArcSwap<HashMap<i64, Arc<VoteCounter>>>> =
     Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
 
 #[inline]
 pub fn vote_stats(id: i64) -> VoteStats {
-    VOTE_INFO.load().get(&id).copied().unwrap_or_default()
+    VOTE_INFO
+        .load()
+        .get(&id)
+        .map(|counter| counter.stats())
+        .unwrap_or_default()
+}
+
+/// Gets the live vote counter of the bot.
+///
+/// The map is only copied for bots which have no counter yet.
+fn vote_counter(bot_id: i64) -> Arc<VoteCounter> {
+    if let Some(counter) = VOTE_INFO.load().get(&bot_id) {
+        return counter.clone();
+    }
+
+    let mut counter = None;
+    VOTE_INFO.rcu(|current| {
+        let mut updated = HashMap::clone(current);
+        counter = Some(updated.entry(bot_id).or_default().clone());
+        updated
+    });
+
+    counter.unwrap_or_default()
 }
 
-pub async fn refresh_latest_votes() -> Result<()> {
+/// Reloads the bot votes from the database.
+///
+/// Returns the ids of all bots whose votes have changed.
+pub async fn refresh_latest_votes() -> Result<Vec<i64>> {
     let iter = session()
-        .query_iter("SELECT id, votes FROM bot_votes;", &[])
+        .query_iter(
+            "SELECT id, votes, all_time_votes FROM bot_votes;",
+            &[],
+            PageSize::Bulk,
+        )
         .await?;
 
-    VOTE_INFO.store(Arc::new(process_rows(iter).await));
+    let latest = process_vote_rows(iter).await;
+    let counters = latest
+        .iter()
+        .map(|(id, stats)| (*id, Arc::new(VoteCounter::new(*stats))))
+        .collect();
+    let previous = VOTE_INFO.swap(Arc::new(counters));
+
+    let changed = latest
+        .iter()
+        .filter(|(id, stats)| {
+            previous.get(id).map(|counter| counter.stats()) != Some(**stats)
+        })
+        .map(|(id, _)| *id)
+        .chain(
+            previous
+                .keys()
+                .filter(|id| !latest.contains_key(id))
+                .copied(),
+        )
+        .collect();
+
+    Ok(changed)
+}
+
+/// Sets the votes of a single bot until the votes are next reloaded.
+///
+/// Returns `false` if the bot already had that many votes.
+pub fn set_bot_votes(bot_id: i64, votes: u64) -> bool {
+    if vote_stats(bot_id).votes() == votes {
+        return false;
+    }
+
+    let counter = vote_counter(bot_id);
+    let previous = counter.votes.swap(votes, Ordering::Relaxed);
+    if previous == votes {
+        return false;
+    }
+
+    counter
+        .all_time_votes
+        .fetch_add(votes.saturating_sub(previous), Ordering::Relaxed);
 
-    Ok(())
+    true
 }
 
-static LIVE_DATA: Lazy<RwLock<HashMap<i64, Bot>>> = Lazy::new(Default::default);
+/// How long a user has to wait before voting for the same bot again.
+pub const VOTE_COOLDOWN: Duration = Duration::from_secs(12 * 60 * 60);
+
+#[derive(Debug, Copy, Clone)]
+pub enum VoteOutcome {
+    /// The vote was counted, these are the bot's votes including it.
+    Counted(VoteStats),
+
+    /// The user already voted for the bot, they can vote again after this.
+    OnCooldown(Duration),
+}
+
+/// Counts a vote for the bot from the user, unless they have already
+/// voted for it within the [VOTE_COOLDOWN].
+///
+/// The in memory votes are updated straight away.
+pub async fn submit_vote(bot_id: i64, user_id: i64) -> Result<VoteOutcome> {
+    let recorded = session()
+        .query_prepared(
+            "INSERT INTO user_votes (bot_id, user_id, voted_on) VALUES (?, ?, toTimestamp(now())) IF NOT EXISTS USING TTL ?;",
+            (bot_id, user_id, VOTE_COOLDOWN.as_secs() as i32),
+        )
+        .await?;
+
+    if !is_applied(&recorded) {
+        let remaining = vote_cooldown(bot_id, user_id).await?;
+        return Ok(VoteOutcome::OnCooldown(remaining));
+    }
+
+    let counted = session()
+        .query_prepared(
+            "UPDATE bot_votes SET votes = votes + 1, all_time_votes = all_time_votes + 1 WHERE id = ?;",
+            (bot_id,),
+        )
+        .await;
+
+    if let Err(e) = counted {
+        // Let the user try again rather than blocking them for a vote
+        // which was never counted.
+        let _ = session()
+            .query_prepared(
+                "DELETE FROM user_votes WHERE bot_id = ? AND user_id = ?;",
+                (bot_id, user_id),
+            )
+            .await;
+        return Err(e);
+    }
+
+    let counter = vote_counter(bot_id);
+    counter.votes.fetch_add(1, Ordering::Relaxed);
+    counter.all_time_votes.fetch_add(1, Ordering::Relaxed);
+
+    Ok(VoteOutcome::Counted(counter.stats()))
+}
+
+/// How long until the user can vote for the bot again.
+async fn vote_cooldown(bot_id: i64, user_id: i64) -> Result<Duration> {
+    let ttl = session()
+        .query_prepared(
+            "SELECT TTL(voted_on) FROM user_votes WHERE bot_id = ? AND user_id = ?;",
+            (bot_id, user_id),
+        )
+        .await?
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(Option<i32>,)>()
+        .next()
+        .transpose()?
+        .and_then(|(ttl,)| ttl);
+
+    // The vote may have just expired, in which case it can be retried now.
+    Ok(ttl
+        .map(|secs| Duration::from_secs(secs.max(0) as u64))
+        .unwrap_or_default())
+}
+
+static LIVE_DATA: Lazy<ArcSwap<HashMap<i64, Arc<Bot>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
 static TRENDING_DATA: Lazy<ArcSwap<HashMap<i64, f64>>> =
     Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
 
 #[inline]
-pub fn set_bot_trending_data(data: HashMap<i64, f64>) {
-    TRENDING_DATA.store(Arc::new(data));
+/// Replaces the bot trending scores.
+///
+/// Returns the ids of all bots whose indexed trending score has changed.
+pub fn set_bot_trending_data(data: HashMap<i64, f64>) -> Vec<i64> {
+    let latest = Arc::new(data);
+    let previous = TRENDING_DATA.swap(latest.clone());
+
+    let scaled = |scores: &HashMap<i64, f64>, id: &i64| {
+        scores.get(id).copied().map(scale_trending_score)
+    };
+
+    latest
+        .keys()
+        .chain(previous.keys())
+        .filter(|id| scaled(latest.as_ref(), id) != scaled(previous.as_ref(), id))
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect()
 }
 
 #[inline]
-pub fn get_bot_data(id: i64) -> Option<Bot> {
-    let txn = LIVE_DATA.read();
+/// Converts a trending score to the fixed point value stored in the index.
+pub fn scale_trending_score(score: f64) -> i64 {
+    (score * TRENDING_SCALE) as i64
+}
+
+#[inline]
+pub fn get_bot_data(id: i64) -> Option<Arc<Bot>> {
+    let txn = LIVE_DATA.load();
     txn.get(&id).cloned()
 }
 
+/// Looks up every given bot from a single load of the live data, bots
+/// which are not live are skipped.
+pub fn get_many_bot_data(ids: impl IntoIterator<Item = i64>) -> Vec<Arc<Bot>> {
+    let txn = LIVE_DATA.load();
+    ids.into_iter()
+        .filter_map(|id| txn.get(&id).cloned())
+        .collect()
+}
+
 #[inline]
 pub fn is_hidden(id: i64) -> bool {
-    let txn = LIVE_DATA.read();
+    let txn = LIVE_DATA.load();
     txn.get(&id)
         .map(|v| !v.is_packable || v.is_hidden || v.is_forced_into_hiding)
         .unwrap_or_default()
@@ -160,37 +470,241 @@ pub fn is_hidden(id: i64) -> bool {
 
 #[inline]
 pub fn remove_bot_from_live(bot_id: i64) {
-    let mut txn = LIVE_DATA.write();
-    txn.remove(&bot_id);
+    remove_many_from_live(vec![bot_id]);
+}
+
+/// Removes every given bot with a single copy of the live data.
+pub fn remove_many_from_live(bot_ids: Vec<i64>) {
+    let live = LIVE_DATA.load();
+    if !bot_ids.iter().any(|id| live.contains_key(id)) {
+        return;
+    }
+
+    LIVE_DATA.rcu(|current| {
+        let mut updated = HashMap::clone(current);
+        for bot_id in bot_ids.iter() {
+            updated.remove(bot_id);
+        }
+        updated
+    });
 }
 
 #[inline]
 pub fn update_live_data(bot: Bot) {
-    let mut txn = LIVE_DATA.write();
-    txn.insert(*bot.id, bot);
+    update_live_data_many(vec![bot]);
+}
+
+/// Replaces every given bot with a single copy of the live data, so
+/// batches don't copy the whole map per bot.
+pub fn update_live_data_many(bots: Vec<Bot>) {
+    if bots.is_empty() {
+        return;
+    }
+
+    let bots = bots
+        .into_iter()
+        .map(|bot| Arc::new(without_unused_fields(bot)))
+        .collect::<Vec<_>>();
+
+    LIVE_DATA.rcu(|current| {
+        let mut updated = HashMap::clone(current);
+        for bot in bots.iter() {
+            updated.insert(*bot.id, bot.clone());
+        }
+        updated
+    });
+}
+
+/// Drops the full description when it is not indexed, so it is not held
+/// in memory for every bot.
+fn without_unused_fields(mut bot: Bot) -> Bot {
+    if !indexes_long_descriptions() {
+        bot.description = None;
+    }
+
+    bot
 }
 
-pub fn all_bots() -> Vec<Bot> {
-    let txn = LIVE_DATA.read();
-    txn.iter().map(|(_, v)| v.clone()).collect()
+pub fn all_bots() -> Vec<Arc<Bot>> {
+    let txn = LIVE_DATA.load();
+    txn.values().cloned().collect()
 }
 
-pub async fn refresh_latest_data() -> Result<()> {
+/// The hosts Discord serves its OAuth2 authorize page on.
+const DISCORD_HOSTS: &[&str] = &[
+    "discord.com",
+    "discordapp.com",
+    "canary.discord.com",
+    "ptb.discord.com",
+];
+
+/// Parses the client ID out of a Discord bot invite or OAuth2 URL, a bare
+/// ID is also accepted.
+pub fn client_id_from_invite(invite: &str) -> Option<i64> {
+    let invite = invite.trim();
+    if let Ok(id) = invite.parse::<i64>() {
+        return Some(id).filter(|id| *id > 0);
+    }
+
+    let url = if invite.contains("://") {
+        reqwest::Url::parse(invite)
+    } else {
+        reqwest::Url::parse(&format!("https://{}", invite))
+    }
+    .ok()?;
+
+    let host = url.host_str()?.trim_start_matches("www.");
+    if !DISCORD_HOSTS.contains(&host) || !url.path().ends_with("/oauth2/authorize") {
+        return None;
+    }
+
+    url.query_pairs()
+        .find(|(key, _)| key == "client_id")
+        .and_then(|(_, id)| id.parse::<i64>().ok())
+        .filter(|id| *id > 0)
+}
+
+/// Finds the listed bot with the given client ID.
+///
+/// Bots whose client ID differs from their user ID are matched by the
+/// client ID of their invite instead.
+pub fn find_by_client_id(client_id: i64) -> Option<Arc<Bot>> {
+    let txn = LIVE_DATA.load();
+    if let Some(bot) = txn.get(&client_id) {
+        return Some(bot.clone());
+    }
+
+    txn.values()
+        .find(|bot| client_id_from_invite(&bot.invite_url) == Some(client_id))
+        .cloned()
+}
+
+/// The number of live bots with each tag.
+pub fn tag_counts() -> HashMap<String, usize> {
+    let txn = LIVE_DATA.load();
+
+    let mut counts = HashMap::new();
+    for tag in txn.values().flat_map(|bot| bot.tags.iter()) {
+        *counts.entry(tag.clone()).or_default() += 1;
+    }
+
+    counts
+}
+
+/// Reloads all bots from the database.
+///
+/// Rows which fail to load are skipped and reported in the returned summary.
+pub async fn refresh_latest_data() -> Result<RefreshSummary> {
     let mut iter = Bot::iter_rows().await?.into_typed::<Bot>();
 
+    let mut summary = RefreshSummary::default();
     let mut bots = HashMap::new();
-    while let Some(Ok(row)) = iter.next().await {
+    while let Some(row) = iter.next().await {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                warn!("Failed to load bot row due to error: {}", e);
+                summary.record_error(e);
+                continue;
+            },
+        };
+
         if row.is_hidden || row.is_forced_into_hiding {
+            summary.skipped += 1;
             continue;
         }
 
-        bots.insert(*row.id, row);
+        bots.insert(*row.id, Arc::new(without_unused_fields(row)));
+    }
+
+    LIVE_DATA.store(Arc::new(bots));
+
+    Ok(summary)
+}
+
+/// The length of time each partition of `bots_by_updated_on` covers.
+const UPDATED_ON_BUCKET_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// How long the rows of `bots_by_updated_on` live for, matching the
+/// table's `default_time_to_live`.
+const UPDATED_ON_RETENTION_MILLIS: i64 = 30 * UPDATED_ON_BUCKET_MILLIS;
+
+/// The number of bots fetched by id per query.
+const FETCH_CHUNK_SIZE: usize = 100;
+
+/// Loads the bots which were updated after the given unix timestamp in
+/// milliseconds, including hidden bots so they can be removed.
+///
+/// The updated bots are found through `bots_by_updated_on`, which is
+/// partitioned by day so only the days since then are read. The site's
+/// backend writes a row to it alongside every update to `bots`, Cronos
+/// only ever reads it. Each row expires after 30 days, so a timestamp
+/// older than that is rejected, see [is_update_tracked].
+///
+/// Unlike [refresh_latest_data] this leaves the live data untouched.
+pub async fn fetch_updated_since(
+    since_millis: i64,
+) -> Result<(Vec<Bot>, RefreshSummary)> {
+    if !is_update_tracked(since_millis) {
+        return Err(anyhow!(
+            "bot updates are only tracked for the last 30 days, use a full refresh instead"
+        ));
+    }
+
+    let now = Utc::now().timestamp_millis();
+
+    let mut ids = HashSet::new();
+    for day in
+        (since_millis / UPDATED_ON_BUCKET_MILLIS)..=(now / UPDATED_ON_BUCKET_MILLIS)
+    {
+        // A CQL timestamp is encoded the same as a bigint of milliseconds.
+        let mut iter = session()
+            .query_iter(
+                "SELECT id FROM bots_by_updated_on WHERE day = ? AND updated_on > ?;",
+                (day, since_millis),
+                PageSize::Bulk,
+            )
+            .await?
+            .into_typed::<(i64,)>();
+
+        while let Some(row) = iter.next().await {
+            let (id,) = row?;
+            ids.insert(id);
+        }
+    }
+
+    let qry = format!(
+        "SELECT {} FROM bots WHERE id IN ?;",
+        Bot::FIELD_NAMES_AS_ARRAY.join(", "),
+    );
+
+    let ids = ids.into_iter().collect::<Vec<_>>();
+    let mut summary = RefreshSummary::default();
+    let mut bots = vec![];
+    for chunk in ids.chunks(FETCH_CHUNK_SIZE) {
+        let mut iter = session()
+            .query_iter(&qry, (chunk.to_vec(),), PageSize::Bulk)
+            .await?
+            .into_typed::<Bot>();
+
+        while let Some(row) = iter.next().await {
+            match row {
+                Ok(row) => bots.push(row),
+                Err(e) => {
+                    warn!("Failed to load bot row due to error: {}", e);
+                    summary.record_error(e);
+                },
+            }
+        }
     }
 
-    let mut lock = LIVE_DATA.write();
-    (*lock) = bots;
+    Ok((bots, summary))
+}
 
-    Ok(())
+/// Whether every bot updated since the given unix timestamp in
+/// milliseconds is still in `bots_by_updated_on`.
+pub fn is_update_tracked(since_millis: i64) -> bool {
+    since_millis >= Utc::now().timestamp_millis() - UPDATED_ON_RETENTION_MILLIS
 }
 
 #[inline]
@@ -198,6 +712,11 @@ pub fn get_bot_votes(bot_id: i64) -> u64 {
     vote_stats(bot_id).votes()
 }
 
+#[inline]
+pub fn get_bot_all_time_votes(bot_id: i64) -> u64 {
+    vote_stats(bot_id).all_time_votes()
+}
+
 #[inline]
 pub fn get_bot_premium(bot_id: i64) -> bool {
     get_bot_data(bot_id)
@@ -205,6 +724,13 @@ pub fn get_bot_premium(bot_id: i64) -> bool {
         .unwrap_or_default()
 }
 
+#[inline]
+pub fn get_bot_certified(bot_id: i64) -> bool {
+    get_bot_data(bot_id)
+        .map(|b| (*b.flags & flags::CERTIFIED) != 0)
+        .unwrap_or_default()
+}
+
 #[inline]
 pub fn get_bot_trending_score(bot_id: i64) -> f64 {
     let txn = TRENDING_DATA.load();
@@ -221,6 +747,35 @@ pub fn get_bot_age(bot_id: i64) -> i64 {
 #[inline]
 pub fn get_bot_guild_count(bot_id: i64) -> u64 {
     get_bot_data(bot_id)
-        .map(|b| *b.guild_count.unwrap_or_default() as u64)
+        .map(|b| b.guild_count.as_deref().copied().unwrap_or_default() as u64)
         .unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_id_from_invite() {
+        let cases = [
+            (
+                "https://discord.com/oauth2/authorize?client_id=123&scope=bot",
+                Some(123),
+            ),
+            (
+                "https://discordapp.com/api/oauth2/authorize?scope=bot&client_id=456",
+                Some(456),
+            ),
+            ("discord.com/oauth2/authorize?client_id=789", Some(789)),
+            (" 101112 ", Some(101112)),
+            ("https://example.com/oauth2/authorize?client_id=123", None),
+            ("https://discord.com/invite/abc", None),
+            ("https://discord.com/oauth2/authorize?client_id=abc", None),
+            ("not a url", None),
+        ];
+
+        for (invite, expected) in cases {
+            assert_eq!(client_id_from_invite(invite), expected, "{}", invite);
+        }
+    }
+}
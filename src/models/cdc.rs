@@ -0,0 +1,145 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use scylla::IntoTypedRows;
+
+use crate::models::connection::{session, PageSize};
+
+/// The number of CDC streams which are read at once.
+const STREAM_CONCURRENCY: usize = 16;
+
+/// The streams of each CDC generation by the time it started, generations
+/// never change once created so these are only read once.
+static GENERATION_STREAMS: Lazy<Mutex<HashMap<i64, Arc<Vec<Vec<u8>>>>>> =
+    Lazy::new(Default::default);
+
+/// Tables with CDC enabled which have their changes applied.
+#[derive(Debug, Copy, Clone)]
+pub enum ChangedTable {
+    Bots,
+    Packs,
+}
+
+impl ChangedTable {
+    fn log_table(&self) -> &'static str {
+        match self {
+            Self::Bots => "bots_scylla_cdc_log",
+            Self::Packs => "packs_scylla_cdc_log",
+        }
+    }
+}
+
+/// Get the IDs of every row in the table changed since the given unix
+/// timestamp in milliseconds.
+///
+/// This reads the CDC log table directly so the rows must be re-fetched
+/// to get their latest state, IDs may be returned more than once across
+/// calls which overlap.
+///
+/// Each stream of the log is read as its own partition, only the streams
+/// of the generations in use since then are read.
+pub async fn changed_since(
+    table: ChangedTable,
+    since_millis: i64,
+) -> Result<HashSet<i64>> {
+    let query = format!(
+        "SELECT id FROM {} WHERE \"cdc$stream_id\" = ? AND \"cdc$time\" > minTimeuuid(?);",
+        table.log_table(),
+    );
+
+    let streams = streams_since(since_millis).await?;
+    let mut reads = futures::stream::iter(streams)
+        .map(|stream| read_stream(&query, stream, since_millis))
+        .buffer_unordered(STREAM_CONCURRENCY);
+
+    let mut changed = HashSet::new();
+    while let Some(ids) = reads.next().await {
+        changed.extend(ids?);
+    }
+
+    Ok(changed)
+}
+
+async fn read_stream(
+    query: &str,
+    stream: Vec<u8>,
+    since_millis: i64,
+) -> Result<Vec<i64>> {
+    // A CQL timestamp is encoded the same as a bigint of milliseconds.
+    let mut iter = session()
+        .query_iter(query, (stream.as_slice(), since_millis), PageSize::Bulk)
+        .await?
+        .into_typed::<(i64,)>();
+
+    let mut ids = vec![];
+    while let Some(row) = iter.next().await {
+        let (id,) = row?;
+        ids.push(id);
+    }
+
+    Ok(ids)
+}
+
+/// The streams of every CDC generation in use at or after the given unix
+/// timestamp in milliseconds.
+async fn streams_since(since_millis: i64) -> Result<Vec<Vec<u8>>> {
+    let mut generations = session()
+        .query(
+            "SELECT toUnixTimestamp(time) FROM system_distributed.cdc_generation_timestamps WHERE key = 'timestamps';",
+            &[],
+        )
+        .await?
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i64,)>()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(time,)| time)
+        .collect::<Vec<_>>();
+    generations.sort_unstable();
+
+    // The generation in use at the time is the last one started before it.
+    let first = generations
+        .iter()
+        .rposition(|time| *time <= since_millis)
+        .unwrap_or_default();
+
+    let mut streams = vec![];
+    for generation in generations[first..].iter() {
+        streams.extend(generation_streams(*generation).await?.iter().cloned());
+    }
+
+    Ok(streams)
+}
+
+async fn generation_streams(generation: i64) -> Result<Arc<Vec<Vec<u8>>>> {
+    if let Some(streams) = GENERATION_STREAMS.lock().get(&generation) {
+        return Ok(streams.clone());
+    }
+
+    let mut iter = session()
+        .query_iter(
+            "SELECT streams FROM system_distributed.cdc_streams_descriptions_v2 WHERE time = ?;",
+            (generation,),
+            PageSize::Bulk,
+        )
+        .await?
+        .into_typed::<(Vec<Vec<u8>>,)>();
+
+    let mut streams = vec![];
+    while let Some(row) = iter.next().await {
+        let (range_streams,) = row?;
+        streams.extend(range_streams);
+    }
+
+    let streams = Arc::new(streams);
+    GENERATION_STREAMS
+        .lock()
+        .insert(generation, streams.clone());
+
+    Ok(streams)
+}
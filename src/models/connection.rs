@@ -1,32 +1,164 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Error, Result};
-use once_cell::sync::OnceCell;
+use anyhow::{anyhow, Context, Error, Result};
+use arc_swap::ArcSwap;
+use clap::ValueEnum;
+use once_cell::sync::{Lazy, OnceCell};
+use openssl::ssl::{SslContext, SslContextBuilder, SslMethod, SslVerifyMode};
+use parking_lot::Mutex;
+use poem_openapi::Object;
 use scylla::frame::value::ValueList;
+use scylla::prepared_statement::PreparedStatement;
 use scylla::query::Query;
+use scylla::retry_policy::{DefaultRetryPolicy, FallthroughRetryPolicy};
+use scylla::speculative_execution::SimpleSpeculativeExecutionPolicy;
 use scylla::transport::iterator::RowIterator;
+use scylla::transport::load_balancing::{DcAwareRoundRobinPolicy, TokenAwarePolicy};
 use scylla::{QueryResult, SessionConfig};
 
 static CONN: OnceCell<Session> = OnceCell::new();
 
+/// The nodes and options the session was connected with, kept to reconnect.
+static SETTINGS: OnceCell<(Vec<String>, ConnectionOptions)> = OnceCell::new();
+
+static HEALTH: Lazy<Mutex<DatabaseHealth>> = Lazy::new(|| {
+    Mutex::new(DatabaseHealth {
+        healthy: true,
+        ..Default::default()
+    })
+});
+
+#[derive(Debug, Default, Clone, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct DatabaseHealth {
+    /// If the last ping of the cluster succeeded.
+    pub healthy: bool,
+
+    /// The number of pings which have failed in a row.
+    pub consecutive_failures: u32,
+
+    /// The unix timestamp of the last successful ping.
+    pub last_success: Option<i64>,
+
+    /// The error of the last failed ping.
+    pub last_error: Option<String>,
+
+    /// The number of times the session has been re-established.
+    pub reconnects: u32,
+}
+
+/// Get the health of the connection as of the last ping.
+pub fn health() -> DatabaseHealth {
+    HEALTH.lock().clone()
+}
+
 #[inline]
 /// Get the currently active session.
 pub(crate) fn session() -> &'static Session {
     CONN.get().unwrap()
 }
 
-/// Establishes a connection with the Scylla cluster.
-pub async fn connect(nodes: &[impl AsRef<str>], init_tables: bool) -> Result<()> {
-    let mut cfg = SessionConfig::new();
-    cfg.add_known_nodes(nodes);
+/// How the session connects to the cluster and handles failed queries.
+#[derive(Debug)]
+pub struct ConnectionOptions {
+    pub username: Option<String>,
+    pub password: Option<String>,
 
-    let session = scylla::Session::connect(cfg).await?;
-    let _ = session.query("CREATE KEYSPACE discordlist WITH replication = {'class': 'SimpleStrategy', 'replication_factor' : 1};", &[]).await;
-    session.use_keyspace("discordlist", false).await?;
+    /// Connections are only encrypted when set.
+    pub tls: Option<TlsOptions>,
+
+    /// How long a query can take before it fails, including any retries.
+    pub request_timeout: Duration,
 
-    let session = Session::from(session);
+    pub retry_policy: RetryPolicy,
+
+    /// Reads which haven't completed after this are sent to another node
+    /// as well, the first response is used.
+    pub speculative_execution: Option<SpeculativeExecution>,
+
+    /// The datacenter of this instance, queries are only routed to the
+    /// replicas in it when set.
+    pub local_datacenter: Option<String>,
+
+    /// The most prepared statements kept, the least recently used are
+    /// evicted past this.
+    pub prepare_cache_size: usize,
+}
+
+#[derive(ValueEnum, Debug, Default, Copy, Clone)]
+pub enum RetryPolicy {
+    /// Retry idempotent queries on another node when one is unavailable
+    /// or overloaded.
+    #[default]
+    Default,
+
+    /// Never retry a failed query.
+    Never,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SpeculativeExecution {
+    /// The most extra nodes a read is sent to.
+    pub max_retries: usize,
+
+    /// How long to wait for a response before each extra attempt.
+    pub interval: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct TlsOptions {
+    /// The PEM file of the CA the node certificates are verified against,
+    /// the system roots are used when not set.
+    pub ca_cert: Option<String>,
+
+    /// The host name the node certificates must be issued for.
+    ///
+    /// Nodes are connected to by IP so this is only checked when set.
+    pub hostname: Option<String>,
+}
+
+impl TlsOptions {
+    fn ssl_context(&self) -> Result<SslContext> {
+        let mut builder = SslContextBuilder::new(SslMethod::tls())?;
+        builder.set_verify(SslVerifyMode::PEER);
+
+        match &self.ca_cert {
+            Some(path) => builder
+                .set_ca_file(path)
+                .with_context(|| format!("failed to load the CA cert {}", path))?,
+            None => builder.set_default_verify_paths()?,
+        }
+
+        if let Some(hostname) = &self.hostname {
+            builder.verify_param_mut().set_host(hostname)?;
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Establishes a connection with the Scylla cluster.
+pub async fn connect(
+    nodes: &[impl AsRef<str>],
+    init_tables: bool,
+    options: ConnectionOptions,
+) -> Result<()> {
+    let nodes = nodes
+        .iter()
+        .map(|node| node.as_ref().to_string())
+        .collect::<Vec<_>>();
+
+    let session = open(&nodes, &options).await?;
+    let session =
+        Session::new(session, options.prepare_cache_size, options.request_timeout);
 
     let _ = CONN.set(session);
+    let _ = SETTINGS.set((nodes, options));
 
     if init_tables {
         create_tables().await?;
@@ -35,6 +167,86 @@ pub async fn connect(nodes: &[impl AsRef<str>], init_tables: bool) -> Result<()>
     Ok(())
 }
 
+/// Replaces the active session with a new connection to the cluster.
+///
+/// Queries already running carry on with the old session.
+pub async fn reconnect() -> Result<()> {
+    let (nodes, options) = SETTINGS
+        .get()
+        .ok_or_else(|| anyhow!("the database was never connected to"))?;
+
+    let new = open(nodes, options).await?;
+    session().replace(new);
+    HEALTH.lock().reconnects += 1;
+
+    Ok(())
+}
+
+/// Checks the cluster is reachable with a cheap query, recording the
+/// result in the connection health.
+pub async fn ping() -> Result<()> {
+    let result = session()
+        .query("SELECT release_version FROM system.local;", &[])
+        .await;
+
+    let mut health = HEALTH.lock();
+    health.healthy = result.is_ok();
+    match result {
+        Ok(_) => {
+            health.consecutive_failures = 0;
+            health.last_success = Some(unix_secs());
+            Ok(())
+        },
+        Err(e) => {
+            health.consecutive_failures += 1;
+            health.last_error = Some(e.to_string());
+            Err(e)
+        },
+    }
+}
+
+async fn open(nodes: &[String], options: &ConnectionOptions) -> Result<scylla::Session> {
+    let mut cfg = SessionConfig::new();
+    cfg.add_known_nodes(nodes);
+
+    match (&options.username, &options.password) {
+        (Some(username), Some(password)) => {
+            cfg.auth_username = Some(username.clone());
+            cfg.auth_password = Some(password.clone());
+        },
+        (None, None) => {},
+        _ => return Err(anyhow!("both a username and password must be given")),
+    }
+
+    if let Some(tls) = &options.tls {
+        cfg.ssl_context = Some(tls.ssl_context()?);
+    }
+
+    cfg.retry_policy = match options.retry_policy {
+        RetryPolicy::Default => Box::new(DefaultRetryPolicy::new()),
+        RetryPolicy::Never => Box::new(FallthroughRetryPolicy::new()),
+    };
+
+    if let Some(dc) = &options.local_datacenter {
+        let local = DcAwareRoundRobinPolicy::new(dc.clone());
+        cfg.load_balancing = Arc::new(TokenAwarePolicy::new(Box::new(local)));
+    }
+
+    if let Some(speculative) = options.speculative_execution {
+        cfg.speculative_execution_policy =
+            Some(Arc::new(SimpleSpeculativeExecutionPolicy {
+                max_retry_count: speculative.max_retries,
+                retry_interval: speculative.interval,
+            }));
+    }
+
+    let session = scylla::Session::connect(cfg).await?;
+    let _ = session.query("CREATE KEYSPACE discordlist WITH replication = {'class': 'SimpleStrategy', 'replication_factor' : 1};", &[]).await;
+    session.use_keyspace("discordlist", false).await?;
+
+    Ok(session)
+}
+
 async fn create_tables() -> Result<()> {
     for table in include_str!("./scripts/test-tables.cql").split(';') {
         let table = table.trim();
@@ -48,15 +260,142 @@ async fn create_tables() -> Result<()> {
     Ok(())
 }
 
-pub struct Session(scylla::CachingSession);
+/// How many rows are fetched per page when iterating over a query.
+#[derive(Debug, Copy, Clone)]
+pub enum PageSize {
+    /// Small pages for queries which only return a few rows.
+    Interactive,
+
+    /// Large pages for reading through whole tables.
+    Bulk,
+}
+
+impl PageSize {
+    pub fn rows(self) -> i32 {
+        match self {
+            Self::Interactive => 500,
+            Self::Bulk => 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct PrepareCacheStats {
+    /// The most statements the cache holds.
+    pub capacity: usize,
+
+    /// The number of statements currently cached.
+    pub size: usize,
+
+    /// The number of queries which used an already prepared statement.
+    pub hits: u64,
+
+    /// The number of queries which had to prepare their statement.
+    pub misses: u64,
+
+    /// The ratio of hits to queries, `0` if there have been no queries.
+    pub hit_rate: f64,
+}
+
+/// The prepared statements of the session keyed by their query.
+struct PrepareCache {
+    capacity: usize,
+    statements: Mutex<HashMap<String, (PreparedStatement, u64)>>,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl PrepareCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            statements: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, query: &str) -> Option<PreparedStatement> {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        let mut statements = self.statements.lock();
+
+        match statements.get_mut(query) {
+            Some((statement, last_used)) => {
+                *last_used = tick;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(statement.clone())
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    fn insert(&self, query: &str, statement: PreparedStatement) {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        let mut statements = self.statements.lock();
+
+        if statements.len() >= self.capacity && !statements.contains_key(query) {
+            let oldest = statements
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(query, _)| query.clone());
+
+            if let Some(oldest) = oldest {
+                statements.remove(&oldest);
+            }
+        }
+
+        statements.insert(query.to_string(), (statement, tick));
+    }
+
+    fn clear(&self) {
+        self.statements.lock().clear();
+    }
+
+    fn stats(&self) -> PrepareCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
 
-impl From<scylla::Session> for Session {
-    fn from(s: scylla::Session) -> Self {
-        Self(scylla::CachingSession::from(s, 100))
+        PrepareCacheStats {
+            capacity: self.capacity,
+            size: self.statements.lock().len(),
+            hits,
+            misses,
+            hit_rate: if total == 0 {
+                0.0
+            } else {
+                hits as f64 / total as f64
+            },
+        }
     }
 }
 
+pub struct Session {
+    inner: ArcSwap<scylla::Session>,
+    cache: PrepareCache,
+    timeout: Duration,
+}
+
 impl Session {
+    fn new(session: scylla::Session, cache_size: usize, timeout: Duration) -> Self {
+        Self {
+            inner: ArcSwap::from_pointee(session),
+            cache: PrepareCache::new(cache_size),
+            timeout,
+        }
+    }
+
+    fn replace(&self, session: scylla::Session) {
+        self.inner.store(Arc::new(session));
+        self.cache.clear();
+    }
+
     #[instrument(skip(self, query), level = "debug")]
     pub async fn query(
         &self,
@@ -64,26 +403,37 @@ impl Session {
         values: impl ValueList + Debug,
     ) -> Result<QueryResult> {
         debug!("executing query {}", query);
-        self.0.execute(query, &values).await.map_err(|e| {
-            error!("Failed to execute query {} with error {:?}", query, e);
-            Error::from(e)
+        self.with_timeout(query, async {
+            let inner = self.inner.load_full();
+            let statement = self.prepare(&inner, query).await?;
+            inner
+                .execute(&statement, &values)
+                .await
+                .map_err(Error::from)
         })
+        .await
     }
 
+    /// Pages through the rows of the query, fetching `page_size` rows at
+    /// a time.
     #[instrument(skip(self, query), level = "debug")]
     pub async fn query_iter(
         &self,
         query: &str,
         values: impl ValueList + Debug,
+        page_size: PageSize,
     ) -> Result<RowIterator> {
         debug!("preparing and paging new statement: {}", query);
-        self.0
-            .execute_iter(Query::from(query), &values)
-            .await
-            .map_err(|e| {
-                error!("Failed to execute query {} with error {:?}", query, e);
-                Error::from(e)
-            })
+        self.with_timeout(query, async {
+            let inner = self.inner.load_full();
+            let mut statement = self.prepare(&inner, query).await?;
+            statement.set_page_size(page_size.rows());
+            inner
+                .execute_iter(statement, &values)
+                .await
+                .map_err(Error::from)
+        })
+        .await
     }
 
     #[instrument(skip(self, query), level = "debug")]
@@ -93,12 +443,71 @@ impl Session {
         values: impl ValueList + Debug,
     ) -> Result<QueryResult> {
         debug!("preparing and executing statement: {}", query);
-        self.0
-            .execute(Query::from(query), &values)
-            .await
-            .map_err(|e| {
-                error!("Failed to execute query {} with error {:?}", query, e);
-                Error::from(e)
-            })
+        self.with_timeout(query, async {
+            let inner = self.inner.load_full();
+            let statement = self.prepare(&inner, query).await?;
+            inner
+                .execute(&statement, &values)
+                .await
+                .map_err(Error::from)
+        })
+        .await
+    }
+
+    pub fn prepare_cache_stats(&self) -> PrepareCacheStats {
+        self.cache.stats()
+    }
+
+    async fn prepare(
+        &self,
+        inner: &scylla::Session,
+        query: &str,
+    ) -> Result<PreparedStatement> {
+        if let Some(statement) = self.cache.get(query) {
+            return Ok(statement);
+        }
+
+        let statement = inner.prepare(statement(query)).await?;
+        self.cache.insert(query, statement.clone());
+
+        Ok(statement)
+    }
+
+    /// Fails the query if it takes longer than the request timeout,
+    /// retries and speculative executions are included in the timeout.
+    async fn with_timeout<T>(
+        &self,
+        query: &str,
+        fut: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let result = match tokio::time::timeout(self.timeout, fut).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("query timed out after {:?}", self.timeout)),
+        };
+
+        result.map_err(|e| {
+            error!("Failed to execute query {} with error {:?}", query, e);
+            e
+        })
     }
 }
+
+/// Marks reads as idempotent so they can be retried and speculatively
+/// executed, writes are never repeated.
+fn statement(query: &str) -> Query {
+    let mut statement = Query::from(query);
+    let is_read = query
+        .trim_start()
+        .get(..6)
+        .map(|kw| kw.eq_ignore_ascii_case("select"))
+        .unwrap_or_default();
+    statement.set_is_idempotent(is_read);
+    statement
+}
+
+fn unix_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+
+use crate::models::connection::{session, PageSize};
+
+static FEATURED_BOTS: Lazy<ArcSwap<HashSet<i64>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashSet::new()));
+
+#[inline]
+/// If the bot has been picked by staff to be featured.
+pub fn is_featured(bot_id: i64) -> bool {
+    FEATURED_BOTS.load().contains(&bot_id)
+}
+
+/// Reloads the featured bots from the database.
+///
+/// Returns the ids of all bots which were featured or unfeatured.
+pub async fn refresh_featured_bots() -> Result<Vec<i64>> {
+    let mut iter = session()
+        .query_iter("SELECT id FROM featured_bots;", &[], PageSize::Interactive)
+        .await?
+        .into_typed::<(i64,)>();
+
+    let mut featured = HashSet::new();
+    while let Some(row) = iter.next().await {
+        let (id,) = row?;
+        featured.insert(id);
+    }
+
+    let previous = FEATURED_BOTS.swap(Arc::new(featured.clone()));
+    let changed = previous.symmetric_difference(&featured).copied().collect();
+
+    Ok(changed)
+}
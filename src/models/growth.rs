@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+
+use crate::models::bots;
+use crate::models::connection::{session, PageSize};
+
+/// How many days back the growth of a bot is measured from.
+pub const GROWTH_WINDOW_DAYS: i64 = 7;
+
+/// The smallest guild count growth is measured against, otherwise a bot
+/// going from 1 to 10 guilds would outgrow any established bot.
+const MIN_GROWTH_BASE: i64 = 10;
+
+/// The precision growth rates are kept to when indexed.
+const GROWTH_SCALE: f64 = 1_000_000.0;
+
+/// The days since the unix epoch.
+pub type DayKey = i64;
+
+/// The last day a snapshot was recorded by this instance.
+static LAST_SNAPSHOT: AtomicI64 = AtomicI64::new(i64::MIN);
+
+static GROWTH_DATA: Lazy<ArcSwap<HashMap<i64, f64>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+pub fn day_key(date: DateTime<Utc>) -> DayKey {
+    date.timestamp().div_euclid(24 * 60 * 60)
+}
+
+/// The growth of the guild count relative to the past guild count.
+pub fn growth_rate(past: i64, current: i64) -> f64 {
+    (current - past) as f64 / past.max(MIN_GROWTH_BASE) as f64
+}
+
+#[inline]
+pub fn get_bot_growth(bot_id: i64) -> f64 {
+    GROWTH_DATA.load().get(&bot_id).copied().unwrap_or_default()
+}
+
+#[inline]
+pub fn scale_growth(growth: f64) -> i64 {
+    (growth * GROWTH_SCALE) as i64
+}
+
+/// Records the guild count of every live bot for the day, unless this
+/// instance already has today.
+///
+/// Returns the number of bots recorded.
+pub async fn record_snapshot_if_due(now: DateTime<Utc>) -> Result<usize> {
+    let day = day_key(now);
+    if LAST_SNAPSHOT.load(Ordering::Relaxed) >= day {
+        return Ok(0);
+    }
+
+    let bots = bots::all_bots();
+    for bot in bots.iter() {
+        let guild_count = bot.guild_count.as_deref().copied().unwrap_or_default();
+
+        session()
+            .query_prepared(
+                "INSERT INTO guild_count_snapshots (day, id, guild_count) VALUES (?, ?, ?);",
+                (day, *bot.id, guild_count),
+            )
+            .await?;
+    }
+
+    LAST_SNAPSHOT.store(day, Ordering::Relaxed);
+
+    Ok(bots.len())
+}
+
+/// Recomputes the growth of every live bot since the snapshot from
+/// [GROWTH_WINDOW_DAYS] ago, bots without a snapshot have no growth.
+///
+/// Returns the ids of all bots whose indexed growth has changed.
+pub async fn refresh_growth(now: DateTime<Utc>) -> Result<Vec<i64>> {
+    let day = day_key(now) - GROWTH_WINDOW_DAYS;
+
+    let mut iter = session()
+        .query_iter(
+            "SELECT id, guild_count FROM guild_count_snapshots WHERE day = ?;",
+            (day,),
+            PageSize::Bulk,
+        )
+        .await?
+        .into_typed::<(i64, i32)>();
+
+    let mut past = HashMap::new();
+    while let Some(row) = iter.next().await {
+        let (id, guild_count) = row?;
+        past.insert(id, guild_count as i64);
+    }
+
+    let latest = bots::all_bots()
+        .into_iter()
+        .filter_map(|bot| {
+            let past = *past.get(&*bot.id)?;
+            let current = bot.guild_count.as_deref().copied().unwrap_or_default();
+            Some((*bot.id, growth_rate(past, current as i64)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let previous = GROWTH_DATA.swap(Arc::new(latest.clone()));
+
+    let scaled =
+        |growth: &HashMap<i64, f64>, id: &i64| growth.get(id).copied().map(scale_growth);
+
+    let changed = latest
+        .keys()
+        .chain(previous.keys())
+        .filter(|id| scaled(&latest, id) != scaled(previous.as_ref(), id))
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_growth_rate() {
+        assert_eq!(growth_rate(100, 150), 0.5);
+        assert_eq!(growth_rate(200, 100), -0.5);
+
+        // Small bots are measured against the minimum base.
+        assert_eq!(growth_rate(1, 11), 1.0);
+        assert_eq!(growth_rate(0, 5), 0.5);
+    }
+
+    #[test]
+    fn test_day_key() {
+        let date = Utc.with_ymd_and_hms(2026, 10, 14, 23, 59, 59).unwrap();
+        let next = Utc.with_ymd_and_hms(2026, 10, 15, 0, 0, 0).unwrap();
+        assert_eq!(day_key(next) - day_key(date), 1);
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::models::connection::session;
+use crate::models::utils::is_applied;
+
+/// Takes the named lease for `holder`, or renews it if `holder` already has it.
+///
+/// Returns `false` if another holder has the lease. Leases expire after
+/// `ttl` unless they are renewed.
+pub async fn try_acquire(name: &str, holder: &str, ttl: Duration) -> Result<bool> {
+    let ttl = ttl.as_secs() as i32;
+
+    let renewed = session()
+        .query_prepared(
+            "UPDATE leases USING TTL ? SET holder = ? WHERE name = ? IF holder = ?;",
+            (ttl, holder, name, holder),
+        )
+        .await?;
+
+    if is_applied(&renewed) {
+        return Ok(true);
+    }
+
+    let acquired = session()
+        .query_prepared(
+            "INSERT INTO leases (name, holder) VALUES (?, ?) IF NOT EXISTS USING TTL ?;",
+            (name, holder, ttl),
+        )
+        .await?;
+
+    Ok(is_applied(&acquired))
+}
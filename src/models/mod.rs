@@ -1,6 +1,14 @@
+pub mod analytics;
 pub mod bots;
+pub mod cdc;
 pub mod connection;
+pub mod featured;
+pub mod growth;
+pub mod leases;
 pub mod packs;
+pub mod ratings;
+pub mod synonyms;
 mod utils;
+pub mod vote_history;
 
-pub use utils::VoteStats;
+pub use utils::{RefreshSummary, VoteStats};
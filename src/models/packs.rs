@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -8,22 +8,28 @@ use backend_common::FieldNamesAsArray;
 use futures::StreamExt;
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
+use poem_openapi::Object;
 use scylla::FromRow;
 use tantivy::schema::Schema;
 
 use crate::models::bots::is_hidden;
-use crate::models::connection::session;
-use crate::models::utils::{process_rows, VoteStats};
+use crate::models::connection::{session, PageSize};
+use crate::models::utils::{process_rows, RefreshSummary, VoteStats};
 use crate::search::index_impls::packs::{
+    CREATED_ON_FIELD,
     DESCRIPTION_FIELD,
     ID_FIELD,
+    LIKES_FIELD,
     NAME_FIELD,
+    NAME_SORT_FIELD,
+    PREMIUM_FIELD,
     TAG_AGG_FIELD,
     TAG_FIELD,
 };
+use crate::search::tokenizer::sort_key;
 use crate::{derive_fetch_by_id, derive_fetch_iter};
 
-#[derive(FromRow, FieldNamesAsArray, Debug, Clone)]
+#[derive(FromRow, FieldNamesAsArray, Object, Debug, Clone)]
 pub struct Pack {
     /// The ID of the pack.
     pub id: JsSafeBigInt,
@@ -55,25 +61,47 @@ pub struct Pack {
 
     /// The primary owner of this pack.
     pub owner_id: JsSafeBigInt,
+
+    /// If the pack is being promoted, this is null for packs which never were.
+    pub premium: Option<bool>,
 }
 derive_fetch_by_id!(Pack, table = "packs");
 derive_fetch_iter!(Pack, table = "packs");
 
 impl Pack {
+    #[inline]
+    pub fn is_premium(&self) -> bool {
+        self.premium.unwrap_or_default()
+    }
+
+    /// If the pack has been hidden by its owner or forced into hiding.
+    #[inline]
+    pub fn is_hidden_from_search(&self) -> bool {
+        self.is_hidden || self.is_forced_into_hiding
+    }
+
     pub fn as_tantivy_doc(&self, schema: &Schema) -> tantivy::Document {
         let mut document = tantivy::Document::new();
 
         let id_field = schema.get_field(ID_FIELD).unwrap();
+        let created_on_field = schema.get_field(CREATED_ON_FIELD).unwrap();
+        let name_sort_field = schema.get_field(NAME_SORT_FIELD).unwrap();
         let name_field = schema.get_field(NAME_FIELD).unwrap();
         let description_field = schema.get_field(DESCRIPTION_FIELD).unwrap();
         let tag_field = schema.get_field(TAG_FIELD).unwrap();
         let tag_agg_field = schema.get_field(TAG_AGG_FIELD).unwrap();
+        let premium_field = schema.get_field(PREMIUM_FIELD).unwrap();
+        let likes_field = schema.get_field(LIKES_FIELD).unwrap();
 
         document.add_i64(id_field, *self.id);
+        document.add_i64(created_on_field, self.created_on.timestamp());
+        document.add_i64(name_sort_field, sort_key(&self.name));
         document.add_text(name_field, &self.name);
         document.add_text(description_field, &self.description);
         document.add_text(tag_field, &self.tag);
         document.add_text(tag_agg_field, &self.tag);
+        document.add_u64(premium_field, self.is_premium() as u64);
+        document.add_i64(likes_field, get_pack_likes(*self.id) as i64);
 
         document
     }
@@ -94,33 +122,67 @@ pub fn vote_stats(id: i64) -> VoteStats {
     VOTE_INFO.load().get(&id).copied().unwrap_or_default()
 }
 
-pub async fn refresh_latest_votes() -> Result<()> {
+/// Reloads the likes of every pack, returning the ids of the packs whose
+/// likes changed.
+pub async fn refresh_latest_votes() -> Result<Vec<i64>> {
     let iter = session()
-        .query_iter("SELECT id, likes FROM pack_likes;", &[])
+        .query_iter("SELECT id, likes FROM pack_likes;", &[], PageSize::Bulk)
         .await?;
 
-    VOTE_INFO.store(Arc::new(process_rows(iter).await));
-
-    Ok(())
+    let latest = process_rows(iter).await;
+    let previous = VOTE_INFO.swap(Arc::new(latest.clone()));
+
+    let changed = latest
+        .iter()
+        .filter(|(id, stats)| previous.get(id) != Some(stats))
+        .map(|(id, _)| *id)
+        .chain(
+            previous
+                .keys()
+                .filter(|id| !latest.contains_key(id))
+                .copied(),
+        )
+        .collect();
+
+    Ok(changed)
 }
 
 static LIVE_DATA: Lazy<RwLock<HashMap<i64, Pack>>> = Lazy::new(Default::default);
 
+/// A reverse lookup of bot ids to the ids of the packs containing them.
+static BOT_PACKS: Lazy<RwLock<HashMap<i64, HashSet<i64>>>> = Lazy::new(Default::default);
+
 #[inline]
 pub fn get_pack_data(id: i64) -> Option<Pack> {
     let txn = LIVE_DATA.read();
     txn.get(&id).cloned()
 }
 
+#[inline]
+pub fn packs_containing(bot_id: i64) -> Vec<i64> {
+    let txn = BOT_PACKS.read();
+    txn.get(&bot_id)
+        .map(|v| v.iter().copied().collect())
+        .unwrap_or_default()
+}
+
 #[inline]
 pub fn remove_pack_from_live(pack_id: i64) {
     let mut txn = LIVE_DATA.write();
-    txn.remove(&pack_id);
+    if let Some(old) = txn.remove(&pack_id) {
+        unlink_bots(&mut BOT_PACKS.write(), &old);
+    }
 }
 
 #[inline]
 pub fn update_live_data(pack: Pack) {
     let mut txn = LIVE_DATA.write();
+    let mut links = BOT_PACKS.write();
+    if let Some(old) = txn.get(&*pack.id) {
+        unlink_bots(&mut links, old);
+    }
+
+    link_bots(&mut links, &pack);
     txn.insert(*pack.id, pack);
 }
 
@@ -129,22 +191,69 @@ pub fn all_packs() -> Vec<Pack> {
     txn.iter().map(|(_, v)| v.clone()).collect()
 }
 
-pub async fn refresh_latest_data() -> Result<()> {
+/// The number of live packs with each tag.
+pub fn tag_counts() -> HashMap<String, usize> {
+    let txn = LIVE_DATA.read();
+
+    let mut counts = HashMap::new();
+    for pack in txn.values() {
+        *counts.entry(pack.tag.clone()).or_default() += 1;
+    }
+
+    counts
+}
+
+/// Reloads all packs from the database.
+///
+/// Rows which fail to load are skipped and reported in the returned summary.
+pub async fn refresh_latest_data() -> Result<RefreshSummary> {
     let mut iter = Pack::iter_rows().await?.into_typed::<Pack>();
 
+    let mut summary = RefreshSummary::default();
     let mut packs = HashMap::new();
-    while let Some(Ok(row)) = iter.next().await {
-        if row.is_hidden || row.is_forced_into_hiding {
+    let mut links = HashMap::new();
+    while let Some(row) = iter.next().await {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                warn!("Failed to load pack row due to error: {}", e);
+                summary.record_error(e);
+                continue;
+            },
+        };
+
+        if row.is_hidden_from_search() {
+            summary.skipped += 1;
             continue;
         }
 
+        link_bots(&mut links, &row);
         packs.insert(*row.id, row);
     }
 
     let mut lock = LIVE_DATA.write();
     (*lock) = packs;
+    (*BOT_PACKS.write()) = links;
+
+    Ok(summary)
+}
+
+fn link_bots(links: &mut HashMap<i64, HashSet<i64>>, pack: &Pack) {
+    for bot_id in pack.bots.iter() {
+        links.entry(**bot_id).or_default().insert(*pack.id);
+    }
+}
 
-    Ok(())
+fn unlink_bots(links: &mut HashMap<i64, HashSet<i64>>, pack: &Pack) {
+    for bot_id in pack.bots.iter() {
+        if let Some(packs) = links.get_mut(&**bot_id) {
+            packs.remove(&*pack.id);
+
+            if packs.is_empty() {
+                links.remove(&**bot_id);
+            }
+        }
+    }
 }
 
 #[inline]
@@ -152,6 +261,13 @@ pub fn get_pack_likes(pack_id: i64) -> u64 {
     vote_stats(pack_id).votes()
 }
 
+#[inline]
+pub fn get_pack_premium(pack_id: i64) -> bool {
+    get_pack_data(pack_id)
+        .map(|v| v.is_premium())
+        .unwrap_or_default()
+}
+
 #[inline]
 pub fn get_pack_trending_score(pack_id: i64) -> f64 {
     let txn = TRENDING_DATA.load();
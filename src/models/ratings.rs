@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use scylla::frame::value::Counter;
+
+use crate::models::connection::{session, PageSize};
+
+/// The precision average ratings are kept to when indexed.
+const RATING_SCALE: f64 = 1_000.0;
+
+/// The highest number of stars a bot can be rated.
+pub const MAX_STARS: usize = 5;
+
+/// The number of ratings a bot has been given per number of stars.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RatingStats {
+    histogram: [u64; MAX_STARS],
+}
+
+impl RatingStats {
+    /// Adds `count` ratings of the given stars, stars out of range are ignored.
+    pub fn record(&mut self, stars: i32, count: u64) {
+        if let Some(bucket) = (stars as usize)
+            .checked_sub(1)
+            .and_then(|i| self.histogram.get_mut(i))
+        {
+            *bucket += count;
+        }
+    }
+
+    /// The number of ratings per star, starting at one star.
+    #[inline]
+    pub fn histogram(&self) -> [u64; MAX_STARS] {
+        self.histogram
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.histogram.iter().sum()
+    }
+
+    /// The average number of stars, or `0` if the bot has not been rated.
+    pub fn average(&self) -> f64 {
+        let count = self.count();
+        if count == 0 {
+            return 0.0;
+        }
+
+        let total = self
+            .histogram
+            .iter()
+            .zip(1u64..)
+            .map(|(ratings, stars)| ratings * stars)
+            .sum::<u64>();
+
+        total as f64 / count as f64
+    }
+}
+
+static RATING_INFO: Lazy<ArcSwap<HashMap<i64, RatingStats>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+#[inline]
+pub fn rating_stats(bot_id: i64) -> RatingStats {
+    RATING_INFO.load().get(&bot_id).copied().unwrap_or_default()
+}
+
+#[inline]
+pub fn get_bot_rating(bot_id: i64) -> f64 {
+    rating_stats(bot_id).average()
+}
+
+#[inline]
+pub fn scale_rating(rating: f64) -> i64 {
+    (rating * RATING_SCALE) as i64
+}
+
+/// Reloads the bot ratings from the database.
+///
+/// Returns the ids of all bots whose ratings have changed.
+pub async fn refresh_latest_ratings() -> Result<Vec<i64>> {
+    let mut iter = session()
+        .query_iter(
+            "SELECT id, stars, ratings FROM bot_ratings;",
+            &[],
+            PageSize::Bulk,
+        )
+        .await?
+        .into_typed::<(i64, i32, Counter)>();
+
+    let mut latest = HashMap::<i64, RatingStats>::new();
+    while let Some(row) = iter.next().await {
+        let (id, stars, Counter(count)) = row?;
+        latest
+            .entry(id)
+            .or_default()
+            .record(stars, count.max(0) as u64);
+    }
+
+    let previous = RATING_INFO.swap(Arc::new(latest.clone()));
+
+    let changed = latest
+        .iter()
+        .filter(|(id, stats)| previous.get(id) != Some(stats))
+        .map(|(id, _)| *id)
+        .chain(
+            previous
+                .keys()
+                .filter(|id| !latest.contains_key(id))
+                .copied(),
+        )
+        .collect();
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating_average() {
+        let mut stats = RatingStats::default();
+        assert_eq!(stats.average(), 0.0);
+
+        stats.record(5, 3);
+        stats.record(2, 1);
+        stats.record(0, 10);
+        stats.record(6, 10);
+
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.histogram(), [0, 1, 0, 0, 3]);
+        assert_eq!(stats.average(), 4.25);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+
+use crate::models::connection::{session, PageSize};
+use crate::search::tokenizer::SimpleUnicodeTokenizer;
+
+static SYNONYMS: Lazy<ArcSwap<HashMap<String, Vec<String>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+#[inline]
+/// Get the currently loaded synonym dictionary.
+///
+/// Keys and values are normalized in the same way the tokenizer
+/// produces tokens, so they can be looked up with token text directly.
+pub fn synonyms() -> Arc<HashMap<String, Vec<String>>> {
+    SYNONYMS.load_full()
+}
+
+pub async fn refresh_synonyms() -> Result<()> {
+    let mut iter = session()
+        .query_iter(
+            "SELECT word, synonyms FROM synonyms;",
+            &[],
+            PageSize::Interactive,
+        )
+        .await?
+        .into_typed::<(String, Vec<String>)>();
+
+    let mut dictionary: HashMap<String, Vec<String>> = HashMap::new();
+    while let Some(row) = iter.next().await {
+        let (word, synonyms) = match row {
+            Ok(row) => row,
+            Err(e) => {
+                warn!("Skipping synonym row which failed to load: {}", e);
+                continue;
+            },
+        };
+
+        let word = match normalize(&word) {
+            Some(word) => word,
+            None => continue,
+        };
+
+        // Synonyms are two-way, `music` should expand to `audio`
+        // as much as `audio` expands to `music`.
+        for synonym in synonyms.iter().filter_map(|v| normalize(v)) {
+            if synonym == word {
+                continue;
+            }
+
+            add_entry(&mut dictionary, &word, &synonym);
+            add_entry(&mut dictionary, &synonym, &word);
+        }
+    }
+
+    info!("Loaded {} synonym entries", dictionary.len());
+    SYNONYMS.store(Arc::new(dictionary));
+
+    Ok(())
+}
+
+fn add_entry(dictionary: &mut HashMap<String, Vec<String>>, word: &str, synonym: &str) {
+    let entries = dictionary.entry(word.to_string()).or_default();
+    if !entries.iter().any(|v| v == synonym) {
+        entries.push(synonym.to_string());
+    }
+}
+
+/// Normalizes the word as a single token.
+///
+/// Queries are expanded a token at a time, so entries of more than one
+/// word could never match and are ignored.
+fn normalize(word: &str) -> Option<String> {
+    let mut tokens = SimpleUnicodeTokenizer::with_limit(2).produce_tokens(word);
+    if tokens.len() > 1 {
+        warn!("Ignoring synonym {:?} as it is more than one word", word);
+        return None;
+    }
+
+    tokens.pop().map(|token| token.text)
+}
@@ -1,8 +1,14 @@
 use std::collections::HashMap;
+use std::fmt::Display;
 
 use futures::StreamExt;
+use poem_openapi::Object;
 use scylla::frame::value::Counter;
 use scylla::transport::iterator::RowIterator;
+use scylla::QueryResult;
+
+/// The maximum number of error messages kept in a refresh summary.
+const MAX_REPORTED_ERRORS: usize = 25;
 
 #[macro_export]
 macro_rules! derive_fetch_by_id {
@@ -39,7 +45,7 @@ macro_rules! derive_fetch_iter {
         impl $slf {
             pub async fn iter_rows() -> Result<scylla::transport::iterator::RowIterator>
             {
-                use super::connection::session;
+                use super::connection::{session, PageSize};
 
                 let qry = format!(
                     "SELECT {} FROM {};",
@@ -48,7 +54,7 @@ macro_rules! derive_fetch_iter {
                 );
 
                 session()
-                    .query_iter(&qry, &[])
+                    .query_iter(&qry, &[], PageSize::Bulk)
                     .await
                     .map_err(anyhow::Error::from)
             }
@@ -56,22 +62,66 @@ macro_rules! derive_fetch_iter {
     };
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct VoteStats {
     votes: u64,
+    all_time_votes: u64,
 }
 
 impl VoteStats {
     pub fn new(votes: i64) -> Self {
         Self {
             votes: votes as u64,
+            all_time_votes: 0,
         }
     }
 
+    /// Sets the total votes of every month including this one.
+    pub fn with_all_time_votes(mut self, all_time_votes: i64) -> Self {
+        self.all_time_votes = all_time_votes as u64;
+        self
+    }
+
     #[inline]
     pub fn votes(&self) -> u64 {
         self.votes
     }
+
+    #[inline]
+    pub fn all_time_votes(&self) -> u64 {
+        self.all_time_votes
+    }
+}
+
+#[derive(Debug, Default, Clone, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct RefreshSummary {
+    /// The number of documents which were indexed.
+    pub indexed: usize,
+
+    /// The number of rows which were intentionally not indexed, i.e hidden entities.
+    pub skipped: usize,
+
+    /// The number of rows which failed to be loaded or indexed.
+    pub errored: usize,
+
+    /// A sample of the errors which occurred.
+    pub errors: Vec<String>,
+}
+
+impl RefreshSummary {
+    pub fn record_error(&mut self, error: impl Display) {
+        self.errored += 1;
+
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error.to_string());
+        }
+    }
+
+    /// The total number of rows which were processed.
+    pub fn total_rows(&self) -> usize {
+        self.indexed + self.skipped + self.errored
+    }
 }
 
 pub async fn process_rows(iter: RowIterator) -> HashMap<i64, VoteStats> {
@@ -84,3 +134,32 @@ pub async fn process_rows(iter: RowIterator) -> HashMap<i64, VoteStats> {
 
     processed_changes
 }
+
+/// Processes rows of the ID, votes this month and all time votes.
+///
+/// Rows from before all time votes were counted fall back to the votes
+/// of this month.
+pub async fn process_vote_rows(iter: RowIterator) -> HashMap<i64, VoteStats> {
+    let mut iter = iter.into_typed::<(i64, Counter, Option<Counter>)>();
+
+    let mut processed_changes = HashMap::new();
+    while let Some(Ok((id, Counter(votes), all_time))) = iter.next().await {
+        let all_time = all_time.map(|Counter(v)| v).unwrap_or(votes);
+        processed_changes
+            .insert(id, VoteStats::new(votes).with_all_time_votes(all_time));
+    }
+
+    processed_changes
+}
+
+/// Lightweight transactions return whether they were applied as the first column.
+pub fn is_applied(result: &QueryResult) -> bool {
+    result
+        .rows
+        .as_ref()
+        .and_then(|rows| rows.first())
+        .and_then(|row| row.columns.first())
+        .and_then(|column| column.as_ref())
+        .and_then(|value| value.as_boolean())
+        .unwrap_or_default()
+}
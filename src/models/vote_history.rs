@@ -0,0 +1,171 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Utc};
+use futures::StreamExt;
+use scylla::frame::value::Counter;
+use scylla::IntoTypedRows;
+
+use crate::models::connection::{session, PageSize};
+use crate::models::utils::is_applied;
+
+/// The most months of history returned for a bot.
+pub const MAX_HISTORY_MONTHS: usize = 120;
+
+/// A month encoded as `year * 100 + month`, i.e `202610` for October 2026.
+pub type MonthKey = i32;
+
+pub fn month_key(date: DateTime<Utc>) -> MonthKey {
+    date.year() * 100 + date.month() as i32
+}
+
+/// Formats the month as `YYYY-MM`.
+pub fn format_month(month: MonthKey) -> String {
+    format!("{:04}-{:02}", month / 100, month % 100)
+}
+
+/// Get the votes of each past month of the bot, newest first.
+pub async fn bot_history(bot_id: i64, limit: usize) -> Result<Vec<(MonthKey, i64)>> {
+    let mut iter = session()
+        .query_iter(
+            "SELECT month, votes FROM bot_vote_history WHERE id = ? LIMIT ?;",
+            (bot_id, limit.min(MAX_HISTORY_MONTHS) as i32),
+            PageSize::Interactive,
+        )
+        .await?
+        .into_typed::<(MonthKey, i64)>();
+
+    let mut history = vec![];
+    while let Some(row) = iter.next().await {
+        history.push(row?);
+    }
+
+    Ok(history)
+}
+
+/// Rolls the vote counters over into the history if the month being
+/// counted has ended.
+///
+/// The first time this runs the current month is only marked as being
+/// counted, as the counters may hold votes from before it. Returns the
+/// number of bots whose votes were rolled over.
+pub async fn rollover_if_due(now: DateTime<Utc>) -> Result<usize> {
+    let current = month_key(now);
+
+    let mut iter = session()
+        .query_iter("SELECT month FROM vote_months;", &[], PageSize::Interactive)
+        .await?
+        .into_typed::<(MonthKey,)>();
+
+    let mut counting = None;
+    while let Some(row) = iter.next().await {
+        let (month,) = row?;
+        counting = counting.max(Some(month));
+    }
+
+    let rolled_over = match counting {
+        Some(month) if month < current => rollover(month).await?,
+        Some(_) => return Ok(0),
+        None => 0,
+    };
+
+    session()
+        .query_prepared("INSERT INTO vote_months (month) VALUES (?);", (current,))
+        .await?;
+
+    Ok(rolled_over)
+}
+
+/// Records the current votes of every bot as the votes of `month` and
+/// takes them off the counters.
+///
+/// Each bot is only recorded once per month and its history row is flagged
+/// once its counter has been decremented, so a failed rollover can be safely
+/// retried, only the rows which were never flagged are decremented again.
+/// Votes made while this runs count towards the next month.
+async fn rollover(month: MonthKey) -> Result<usize> {
+    info!("Rolling over the votes of {}", format_month(month));
+
+    let mut iter = session()
+        .query_iter("SELECT id, votes FROM bot_votes;", &[], PageSize::Bulk)
+        .await?
+        .into_typed::<(i64, Counter)>();
+
+    let mut rolled_over = 0;
+    while let Some(row) = iter.next().await {
+        let (id, Counter(votes)) = row?;
+
+        let recorded = session()
+            .query_prepared(
+                "INSERT INTO bot_vote_history (id, month, votes, decremented) VALUES (?, ?, ?, false) IF NOT EXISTS;",
+                (id, month, votes),
+            )
+            .await?;
+
+        // A previous attempt recorded the bot, the votes it recorded are the
+        // ones to take off as the counter has moved on since.
+        let votes = if is_applied(&recorded) {
+            votes
+        } else {
+            match recorded_votes(id, month).await? {
+                Some(votes) => votes,
+                None => continue,
+            }
+        };
+
+        if votes != 0 {
+            session()
+                .query_prepared(
+                    "UPDATE bot_votes SET votes = votes - ? WHERE id = ?;",
+                    (Counter(votes), id),
+                )
+                .await?;
+        }
+
+        session()
+            .query_prepared(
+                "UPDATE bot_vote_history SET decremented = true WHERE id = ? AND month = ?;",
+                (id, month),
+            )
+            .await?;
+
+        rolled_over += 1;
+    }
+
+    Ok(rolled_over)
+}
+
+/// Get the votes recorded for the bot in `month` if its counter has not
+/// been decremented by them yet.
+async fn recorded_votes(bot_id: i64, month: MonthKey) -> Result<Option<i64>> {
+    let row = session()
+        .query_prepared(
+            "SELECT votes, decremented FROM bot_vote_history WHERE id = ? AND month = ?;",
+            (bot_id, month),
+        )
+        .await?
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i64, Option<bool>)>()
+        .next()
+        .transpose()?;
+
+    Ok(row
+        .filter(|(_, decremented)| !decremented.unwrap_or_default())
+        .map(|(votes, _)| votes))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_month_key() {
+        let date = Utc.with_ymd_and_hms(2026, 10, 14, 12, 0, 0).unwrap();
+        assert_eq!(month_key(date), 202610);
+        assert_eq!(format_month(month_key(date)), "2026-10");
+
+        let new_year = Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap();
+        assert!(month_key(new_year) > month_key(date));
+    }
+}
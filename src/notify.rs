@@ -0,0 +1,203 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::broadcast;
+
+/// The maximum number of events waiting to be delivered to a URL before new
+/// ones for it are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// How many times delivering an event to a URL is attempted.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first retry, this doubles after each attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many events a slow subscriber can fall behind by before it misses some.
+const SUBSCRIBER_BUFFER: usize = 256;
+
+/// The queue of each webhook URL.
+static EVENTS: OnceCell<Vec<flume::Sender<IndexEvent>>> = OnceCell::new();
+static SUBSCRIBERS: Lazy<broadcast::Sender<IndexEvent>> =
+    Lazy::new(|| broadcast::channel(SUBSCRIBER_BUFFER).0);
+
+/// A change made to one of the indexes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum IndexEvent {
+    #[serde(rename = "bot.upserted")]
+    BotUpserted {
+        #[serde(with = "string_id")]
+        id: i64,
+    },
+    #[serde(rename = "bot.removed")]
+    BotRemoved {
+        #[serde(with = "string_id")]
+        id: i64,
+    },
+    #[serde(rename = "pack.upserted")]
+    PackUpserted {
+        #[serde(with = "string_id")]
+        id: i64,
+    },
+    #[serde(rename = "pack.removed")]
+    PackRemoved {
+        #[serde(with = "string_id")]
+        id: i64,
+    },
+    #[serde(rename = "refresh.completed")]
+    RefreshCompleted { index: &'static str },
+}
+
+impl IndexEvent {
+    /// The name of the event when streamed to subscribers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BotUpserted { .. } => "bot_upserted",
+            Self::BotRemoved { .. } => "bot_removed",
+            Self::PackUpserted { .. } => "pack_upserted",
+            Self::PackRemoved { .. } => "pack_removed",
+            Self::RefreshCompleted { .. } => "refresh_completed",
+        }
+    }
+}
+
+struct Dispatcher {
+    http: reqwest::Client,
+    secret: String,
+}
+
+/// Starts posting index events to each of the URLs, signed with the secret.
+///
+/// Each URL has its own queue and worker, so events are delivered to it
+/// in order and a slow URL never holds up the others.
+pub fn init(urls: Vec<String>, secret: String) -> Result<()> {
+    let http = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+
+    let dispatcher = Arc::new(Dispatcher { http, secret });
+    let mut queues = vec![];
+    for url in urls {
+        let (tx, rx) = flume::bounded(QUEUE_SIZE);
+        queues.push(tx);
+
+        let dispatcher = dispatcher.clone();
+        tokio::spawn(async move {
+            while let Ok(event) = rx.recv_async().await {
+                dispatcher.dispatch(&url, event).await;
+            }
+        });
+    }
+
+    let _ = EVENTS.set(queues);
+
+    Ok(())
+}
+
+/// Receives every index event from now on.
+pub fn subscribe() -> broadcast::Receiver<IndexEvent> {
+    SUBSCRIBERS.subscribe()
+}
+
+/// Sends the event to every subscriber and queues it to be posted to
+/// every webhook URL.
+///
+/// This never blocks, the event is dropped for any URL whose queue is full.
+pub fn notify(event: IndexEvent) {
+    // This only errors when there are no subscribers.
+    let _ = SUBSCRIBERS.send(event.clone());
+
+    for events in EVENTS.get().into_iter().flatten() {
+        if events.try_send(event.clone()).is_err() {
+            warn!("Dropping webhook event, the webhook queue is full");
+        }
+    }
+}
+
+/// Signs the body with HMAC-SHA256, returning the value of the `X-Signature` header.
+pub fn sign(secret: &str, body: &[u8]) -> Result<String> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| anyhow!(e))?;
+    mac.update(body);
+
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+impl Dispatcher {
+    async fn dispatch(&self, url: &str, event: IndexEvent) {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize webhook event {:?}: {}", event, e);
+                return;
+            },
+        };
+
+        let signature = match sign(&self.secret, &body) {
+            Ok(signature) => signature,
+            Err(e) => {
+                error!("Failed to sign webhook event {:?}: {}", event, e);
+                return;
+            },
+        };
+
+        if let Err(e) = self.deliver(url, &body, &signature).await {
+            error!(
+                "Giving up delivering webhook event {:?} to {} after {} attempts: {}",
+                event, url, MAX_ATTEMPTS, e
+            );
+        }
+    }
+
+    async fn deliver(&self, url: &str, body: &[u8], signature: &str) -> Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+
+        loop {
+            let result = self
+                .http
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header("X-Signature", signature)
+                .body(body.to_vec())
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt >= MAX_ATTEMPTS => return Err(e.into()),
+                Err(e) => {
+                    warn!(
+                        "Failed to deliver webhook to {} (attempt {}), retrying in {:?}: {}",
+                        url, attempt, backoff, e
+                    );
+                },
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// IDs are sent as strings as they don't fit in a JS number.
+mod string_id {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(id: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(id)
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use poem::http::StatusCode;
+use poem::{Body, Result};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::{Binary, Json};
+use poem_openapi::types::ParseFromJSON;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use crate::models;
+use crate::models::bots::Bot;
+use crate::models::connection::{self, PrepareCacheStats};
+use crate::models::packs::Pack;
+use crate::models::RefreshSummary;
+use crate::routes::{admin_token, AdminAuth, StandardResponse};
+use crate::search::metadata::BuildMetadata;
+use crate::search::readers::IndexChecksum;
+use crate::search::replication::{self, IndexName, ManifestEntry};
+use crate::search::{index_impls, readers, MergeSummary, WriterStats};
+use crate::supervisor::{self, TaskRunning, TaskStatus};
+use crate::tasks::{self, ManualTask};
+
+/// How long to wait for a peer to respond with its checksums.
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of imported rows sent to the writer at once.
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// The longest line accepted by an import, a single row is never near this.
+const MAX_IMPORT_LINE_BYTES: u64 = 1 << 20;
+
+static VERIFY_PEERS: OnceCell<Vec<String>> = OnceCell::new();
+
+/// Sets the peers replicas can be verified against, any other peer is rejected.
+pub fn set_verify_peers(peers: Vec<String>) {
+    let peers = peers
+        .into_iter()
+        .map(|peer| peer.trim_end_matches('/').to_string())
+        .collect();
+    let _ = VERIFY_PEERS.set(peers);
+}
+
+fn is_verify_peer(peer: &str) -> bool {
+    let peer = peer.trim_end_matches('/');
+    VERIFY_PEERS
+        .get()
+        .map(|peers| peers.iter().any(|v| v == peer))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct IndexStats {
+    /// The name of the index.
+    name: String,
+
+    /// The number of documents currently searchable.
+    num_docs: u64,
+
+    /// Information about how the index was last built.
+    metadata: BuildMetadata,
+
+    /// The state of the index's write queue.
+    writer: WriterStats,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct IndexChecksums {
+    bots: IndexChecksum,
+    packs: IndexChecksum,
+}
+
+impl IndexChecksums {
+    fn local() -> anyhow::Result<Self> {
+        Ok(Self {
+            bots: readers::bots::reader().checksum()?,
+            packs: readers::packs::reader().checksum()?,
+        })
+    }
+}
+
+#[derive(Debug, Object)]
+pub struct VerifyReplicaPayload {
+    /// The base url of the peer instance, i.e `http://10.0.0.2:7700/v0`.
+    ///
+    /// This must be one of the configured verify peers.
+    peer: String,
+}
+
+#[derive(Debug, Object)]
+pub struct IndexComparison {
+    /// The name of the index.
+    name: String,
+
+    /// If the local and peer checksums match.
+    consistent: bool,
+
+    /// The checksum of the local index.
+    local: IndexChecksum,
+
+    /// The checksum of the peer's index.
+    peer: IndexChecksum,
+}
+
+impl IndexComparison {
+    fn new(name: &str, local: IndexChecksum, peer: IndexChecksum) -> Self {
+        Self {
+            name: name.to_string(),
+            consistent: local == peer,
+            local,
+            peer,
+        }
+    }
+}
+
+#[derive(Debug, Object)]
+pub struct ReplicaReport {
+    /// If every index matches the peer.
+    consistent: bool,
+
+    indexes: Vec<IndexComparison>,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum FileResponse {
+    /// The raw contents of the file.
+    #[oai(status = 200)]
+    Ok(Binary<Vec<u8>>),
+
+    /// The file does not exist or cannot be shipped.
+    #[oai(status = 404)]
+    NotFound,
+}
+
+pub struct AdminApi;
+
+#[OpenApi]
+impl AdminApi {
+    /// Index Stats
+    #[oai(
+        path = "/admin/indexes/stats",
+        method = "get",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn index_stats(&self, auth: AdminAuth) -> Result<Json<Vec<IndexStats>>> {
+        auth.verify()?;
+
+        let stats = vec![
+            IndexStats {
+                name: "bots".to_string(),
+                num_docs: readers::bots::reader().num_docs(),
+                metadata: index_impls::bots::writer().metadata(),
+                writer: index_impls::bots::writer().writer_stats(),
+            },
+            IndexStats {
+                name: "packs".to_string(),
+                num_docs: readers::packs::reader().num_docs(),
+                metadata: index_impls::packs::writer().metadata(),
+                writer: index_impls::packs::writer().writer_stats(),
+            },
+        ];
+
+        Ok(Json(stats))
+    }
+
+    /// Database Stats
+    ///
+    /// How often queries reuse an already prepared statement.
+    #[oai(
+        path = "/admin/database/stats",
+        method = "get",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn database_stats(
+        &self,
+        auth: AdminAuth,
+    ) -> Result<Json<PrepareCacheStats>> {
+        auth.verify()?;
+
+        Ok(Json(connection::session().prepare_cache_stats()))
+    }
+
+    /// Index Checksums
+    ///
+    /// A document count and sampled id hash of each index.
+    #[oai(
+        path = "/admin/indexes/checksum",
+        method = "get",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn index_checksums(
+        &self,
+        auth: AdminAuth,
+    ) -> Result<Json<IndexChecksums>> {
+        auth.verify()?;
+
+        Ok(Json(IndexChecksums::local()?))
+    }
+
+    /// Verify Replica
+    ///
+    /// Compares the local index checksums with the ones of a peer instance.
+    #[oai(
+        path = "/admin/indexes/verify",
+        method = "post",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn verify_replica(
+        &self,
+        auth: AdminAuth,
+        payload: Json<VerifyReplicaPayload>,
+    ) -> Result<Json<ReplicaReport>> {
+        auth.verify()?;
+
+        if !is_verify_peer(&payload.0.peer) {
+            return Err(poem::Error::from_string(
+                "The peer is not a configured verify peer.",
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        let peer = fetch_peer_checksums(&payload.0.peer).await?;
+        let local = IndexChecksums::local()?;
+
+        let indexes = vec![
+            IndexComparison::new("bots", local.bots, peer.bots),
+            IndexComparison::new("packs", local.packs, peer.packs),
+        ];
+
+        for index in indexes.iter().filter(|v| !v.consistent) {
+            warn!(
+                "Index {:?} diverges from peer {}, local: {:?}, peer: {:?}",
+                index.name, payload.0.peer, index.local, index.peer,
+            );
+        }
+
+        let consistent = indexes.iter().all(|v| v.consistent);
+
+        Ok(Json(ReplicaReport {
+            consistent,
+            indexes,
+        }))
+    }
+
+    /// Commit Index
+    ///
+    /// Forces pending writes to be committed and searchable immediately rather
+    /// than waiting for the auto commit.
+    ///
+    /// Replicas only reload their readers as they never write to the index.
+    #[oai(
+        path = "/admin/indexes/:name/commit",
+        method = "post",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn commit_index(
+        &self,
+        auth: AdminAuth,
+        name: Path<IndexName>,
+    ) -> Result<StandardResponse> {
+        auth.verify()?;
+
+        let is_replica = replication::is_replica();
+
+        match name.0 {
+            IndexName::Bots => {
+                if !is_replica {
+                    index_impls::bots::writer().commit().await?;
+                }
+                readers::bots::reader().reload()?;
+            },
+            IndexName::Packs => {
+                if !is_replica {
+                    index_impls::packs::writer().commit().await?;
+                }
+                readers::packs::reader().reload()?;
+            },
+        }
+
+        Ok(StandardResponse::Ok)
+    }
+
+    /// Merge Index Segments
+    ///
+    /// Merges the smallest segments of the index until at most `target`
+    /// remain, defaults to a single segment.
+    ///
+    /// This is mostly useful after a full refresh, which leaves many small segments.
+    #[oai(
+        path = "/admin/indexes/:name/merge",
+        method = "post",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn merge_index(
+        &self,
+        auth: AdminAuth,
+        name: Path<IndexName>,
+        #[oai(validator(minimum(value = "1")))] target: Query<Option<usize>>,
+    ) -> Result<Json<MergeSummary>> {
+        auth.verify()?;
+
+        if replication::is_replica() {
+            return Err(poem::Error::from_string(
+                "Replicas never write to their indexes.",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let target = target.0.unwrap_or(1);
+        let summary = match name.0 {
+            IndexName::Bots => index_impls::bots::writer().merge(target).await?,
+            IndexName::Packs => index_impls::packs::writer().merge(target).await?,
+        };
+
+        info!(
+            "Merged {} index from {} to {} segments",
+            name.0.as_str(),
+            summary.segments_before,
+            summary.segments_after,
+        );
+
+        Ok(Json(summary))
+    }
+
+    /// Replication Manifest
+    ///
+    /// Lists the files of the index which replicas can pull.
+    #[oai(
+        path = "/admin/replication/:index/manifest",
+        method = "get",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn replication_manifest(
+        &self,
+        auth: AdminAuth,
+        index: Path<IndexName>,
+    ) -> Result<Json<Vec<ManifestEntry>>> {
+        auth.verify()?;
+
+        Ok(Json(replication::manifest(index.0.path())?))
+    }
+
+    /// Replication File
+    ///
+    /// Sends a single file of the index to a replica.
+    #[oai(
+        path = "/admin/replication/:index/files/:name",
+        method = "get",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn replication_file(
+        &self,
+        auth: AdminAuth,
+        index: Path<IndexName>,
+        name: Path<String>,
+    ) -> Result<FileResponse> {
+        auth.verify()?;
+
+        match replication::read_file(index.0.path(), &name.0).await? {
+            Some(data) => Ok(FileResponse::Ok(Binary(data))),
+            None => Ok(FileResponse::NotFound),
+        }
+    }
+
+    /// Import Documents
+    ///
+    /// Streams newline delimited JSON rows straight into the index, bypassing
+    /// the database, for restores and seeding local instances.
+    ///
+    /// Each line is a full bot or pack row of at most 1 MiB, lines which fail
+    /// to parse are skipped and reported in the summary. Rows are indexed in
+    /// batches which either apply completely or not at all.
+    ///
+    /// Replicas reject imports as they never write to their indexes.
+    #[oai(
+        path = "/admin/import/:index",
+        method = "post",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn import_documents(
+        &self,
+        auth: AdminAuth,
+        index: Path<IndexName>,
+        body: Binary<Body>,
+    ) -> Result<Json<RefreshSummary>> {
+        auth.verify()?;
+
+        if replication::is_replica() {
+            return Err(poem::Error::from_string(
+                "Replicas never write to their indexes.",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let mut reader = BufReader::new(body.0.into_async_read());
+        let mut summary = RefreshSummary::default();
+
+        let mut bots = vec![];
+        let mut packs = vec![];
+        let mut buffer = vec![];
+        loop {
+            buffer.clear();
+            let read = (&mut reader)
+                .take(MAX_IMPORT_LINE_BYTES + 1)
+                .read_until(b'\n', &mut buffer)
+                .await
+                .map_err(anyhow::Error::from)?;
+            if read == 0 {
+                break;
+            }
+
+            if read as u64 > MAX_IMPORT_LINE_BYTES {
+                return Err(poem::Error::from_string(
+                    format!(
+                        "Lines can be at most {} bytes long.",
+                        MAX_IMPORT_LINE_BYTES
+                    ),
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                ));
+            }
+
+            let line = match std::str::from_utf8(&buffer) {
+                Ok(line) => line.trim(),
+                Err(e) => {
+                    summary.record_error(e);
+                    continue;
+                },
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            let parsed = match index.0 {
+                IndexName::Bots => parse_row::<Bot>(line).map(|bot| bots.push(bot)),
+                IndexName::Packs => parse_row::<Pack>(line).map(|pack| packs.push(pack)),
+            };
+
+            if let Err(e) = parsed {
+                summary.record_error(e);
+            }
+
+            if bots.len() + packs.len() >= IMPORT_BATCH_SIZE {
+                import_batch(
+                    std::mem::take(&mut bots),
+                    std::mem::take(&mut packs),
+                    &mut summary,
+                )
+                .await;
+            }
+        }
+
+        import_batch(bots, packs, &mut summary).await;
+
+        info!(
+            "Imported {} documents into the {} index, skipped: {}, errored: {}",
+            summary.indexed,
+            index.0.as_str(),
+            summary.skipped,
+            summary.errored,
+        );
+
+        Ok(Json(summary))
+    }
+
+    /// Task Statuses
+    ///
+    /// The restarts, last success and last error of each background task.
+    #[oai(path = "/admin/tasks", method = "get", tag = "crate::ApiTags::Admin")]
+    pub async fn task_statuses(&self, auth: AdminAuth) -> Result<Json<Vec<TaskStatus>>> {
+        auth.verify()?;
+
+        Ok(Json(supervisor::statuses()))
+    }
+
+    /// Run Task
+    ///
+    /// Runs the task immediately rather than waiting for its next tick,
+    /// returning the task's status once it has finished.
+    #[oai(
+        path = "/admin/tasks/:name/run",
+        method = "post",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn run_task(
+        &self,
+        auth: AdminAuth,
+        name: Path<ManualTask>,
+    ) -> Result<Json<TaskStatus>> {
+        auth.verify()?;
+
+        if matches!(name.0, ManualTask::Featured) && replication::is_replica() {
+            return Err(poem::Error::from_string(
+                "Replicas never write to their indexes.",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        tasks::run_now(name.0).await.map_err(|e| {
+            if e.is::<TaskRunning>() {
+                poem::Error::from_string(e.to_string(), StatusCode::CONFLICT)
+            } else {
+                e.into()
+            }
+        })?;
+
+        let status = supervisor::status(name.0.as_str()).unwrap_or_else(|| TaskStatus {
+            name: name.0.as_str().to_string(),
+            ..Default::default()
+        });
+
+        Ok(Json(status))
+    }
+
+    /// Reload Synonyms
+    ///
+    /// This internally pulls the synonym dictionary from the database.
+    #[oai(
+        path = "/admin/synonyms/reload",
+        method = "post",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn reload_synonyms(&self, auth: AdminAuth) -> Result<StandardResponse> {
+        auth.verify()?;
+
+        models::synonyms::refresh_synonyms().await?;
+
+        Ok(StandardResponse::Ok)
+    }
+
+    /// Push Bot Trending Scores
+    ///
+    /// Replaces all bot trending scores, this is used by the `push` scores provider.
+    #[oai(
+        path = "/admin/trending/bots",
+        method = "put",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn push_bot_trending(
+        &self,
+        auth: AdminAuth,
+        payload: Json<HashMap<String, f64>>,
+    ) -> Result<StandardResponse> {
+        auth.verify()?;
+
+        match parse_scores(payload.0) {
+            Some(data) => {
+                let changed = models::bots::set_bot_trending_data(data);
+                index_impls::bots::queue_reindex(changed);
+                Ok(StandardResponse::Ok)
+            },
+            None => Ok(StandardResponse::BadRequest),
+        }
+    }
+
+    /// Push Pack Trending Scores
+    ///
+    /// Replaces all pack trending scores, this is used by the `push` scores provider.
+    #[oai(
+        path = "/admin/trending/packs",
+        method = "put",
+        tag = "crate::ApiTags::Admin"
+    )]
+    pub async fn push_pack_trending(
+        &self,
+        auth: AdminAuth,
+        payload: Json<HashMap<String, f64>>,
+    ) -> Result<StandardResponse> {
+        auth.verify()?;
+
+        match parse_scores(payload.0) {
+            Some(data) => {
+                models::packs::set_pack_trending_data(data);
+                Ok(StandardResponse::Ok)
+            },
+            None => Ok(StandardResponse::BadRequest),
+        }
+    }
+}
+
+async fn fetch_peer_checksums(peer: &str) -> anyhow::Result<IndexChecksums> {
+    let url = format!("{}/admin/indexes/checksum", peer.trim_end_matches('/'));
+    let mut request = reqwest::Client::new().get(url).timeout(PEER_TIMEOUT);
+    if let Some(token) = admin_token() {
+        request = request.bearer_auth(token);
+    }
+
+    let checksums = request
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<IndexChecksums>()
+        .await?;
+
+    Ok(checksums)
+}
+
+/// Indexes a batch of imported rows, only one of `bots` or `packs` is
+/// expected to have any rows.
+async fn import_batch(bots: Vec<Bot>, packs: Vec<Pack>, summary: &mut RefreshSummary) {
+    let rows = bots.len() + packs.len();
+    if rows == 0 {
+        return;
+    }
+
+    let result = async {
+        let mut indexed = 0;
+        if !bots.is_empty() {
+            indexed += bots.len();
+            index_impls::bots::writer().import_bots(bots).await?;
+        }
+        if !packs.is_empty() {
+            indexed += index_impls::packs::writer().import_packs(packs).await?;
+        }
+
+        Ok::<_, anyhow::Error>(indexed)
+    };
+
+    match result.await {
+        Ok(indexed) => {
+            summary.indexed += indexed;
+            summary.skipped += rows - indexed;
+        },
+        Err(e) => {
+            // A batch which fails is never applied, so every row of it errored.
+            summary.record_error(e);
+            summary.errored += rows - 1;
+        },
+    }
+}
+
+fn parse_row<T: ParseFromJSON>(line: &str) -> anyhow::Result<T> {
+    let value = serde_json::from_str(line)?;
+    T::parse_from_json(Some(value)).map_err(|e| anyhow::anyhow!(e.into_message()))
+}
+
+fn parse_scores(scores: HashMap<String, f64>) -> Option<HashMap<i64, f64>> {
+    scores
+        .into_iter()
+        .map(|(k, v)| Some((k.parse::<i64>().ok()?, v)))
+        .collect()
+}
@@ -1,20 +1,55 @@
 use std::collections::HashMap;
+use std::time::Instant;
 
+use anyhow::anyhow;
 use backend_common::types::{JsSafeBigInt, JsSafeInt, Set, Timestamp};
-use poem::Result;
-use poem_openapi::param::Path;
+use poem::http::StatusCode;
+use poem::{Body, Request, Result};
+use poem_openapi::param::{Path, Query};
 use poem_openapi::payload::Json;
-use poem_openapi::{Object, OpenApi};
+use poem_openapi::types::{ParseFromJSON, ToJSON};
+use poem_openapi::{ApiResponse, Object, OpenApi};
 use tantivy::schema::Field;
 use tantivy::Document;
 
-use crate::models::bots::{get_bot_data, get_bot_votes, Bot};
-use crate::routes::StandardResponse;
-use crate::search::readers::bots::{BotFilter, BotsSortBy};
-use crate::search::readers::Order;
-use crate::search::{index_impls, readers, FromTantivyDoc};
-
-#[derive(Debug, Object)]
+use crate::models::analytics::{self, SearchRecord};
+use crate::models::bots::{
+    client_id_from_invite,
+    find_by_client_id,
+    get_bot_all_time_votes,
+    get_bot_data,
+    get_bot_votes,
+    submit_vote,
+    Bot,
+    VoteOutcome,
+};
+use crate::models::ratings::{self, get_bot_rating};
+use crate::models::{vote_history, RefreshSummary};
+use crate::routes::csv::{self, SearchFormat, SearchResponse};
+use crate::routes::fields::{BotField, BotFields};
+use crate::routes::{
+    client_ip,
+    search_error,
+    sorted_tags,
+    v1,
+    writer_error,
+    AdminAuth,
+    Pagination,
+    StandardResponse,
+    TagCount,
+};
+use crate::search::es_query::EsQuery;
+use crate::search::index_impls::bots::stored_bot;
+use crate::search::readers::bots::{
+    BotFilter,
+    BotsRanking,
+    BotsSortBy,
+    BotsSortCriterion,
+};
+use crate::search::readers::{CountMode, Highlighter, Order};
+use crate::search::{autocomplete, index_impls, readers, replication, FromTantivyDoc};
+
+#[derive(Debug, Clone, Object)]
 #[oai(rename_all = "camelCase")]
 pub struct BotHit {
     /// The snowflake ID of the bot.
@@ -61,43 +96,143 @@ pub struct BotHit {
     /// The number of votes the bot currently has this month.
     pub votes: JsSafeBigInt,
 
+    /// The number of votes the bot has had across every month.
+    pub all_time_votes: JsSafeBigInt,
+
+    /// The average number of stars the bot has been rated, `0` if unrated.
+    pub rating: f64,
+
     /// The invite url of the bot.
     pub invite_url: String,
+
+    /// The matched fragments of the `username` and `description` fields.
+    ///
+    /// Matches are wrapped in `<b>` tags, fields without a match are omitted.
+    pub highlights: HashMap<String, String>,
 }
 
-impl From<Bot> for BotHit {
-    fn from(bot: Bot) -> Self {
+impl From<&Bot> for BotHit {
+    fn from(bot: &Bot) -> Self {
+        Self::with_fields(bot, &BotFields::default())
+    }
+}
+
+impl BotHit {
+    /// Builds the hit skipping the lookups and copies of every field which
+    /// was not requested, these are left empty.
+    pub fn with_fields(bot: &Bot, fields: &BotFields) -> Self {
+        let has = |field| fields.contains(field);
+        let id = *bot.id;
+
         Self {
-            id: bot.id,
-            username: bot.username,
-            avatar: bot.avatar,
+            id: JsSafeBigInt::from(id),
+            username: has(BotField::Username)
+                .then(|| bot.username.clone())
+                .unwrap_or_default(),
+            avatar: has(BotField::Avatar).then(|| bot.avatar.clone()).flatten(),
             discriminator: bot.discriminator,
-            prefix: bot.prefix,
+            prefix: has(BotField::Prefix).then(|| bot.prefix.clone()).flatten(),
             flags: bot.features,
             features: bot.features,
-            tags: bot.tags,
-            created_on: bot.created_on,
+            tags: has(BotField::Tags)
+                .then(|| bot.tags.clone())
+                .unwrap_or_default(),
+            created_on: bot.created_on.clone(),
             owner_id: bot.owner_id,
-            co_owner_ids: bot.co_owner_ids,
+            co_owner_ids: bot.co_owner_ids.clone(),
             guild_count: bot.guild_count,
-            brief_description: bot.brief_description,
-            votes: JsSafeBigInt::from(get_bot_votes(*bot.id) as i64),
-            invite_url: bot.invite_url,
+            brief_description: has(BotField::BriefDescription)
+                .then(|| bot.brief_description.clone())
+                .unwrap_or_default(),
+            votes: JsSafeBigInt::from(
+                has(BotField::Votes)
+                    .then(|| get_bot_votes(id) as i64)
+                    .unwrap_or_default(),
+            ),
+            all_time_votes: JsSafeBigInt::from(
+                has(BotField::AllTimeVotes)
+                    .then(|| get_bot_all_time_votes(id) as i64)
+                    .unwrap_or_default(),
+            ),
+            rating: has(BotField::Rating)
+                .then(|| get_bot_rating(id))
+                .unwrap_or_default(),
+            invite_url: has(BotField::InviteUrl)
+                .then(|| bot.invite_url.clone())
+                .unwrap_or_default(),
+            highlights: HashMap::new(),
+        }
+    }
+}
+
+/// A bot loaded from its stored document, this is built into a [BotHit]
+/// once the requested fields are known.
+pub struct StoredBotHit {
+    bot: Bot,
+    highlights: HashMap<String, String>,
+}
+
+impl StoredBotHit {
+    /// Builds the hit with only the requested fields.
+    pub fn into_hit(self, fields: &BotFields) -> BotHit {
+        let mut hit = BotHit::with_fields(&self.bot, fields);
+        if fields.contains(BotField::Highlights) {
+            hit.highlights = self.highlights;
         }
+
+        hit
     }
 }
 
-impl FromTantivyDoc for BotHit {
-    fn from_doc(id_field: Field, doc: Document) -> Option<Self> {
-        let id = doc.get_first(id_field)?.as_i64()?;
-        let bot = get_bot_data(id)?;
+impl FromTantivyDoc for StoredBotHit {
+    fn from_doc(_id_field: Field, doc: Document) -> Option<Self> {
+        // Only the votes and other counts which change often are taken
+        // from the live data, the rest is stored in the document.
+        stored_bot(&doc).map(|bot| Self {
+            bot,
+            highlights: HashMap::new(),
+        })
+    }
+
+    fn highlight(&mut self, highlighter: &Highlighter) {
+        let fields = [
+            ("username", &self.bot.username),
+            ("description", &self.bot.brief_description),
+        ];
 
-        Some(Self::from(bot))
+        for (name, text) in fields {
+            if let Some(fragment) = highlighter.fragment(name, text) {
+                self.highlights.insert(name.to_string(), fragment);
+            }
+        }
     }
 }
 
 #[derive(Debug, Object)]
 #[oai(rename_all = "camelCase")]
+pub struct BotSuggestion {
+    /// The snowflake ID of the bot.
+    pub id: JsSafeBigInt,
+
+    /// The bot's username.
+    pub username: String,
+
+    /// The bot's avatar hash if applicable.
+    pub avatar: Option<String>,
+}
+
+impl From<&Bot> for BotSuggestion {
+    fn from(bot: &Bot) -> Self {
+        Self {
+            id: JsSafeBigInt::from(*bot.id),
+            username: bot.username.clone(),
+            avatar: bot.avatar.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Object)]
+#[oai(rename_all = "camelCase")]
 pub struct BotSearchPayload {
     /// The query to be searched.
     ///
@@ -119,6 +254,13 @@ pub struct BotSearchPayload {
     #[oai(default)]
     filter: BotFilter,
 
+    /// A structured query results must also match, using a subset of the
+    /// Elasticsearch query DSL.
+    ///
+    /// Supports `bool`, `term`, `match` and `range` queries on the indexed
+    /// fields by their name in the index.
+    es_query: Option<EsQuery>,
+
     /// How to sort results.
     #[oai(default)]
     sort: BotsSortBy,
@@ -126,13 +268,64 @@ pub struct BotSearchPayload {
     /// Order results Asc or Desc.
     #[oai(default)]
     order: Order,
+
+    /// Further sorts applied in order to break ties of the primary sort.
+    ///
+    /// E.g. premium desc, then votes desc, then relevancy.
+    #[oai(validator(max_items = 3), default)]
+    then_by: Vec<BotsSortCriterion>,
+
+    /// A ranking formula to sort by instead of `sort` and `thenBy`, which
+    /// can't be used along with it.
+    ranking: Option<BotsRanking>,
+
+    /// Only match results containing the exact query terms.
+    ///
+    /// This bypasses any typo tolerance.
+    #[oai(default)]
+    exact: bool,
+
+    /// If the hit and facet counts should be exact or estimated.
+    ///
+    /// Estimated counts are faster for broad queries matching most documents.
+    #[oai(default)]
+    count_mode: CountMode,
+
+    /// Only return these fields of each hit, the `id` is always returned.
+    ///
+    /// Every field is returned if empty.
+    #[oai(validator(max_items = 18), default)]
+    fields: Vec<BotField>,
+}
+
+impl BotSearchPayload {
+    /// The fields of each hit to return.
+    pub fn fields(&self) -> BotFields {
+        BotFields::new(self.fields.clone())
+    }
 }
 
 #[derive(Debug, Object)]
-#[oai(rename_all = "camelCase")]
-pub struct BotSearchResult {
+pub struct GuildCountBucket {
+    /// The lowest guild count of the bucket.
+    start: u64,
+
+    /// The guild count the bucket ends before, the last bucket has no end.
+    end: Option<u64>,
+
+    /// The number of results within the bucket.
+    count: usize,
+}
+
+#[derive(Debug, Object)]
+#[oai(
+    rename_all = "camelCase",
+    concretes(name = "BotSearchResult", params(BotHit)),
+    concretes(name = "BotSearchResultV1", params(v1::BotHit))
+)]
+pub struct BotSearchResult<T: ParseFromJSON + ToJSON> {
     /// The search results themselves.
-    hits: Vec<BotHit>,
+    hits: Vec<T>,
 
     /// The maximum amount of docs that could get returned.
     limit: usize,
@@ -145,11 +338,169 @@ pub struct BotSearchResult {
 
     /// The total number of documents that matched the query.
     ///
-    /// This is a best-guess estimate.
+    /// This is an estimate if the `estimate` count mode was used.
     nb_hits: usize,
 
+    /// If there are more results after this page.
+    has_more: bool,
+
+    /// The number of pages of `limit` results which can be reached.
+    total_pages: usize,
+
+    /// The number of results which can be paged through.
+    ///
+    /// This is `nbHits` capped by the maximum offset a search can use.
+    max_window: usize,
+
     /// The distribution of tags/categories across the results.
     tag_distribution: HashMap<String, usize>,
+
+    /// The number of results which are premium.
+    premium_count: usize,
+
+    /// The number of results which are not premium.
+    non_premium_count: usize,
+
+    /// The number of results with each feature flag set, keyed by the flag.
+    feature_distribution: HashMap<String, usize>,
+
+    /// The number of results within each range of guild counts.
+    guild_count_histogram: Vec<GuildCountBucket>,
+
+    /// A corrected version of the query if it produced very few hits.
+    suggestion: Option<String>,
+}
+
+impl<T: ParseFromJSON + ToJSON> BotSearchResult<T> {
+    /// Converts the hits of the result into the hits of another version.
+    pub fn map_hits<U: ParseFromJSON + ToJSON>(
+        self,
+        f: impl FnMut(T) -> U,
+    ) -> BotSearchResult<U> {
+        BotSearchResult {
+            hits: self.hits.into_iter().map(f).collect(),
+            limit: self.limit,
+            offset: self.offset,
+            query: self.query,
+            nb_hits: self.nb_hits,
+            has_more: self.has_more,
+            total_pages: self.total_pages,
+            max_window: self.max_window,
+            tag_distribution: self.tag_distribution,
+            premium_count: self.premium_count,
+            non_premium_count: self.non_premium_count,
+            feature_distribution: self.feature_distribution,
+            guild_count_histogram: self.guild_count_histogram,
+            suggestion: self.suggestion,
+        }
+    }
+}
+
+#[derive(Debug, Object)]
+pub struct PopularSearch {
+    /// The normalized query.
+    query: String,
+
+    /// The number of times the query was searched within the window.
+    count: usize,
+}
+
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct VotePayload {
+    /// The user voting for the bot.
+    user_id: JsSafeBigInt,
+}
+
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct VoteResult {
+    /// The number of votes the bot has this month including the vote.
+    votes: JsSafeBigInt,
+
+    /// The number of votes the bot has had across every month.
+    all_time_votes: JsSafeBigInt,
+}
+
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct VoteCooldown {
+    /// The number of seconds until the user can vote for the bot again.
+    retry_after: u64,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum VoteResponse {
+    /// The vote was counted.
+    #[oai(status = 200)]
+    Ok(Json<VoteResult>),
+
+    /// The bot does not exist.
+    #[oai(status = 404)]
+    NotFound,
+
+    /// The user has already voted for the bot in the last 12 hours.
+    #[oai(status = 429)]
+    OnCooldown(Json<VoteCooldown>),
+}
+
+#[derive(Debug, Object)]
+pub struct MonthlyVotes {
+    /// The month the votes were made in, i.e `2026-10`.
+    month: String,
+
+    /// The number of votes the bot had that month.
+    votes: JsSafeBigInt,
+}
+
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct ReviewSummary {
+    /// The number of ratings the bot has been given.
+    count: JsSafeBigInt,
+
+    /// The average number of stars, `0` if the bot has not been rated.
+    average: f64,
+
+    /// The number of ratings per number of stars, starting at one star.
+    histogram: Vec<JsSafeBigInt>,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum ReviewSummaryResponse {
+    /// The ratings of the bot.
+    #[oai(status = 200)]
+    Ok(Json<ReviewSummary>),
+
+    /// The bot does not exist.
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum InviteLookupResponse {
+    /// The bot listed with the invite's client ID.
+    #[oai(status = 200)]
+    Ok(Json<BotHit>),
+
+    /// The url is not a Discord bot invite.
+    #[oai(status = 400)]
+    InvalidInvite,
+
+    /// No listed bot matches the invite.
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum SimilarBotsResponse {
+    /// The bots most similar to the given bot.
+    #[oai(status = 200)]
+    Ok(Json<Vec<BotHit>>),
+
+    /// The bot does not exist.
+    #[oai(status = 404)]
+    NotFound,
 }
 
 pub struct BotApi;
@@ -161,68 +512,426 @@ impl BotApi {
     pub async fn get_bot_ids(&self) -> Json<Vec<JsSafeBigInt>> {
         let ids = crate::models::bots::all_bots()
             .into_iter()
-            .map(|b| b.id)
+            .map(|b| JsSafeBigInt::from(*b.id))
             .collect::<Vec<_>>();
 
         Json(ids)
     }
 
+    /// Lookup Bot By Invite
+    ///
+    /// Finds the listed bot the invite or OAuth2 url is for, this is used
+    /// to detect bots which are already listed.
+    #[oai(path = "/bots/by-invite", method = "get", tag = "crate::ApiTags::Bots")]
+    pub async fn get_bot_by_invite(&self, url: Query<String>) -> InviteLookupResponse {
+        let client_id = match client_id_from_invite(&url) {
+            Some(client_id) => client_id,
+            None => return InviteLookupResponse::InvalidInvite,
+        };
+
+        match find_by_client_id(client_id) {
+            Some(bot) => InviteLookupResponse::Ok(Json(BotHit::from(bot.as_ref()))),
+            None => InviteLookupResponse::NotFound,
+        }
+    }
+
+    /// List Bot Tags
+    ///
+    /// Every tag used by a listed bot along with how many bots use it.
+    #[oai(path = "/bots/tags", method = "get", tag = "crate::ApiTags::Bots")]
+    pub async fn get_bot_tags(&self) -> Json<Vec<TagCount>> {
+        Json(sorted_tags(crate::models::bots::tag_counts()))
+    }
+
     /// Update Bot Data
     ///
     /// This internally pulls data from the database.
     #[oai(path = "/bots/:id", method = "post", tag = "crate::ApiTags::Bots")]
     pub async fn update_bot(&self, id: Path<u64>) -> Result<StandardResponse> {
-        index_impls::bots::writer().upsert_bot(*id as i64).await?;
+        index_impls::bots::writer()
+            .upsert_bot(*id as i64)
+            .await
+            .map_err(writer_error)?;
 
         Ok(StandardResponse::Ok)
     }
 
+    /// Vote For Bot
+    ///
+    /// Counts a vote from the user, each user can vote for a bot once
+    /// every 12 hours.
+    #[oai(path = "/bots/:id/vote", method = "post", tag = "crate::ApiTags::Bots")]
+    pub async fn vote(
+        &self,
+        auth: AdminAuth,
+        id: Path<u64>,
+        payload: Json<VotePayload>,
+    ) -> Result<VoteResponse> {
+        auth.verify()?;
+
+        let bot_id = *id as i64;
+        if get_bot_data(bot_id).is_none() {
+            return Ok(VoteResponse::NotFound);
+        }
+
+        match submit_vote(bot_id, *payload.0.user_id).await? {
+            VoteOutcome::Counted(stats) => {
+                // The vote is already counted, so a failed re-index is only
+                // logged and picked up when the votes are next reloaded.
+                if !replication::is_replica() {
+                    if let Err(e) =
+                        index_impls::bots::writer().reindex_live(bot_id).await
+                    {
+                        warn!("Failed to re-index bot {} after a vote: {}", bot_id, e);
+                    }
+                }
+
+                Ok(VoteResponse::Ok(Json(VoteResult {
+                    votes: JsSafeBigInt::from(stats.votes() as i64),
+                    all_time_votes: JsSafeBigInt::from(stats.all_time_votes() as i64),
+                })))
+            },
+            VoteOutcome::OnCooldown(remaining) => {
+                Ok(VoteResponse::OnCooldown(Json(VoteCooldown {
+                    retry_after: remaining.as_secs(),
+                })))
+            },
+        }
+    }
+
+    /// Bot Vote History
+    ///
+    /// The votes of up to `limit` past months, newest first, defaults to 12.
+    /// The votes of the current month are the `votes` of the bot.
+    #[oai(
+        path = "/bots/:id/votes/history",
+        method = "get",
+        tag = "crate::ApiTags::Bots"
+    )]
+    pub async fn vote_history(
+        &self,
+        id: Path<u64>,
+        #[oai(validator(maximum(value = "120")))] limit: Query<Option<usize>>,
+    ) -> Result<Json<Vec<MonthlyVotes>>> {
+        let history = vote_history::bot_history(*id as i64, limit.0.unwrap_or(12))
+            .await?
+            .into_iter()
+            .map(|(month, votes)| MonthlyVotes {
+                month: vote_history::format_month(month),
+                votes: JsSafeBigInt::from(votes),
+            })
+            .collect();
+
+        Ok(Json(history))
+    }
+
+    /// Bot Review Summary
+    ///
+    /// The number of ratings, average rating and ratings per star of the bot.
+    /// These are refreshed along with the votes.
+    #[oai(
+        path = "/bots/:id/reviews/summary",
+        method = "get",
+        tag = "crate::ApiTags::Bots"
+    )]
+    pub async fn review_summary(&self, id: Path<u64>) -> ReviewSummaryResponse {
+        let bot_id = *id as i64;
+        if get_bot_data(bot_id).is_none() {
+            return ReviewSummaryResponse::NotFound;
+        }
+
+        let stats = ratings::rating_stats(bot_id);
+        ReviewSummaryResponse::Ok(Json(ReviewSummary {
+            count: JsSafeBigInt::from(stats.count() as i64),
+            average: stats.average(),
+            histogram: stats
+                .histogram()
+                .iter()
+                .map(|&count| JsSafeBigInt::from(count as i64))
+                .collect(),
+        }))
+    }
+
     /// Remove Bot Data
     #[oai(path = "/bots/:id", method = "delete", tag = "crate::ApiTags::Bots")]
     pub async fn remove_bot(&self, id: Path<u64>) -> Result<StandardResponse> {
-        index_impls::bots::writer().remove_bot(*id as i64).await?;
+        index_impls::bots::writer()
+            .remove_bot(*id as i64)
+            .await
+            .map_err(writer_error)?;
 
         Ok(StandardResponse::Ok)
     }
 
     /// Refresh Bot Data
+    ///
+    /// If `since` is given only the bots updated after that unix timestamp
+    /// in seconds are re-indexed, otherwise the whole index is rebuilt.
+    ///
+    /// Updates are only tracked for 30 days, the whole index is rebuilt
+    /// when `since` is older than that.
     #[oai(path = "/bots/refresh", method = "post", tag = "crate::ApiTags::Bots")]
-    pub async fn refresh_bots(&self) -> Result<StandardResponse> {
-        index_impls::bots::writer().full_refresh().await?;
+    pub async fn refresh_bots(
+        &self,
+        since: Query<Option<i64>>,
+    ) -> Result<Json<RefreshSummary>> {
+        let writer = index_impls::bots::writer();
+        let summary = match since.0 {
+            Some(since) => writer.delta_refresh(since).await?,
+            None => writer.full_refresh().await?,
+        };
 
-        Ok(StandardResponse::Ok)
+        Ok(Json(summary))
+    }
+
+    /// Suggest Bots
+    ///
+    /// Autocompletes bot usernames by prefix, returning at most 10 bots.
+    ///
+    /// This bypasses the regular search entirely.
+    #[oai(path = "/bots/suggest", method = "get", tag = "crate::ApiTags::Bots")]
+    pub async fn suggest(
+        &self,
+        #[oai(validator(max_length = 50))] q: Query<String>,
+        limit: Query<Option<usize>>,
+    ) -> Json<Vec<BotSuggestion>> {
+        let limit = limit.0.unwrap_or(10).clamp(1, 10);
+
+        let suggestions = autocomplete::suggest(&q.0, limit)
+            .into_iter()
+            .filter_map(get_bot_data)
+            .map(|bot| BotSuggestion::from(bot.as_ref()))
+            .collect();
+
+        Json(suggestions)
+    }
+
+    /// Popular Searches
+    ///
+    /// The queries searched by the most people over the last 24 hours,
+    /// returning at most `limit` queries, defaults to 10 queries.
+    ///
+    /// This is only populated when search analytics are recorded.
+    #[oai(
+        path = "/bots/search/popular",
+        method = "get",
+        tag = "crate::ApiTags::Bots"
+    )]
+    pub async fn popular_searches(
+        &self,
+        limit: Query<Option<usize>>,
+    ) -> Json<Vec<PopularSearch>> {
+        let limit = limit.0.unwrap_or(10).clamp(1, 50);
+
+        let searches = analytics::popular_searches("bots", limit)
+            .into_iter()
+            .map(|(query, count)| PopularSearch { query, count })
+            .collect();
+
+        Json(searches)
+    }
+}
+
+/// The bot routes whose responses contain bot hits, these are versioned
+/// as the shape of a hit differs between versions.
+pub struct BotSearchApi;
+
+#[OpenApi]
+impl BotSearchApi {
+    /// Similar Bots
+    ///
+    /// Finds up to `limit` other bots sharing the most tags and description
+    /// terms with the given bot, defaults to 10 bots.
+    #[oai(
+        path = "/bots/:id/similar",
+        method = "get",
+        tag = "crate::ApiTags::Bots"
+    )]
+    pub async fn similar_bots(
+        &self,
+        id: Path<u64>,
+        limit: Query<Option<usize>>,
+    ) -> Result<SimilarBotsResponse> {
+        match similar_bots(*id as i64, limit.0).await? {
+            Some(hits) => Ok(SimilarBotsResponse::Ok(Json(hits))),
+            None => Ok(SimilarBotsResponse::NotFound),
+        }
     }
 
     /// Search Bots
+    ///
+    /// If `format` is `csv`, or CSV is accepted, every matching bot from
+    /// `offset` onwards is exported as CSV instead.
     #[oai(path = "/bots/search", method = "post", tag = "crate::ApiTags::Bots")]
     pub async fn search(
         &self,
+        req: &Request,
         payload: Json<BotSearchPayload>,
-    ) -> Result<Json<BotSearchResult>> {
-        let limit = payload.0.limit.unwrap_or(20);
-        let offset = payload.0.offset;
-        let query = payload.0.query.clone();
-
-        let (num_hits, dist, hits) = readers::bots::reader()
-            .search::<BotHit>(
-                payload.0.query,
-                payload.0.filter,
-                limit,
-                offset,
-                payload.0.sort,
-                payload.0.order,
-            )
-            .await?;
-
-        let result = BotSearchResult {
-            hits,
-            limit,
+        format: Query<Option<SearchFormat>>,
+    ) -> Result<SearchResponse<BotSearchResult<BotHit>>> {
+        if csv::wants_csv(req, format.0) {
+            return Ok(SearchResponse::Csv(export_bots(payload.0)));
+        }
+
+        let fields = payload.fields();
+        let result = record_search_bots(req, payload.0).await?;
+
+        Ok(fields.respond(result, None))
+    }
+}
+
+/// Finds the bots most similar to the given bot, excluding hidden bots.
+///
+/// Returns `None` if the bot does not exist.
+pub async fn similar_bots(
+    bot_id: i64,
+    limit: Option<usize>,
+) -> Result<Option<Vec<BotHit>>> {
+    let limit = limit.unwrap_or(10).clamp(1, 50);
+
+    let hits = readers::bots::reader()
+        .similar::<StoredBotHit>(bot_id, limit)
+        .await?
+        .map(|hits| {
+            hits.into_iter()
+                .map(|hit| hit.into_hit(&BotFields::default()))
+                .collect()
+        });
+
+    Ok(hits)
+}
+
+/// Runs the search and records it in the search analytics.
+pub async fn record_search_bots(
+    req: &Request,
+    payload: BotSearchPayload,
+) -> Result<BotSearchResult<BotHit>> {
+    let filters = payload.filter.to_json_string();
+    let start = Instant::now();
+
+    let result = search_bots(payload).await?;
+
+    analytics::record_search(SearchRecord {
+        entity: "bots",
+        query: result.query.clone(),
+        filters,
+        nb_hits: result.nb_hits,
+        latency: start.elapsed(),
+        ip_hash: analytics::hash_ip(&client_ip(req)),
+    });
+
+    Ok(result)
+}
+
+/// Streams every bot matching the search as CSV.
+pub fn export_bots(payload: BotSearchPayload) -> Body {
+    let columns = &["id", "username", "votes", "guild_count", "tags"];
+
+    csv::export(columns, payload.offset, move |offset, limit| {
+        let page = BotSearchPayload {
             offset,
-            query: query.unwrap_or_else(|| "*".to_string()),
-            nb_hits: num_hits,
-            tag_distribution: dist,
+            limit: Some(limit),
+            fields: vec![BotField::Username, BotField::Votes, BotField::Tags],
+            ..payload.clone()
         };
 
-        Ok(Json(result))
+        async move {
+            let result = search_bots(page).await.map_err(|e| anyhow!("{}", e))?;
+
+            let rows = result
+                .hits
+                .into_iter()
+                .map(|hit| {
+                    vec![
+                        (*hit.id).to_string(),
+                        hit.username,
+                        (*hit.votes).to_string(),
+                        hit.guild_count
+                            .as_deref()
+                            .copied()
+                            .unwrap_or_default()
+                            .to_string(),
+                        hit.tags.join(", "),
+                    ]
+                })
+                .collect();
+
+            Ok(rows)
+        }
+    })
+}
+
+/// Runs the search without recording it.
+pub async fn search_bots(payload: BotSearchPayload) -> Result<BotSearchResult<BotHit>> {
+    let limit = payload.limit.unwrap_or(20);
+    let offset = payload.offset;
+    let query = payload.query.clone();
+    let fields = payload.fields();
+    let sorted =
+        !matches!(payload.sort, BotsSortBy::Relevancy) || !payload.then_by.is_empty();
+    if payload.ranking.is_some() && sorted {
+        return Err(poem::Error::from_string(
+            "`ranking` can't be used along with `sort` or `thenBy`.",
+            StatusCode::BAD_REQUEST,
+        ));
     }
+    let ranking = payload.ranking.map(BotsRanking::strategy);
+
+    let result = readers::bots::reader()
+        .search::<StoredBotHit>(
+            payload.query,
+            payload.filter,
+            payload.es_query,
+            limit,
+            offset,
+            payload.sort,
+            payload.order,
+            payload.then_by,
+            ranking,
+            payload.exact,
+            payload.count_mode,
+        )
+        .await
+        .map_err(search_error)?;
+
+    let premium_count = result.facets.premium_hits;
+    let feature_distribution = result
+        .facets
+        .feature_hits
+        .into_iter()
+        .map(|(flag, count)| (flag.to_string(), count))
+        .collect();
+    let guild_count_histogram = result
+        .facets
+        .guild_counts
+        .into_iter()
+        .map(|(start, end, count)| GuildCountBucket {
+            start: start as u64,
+            end: end.map(|end| end as u64),
+            count,
+        })
+        .collect();
+
+    let pagination = Pagination::new(result.num_hits, limit, offset);
+
+    Ok(BotSearchResult {
+        hits: result
+            .hits
+            .into_iter()
+            .map(|hit| hit.into_hit(&fields))
+            .collect(),
+        limit,
+        offset,
+        query: query.unwrap_or_else(|| "*".to_string()),
+        nb_hits: result.num_hits,
+        has_more: pagination.has_more,
+        total_pages: pagination.total_pages,
+        max_window: pagination.max_window,
+        tag_distribution: result.distribution,
+        premium_count,
+        non_premium_count: result.num_hits.saturating_sub(premium_count),
+        feature_distribution,
+        guild_count_histogram,
+        suggestion: result.suggestion,
+    })
 }
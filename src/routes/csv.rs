@@ -0,0 +1,182 @@
+use std::future::Future;
+
+use anyhow::Result;
+use futures::StreamExt;
+use poem::http::header;
+use poem::{Body, IntoResponse, Request, Response};
+use poem_openapi::payload::Json;
+use poem_openapi::registry::{
+    MetaMediaType,
+    MetaResponse,
+    MetaResponses,
+    MetaSchema,
+    MetaSchemaRef,
+    Registry,
+};
+use poem_openapi::types::{ToJSON, Type};
+use poem_openapi::{ApiResponse, Enum};
+
+use crate::routes::MAX_OFFSET;
+
+/// The number of hits fetched per search while exporting.
+const PAGE_SIZE: usize = 500;
+
+static CSV_CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+
+#[derive(Debug, Copy, Clone, Enum)]
+#[oai(rename_all = "lowercase")]
+pub enum SearchFormat {
+    Json,
+    Csv,
+}
+
+/// If the results should be exported as CSV, either by the `format`
+/// param or by the `Accept` header.
+pub fn wants_csv(req: &Request, format: Option<SearchFormat>) -> bool {
+    match format {
+        Some(format) => matches!(format, SearchFormat::Csv),
+        None => req
+            .header(header::ACCEPT)
+            .map(|accept| accept.contains("text/csv"))
+            .unwrap_or_default(),
+    }
+}
+
+/// The results of a search as JSON, or every hit exported as CSV.
+pub enum SearchResponse<T> {
+    Json(T),
+    /// The results as JSON with only some fields of each hit.
+    Projected(serde_json::Value),
+    Csv(Body),
+}
+
+impl<T: ToJSON> IntoResponse for SearchResponse<T> {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Json(result) => Json(result).into_response(),
+            Self::Projected(value) => poem::web::Json(value).into_response(),
+            Self::Csv(body) => Response::builder()
+                .content_type(CSV_CONTENT_TYPE)
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"search.csv\"",
+                )
+                .body(body),
+        }
+    }
+}
+
+impl<T: ToJSON> ApiResponse for SearchResponse<T> {
+    fn meta() -> MetaResponses {
+        MetaResponses {
+            responses: vec![MetaResponse {
+                description: "The search results, or every matching hit as CSV \
+                              from `offset` onwards if requested.",
+                status: Some(200),
+                content: vec![
+                    MetaMediaType {
+                        content_type: "application/json; charset=utf-8",
+                        schema: T::schema_ref(),
+                    },
+                    MetaMediaType {
+                        content_type: CSV_CONTENT_TYPE,
+                        schema: MetaSchemaRef::Inline(Box::new(MetaSchema::new(
+                            "string",
+                        ))),
+                    },
+                ],
+                headers: vec![],
+            }],
+        }
+    }
+
+    fn register(registry: &mut Registry) {
+        T::register(registry);
+    }
+}
+
+/// Streams the column names then the rows of every page of hits, starting
+/// at `offset` until there are no more results or the maximum offset is
+/// reached.
+///
+/// `fetch` is given the offset and limit of each page.
+pub fn export<F, Fut>(columns: &'static [&'static str], offset: usize, fetch: F) -> Body
+where
+    F: FnMut(usize, usize) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Vec<Vec<String>>>> + Send + 'static,
+{
+    let head = futures::stream::once(async move {
+        Ok::<_, std::io::Error>(encode_row(columns.iter().copied()))
+    });
+
+    let rows = futures::stream::unfold(Some((fetch, offset)), |state| async move {
+        let (mut fetch, offset) = state?;
+        if offset > MAX_OFFSET {
+            return None;
+        }
+
+        let rows = match fetch(offset, PAGE_SIZE).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Failed to export search results: {}", e);
+                let err = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+                return Some((Err(err), None));
+            },
+        };
+
+        if rows.is_empty() {
+            return None;
+        }
+
+        let next = (rows.len() == PAGE_SIZE).then_some((fetch, offset + rows.len()));
+        let data = rows
+            .iter()
+            .flat_map(|row| encode_row(row.iter().map(String::as_str)))
+            .collect::<Vec<u8>>();
+
+        Some((Ok(data), next))
+    });
+
+    Body::from_bytes_stream(head.chain(rows))
+}
+
+fn encode_row<'a>(fields: impl Iterator<Item = &'a str>) -> Vec<u8> {
+    let mut row = fields.map(escape).collect::<Vec<_>>().join(",");
+    row.push_str("\r\n");
+    row.into_bytes()
+}
+
+/// Quotes the field if it contains a separator, quote or line break.
+///
+/// Fields which a spreadsheet would run as a formula are prefixed with `'`
+/// so they are shown as text instead.
+fn escape(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_row() {
+        let row = encode_row(["1", "Music, Bot", "say \"hi\"", ""].into_iter());
+        assert_eq!(row, b"1,\"Music, Bot\",\"say \"\"hi\"\"\",\r\n");
+    }
+
+    #[test]
+    fn test_encode_row_neutralises_formulas() {
+        let row = encode_row(["=1+1", "@SUM(A1)", "-2", "\tx", "a=b"].into_iter());
+        assert_eq!(row, b"'=1+1,'@SUM(A1),'-2,'\tx,a=b\r\n");
+    }
+}
@@ -0,0 +1,166 @@
+//! Projection of bot hits down to the fields a client asked for.
+
+use poem_openapi::types::ToJSON;
+use poem_openapi::Enum;
+use serde_json::Value;
+
+use crate::routes::csv::SearchResponse;
+
+/// A field of a bot hit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Enum)]
+#[oai(rename_all = "camelCase")]
+pub enum BotField {
+    Id,
+    Username,
+    Avatar,
+    Discriminator,
+    Prefix,
+    Flags,
+    Features,
+    Tags,
+    CreatedOn,
+    OwnerId,
+    CoOwnerIds,
+    GuildCount,
+    BriefDescription,
+    Votes,
+    AllTimeVotes,
+    Rating,
+    InviteUrl,
+    Highlights,
+}
+
+impl BotField {
+    /// The name of the field in a serialized hit.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Username => "username",
+            Self::Avatar => "avatar",
+            Self::Discriminator => "discriminator",
+            Self::Prefix => "prefix",
+            Self::Flags => "flags",
+            Self::Features => "features",
+            Self::Tags => "tags",
+            Self::CreatedOn => "createdOn",
+            Self::OwnerId => "ownerId",
+            Self::CoOwnerIds => "coOwnerIds",
+            Self::GuildCount => "guildCount",
+            Self::BriefDescription => "briefDescription",
+            Self::Votes => "votes",
+            Self::AllTimeVotes => "allTimeVotes",
+            Self::Rating => "rating",
+            Self::InviteUrl => "inviteUrl",
+            Self::Highlights => "highlights",
+        }
+    }
+}
+
+/// The fields of each bot hit to return, every field if none are given.
+///
+/// The `id` is always returned.
+#[derive(Debug, Default, Clone)]
+pub struct BotFields(Vec<BotField>);
+
+impl BotFields {
+    pub fn new(fields: Vec<BotField>) -> Self {
+        Self(fields)
+    }
+
+    #[inline]
+    pub fn is_all(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn contains(&self, field: BotField) -> bool {
+        self.is_all() || field == BotField::Id || self.0.contains(&field)
+    }
+
+    /// Responds with the search result, only keeping the requested fields
+    /// of each bot hit.
+    ///
+    /// If `nested` is given the bot hits are found under that key of each
+    /// hit rather than being the hits themselves.
+    pub fn respond<T: ToJSON>(
+        &self,
+        result: T,
+        nested: Option<&str>,
+    ) -> SearchResponse<T> {
+        if self.is_all() {
+            return SearchResponse::Json(result);
+        }
+
+        SearchResponse::Projected(self.project(&result, nested))
+    }
+
+    /// Converts the search result to JSON, only keeping the requested
+    /// fields of each bot hit.
+    pub fn project<T: ToJSON>(&self, result: &T, nested: Option<&str>) -> Value {
+        let mut value = result.to_json().unwrap_or_default();
+        if !self.is_all() {
+            self.project_hits(&mut value, nested);
+        }
+
+        value
+    }
+
+    fn project_hits(&self, result: &mut Value, nested: Option<&str>) {
+        let hits = match result.get_mut("hits").and_then(Value::as_array_mut) {
+            Some(hits) => hits,
+            None => return,
+        };
+
+        for hit in hits {
+            match nested {
+                Some(key) => {
+                    let bots = hit.get_mut(key).and_then(Value::as_array_mut);
+                    for bot in bots.into_iter().flatten() {
+                        self.retain(bot);
+                    }
+                },
+                None => self.retain(hit),
+            }
+        }
+    }
+
+    fn retain(&self, hit: &mut Value) {
+        if let Some(hit) = hit.as_object_mut() {
+            hit.retain(|key, _| {
+                key == BotField::Id.name()
+                    || self.0.iter().any(|field| field.name() == key)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_project_hits() {
+        let fields = BotFields::new(vec![BotField::Username, BotField::Votes]);
+
+        let mut bots = json!({
+            "hits": [{"id": "1", "username": "a", "avatar": null, "votes": "3"}],
+            "nbHits": 1,
+        });
+        fields.project_hits(&mut bots, None);
+        assert_eq!(
+            bots,
+            json!({"hits": [{"id": "1", "username": "a", "votes": "3"}], "nbHits": 1}),
+        );
+
+        let mut packs = json!({
+            "hits": [{"id": "2", "bots": [{"id": "1", "username": "a", "tags": []}]}],
+        });
+        fields.project_hits(&mut packs, Some("bots"));
+        assert_eq!(
+            packs,
+            json!({"hits": [{"id": "2", "bots": [{"id": "1", "username": "a"}]}]}),
+        );
+    }
+}
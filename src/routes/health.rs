@@ -0,0 +1,27 @@
+use poem::http::StatusCode;
+use poem::web::Json;
+use poem::{handler, IntoResponse, Response};
+use poem_openapi::types::ToJSON;
+
+use crate::models::connection;
+
+/// Health Check
+///
+/// Responds with `503` while the database is unreachable so the instance
+/// can be taken out of rotation, the body is the database health.
+#[handler]
+pub fn health() -> Response {
+    let database = connection::health();
+    let status = if database.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = serde_json::json!({
+        "healthy": database.healthy,
+        "database": database.to_json(),
+    });
+
+    Json(body).with_status(status).into_response()
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use poem::web::sse::{Event, SSE};
+use poem::web::websocket::{Message, WebSocket};
+use poem::{handler, IntoResponse};
+use poem_openapi::types::ParseFromJSON;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::notify;
+use crate::routes::bots::{search_bots, BotSearchPayload};
+use crate::search::readers;
+
+/// How often subscriptions check if the index has been committed to.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a comment is sent to keep idle event streams open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Index Events
+///
+/// Streams every change made to the indexes as server sent events, the
+/// event type is the kind of change and the data is the event as JSON.
+#[handler]
+pub fn events() -> SSE {
+    let stream = futures::stream::unfold(notify::subscribe(), |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let data = serde_json::to_string(&event).unwrap_or_default();
+                    let event = Event::message(data).event_type(event.name());
+                    return Some((event, events));
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Event stream fell behind, {} events were missed", missed);
+                },
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    SSE::new(stream).keep_alive(KEEP_ALIVE)
+}
+
+/// Live Bot Search
+///
+/// Each text message sent is a bot search payload which replaces the
+/// current subscription. The results are sent straight away and again
+/// every time a commit changes them.
+#[handler]
+pub fn subscribe(ws: WebSocket) -> impl IntoResponse {
+    ws.on_upgrade(|socket| async move {
+        let (mut sink, mut stream) = socket.split();
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+
+        let mut payload: Option<String> = None;
+        let mut last_generation = None;
+        let mut last_sent: Option<String> = None;
+
+        loop {
+            tokio::select! {
+                msg = stream.next() => match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        payload = Some(text);
+                        last_generation = None;
+                        last_sent = None;
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+                _ = ticker.tick() => {},
+            }
+
+            let payload = match payload.as_deref() {
+                Some(payload) => payload,
+                None => continue,
+            };
+
+            let generation = readers::bots::reader().generation();
+            if last_generation == Some(generation) {
+                continue;
+            }
+            last_generation = Some(generation);
+
+            let response = match run_search(payload).await {
+                Ok(response) => response,
+                Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+            };
+
+            if last_sent.as_deref() == Some(response.as_str()) {
+                continue;
+            }
+
+            if sink.send(Message::Text(response.clone())).await.is_err() {
+                break;
+            }
+            last_sent = Some(response);
+        }
+    })
+}
+
+async fn run_search(payload: &str) -> poem::Result<String> {
+    let value = serde_json::from_str(payload).map_err(anyhow::Error::from)?;
+    let payload = BotSearchPayload::parse_from_json(Some(value))
+        .map_err(|e| anyhow::anyhow!(e.into_message()))?;
+
+    let fields = payload.fields();
+    let result = search_bots(payload).await?;
+
+    Ok(fields.project(&result, None).to_string())
+}
@@ -0,0 +1,542 @@
+use std::time::Instant;
+
+use poem::http::StatusCode;
+use poem::web::Json;
+use poem::{handler, IntoResponse, Request, Response};
+use poem_openapi::types::{ParseFromJSON, ToJSON};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::models::analytics::{self, SearchRecord};
+use crate::routes::bots::{search_bots, BotSearchPayload};
+use crate::routes::client_ip;
+
+/// The facets which can be requested, `*` requests all of them.
+const FACETS: &[&str] = &["tags", "premium"];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeiliSearchPayload {
+    #[serde(default)]
+    q: Option<String>,
+
+    #[serde(default)]
+    filter: Option<MeiliFilter>,
+
+    #[serde(default)]
+    facets: Vec<String>,
+
+    #[serde(default)]
+    limit: Option<usize>,
+
+    #[serde(default)]
+    offset: usize,
+
+    #[serde(default)]
+    sort: Vec<String>,
+}
+
+/// A filter expression, or an array of expressions which must all match
+/// where nested arrays match if any of their expressions do.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MeiliFilter {
+    Expr(String),
+    Array(Vec<FilterItem>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FilterItem {
+    Expr(String),
+    AnyOf(Vec<String>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Condition {
+    Eq(String, String),
+    NotEq(String, String),
+    In(String, Vec<String>),
+}
+
+/// Meilisearch Bot Search
+///
+/// A minimal Meilisearch compatible search so code written against
+/// Meilisearch can search Cronos instead. Only `q`, `filter`, `facets`,
+/// `limit`, `offset` and `sort` are supported.
+///
+/// Filters support `=` and `!=` on `tags`, `premium`, `certified`,
+/// `owner_id` and `co_owner_id` joined by `AND`, along with `tags IN [...]`
+/// or nested arrays of tag filters to match any of the tags.
+#[handler]
+pub async fn search(req: &Request, payload: Json<MeiliSearchPayload>) -> Response {
+    let start = Instant::now();
+
+    let query = payload.q.clone().unwrap_or_default();
+    let (payload, facets) = match translate(payload.0) {
+        Ok(translated) => translated,
+        Err(e) => return e.into_response(),
+    };
+
+    let filters = payload
+        .get("filter")
+        .map(|filter| filter.to_string())
+        .unwrap_or_default();
+    let payload = match BotSearchPayload::parse_from_json(Some(payload)) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return MeiliError::new("invalid_search_payload", e.into_message())
+                .into_response()
+        },
+    };
+
+    let result = match search_bots(payload).await {
+        Ok(result) => result.to_json().unwrap_or_default(),
+        Err(e) => {
+            return MeiliError {
+                status: e.status(),
+                code: "internal",
+                message: e.to_string(),
+            }
+            .into_response()
+        },
+    };
+
+    let nb_hits = result["nbHits"].as_u64().unwrap_or_default();
+    analytics::record_search(SearchRecord {
+        entity: "bots",
+        query: result["query"].as_str().unwrap_or("*").to_string(),
+        filters,
+        nb_hits: nb_hits as usize,
+        latency: start.elapsed(),
+        ip_hash: analytics::hash_ip(&client_ip(req)),
+    });
+
+    let mut distribution = Map::new();
+    for facet in facets {
+        let counts = match facet {
+            "tags" => result["tagDistribution"].clone(),
+            "premium" => json!({
+                "true": result["premiumCount"],
+                "false": result["nonPremiumCount"],
+            }),
+            _ => continue,
+        };
+        distribution.insert(facet.to_string(), counts);
+    }
+
+    let mut resp = json!({
+        "hits": result["hits"],
+        "query": query,
+        "processingTimeMs": start.elapsed().as_millis() as u64,
+        "limit": result["limit"],
+        "offset": result["offset"],
+        "estimatedTotalHits": nb_hits,
+    });
+    if !distribution.is_empty() {
+        resp["facetDistribution"] = Value::Object(distribution);
+    }
+
+    Json(resp).into_response()
+}
+
+/// An error in the same shape as a Meilisearch error.
+#[derive(Debug)]
+struct MeiliError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl MeiliError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for MeiliError {
+    fn into_response(self) -> Response {
+        let kind = if self.status.is_client_error() {
+            "invalid_request"
+        } else {
+            "internal"
+        };
+
+        Json(json!({
+            "message": self.message,
+            "code": self.code,
+            "type": kind,
+        }))
+        .with_status(self.status)
+        .into_response()
+    }
+}
+
+/// Translates the search into the JSON of a bot search payload and the
+/// facets to return.
+fn translate(
+    payload: MeiliSearchPayload,
+) -> Result<(Value, Vec<&'static str>), MeiliError> {
+    let mut search = Map::new();
+
+    if let Some(q) = payload.q.filter(|q| !q.trim().is_empty()) {
+        search.insert("query".to_string(), q.into());
+    }
+    if let Some(limit) = payload.limit {
+        search.insert("limit".to_string(), limit.into());
+    }
+    search.insert("offset".to_string(), payload.offset.into());
+
+    if let Some(filter) = payload.filter {
+        let filter = translate_filter(filter)
+            .map_err(|e| MeiliError::new("invalid_search_filter", e))?;
+        search.insert("filter".to_string(), filter);
+    }
+
+    let mut sorts = payload.sort.iter().map(|rule| parse_sort(rule));
+    if let Some(sort) = sorts.next() {
+        let (by, order) = sort.map_err(|e| MeiliError::new("invalid_search_sort", e))?;
+        search.insert("sort".to_string(), by.into());
+        search.insert("order".to_string(), order.into());
+
+        let then_by = sorts
+            .map(|sort| sort.map(|(by, order)| json!({ "by": by, "order": order })))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| MeiliError::new("invalid_search_sort", e))?;
+        search.insert("thenBy".to_string(), then_by.into());
+    }
+
+    let mut facets = Vec::new();
+    for name in payload.facets {
+        if name == "*" {
+            facets = FACETS.to_vec();
+            break;
+        }
+
+        let facet = FACETS.iter().find(|facet| **facet == name).ok_or_else(|| {
+            MeiliError::new(
+                "invalid_search_facets",
+                format!(
+                    "Attribute `{}` is not facet-able, available facets are {:?}",
+                    name, FACETS
+                ),
+            )
+        })?;
+        if !facets.contains(facet) {
+            facets.push(*facet);
+        }
+    }
+
+    Ok((Value::Object(search), facets))
+}
+
+/// Parses a sort rule in the form of `attribute:asc` or `attribute:desc`.
+fn parse_sort(rule: &str) -> Result<(String, &'static str), String> {
+    let (by, order) = rule.rsplit_once(':').ok_or_else(|| {
+        format!("Invalid sort rule {:?}, expected `attribute:asc`", rule)
+    })?;
+
+    let order = match order {
+        "asc" => "asc",
+        "desc" => "desc",
+        _ => return Err(format!("Invalid sort order {:?} in {:?}", order, rule)),
+    };
+
+    Ok((by.trim().to_lowercase(), order))
+}
+
+/// Translates the filter into the JSON of a bot filter.
+fn translate_filter(filter: MeiliFilter) -> Result<Value, String> {
+    let mut conditions = Vec::new();
+    match filter {
+        MeiliFilter::Expr(expr) => conditions.extend(parse_expression(&expr)?),
+        MeiliFilter::Array(items) => {
+            for item in items {
+                match item {
+                    FilterItem::Expr(expr) => {
+                        conditions.extend(parse_expression(&expr)?)
+                    },
+                    FilterItem::AnyOf(exprs) => conditions.push(parse_any_of(&exprs)?),
+                }
+            }
+        },
+    }
+
+    let mut filter = Map::new();
+    let mut all_tags = Vec::new();
+    let mut any_tags = None;
+    let mut exclude_tags = Vec::new();
+
+    for condition in conditions {
+        match condition {
+            Condition::Eq(attr, value) if attr == "tags" => all_tags.push(value),
+            Condition::NotEq(attr, value) if attr == "tags" => exclude_tags.push(value),
+            Condition::In(attr, values) if attr == "tags" => {
+                if any_tags.replace(values).is_some() {
+                    return Err(
+                        "Only one set of tags to match any of is supported".into()
+                    );
+                }
+            },
+            Condition::Eq(attr, value) => {
+                let (key, value) = match attr.as_str() {
+                    "premium" => ("premium", parse_bool(&attr, &value)?),
+                    "certified" => ("certified", parse_bool(&attr, &value)?),
+                    "owner_id" => ("ownerId", Value::String(value)),
+                    "co_owner_id" => ("coOwnerId", Value::String(value)),
+                    _ => return Err(format!("Attribute `{}` is not filterable", attr)),
+                };
+                filter.insert(key.to_string(), value);
+            },
+            Condition::NotEq(attr, value) => {
+                let key = match attr.as_str() {
+                    "premium" => "premium",
+                    "certified" => "certified",
+                    _ => {
+                        return Err(format!(
+                            "Attribute `{}` does not support `!=`",
+                            attr
+                        ))
+                    },
+                };
+
+                let value = !parse_bool(&attr, &value)?.as_bool().unwrap_or_default();
+                filter.insert(key.to_string(), value.into());
+            },
+            Condition::In(attr, _) => {
+                return Err(format!("Attribute `{}` does not support `IN`", attr))
+            },
+        }
+    }
+
+    match any_tags {
+        Some(_) if !all_tags.is_empty() => {
+            return Err("Tags must all be matched or any matched, not both".into());
+        },
+        Some(tags) => {
+            filter.insert("tags".to_string(), tags.into());
+            filter.insert("filterMode".to_string(), "union".into());
+        },
+        None if !all_tags.is_empty() => {
+            filter.insert("tags".to_string(), all_tags.into());
+            filter.insert("filterMode".to_string(), "intersection".into());
+        },
+        None => {},
+    }
+
+    if !exclude_tags.is_empty() {
+        filter.insert("excludeTags".to_string(), exclude_tags.into());
+    }
+
+    Ok(Value::Object(filter))
+}
+
+/// Parses a nested array of tag expressions where any can match.
+fn parse_any_of(exprs: &[String]) -> Result<Condition, String> {
+    let mut tags = Vec::new();
+    for expr in exprs {
+        match parse_condition(expr)? {
+            Condition::Eq(attr, value) if attr == "tags" => tags.push(value),
+            Condition::In(attr, values) if attr == "tags" => tags.extend(values),
+            _ => {
+                return Err(format!(
+                    "Only tags can be matched by any of, got {:?}",
+                    expr
+                ))
+            },
+        }
+    }
+
+    Ok(Condition::In("tags".to_string(), tags))
+}
+
+/// Parses conditions joined by `AND`.
+fn parse_expression(expr: &str) -> Result<Vec<Condition>, String> {
+    let mut conditions = Vec::new();
+    let mut current = Vec::new();
+
+    for token in tokenize(expr)? {
+        match token.to_uppercase().as_str() {
+            "AND" => {
+                conditions.push(parse_condition(&current.join(" "))?);
+                current.clear();
+            },
+            "OR" | "NOT" => {
+                return Err(format!(
+                    "`{}` is not supported, use a nested array or `IN` instead",
+                    token
+                ))
+            },
+            _ => current.push(token),
+        }
+    }
+
+    if !current.is_empty() {
+        conditions.push(parse_condition(&current.join(" "))?);
+    }
+
+    Ok(conditions)
+}
+
+/// Splits the expression on whitespace outside of quotes and brackets.
+fn tokenize(expr: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quote = None;
+    let mut in_brackets = false;
+
+    for c in expr.chars() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('[', None) => in_brackets = true,
+            (']', None) => in_brackets = false,
+            ('(' | ')', None) => return Err("Parentheses are not supported".into()),
+            (c, None) if c.is_whitespace() && !in_brackets => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue;
+            },
+            _ => {},
+        }
+
+        token.push(c);
+    }
+
+    if quote.is_some() || in_brackets {
+        return Err(format!("Unterminated quote or bracket in {:?}", expr));
+    }
+
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a single `attribute = value`, `attribute != value` or
+/// `attribute IN [value, ...]` condition.
+fn parse_condition(expr: &str) -> Result<Condition, String> {
+    let tokens = tokenize(expr)?;
+    if let [attr, op, values] = tokens.as_slice() {
+        if op.eq_ignore_ascii_case("IN") {
+            let values = values
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .ok_or_else(|| format!("Expected a list of values in {:?}", expr))?;
+
+            let values = values
+                .split(',')
+                .map(unquote)
+                .filter(|v| !v.is_empty())
+                .collect();
+
+            return Ok(Condition::In(attr.to_lowercase(), values));
+        }
+    }
+
+    // Quoted values may contain operators so the first one outside of
+    // the attribute name is used.
+    let (attr, rest, negated) = match expr.find("!=") {
+        Some(i) if expr[..i].find('=').is_none() => (&expr[..i], &expr[i + 2..], true),
+        _ => match expr.find('=') {
+            Some(i) => (&expr[..i], &expr[i + 1..], false),
+            None => return Err(format!("Expected a condition, got {:?}", expr)),
+        },
+    };
+
+    let attr = attr.trim().to_lowercase();
+    let value = unquote(rest);
+    if attr.is_empty() || value.is_empty() {
+        return Err(format!("Expected a condition, got {:?}", expr));
+    }
+
+    if negated {
+        Ok(Condition::NotEq(attr, value))
+    } else {
+        Ok(Condition::Eq(attr, value))
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner.to_string();
+        }
+    }
+
+    value.to_string()
+}
+
+fn parse_bool(attr: &str, value: &str) -> Result<Value, String> {
+    match value {
+        "true" => Ok(true.into()),
+        "false" => Ok(false.into()),
+        _ => Err(format!("Attribute `{}` must be `true` or `false`", attr)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(value: Value) -> Result<Value, String> {
+        translate_filter(serde_json::from_value(value).unwrap())
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            parse_condition("tags = music"),
+            Ok(Condition::Eq("tags".into(), "music".into()))
+        );
+        assert_eq!(
+            parse_condition("tags != 'role play'"),
+            Ok(Condition::NotEq("tags".into(), "role play".into()))
+        );
+        assert_eq!(
+            parse_condition("tags IN [music, \"role play\"]"),
+            Ok(Condition::In(
+                "tags".into(),
+                vec!["music".into(), "role play".into()]
+            ))
+        );
+        assert!(parse_condition("tags").is_err());
+    }
+
+    #[test]
+    fn test_translate_filter() {
+        assert_eq!(
+            filter(json!("tags = music AND premium = true AND tags != nsfw")),
+            Ok(json!({
+                "premium": true,
+                "tags": ["music"],
+                "filterMode": "intersection",
+                "excludeTags": ["nsfw"],
+            }))
+        );
+        assert_eq!(
+            filter(json!([
+                ["tags = music", "tags = games"],
+                "certified != true"
+            ])),
+            Ok(json!({
+                "certified": false,
+                "tags": ["music", "games"],
+                "filterMode": "union",
+            }))
+        );
+        assert!(filter(json!("tags = music OR tags = games")).is_err());
+        assert!(filter(json!("tags = music AND tags IN [games]")).is_err());
+        assert!(filter(json!("username = foo")).is_err());
+    }
+}
@@ -1,7 +1,136 @@
-use poem_openapi::ApiResponse;
+use std::collections::HashMap;
 
+use once_cell::sync::OnceCell;
+use poem::http::StatusCode;
+use poem::Request;
+use poem_openapi::auth::Bearer;
+use poem_openapi::{ApiResponse, Object, SecurityScheme};
+
+use crate::search::es_query::InvalidQuery;
+use crate::search::WriterSaturated;
+
+pub mod admin;
 pub mod bots;
+pub mod csv;
+pub mod fields;
+pub mod health;
+pub mod live;
+pub mod meili;
 pub mod packs;
+pub mod v1;
+pub mod webhooks;
+
+/// The largest `offset` a search payload accepts.
+///
+/// This must match the `maximum` validators of the search payloads.
+pub const MAX_OFFSET: usize = 40000;
+
+/// How far into the results a search can be paged.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pagination {
+    pub has_more: bool,
+    pub total_pages: usize,
+    pub max_window: usize,
+}
+
+impl Pagination {
+    pub fn new(nb_hits: usize, limit: usize, offset: usize) -> Self {
+        let limit = limit.max(1);
+        let max_window = nb_hits.min(MAX_OFFSET + limit);
+
+        Self {
+            has_more: offset + limit < max_window,
+            total_pages: max_window.div_ceil(limit),
+            max_window,
+        }
+    }
+}
+
+static ADMIN_TOKEN: OnceCell<String> = OnceCell::new();
+
+/// Sets the token required by admin routes which use [AdminAuth].
+pub fn set_admin_token(token: String) {
+    let _ = ADMIN_TOKEN.set(token);
+}
+
+/// The configured admin token, sent along when calling the admin routes
+/// of other instances.
+pub fn admin_token() -> Option<&'static str> {
+    ADMIN_TOKEN
+        .get()
+        .map(String::as_str)
+        .filter(|token| !token.is_empty())
+}
+
+/// A bearer token matching the configured admin token.
+///
+/// Requests are always rejected if no admin token is configured.
+#[derive(SecurityScheme)]
+#[oai(type = "bearer")]
+pub struct AdminAuth(Bearer);
+
+impl AdminAuth {
+    pub fn verify(&self) -> poem::Result<()> {
+        match ADMIN_TOKEN.get() {
+            Some(token) if !token.is_empty() && *token == self.0.token => Ok(()),
+            _ => Err(poem::Error::from_status(StatusCode::UNAUTHORIZED)),
+        }
+    }
+}
+
+/// The IP of the client which sent the request.
+///
+/// This prefers the IP forwarded by Cloudflare in the same way as the ratelimiter.
+pub fn client_ip(req: &Request) -> String {
+    if let Some(ip) = req.header("CF-Connecting-IP") {
+        return ip.to_string();
+    }
+
+    req.remote_addr()
+        .as_socket_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| req.remote_addr().to_string())
+}
+
+/// Converts an error from an index write, so a saturated writer is
+/// reported as `503` rather than an internal error.
+pub fn writer_error(e: anyhow::Error) -> poem::Error {
+    if e.is::<WriterSaturated>() {
+        poem::Error::from_string(e.to_string(), StatusCode::SERVICE_UNAVAILABLE)
+    } else {
+        e.into()
+    }
+}
+
+/// Converts an error from a search, so an invalid structured query is
+/// reported as `400` rather than an internal error.
+pub fn search_error(e: anyhow::Error) -> poem::Error {
+    if e.is::<InvalidQuery>() {
+        poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST)
+    } else {
+        e.into()
+    }
+}
+
+#[derive(Debug, Object)]
+pub struct TagCount {
+    /// The name of the tag.
+    name: String,
+
+    /// The number of listed entities with the tag.
+    count: usize,
+}
+
+/// Lists the tags from the most to least used, ties are ordered by name.
+pub fn sorted_tags(counts: HashMap<String, usize>) -> Vec<TagCount> {
+    let mut tags = counts
+        .into_iter()
+        .map(|(name, count)| TagCount { name, count })
+        .collect::<Vec<_>>();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    tags
+}
 
 #[derive(Debug, ApiResponse)]
 pub enum StandardResponse {
@@ -13,3 +142,51 @@ pub enum StandardResponse {
     #[oai(status = 400)]
     BadRequest,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        assert_eq!(
+            Pagination::new(0, 20, 0),
+            Pagination {
+                has_more: false,
+                total_pages: 0,
+                max_window: 0,
+            }
+        );
+        assert_eq!(
+            Pagination::new(45, 20, 20),
+            Pagination {
+                has_more: true,
+                total_pages: 3,
+                max_window: 45,
+            }
+        );
+        assert_eq!(
+            Pagination::new(100_000, 50, 40000),
+            Pagination {
+                has_more: false,
+                total_pages: 801,
+                max_window: 40050,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sorted_tags() {
+        let counts = HashMap::from([
+            ("music".to_string(), 2),
+            ("fun".to_string(), 5),
+            ("games".to_string(), 2),
+        ]);
+
+        let names = sorted_tags(counts)
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["fun", "games", "music"]);
+    }
+}
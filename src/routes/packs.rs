@@ -1,24 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 use backend_common::types::{JsSafeBigInt, Timestamp};
-use poem::Result;
-use poem_openapi::param::Path;
+use poem::{Body, Request, Result};
+use poem_openapi::param::{Path, Query};
 use poem_openapi::payload::Json;
+use poem_openapi::types::{ParseFromJSON, ToJSON};
 use poem_openapi::{Object, OpenApi};
 use tantivy::schema::Field;
 use tantivy::Document;
 
-use crate::models::bots::get_bot_data;
+use crate::models::analytics::{self, SearchRecord};
+use crate::models::bots::get_many_bot_data;
 use crate::models::packs::{get_pack_data, get_pack_likes};
+use crate::models::RefreshSummary;
 use crate::routes::bots::BotHit;
-use crate::routes::StandardResponse;
-use crate::search::readers::packs::{PackFilter, PacksSortBy};
-use crate::search::readers::Order;
+use crate::routes::csv::{self, SearchFormat, SearchResponse};
+use crate::routes::fields::{BotField, BotFields};
+use crate::routes::{
+    client_ip,
+    search_error,
+    sorted_tags,
+    v1,
+    writer_error,
+    Pagination,
+    StandardResponse,
+    TagCount,
+};
+use crate::search::es_query::EsQuery;
+use crate::search::readers::packs::{PackFilter, PacksSortBy, PacksSortCriterion};
+use crate::search::readers::{CountMode, Highlighter, Order};
 use crate::search::{index_impls, readers, FromTantivyDoc};
 
 #[derive(Debug, Object)]
-#[oai(rename_all = "camelCase")]
-pub struct PackHit {
+#[oai(
+    rename_all = "camelCase",
+    concretes(name = "PackHit", params(BotHit)),
+    concretes(name = "PackHitV1", params(v1::BotHit))
+)]
+pub struct PackHit<B: ParseFromJSON + ToJSON> {
     /// The ID of the pack.
     pub id: JsSafeBigInt,
 
@@ -36,26 +56,64 @@ pub struct PackHit {
     pub tag: String,
 
     /// The bots that this pack contains.
-    pub bots: Vec<BotHit>,
+    ///
+    /// This is empty if only the bot IDs were requested.
+    pub bots: Vec<B>,
+
+    /// The IDs of the bots that this pack contains.
+    pub bot_ids: Vec<JsSafeBigInt>,
 
     /// The primary owner of this pack.
     pub owner_id: JsSafeBigInt,
 
     /// The number of likes the pack has.
     pub likes: JsSafeBigInt,
+
+    /// If the pack is being promoted.
+    pub premium: bool,
+
+    /// The matched fragments of the `name` and `description` fields.
+    ///
+    /// Matches are wrapped in `<b>` tags, fields without a match are omitted.
+    pub highlights: HashMap<String, String>,
+}
+
+impl<B: ParseFromJSON + ToJSON> PackHit<B> {
+    /// Converts the bots of the hit into the bot hits of another version.
+    pub fn map_bots<U: ParseFromJSON + ToJSON>(
+        self,
+        f: impl FnMut(B) -> U,
+    ) -> PackHit<U> {
+        PackHit {
+            id: self.id,
+            name: self.name,
+            description: self.description,
+            created_on: self.created_on,
+            tag: self.tag,
+            bots: self.bots.into_iter().map(f).collect(),
+            bot_ids: self.bot_ids,
+            owner_id: self.owner_id,
+            likes: self.likes,
+            premium: self.premium,
+            highlights: self.highlights,
+        }
+    }
 }
 
-impl FromTantivyDoc for PackHit {
+impl FromTantivyDoc for PackHit<BotHit> {
     fn from_doc(id_field: Field, doc: Document) -> Option<Self> {
         let id = doc.get_first(id_field)?.as_i64()?;
         let likes = get_pack_likes(id);
         let pack = get_pack_data(id)?;
-        let bots = pack
-            .bots
-            .iter()
-            .filter_map(|v| get_bot_data(v.0))
+        if pack.is_hidden_from_search() {
+            return None;
+        }
+
+        // The bots themselves are hydrated for the whole page at once.
+        let bot_ids = get_many_bot_data(pack.bots.iter().map(|v| v.0))
+            .into_iter()
             .filter(|b| b.is_packable)
-            .map(BotHit::from)
+            .map(|b| b.id)
             .collect();
 
         Some(Self {
@@ -65,13 +123,26 @@ impl FromTantivyDoc for PackHit {
             owner_id: pack.owner_id,
             description: pack.description,
             tag: pack.tag,
-            bots,
+            bots: vec![],
+            bot_ids,
             likes: JsSafeBigInt::from(likes as i64),
+            premium: pack.is_premium(),
+            highlights: HashMap::new(),
         })
     }
+
+    fn highlight(&mut self, highlighter: &Highlighter) {
+        let fields = [("name", &self.name), ("description", &self.description)];
+
+        for (name, text) in fields {
+            if let Some(fragment) = highlighter.fragment(name, text) {
+                self.highlights.insert(name.to_string(), fragment);
+            }
+        }
+    }
 }
 
-#[derive(Debug, Object)]
+#[derive(Debug, Clone, Object)]
 #[oai(rename_all = "camelCase")]
 pub struct PackSearchPayload {
     /// The query to be searched.
@@ -94,6 +165,13 @@ pub struct PackSearchPayload {
     #[oai(default)]
     filter: PackFilter,
 
+    /// A structured query results must also match, using a subset of the
+    /// Elasticsearch query DSL.
+    ///
+    /// Supports `bool`, `term`, `match` and `range` queries on the indexed
+    /// fields by their name in the index.
+    es_query: Option<EsQuery>,
+
     /// How to sort results.
     #[oai(default)]
     sort: PacksSortBy,
@@ -101,13 +179,53 @@ pub struct PackSearchPayload {
     /// Order results Asc or Desc.
     #[oai(default)]
     order: Order,
+
+    /// Further sorts applied in order to break ties of the primary sort.
+    ///
+    /// E.g. premium desc, then votes desc, then relevancy.
+    #[oai(validator(max_items = 3), default)]
+    then_by: Vec<PacksSortCriterion>,
+
+    /// Only return the IDs of the bots in each pack, leaving `bots` empty.
+    #[oai(default)]
+    bot_ids_only: bool,
+
+    /// Only match results containing the exact query terms.
+    ///
+    /// This bypasses any typo tolerance.
+    #[oai(default)]
+    exact: bool,
+
+    /// If the hit and facet counts should be exact or estimated.
+    ///
+    /// Estimated counts are faster for broad queries matching most documents.
+    #[oai(default)]
+    count_mode: CountMode,
+
+    /// Only return these fields of each bot in a pack, the `id` is always
+    /// returned.
+    ///
+    /// Every field is returned if empty.
+    #[oai(validator(max_items = 18), default)]
+    bot_fields: Vec<BotField>,
+}
+
+impl PackSearchPayload {
+    /// The fields of each bot in a pack to return.
+    pub fn bot_fields(&self) -> BotFields {
+        BotFields::new(self.bot_fields.clone())
+    }
 }
 
 #[derive(Debug, Object)]
-#[oai(rename_all = "camelCase")]
-pub struct PackSearchResult {
+#[oai(
+    rename_all = "camelCase",
+    concretes(name = "PackSearchResult", params(PackHit<BotHit>)),
+    concretes(name = "PackSearchResultV1", params(PackHit<v1::BotHit>))
+)]
+pub struct PackSearchResult<T: ParseFromJSON + ToJSON> {
     /// The search results themselves.
-    hits: Vec<PackHit>,
+    hits: Vec<T>,
 
     /// The maximum amount of docs that could get returned.
     limit: usize,
@@ -120,11 +238,46 @@ pub struct PackSearchResult {
 
     /// The total number of documents that matched the query.
     ///
-    /// This is a best-guess estimate.
+    /// This is an estimate if the `estimate` count mode was used.
     nb_hits: usize,
 
+    /// If there are more results after this page.
+    has_more: bool,
+
+    /// The number of pages of `limit` results which can be reached.
+    total_pages: usize,
+
+    /// The number of results which can be paged through.
+    ///
+    /// This is `nbHits` capped by the maximum offset a search can use.
+    max_window: usize,
+
     /// The distribution of tags/categories across the results.
     tag_distribution: HashMap<String, usize>,
+
+    /// A corrected version of the query if it produced very few hits.
+    suggestion: Option<String>,
+}
+
+impl<T: ParseFromJSON + ToJSON> PackSearchResult<T> {
+    /// Converts the hits of the result into the hits of another version.
+    pub fn map_hits<U: ParseFromJSON + ToJSON>(
+        self,
+        f: impl FnMut(T) -> U,
+    ) -> PackSearchResult<U> {
+        PackSearchResult {
+            hits: self.hits.into_iter().map(f).collect(),
+            limit: self.limit,
+            offset: self.offset,
+            query: self.query,
+            nb_hits: self.nb_hits,
+            has_more: self.has_more,
+            total_pages: self.total_pages,
+            max_window: self.max_window,
+            tag_distribution: self.tag_distribution,
+            suggestion: self.suggestion,
+        }
+    }
 }
 
 pub struct PackApi;
@@ -142,12 +295,23 @@ impl PackApi {
         Json(ids)
     }
 
+    /// List Pack Tags
+    ///
+    /// Every tag used by a listed pack along with how many packs use it.
+    #[oai(path = "/packs/tags", method = "get", tag = "crate::ApiTags::Packs")]
+    pub async fn get_pack_tags(&self) -> Json<Vec<TagCount>> {
+        Json(sorted_tags(crate::models::packs::tag_counts()))
+    }
+
     /// Update Pack Data
     ///
     /// This internally pulls data from the database.
     #[oai(path = "/packs/:id", method = "post", tag = "crate::ApiTags::Packs")]
     pub async fn update_pack(&self, id: Path<u64>) -> Result<StandardResponse> {
-        index_impls::packs::writer().upsert_pack(*id as i64).await?;
+        index_impls::packs::writer()
+            .upsert_pack(*id as i64)
+            .await
+            .map_err(writer_error)?;
 
         Ok(StandardResponse::Ok)
     }
@@ -155,7 +319,10 @@ impl PackApi {
     /// Remove Pack Data
     #[oai(path = "/packs/:id", method = "delete", tag = "crate::ApiTags::Packs")]
     pub async fn remove_pack(&self, id: Path<u64>) -> Result<StandardResponse> {
-        index_impls::packs::writer().remove_pack(*id as i64).await?;
+        index_impls::packs::writer()
+            .remove_pack(*id as i64)
+            .await
+            .map_err(writer_error)?;
 
         Ok(StandardResponse::Ok)
     }
@@ -166,42 +333,162 @@ impl PackApi {
         method = "post",
         tag = "crate::ApiTags::Packs"
     )]
-    pub async fn refresh_packs(&self) -> Result<StandardResponse> {
-        index_impls::packs::writer().full_refresh().await?;
+    pub async fn refresh_packs(&self) -> Result<Json<RefreshSummary>> {
+        let summary = index_impls::packs::writer().full_refresh().await?;
 
-        Ok(StandardResponse::Ok)
+        Ok(Json(summary))
     }
+}
 
+/// The pack routes whose responses contain bot hits, these are versioned
+/// as the shape of a hit differs between versions.
+pub struct PackSearchApi;
+
+#[OpenApi]
+impl PackSearchApi {
     /// Search Packs
+    ///
+    /// If `format` is `csv`, or CSV is accepted, every matching pack from
+    /// `offset` onwards is exported as CSV instead.
     #[oai(path = "/packs/search", method = "post", tag = "crate::ApiTags::Packs")]
     pub async fn search(
         &self,
+        req: &Request,
         payload: Json<PackSearchPayload>,
-    ) -> Result<Json<PackSearchResult>> {
-        let limit = payload.0.limit.unwrap_or(20);
-        let offset = payload.0.offset;
-        let query = payload.0.query.clone();
-
-        let (num_hits, dist, hits) = readers::packs::reader()
-            .search::<PackHit>(
-                payload.0.query,
-                payload.0.filter,
-                limit,
-                offset,
-                payload.0.sort,
-                payload.0.order,
-            )
-            .await?;
-
-        let result = PackSearchResult {
-            hits,
+        format: Query<Option<SearchFormat>>,
+    ) -> Result<SearchResponse<PackSearchResult<PackHit<BotHit>>>> {
+        if csv::wants_csv(req, format.0) {
+            return Ok(SearchResponse::Csv(export_packs(payload.0)));
+        }
+
+        let fields = payload.bot_fields();
+        let result = record_search_packs(req, payload.0).await?;
+
+        Ok(fields.respond(result, Some("bots")))
+    }
+}
+
+/// Runs the search and records it in the search analytics.
+pub async fn record_search_packs(
+    req: &Request,
+    payload: PackSearchPayload,
+) -> Result<PackSearchResult<PackHit<BotHit>>> {
+    let limit = payload.limit.unwrap_or(20);
+    let offset = payload.offset;
+    let query = payload.query.clone();
+    let filters = payload.filter.to_json_string();
+    let bot_ids_only = payload.bot_ids_only;
+    let bot_fields = payload.bot_fields();
+
+    let start = Instant::now();
+
+    let result = readers::packs::reader()
+        .search::<PackHit<BotHit>>(
+            payload.query,
+            payload.filter,
+            payload.es_query,
             limit,
             offset,
-            query: query.unwrap_or_else(|| "*".to_string()),
-            nb_hits: num_hits,
-            tag_distribution: dist,
-        };
+            payload.sort,
+            payload.order,
+            payload.then_by,
+            payload.exact,
+            payload.count_mode,
+        )
+        .await
+        .map_err(search_error)?;
+
+    analytics::record_search(SearchRecord {
+        entity: "packs",
+        query: query.clone().unwrap_or_else(|| "*".to_string()),
+        filters,
+        nb_hits: result.num_hits,
+        latency: start.elapsed(),
+        ip_hash: analytics::hash_ip(&client_ip(req)),
+    });
+
+    let mut hits = result.hits;
+    if !bot_ids_only {
+        hydrate_bots(&mut hits, &bot_fields);
+    }
+
+    let pagination = Pagination::new(result.num_hits, limit, offset);
+    let result = PackSearchResult {
+        hits,
+        limit,
+        offset,
+        query: query.unwrap_or_else(|| "*".to_string()),
+        nb_hits: result.num_hits,
+        has_more: pagination.has_more,
+        total_pages: pagination.total_pages,
+        max_window: pagination.max_window,
+        tag_distribution: result.distribution,
+        suggestion: result.suggestion,
+    };
+
+    Ok(result)
+}
 
-        Ok(Json(result))
+/// Fills in the bots of every hit from a single load of the live bot data,
+/// converting each bot once however many of the packs contain it.
+fn hydrate_bots(hits: &mut [PackHit<BotHit>], fields: &BotFields) {
+    let ids = hits
+        .iter()
+        .flat_map(|hit| hit.bot_ids.iter().map(|id| **id))
+        .collect::<HashSet<_>>();
+
+    let bots = get_many_bot_data(ids)
+        .into_iter()
+        .map(|bot| (*bot.id, BotHit::with_fields(&bot, fields)))
+        .collect::<HashMap<_, _>>();
+
+    for hit in hits {
+        hit.bots = hit
+            .bot_ids
+            .iter()
+            .filter_map(|id| bots.get(&**id).cloned())
+            .collect();
     }
 }
+
+/// Streams every pack matching the search as CSV.
+pub fn export_packs(payload: PackSearchPayload) -> Body {
+    let columns = &["id", "name", "category", "likes", "bots"];
+
+    csv::export(columns, payload.offset, move |offset, limit| {
+        let payload = payload.clone();
+
+        async move {
+            let result = readers::packs::reader()
+                .search::<PackHit<BotHit>>(
+                    payload.query,
+                    payload.filter,
+                    payload.es_query,
+                    limit,
+                    offset,
+                    payload.sort,
+                    payload.order,
+                    payload.then_by,
+                    payload.exact,
+                    payload.count_mode,
+                )
+                .await?;
+
+            let rows = result
+                .hits
+                .into_iter()
+                .map(|hit| {
+                    vec![
+                        (*hit.id).to_string(),
+                        hit.name,
+                        hit.tag,
+                        (*hit.likes).to_string(),
+                        hit.bot_ids.len().to_string(),
+                    ]
+                })
+                .collect();
+
+            Ok(rows)
+        }
+    })
+}
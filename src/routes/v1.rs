@@ -0,0 +1,189 @@
+//! The routes which changed shape in `/v1`, every other route is shared
+//! with `/v0` as is.
+//!
+//! Both versions are served from the same readers, the `/v1` hits are
+//! converted from the `/v0` hits.
+
+use std::collections::HashMap;
+
+use backend_common::types::{JsSafeBigInt, JsSafeInt, Set, Timestamp};
+use poem::{Request, Result};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+
+use crate::routes::bots::{self, BotSearchPayload, BotSearchResult};
+use crate::routes::csv::{self, SearchFormat, SearchResponse};
+use crate::routes::packs::{self, PackHit, PackSearchPayload, PackSearchResult};
+
+/// A bot in the `/v1` shape, which drops `flags` as it only ever
+/// duplicated `features`.
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct BotHit {
+    /// The snowflake ID of the bot.
+    pub id: JsSafeBigInt,
+
+    /// The bot's username.
+    pub username: String,
+
+    /// The bot's avatar hash if applicable.
+    pub avatar: Option<String>,
+
+    /// The bot's discriminator i.e `0001`
+    pub discriminator: JsSafeInt,
+
+    /// The bot's given prefix.
+    pub prefix: Option<String>,
+
+    /// The bot's given list of features.
+    ///
+    /// This is stored in the form of a bitflag(s).
+    pub features: JsSafeBigInt,
+
+    /// The bot's associated tags.
+    pub tags: Vec<String>,
+
+    /// The timestamp that the bot was first created on.
+    pub created_on: Timestamp,
+
+    /// The bot's primary owner.
+    pub owner_id: JsSafeBigInt,
+
+    /// The bot's secondary/co-owners
+    pub co_owner_ids: Set<JsSafeBigInt>,
+
+    /// The amount of guilds the bot is in.
+    pub guild_count: Option<JsSafeInt>,
+
+    /// The short description of the bot.
+    pub brief_description: String,
+
+    /// The number of votes the bot currently has this month.
+    pub votes: JsSafeBigInt,
+
+    /// The number of votes the bot has had across every month.
+    pub all_time_votes: JsSafeBigInt,
+
+    /// The average number of stars the bot has been rated, `0` if unrated.
+    pub rating: f64,
+
+    /// The invite url of the bot.
+    pub invite_url: String,
+
+    /// The matched fragments of the `username` and `description` fields.
+    ///
+    /// Matches are wrapped in `<b>` tags, fields without a match are omitted.
+    pub highlights: HashMap<String, String>,
+}
+
+impl From<bots::BotHit> for BotHit {
+    fn from(hit: bots::BotHit) -> Self {
+        Self {
+            id: hit.id,
+            username: hit.username,
+            avatar: hit.avatar,
+            discriminator: hit.discriminator,
+            prefix: hit.prefix,
+            features: hit.features,
+            tags: hit.tags,
+            created_on: hit.created_on,
+            owner_id: hit.owner_id,
+            co_owner_ids: hit.co_owner_ids,
+            guild_count: hit.guild_count,
+            brief_description: hit.brief_description,
+            votes: hit.votes,
+            all_time_votes: hit.all_time_votes,
+            rating: hit.rating,
+            invite_url: hit.invite_url,
+            highlights: hit.highlights,
+        }
+    }
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum SimilarBotsResponse {
+    /// The bots most similar to the given bot.
+    #[oai(status = 200)]
+    Ok(Json<Vec<BotHit>>),
+
+    /// The bot does not exist.
+    #[oai(status = 404)]
+    NotFound,
+}
+
+pub struct BotSearchApi;
+
+#[OpenApi]
+impl BotSearchApi {
+    /// Similar Bots
+    ///
+    /// Finds up to `limit` other bots sharing the most tags and description
+    /// terms with the given bot, defaults to 10 bots.
+    #[oai(
+        path = "/bots/:id/similar",
+        method = "get",
+        tag = "crate::ApiTags::Bots"
+    )]
+    pub async fn similar_bots(
+        &self,
+        id: Path<u64>,
+        limit: Query<Option<usize>>,
+    ) -> Result<SimilarBotsResponse> {
+        match bots::similar_bots(*id as i64, limit.0).await? {
+            Some(hits) => {
+                let hits = hits.into_iter().map(BotHit::from).collect();
+                Ok(SimilarBotsResponse::Ok(Json(hits)))
+            },
+            None => Ok(SimilarBotsResponse::NotFound),
+        }
+    }
+
+    /// Search Bots
+    ///
+    /// If `format` is `csv`, or CSV is accepted, every matching bot from
+    /// `offset` onwards is exported as CSV instead.
+    #[oai(path = "/bots/search", method = "post", tag = "crate::ApiTags::Bots")]
+    pub async fn search(
+        &self,
+        req: &Request,
+        payload: Json<BotSearchPayload>,
+        format: Query<Option<SearchFormat>>,
+    ) -> Result<SearchResponse<BotSearchResult<BotHit>>> {
+        if csv::wants_csv(req, format.0) {
+            return Ok(SearchResponse::Csv(bots::export_bots(payload.0)));
+        }
+
+        let fields = payload.fields();
+        let result = bots::record_search_bots(req, payload.0).await?;
+
+        Ok(fields.respond(result.map_hits(BotHit::from), None))
+    }
+}
+
+pub struct PackSearchApi;
+
+#[OpenApi]
+impl PackSearchApi {
+    /// Search Packs
+    ///
+    /// If `format` is `csv`, or CSV is accepted, every matching pack from
+    /// `offset` onwards is exported as CSV instead.
+    #[oai(path = "/packs/search", method = "post", tag = "crate::ApiTags::Packs")]
+    pub async fn search(
+        &self,
+        req: &Request,
+        payload: Json<PackSearchPayload>,
+        format: Query<Option<SearchFormat>>,
+    ) -> Result<SearchResponse<PackSearchResult<PackHit<BotHit>>>> {
+        if csv::wants_csv(req, format.0) {
+            return Ok(SearchResponse::Csv(packs::export_packs(payload.0)));
+        }
+
+        let fields = payload.bot_fields();
+        let result = packs::record_search_packs(req, payload.0).await?;
+        let result = result.map_hits(|hit| hit.map_bots(BotHit::from));
+
+        Ok(fields.respond(result, Some("bots")))
+    }
+}
@@ -0,0 +1,121 @@
+use backend_common::types::JsSafeBigInt;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use poem::http::StatusCode;
+use poem::Result;
+use poem_openapi::param::Header;
+use poem_openapi::payload::Binary;
+use poem_openapi::types::ParseFromJSON;
+use poem_openapi::{Object, OpenApi};
+use sha2::Sha256;
+
+use crate::models;
+use crate::routes::StandardResponse;
+use crate::search::{index_impls, replication};
+
+/// How far the timestamp of a vote event can be from now in seconds,
+/// older events are rejected so a captured request can't be replayed.
+const MAX_TIMESTAMP_SKEW_SECS: i64 = 5 * 60;
+
+static VOTE_SECRET: OnceCell<String> = OnceCell::new();
+
+/// Sets the secret vote events are signed with.
+pub fn set_vote_secret(secret: String) {
+    let _ = VOTE_SECRET.set(secret);
+}
+
+#[derive(Debug, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct VoteEvent {
+    /// The bot which was voted for.
+    bot_id: JsSafeBigInt,
+
+    /// The bot's total number of votes after the vote.
+    votes: u64,
+}
+
+pub struct WebhookApi;
+
+#[OpenApi]
+impl WebhookApi {
+    /// Vote Event
+    ///
+    /// Updates the votes of a bot as soon as it's voted for, rather than
+    /// waiting for the next time votes are reloaded.
+    ///
+    /// The `X-Timestamp` header must be the unix timestamp in seconds the
+    /// event was sent at, events more than 5 minutes away from now are rejected.
+    ///
+    /// The `X-Signature` header must be `sha256=` followed by the hex encoded
+    /// HMAC-SHA256 of the timestamp, a `.` and then the body using the vote
+    /// webhook secret.
+    #[oai(
+        path = "/webhooks/votes",
+        method = "post",
+        tag = "crate::ApiTags::Webhooks"
+    )]
+    pub async fn vote_event(
+        &self,
+        #[oai(name = "X-Signature")] signature: Header<String>,
+        #[oai(name = "X-Timestamp")] timestamp: Header<i64>,
+        body: Binary<Vec<u8>>,
+    ) -> Result<StandardResponse> {
+        if !verify_signature(&signature.0, timestamp.0, &body.0) {
+            return Err(poem::Error::from_status(StatusCode::UNAUTHORIZED));
+        }
+
+        if (Utc::now().timestamp() - timestamp.0).abs() > MAX_TIMESTAMP_SKEW_SECS {
+            return Err(poem::Error::from_string(
+                "The event timestamp is too far from the current time.",
+                StatusCode::UNAUTHORIZED,
+            ));
+        }
+
+        let event = serde_json::from_slice(&body.0)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| {
+                VoteEvent::parse_from_json(Some(value))
+                    .map_err(|e| anyhow::anyhow!(e.into_message()))
+            })
+            .map_err(|e| {
+                poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST)
+            })?;
+
+        let bot_id = *event.bot_id;
+        let changed = models::bots::set_bot_votes(bot_id, event.votes);
+
+        // Replicas pick up the new votes when they next sync from the primary.
+        if changed && !replication::is_replica() {
+            index_impls::bots::writer().reindex_live(bot_id).await?;
+        }
+
+        Ok(StandardResponse::Ok)
+    }
+}
+
+fn verify_signature(signature: &str, timestamp: i64, body: &[u8]) -> bool {
+    let secret = match VOTE_SECRET.get() {
+        Some(secret) if !secret.is_empty() => secret,
+        _ => return false,
+    };
+
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|sig| hex::decode(sig).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    // This compares in constant time.
+    mac.verify_slice(&signature).is_ok()
+}
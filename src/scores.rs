@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use clap::ValueEnum;
+
+use crate::{models, search};
+
+/// The gravity applied to an entity's age when calculating internal scores.
+///
+/// Higher values make older entities fall off the trending list faster.
+const INTERNAL_GRAVITY: f64 = 1.8;
+
+#[derive(ValueEnum, Debug, Copy, Clone)]
+pub enum ScoresSource {
+    /// Periodically fetch scores from the a7s server.
+    A7s,
+
+    /// Scores are pushed to the admin trending endpoints with the admin token.
+    Push,
+
+    /// Scores are calculated internally from vote data.
+    Internal,
+}
+
+impl ScoresSource {
+    pub fn into_provider(
+        self,
+        a7s_uri: String,
+        a7s_auth: String,
+    ) -> Arc<dyn ScoresProvider> {
+        match self {
+            Self::A7s => Arc::new(A7sProvider::new(a7s_uri, a7s_auth)),
+            Self::Push => Arc::new(PushProvider),
+            Self::Internal => Arc::new(InternalProvider),
+        }
+    }
+}
+
+/// A source of trending scores for bots and packs.
+#[async_trait]
+pub trait ScoresProvider: Send + Sync + 'static {
+    /// Produces the latest bot trending scores.
+    ///
+    /// Returning `None` leaves the existing scores untouched.
+    async fn bot_scores(&self) -> Result<Option<HashMap<i64, f64>>>;
+
+    /// Produces the latest pack trending scores.
+    ///
+    /// Returning `None` leaves the existing scores untouched.
+    async fn pack_scores(&self) -> Result<Option<HashMap<i64, f64>>>;
+}
+
+/// Fetches and applies the latest scores from the given provider.
+pub async fn refresh_scores(provider: &dyn ScoresProvider) {
+    match provider.pack_scores().await {
+        Ok(Some(data)) => models::packs::set_pack_trending_data(data),
+        Ok(None) => {},
+        Err(e) => error!("Failed to update pack trending data due to error: {}", e),
+    }
+
+    match provider.bot_scores().await {
+        Ok(Some(data)) => {
+            let changed = models::bots::set_bot_trending_data(data);
+            search::index_impls::bots::queue_reindex(changed);
+        },
+        Ok(None) => {},
+        Err(e) => error!("Failed to update bot trending data due to error: {}", e),
+    }
+}
+
+pub struct A7sProvider {
+    client: reqwest::Client,
+    uri: String,
+    auth: String,
+}
+
+impl A7sProvider {
+    pub fn new(uri: String, auth: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            uri,
+            auth,
+        }
+    }
+
+    async fn fetch(&self, entity: &str) -> Result<HashMap<i64, f64>> {
+        let data = self
+            .client
+            .get(format!("{}/{}/trending-scores", self.uri, entity))
+            .bearer_auth(&self.auth)
+            .send()
+            .await?
+            .json::<HashMap<i64, String>>()
+            .await?;
+
+        let data = data
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v.parse::<f64>().ok()?)))
+            .collect();
+
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl ScoresProvider for A7sProvider {
+    async fn bot_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+        self.fetch("bots").await.map(Some)
+    }
+
+    async fn pack_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+        self.fetch("packs").await.map(Some)
+    }
+}
+
+/// Scores are pushed externally so there is nothing to fetch.
+pub struct PushProvider;
+
+#[async_trait]
+impl ScoresProvider for PushProvider {
+    async fn bot_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+        Ok(None)
+    }
+
+    async fn pack_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+        Ok(None)
+    }
+}
+
+/// Calculates scores from the live vote data, decaying with the entity's age.
+pub struct InternalProvider;
+
+#[async_trait]
+impl ScoresProvider for InternalProvider {
+    async fn bot_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+        let now = now_secs();
+        let scores = models::bots::all_bots()
+            .into_iter()
+            .map(|bot| {
+                let votes = models::bots::get_bot_votes(*bot.id);
+                let score = decayed_score(votes, bot.created_on.timestamp(), now);
+
+                (*bot.id, score)
+            })
+            .collect();
+
+        Ok(Some(scores))
+    }
+
+    async fn pack_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+        let now = now_secs();
+        let scores = models::packs::all_packs()
+            .into_iter()
+            .map(|pack| {
+                let likes = models::packs::get_pack_likes(*pack.id);
+                let score = decayed_score(likes, pack.created_on.timestamp(), now);
+
+                (*pack.id, score)
+            })
+            .collect();
+
+        Ok(Some(scores))
+    }
+}
+
+fn decayed_score(votes: u64, created_on: i64, now: i64) -> f64 {
+    let age_hours = (now - created_on).max(0) as f64 / 3600.0;
+    votes as f64 / (age_hours + 2.0).powf(INTERNAL_GRAVITY)
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockProvider;
+
+    #[async_trait]
+    impl ScoresProvider for MockProvider {
+        async fn bot_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+            Ok(Some(HashMap::from([(1, 2.5), (2, 1.0)])))
+        }
+
+        async fn pack_scores(&self) -> Result<Option<HashMap<i64, f64>>> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_from_provider() {
+        refresh_scores(&MockProvider).await;
+
+        assert_eq!(models::bots::get_bot_trending_score(1), 2.5);
+        assert_eq!(models::bots::get_bot_trending_score(2), 1.0);
+        assert_eq!(models::bots::get_bot_trending_score(3), 0.0);
+        assert_eq!(models::packs::get_pack_trending_score(1), 0.0);
+    }
+
+    #[test]
+    fn test_decayed_score_favours_newer() {
+        let now = 1_000_000;
+        let newer = decayed_score(100, now - 3600, now);
+        let older = decayed_score(100, now - 3600 * 48, now);
+
+        assert!(newer > older);
+        assert_eq!(decayed_score(0, now, now), 0.0);
+    }
+}
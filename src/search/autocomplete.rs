@@ -0,0 +1,138 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+use crate::models::bots::{all_bots, get_bot_votes, Bot};
+use crate::search::tokenizer::SimpleUnicodeTokenizer;
+
+/// The maximum number of words of a username which are made searchable.
+const MAX_USERNAME_TOKENS: usize = 8;
+
+/// The normalized username keys of every visible bot.
+///
+/// Each bot is keyed by its full normalized username and each individual
+/// word of it, so `mu` matches both `Music Bot` and `Groovy Music`.
+#[derive(Default)]
+struct Usernames {
+    /// Every key along with the bot it belongs to, sorted by key.
+    keys: BTreeSet<(String, i64)>,
+
+    /// The keys of each bot so they can be removed when it changes.
+    by_bot: HashMap<i64, Vec<String>>,
+}
+
+impl Usernames {
+    fn insert(&mut self, tokenizer: &SimpleUnicodeTokenizer, bot: &Bot) {
+        self.remove(*bot.id);
+        if bot.is_hidden || bot.is_forced_into_hiding {
+            return;
+        }
+
+        let keys = username_keys(tokenizer, &bot.username);
+        for key in keys.iter() {
+            self.keys.insert((key.clone(), *bot.id));
+        }
+        self.by_bot.insert(*bot.id, keys);
+    }
+
+    fn remove(&mut self, bot_id: i64) {
+        for key in self.by_bot.remove(&bot_id).into_iter().flatten() {
+            self.keys.remove(&(key, bot_id));
+        }
+    }
+}
+
+static USERNAMES: Lazy<RwLock<Usernames>> =
+    Lazy::new(|| RwLock::new(Usernames::default()));
+
+fn tokenizer() -> SimpleUnicodeTokenizer {
+    SimpleUnicodeTokenizer::with_limit(MAX_USERNAME_TOKENS).with_stopwords(&[])
+}
+
+fn username_keys(tokenizer: &SimpleUnicodeTokenizer, username: &str) -> Vec<String> {
+    let words = tokenizer
+        .produce_tokens(username)
+        .into_iter()
+        .map(|token| token.text)
+        .collect::<Vec<_>>();
+
+    let mut keys = Vec::with_capacity(words.len() + 1);
+    if words.len() > 1 {
+        keys.push(words.join(" "));
+    }
+    keys.extend(words);
+    keys.sort_unstable();
+    keys.dedup();
+
+    keys
+}
+
+/// Rebuilds the username prefix index from the live bot data.
+pub fn rebuild() {
+    let tokenizer = tokenizer();
+
+    let mut usernames = Usernames::default();
+    for bot in all_bots() {
+        usernames.insert(&tokenizer, &bot);
+    }
+
+    *USERNAMES.write() = usernames;
+}
+
+/// Replaces the usernames of the given bots, removing any which are hidden.
+pub fn update_bots(bots: &[Bot]) {
+    let tokenizer = tokenizer();
+
+    let mut usernames = USERNAMES.write();
+    for bot in bots {
+        usernames.insert(&tokenizer, bot);
+    }
+}
+
+/// Removes the usernames of the given bots.
+pub fn remove_bots(bot_ids: &[i64]) {
+    let mut usernames = USERNAMES.write();
+    for bot_id in bot_ids {
+        usernames.remove(*bot_id);
+    }
+}
+
+/// Finds the bots whose username starts with the given prefix.
+///
+/// Every match is ranked by its votes.
+pub fn suggest(prefix: &str, limit: usize) -> Vec<i64> {
+    let prefix = tokenizer()
+        .produce_tokens(prefix)
+        .into_iter()
+        .map(|token| token.text)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if prefix.is_empty() || limit == 0 {
+        return vec![];
+    }
+
+    let matches = {
+        let usernames = USERNAMES.read();
+        usernames
+            .keys
+            .range((prefix.clone(), i64::MIN)..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, id)| *id)
+            .collect::<HashSet<_>>()
+    };
+
+    let mut candidates = matches
+        .into_iter()
+        .map(|id| (get_bot_votes(id), id))
+        .collect::<Vec<_>>();
+
+    if candidates.len() > limit {
+        candidates.select_nth_unstable_by(limit, |a, b| b.cmp(a));
+        candidates.truncate(limit);
+    }
+
+    candidates.sort_unstable_by(|a, b| b.cmp(a));
+    candidates.into_iter().map(|(_, id)| id).collect()
+}
@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Bound;
+
+use anyhow::Result;
+use poem_openapi::types::Any;
+use poem_openapi::Object;
+use serde_json::Value;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, RangeQuery, TermQuery};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema};
+use tantivy::{Index, Term};
+
+/// How deeply `bool` queries can be nested.
+const MAX_DEPTH: usize = 5;
+
+/// A subset of the Elasticsearch query DSL.
+///
+/// Fields are referred to by their name in the index, every query set
+/// on the same object must match.
+#[derive(Debug, Clone, Default, Object)]
+pub struct EsQuery {
+    /// Combines other queries.
+    #[oai(rename = "bool")]
+    bool_query: Option<EsBoolQuery>,
+
+    /// Matches documents with the exact value in each field, the value is not tokenized.
+    term: Option<HashMap<String, Any<Value>>>,
+
+    /// Matches documents containing any of the words of the text in each field.
+    #[oai(rename = "match")]
+    match_query: Option<HashMap<String, String>>,
+
+    /// Matches documents with a value of each numeric field within the range.
+    range: Option<HashMap<String, EsRange>>,
+}
+
+#[derive(Debug, Clone, Default, Object)]
+pub struct EsBoolQuery {
+    /// Queries which must all match.
+    #[oai(validator(max_items = 20), default)]
+    must: Vec<EsQuery>,
+
+    /// Queries which must all match, the same as `must`.
+    #[oai(validator(max_items = 20), default)]
+    filter: Vec<EsQuery>,
+
+    /// Queries where at least one must match if there are no `must` or
+    /// `filter` queries, otherwise they only affect the relevance.
+    #[oai(validator(max_items = 20), default)]
+    should: Vec<EsQuery>,
+
+    /// Queries which must not match.
+    #[oai(validator(max_items = 20), default)]
+    must_not: Vec<EsQuery>,
+}
+
+#[derive(Debug, Copy, Clone, Default, Object)]
+pub struct EsRange {
+    /// Greater than.
+    gt: Option<f64>,
+
+    /// Greater than or equal to.
+    gte: Option<f64>,
+
+    /// Less than.
+    lt: Option<f64>,
+
+    /// Less than or equal to.
+    lte: Option<f64>,
+}
+
+/// The query can not be run against the index, this is the fault of
+/// the query rather than the index.
+#[derive(Debug)]
+pub struct InvalidQuery(String);
+
+impl Display for InvalidQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid esQuery: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidQuery {}
+
+fn invalid<T>(msg: impl Into<String>) -> Result<T> {
+    Err(InvalidQuery(msg.into()).into())
+}
+
+/// Restricts the query to the documents also matching the structured query.
+pub fn restrict(query: Box<dyn Query>, es_query: Option<&dyn Query>) -> Box<dyn Query> {
+    match es_query {
+        Some(es_query) => Box::new(BooleanQuery::new(vec![
+            (Occur::Must, query),
+            (Occur::Must, es_query.box_clone()),
+        ])),
+        None => query,
+    }
+}
+
+impl EsQuery {
+    /// Translates the query into a tantivy query for the given index.
+    ///
+    /// Returns an [InvalidQuery] error if it uses unknown fields or values
+    /// which don't match the type of the field.
+    pub fn build(&self, index: &Index) -> Result<Box<dyn Query>> {
+        self.build_at(index, &index.schema(), 0)
+    }
+
+    fn build_at(
+        &self,
+        index: &Index,
+        schema: &Schema,
+        depth: usize,
+    ) -> Result<Box<dyn Query>> {
+        if depth > MAX_DEPTH {
+            return invalid(format!(
+                "bool queries can be nested at most {} deep",
+                MAX_DEPTH
+            ));
+        }
+
+        let mut clauses = Vec::new();
+
+        if let Some(bool_query) = &self.bool_query {
+            clauses.push((Occur::Must, bool_query.build(index, schema, depth + 1)?));
+        }
+
+        for (name, value) in self.term.iter().flatten() {
+            let field = indexed_field(schema, name)?;
+            let term = value_term(schema, field, name, &value.0)?;
+            let query = TermQuery::new(term, IndexRecordOption::Basic);
+            clauses.push((Occur::Must, Box::new(query) as Box<dyn Query>));
+        }
+
+        for (name, text) in self.match_query.iter().flatten() {
+            let field = indexed_field(schema, name)?;
+            if !matches!(
+                schema.get_field_entry(field).field_type(),
+                FieldType::Str(_)
+            ) {
+                return invalid(format!(
+                    "match requires a text field, {:?} is not",
+                    name
+                ));
+            }
+
+            let mut terms = Vec::new();
+            index
+                .tokenizer_for_field(field)?
+                .token_stream(text)
+                .process(&mut |token| {
+                    terms.push(Term::from_field_text(field, &token.text))
+                });
+
+            // A text without any terms matches nothing, the same as Elasticsearch.
+            let parts = terms
+                .into_iter()
+                .map(|term| {
+                    let query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+                    (Occur::Should, Box::new(query) as Box<dyn Query>)
+                })
+                .collect();
+            clauses.push((Occur::Must, Box::new(BooleanQuery::new(parts))));
+        }
+
+        for (name, range) in self.range.iter().flatten() {
+            let field = indexed_field(schema, name)?;
+            clauses.push((Occur::Must, range.build(schema, field, name)?));
+        }
+
+        match clauses.len() {
+            0 => invalid("every query must set `bool`, `term`, `match` or `range`"),
+            1 => Ok(clauses.pop().unwrap().1),
+            _ => Ok(Box::new(BooleanQuery::new(clauses))),
+        }
+    }
+}
+
+impl EsBoolQuery {
+    fn build(
+        &self,
+        index: &Index,
+        schema: &Schema,
+        depth: usize,
+    ) -> Result<Box<dyn Query>> {
+        let groups = [
+            (Occur::Must, &self.must),
+            (Occur::Must, &self.filter),
+            (Occur::Should, &self.should),
+            (Occur::MustNot, &self.must_not),
+        ];
+
+        let mut clauses = Vec::new();
+        for (occur, queries) in groups {
+            for query in queries {
+                clauses.push((occur, query.build_at(index, schema, depth)?));
+            }
+        }
+
+        // Tantivy matches nothing when there are only excluding clauses.
+        if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+        }
+
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+}
+
+impl EsRange {
+    fn build(
+        &self,
+        schema: &Schema,
+        field: Field,
+        name: &str,
+    ) -> Result<Box<dyn Query>> {
+        let (lower, upper) = match (self.gt, self.gte, self.lt, self.lte) {
+            (Some(_), Some(_), _, _) => {
+                return invalid("range can't set both gt and gte")
+            },
+            (_, _, Some(_), Some(_)) => {
+                return invalid("range can't set both lt and lte")
+            },
+            (gt, gte, lt, lte) => (
+                gt.map(Bound::Excluded)
+                    .or_else(|| gte.map(Bound::Included))
+                    .unwrap_or(Bound::Unbounded),
+                lt.map(Bound::Excluded)
+                    .or_else(|| lte.map(Bound::Included))
+                    .unwrap_or(Bound::Unbounded),
+            ),
+        };
+
+        let query = match schema.get_field_entry(field).field_type() {
+            FieldType::I64(_) => RangeQuery::new_i64_bounds(
+                field,
+                integer_bound(lower, f64::ceil),
+                integer_bound(upper, f64::floor),
+            ),
+            FieldType::U64(_) => {
+                // Every value is at least zero so negative bounds are clamped to it.
+                let lower = match lower {
+                    Bound::Included(v) | Bound::Excluded(v) if v < 0.0 => {
+                        Bound::Unbounded
+                    },
+                    lower => lower,
+                };
+                let upper = match upper {
+                    Bound::Included(v) | Bound::Excluded(v) if v < 0.0 => {
+                        Bound::Excluded(0.0)
+                    },
+                    upper => upper,
+                };
+
+                RangeQuery::new_u64_bounds(
+                    field,
+                    integer_bound(lower, f64::ceil),
+                    integer_bound(upper, f64::floor),
+                )
+            },
+            FieldType::F64(_) => RangeQuery::new_f64_bounds(field, lower, upper),
+            _ => {
+                return invalid(format!(
+                    "range requires a numeric field, {:?} is not",
+                    name
+                ))
+            },
+        };
+
+        Ok(Box::new(query))
+    }
+}
+
+/// Converts the bound to an integer bound containing the same integers,
+/// `round` is `ceil` for lower bounds and `floor` for upper bounds.
+fn integer_bound<T: FromF64>(bound: Bound<f64>, round: fn(f64) -> f64) -> Bound<T> {
+    match bound {
+        Bound::Excluded(v) if v.fract() == 0.0 => Bound::Excluded(T::from_f64(v)),
+        Bound::Included(v) | Bound::Excluded(v) => {
+            Bound::Included(T::from_f64(round(v)))
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+trait FromF64 {
+    fn from_f64(v: f64) -> Self;
+}
+
+impl FromF64 for i64 {
+    fn from_f64(v: f64) -> Self {
+        v as i64
+    }
+}
+
+impl FromF64 for u64 {
+    fn from_f64(v: f64) -> Self {
+        v as u64
+    }
+}
+
+fn indexed_field(schema: &Schema, name: &str) -> Result<Field> {
+    let field = match schema.get_field(name) {
+        Some(field) => field,
+        None => return invalid(format!("unknown field {:?}", name)),
+    };
+
+    if !schema.get_field_entry(field).is_indexed() {
+        return invalid(format!("field {:?} can't be queried", name));
+    }
+
+    Ok(field)
+}
+
+fn value_term(schema: &Schema, field: Field, name: &str, value: &Value) -> Result<Term> {
+    let term = match (schema.get_field_entry(field).field_type(), value) {
+        (FieldType::Str(_), Value::String(v)) => Term::from_field_text(field, v),
+        (FieldType::I64(_), Value::Bool(v)) => Term::from_field_i64(field, *v as i64),
+        (FieldType::U64(_), Value::Bool(v)) => Term::from_field_u64(field, *v as u64),
+        (FieldType::I64(_), v) if v.is_i64() => {
+            Term::from_field_i64(field, v.as_i64().unwrap())
+        },
+        (FieldType::U64(_), v) if v.is_u64() => {
+            Term::from_field_u64(field, v.as_u64().unwrap())
+        },
+        (FieldType::F64(_), v) if v.is_number() => {
+            Term::from_field_f64(field, v.as_f64().unwrap())
+        },
+        // Snowflake IDs are given as strings so they stay exact in JavaScript.
+        (FieldType::I64(_), Value::String(v)) if v.parse::<i64>().is_ok() => {
+            Term::from_field_i64(field, v.parse().unwrap())
+        },
+        _ => {
+            return invalid(format!(
+                "{} is not a valid value for field {:?}",
+                value, name
+            ))
+        },
+    };
+
+    Ok(term)
+}
+
+#[cfg(test)]
+mod tests {
+    use poem_openapi::types::ParseFromJSON;
+    use serde_json::json;
+    use tantivy::collector::Count;
+    use tantivy::doc;
+    use tantivy::schema::{SchemaBuilder, FAST, INDEXED, STRING, TEXT};
+
+    use super::*;
+
+    fn test_index() -> Index {
+        let mut builder = SchemaBuilder::new();
+        let tags = builder.add_text_field("tags_agg", STRING);
+        let username = builder.add_text_field("username", TEXT);
+        let votes = builder.add_i64_field("votes", INDEXED | FAST);
+        let premium = builder.add_u64_field("premium", INDEXED);
+        let sort = builder.add_i64_field("username_sort", FAST);
+
+        let index = Index::create_in_ram(builder.build());
+        let mut writer = index.writer(15_000_000).unwrap();
+        writer
+            .add_document(doc!(
+                tags => "music",
+                username => "Music Bot",
+                votes => 10i64,
+                premium => 1u64,
+                sort => 0i64,
+            ))
+            .unwrap();
+        writer
+            .add_document(doc!(
+                tags => "games",
+                username => "Game Bot",
+                votes => 250i64,
+                premium => 0u64,
+                sort => 1i64,
+            ))
+            .unwrap();
+        writer.commit().unwrap();
+
+        index
+    }
+
+    fn count(index: &Index, query: Value) -> Result<usize> {
+        let query = EsQuery::parse_from_json(Some(query))
+            .map_err(|e| anyhow::anyhow!(e.into_message()))?
+            .build(index)?;
+
+        let searcher = index.reader()?.searcher();
+        Ok(searcher.search(&query, &Count)?)
+    }
+
+    #[test]
+    fn test_queries() {
+        let index = test_index();
+
+        assert_eq!(
+            count(&index, json!({"term": {"tags_agg": "music"}})).unwrap(),
+            1
+        );
+        assert_eq!(
+            count(&index, json!({"term": {"premium": true}})).unwrap(),
+            1
+        );
+        assert_eq!(
+            count(&index, json!({"match": {"username": "music game"}})).unwrap(),
+            2
+        );
+        assert_eq!(
+            count(&index, json!({"range": {"votes": {"gt": 10}}})).unwrap(),
+            1
+        );
+        assert_eq!(
+            count(&index, json!({"range": {"votes": {"gte": 9.5}}})).unwrap(),
+            2
+        );
+        assert_eq!(
+            count(
+                &index,
+                json!({"bool": {"must_not": [{"term": {"tags_agg": "music"}}]}})
+            )
+            .unwrap(),
+            1
+        );
+        assert_eq!(
+            count(
+                &index,
+                json!({"bool": {
+                    "should": [
+                        {"term": {"tags_agg": "music"}},
+                        {"range": {"votes": {"gte": 100}}},
+                    ],
+                    "must_not": [{"term": {"premium": true}}],
+                }})
+            )
+            .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        let index = test_index();
+
+        for query in [
+            json!({}),
+            json!({"term": {"unknown": "music"}}),
+            json!({"term": {"username_sort": 1}}),
+            json!({"term": {"votes": "music"}}),
+            json!({"match": {"votes": "music"}}),
+            json!({"range": {"username": {"gt": 1}}}),
+            json!({"range": {"votes": {"gt": 1, "gte": 1}}}),
+        ] {
+            let err = count(&index, query).unwrap_err();
+            assert!(err.is::<InvalidQuery>(), "{}", err);
+        }
+    }
+}
@@ -1,15 +1,24 @@
 use std::fs;
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
 use tantivy::directory::MmapDirectory;
 use tantivy::schema::Schema;
-use tantivy::tokenizer::RawTokenizer;
+use tantivy::tokenizer::{Language, RawTokenizer, Stemmer, TextAnalyzer};
 use tantivy::{IndexReader, ReloadPolicy};
 
 use crate::search::tokenizer::SimpleUnicodeTokenizer;
 use crate::search::writer::Writer;
 
+static RECREATE_OUTDATED: OnceCell<bool> = OnceCell::new();
+
+/// Allows indexes built with an outdated schema to be deleted and
+/// recreated empty when opened, otherwise opening them fails.
+pub fn set_recreate_outdated_indexes(enabled: bool) {
+    let _ = RECREATE_OUTDATED.set(enabled);
+}
+
 pub async fn open_or_create(
     path: &Path,
     schema: Schema,
@@ -20,10 +29,31 @@ pub async fn open_or_create(
     let dir = MmapDirectory::open(path)?;
 
     let index = if tantivy::Index::exists(&dir)? {
-        tantivy::Index::open(dir)
+        let index = tantivy::Index::open(dir)?;
+
+        if index.schema() == schema {
+            index
+        } else if !RECREATE_OUTDATED.get().copied().unwrap_or_default() {
+            return Err(anyhow!(
+                "Index at {} was built with an outdated schema, start with \
+                `--recreate-outdated-indexes` to delete and rebuild it",
+                path.display(),
+            ));
+        } else {
+            warn!(
+                "Index at {} was built with an outdated schema, recreating it",
+                path.display(),
+            );
+
+            drop(index);
+            fs::remove_dir_all(path)?;
+            fs::create_dir_all(path)?;
+
+            tantivy::Index::open_or_create(MmapDirectory::open(path)?, schema)?
+        }
     } else {
-        tantivy::Index::open_or_create(dir, schema)
-    }?;
+        tantivy::Index::open_or_create(dir, schema)?
+    };
 
     index
         .tokenizers()
@@ -31,6 +61,12 @@ pub async fn open_or_create(
 
     index.tokenizers().register("raw", RawTokenizer);
 
+    index.tokenizers().register(
+        "stemmed",
+        TextAnalyzer::from(SimpleUnicodeTokenizer::default())
+            .filter(Stemmer::new(Language::English)),
+    );
+
     let reader = index
         .reader_builder()
         .reload_policy(ReloadPolicy::OnCommit)
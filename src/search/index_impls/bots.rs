@@ -1,8 +1,11 @@
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use once_cell::sync::OnceCell;
+use rayon::prelude::*;
 use tantivy::schema::{
     Field,
     IndexRecordOption,
@@ -15,29 +18,71 @@ use tantivy::schema::{
     STORED,
     TEXT,
 };
-use tantivy::Term;
-use tokio::sync::Semaphore;
+use tantivy::{Document, Term};
 
 use crate::models;
-use crate::models::bots::{remove_bot_from_live, update_live_data, Bot};
-use crate::search::index;
+use crate::models::bots::{
+    remove_bot_from_live,
+    remove_many_from_live,
+    update_live_data,
+    update_live_data_many,
+    Bot,
+};
+use crate::models::RefreshSummary;
+use crate::notify::{notify, IndexEvent};
+use crate::search::limiter::AdaptiveLimiter;
+use crate::search::metadata::{BuildMetadata, MetadataStore};
+use crate::search::queries::SearchField;
 use crate::search::readers::bots;
 use crate::search::readers::bots::FieldContext;
 use crate::search::writer::Writer;
+use crate::search::{autocomplete, index, replication, MergeSummary};
 
 pub static ID_FIELD: &str = "id";
 pub static PREMIUM_FIELD: &str = "premium";
+pub static NSFW_FIELD: &str = "nsfw";
+pub static HIDDEN_FIELD: &str = "hidden";
+pub static CERTIFIED_FIELD: &str = "certified";
+pub static VOTES_FIELD: &str = "votes";
+pub static TRENDING_FIELD: &str = "trending";
+pub static RATING_FIELD: &str = "rating";
+pub static GROWTH_FIELD: &str = "growth";
+pub static PREFIX_FIELD: &str = "prefix";
+pub static GUILD_COUNT_FIELD: &str = "guild_count";
+pub static OWNER_ID_FIELD: &str = "owner_id";
+pub static CO_OWNER_IDS_FIELD: &str = "co_owner_ids";
 pub static FEATURES_FIELD: &str = "features";
+pub static FEATURED_FIELD: &str = "featured";
+pub static CREATED_ON_FIELD: &str = "created_on";
+pub static USERNAME_SORT_FIELD: &str = "username_sort";
 pub static USERNAME_FIELD: &str = "username";
 pub static DESCRIPTION_FIELD: &str = "brief_description";
+pub static LONG_DESCRIPTION_FIELD: &str = "description";
+pub static DATA_FIELD: &str = "data";
 pub static TAGS_FIELD: &str = "tags";
 pub static TAGS_AGG_FIELD: &str = "tags_agg";
 
+/// How long to wait for more bots to be queued before re-indexing them.
+const REINDEX_DEBOUNCE: Duration = Duration::from_secs(2);
+
 static BOT_INDEX: OnceCell<BotIndex> = OnceCell::new();
 
+static LONG_DESCRIPTIONS: OnceCell<bool> = OnceCell::new();
+
+/// Sets if the full descriptions of bots are indexed and searched, this
+/// must be set before the index is opened.
+pub fn set_index_long_descriptions(enabled: bool) {
+    let _ = LONG_DESCRIPTIONS.set(enabled);
+}
+
+#[inline]
+pub fn indexes_long_descriptions() -> bool {
+    LONG_DESCRIPTIONS.get().copied().unwrap_or_default()
+}
+
 pub async fn init_index(
     path: &Path,
-    limiter: Arc<Semaphore>,
+    limiter: Arc<AdaptiveLimiter>,
     max_concurrency: usize,
 ) -> Result<()> {
     let index = BotIndex::create(path, limiter, max_concurrency).await?;
@@ -50,45 +95,125 @@ pub fn writer() -> &'static BotIndex {
     BOT_INDEX.get().unwrap()
 }
 
+/// Loads the bot from the copy stored in its document.
+///
+/// Returns `None` if the index is not yet open or the copy is invalid.
+pub fn stored_bot(doc: &Document) -> Option<Bot> {
+    let data_field = BOT_INDEX.get()?.data_field;
+    Bot::from_stored(doc.get_first(data_field)?.as_text()?)
+}
+
+/// Queues the given bots to be re-indexed from their live data.
+///
+/// This does nothing if the index is not yet open, as it will pick up the
+/// latest data when it is first refreshed, or if this instance is a replica.
+pub fn queue_reindex(bot_ids: impl IntoIterator<Item = i64>) {
+    if replication::is_replica() {
+        return;
+    }
+
+    if let Some(index) = BOT_INDEX.get() {
+        for bot_id in bot_ids {
+            let _ = index.reindex_queue.send(bot_id);
+        }
+    }
+}
+
 pub struct BotIndex {
     id_field: Field,
+    data_field: Field,
     writer: Writer,
     schema: Schema,
+    path: PathBuf,
+    metadata: MetadataStore,
+    reindex_queue: flume::Sender<i64>,
 }
 
 impl BotIndex {
     pub async fn create(
         path: &Path,
-        limiter: Arc<Semaphore>,
+        limiter: Arc<AdaptiveLimiter>,
         max_concurrency: usize,
     ) -> Result<Self> {
         let (reader, schema, writer) =
             index::open_or_create(path, default_schema(), max_concurrency).await?;
 
         let id_field = schema.get_field(ID_FIELD).unwrap();
+        let created_on_field = schema.get_field(CREATED_ON_FIELD).unwrap();
+        let name_sort_field = schema.get_field(USERNAME_SORT_FIELD).unwrap();
         let premium_field = schema.get_field(PREMIUM_FIELD).unwrap();
+        let nsfw_field = schema.get_field(NSFW_FIELD).unwrap();
+        let hidden_field = schema.get_field(HIDDEN_FIELD).unwrap();
+        let certified_field = schema.get_field(CERTIFIED_FIELD).unwrap();
+        let votes_field = schema.get_field(VOTES_FIELD).unwrap();
+        let trending_field = schema.get_field(TRENDING_FIELD).unwrap();
+        let rating_field = schema.get_field(RATING_FIELD).unwrap();
+        let growth_field = schema.get_field(GROWTH_FIELD).unwrap();
+        let guild_count_field = schema.get_field(GUILD_COUNT_FIELD).unwrap();
+        let prefix_field = schema.get_field(PREFIX_FIELD).unwrap();
+        let owner_id_field = schema.get_field(OWNER_ID_FIELD).unwrap();
+        let co_owner_ids_field = schema.get_field(CO_OWNER_IDS_FIELD).unwrap();
         let tags_field = schema.get_field(TAGS_FIELD).unwrap();
         let features_field = schema.get_field(FEATURES_FIELD).unwrap();
+        let featured_field = schema.get_field(FEATURED_FIELD).unwrap();
         let tags_agg_field = schema.get_field(TAGS_AGG_FIELD).unwrap();
-        let search_fields = vec![
-            schema.get_field(USERNAME_FIELD).unwrap(),
-            schema.get_field(DESCRIPTION_FIELD).unwrap(),
-            tags_field,
+        let username_field = schema.get_field(USERNAME_FIELD).unwrap();
+        let description_field = schema.get_field(DESCRIPTION_FIELD).unwrap();
+        let mut search_fields = vec![
+            SearchField::plain(username_field),
+            SearchField::stemmed(description_field),
+            SearchField::plain(tags_field),
         ];
 
+        // The last field has the lowest boost, as the full description often
+        // mentions terms which are not what the bot is about.
+        if indexes_long_descriptions() {
+            let long_description_field =
+                schema.get_field(LONG_DESCRIPTION_FIELD).unwrap();
+            search_fields.push(SearchField::stemmed(long_description_field));
+        }
+
         let ctx = FieldContext {
             id_field,
+            created_on_field,
+            name_sort_field,
             premium_field,
+            nsfw_field,
+            hidden_field,
+            certified_field,
+            votes_field,
+            trending_field,
+            rating_field,
+            growth_field,
+            guild_count_field,
+            prefix_field,
+            owner_id_field,
+            co_owner_ids_field,
             tags_agg_field,
             features_field,
+            featured_field,
+            description_field,
+            highlight_fields: [
+                ("username", username_field),
+                ("description", description_field),
+            ],
+            suggestion_fields: [username_field, tags_field],
         };
 
         bots::init(ctx, search_fields, reader, limiter);
 
+        let (reindex_queue, rx) = flume::unbounded();
+        tokio::spawn(run_reindex_queue(rx));
+
+        let metadata = MetadataStore::load(path, &schema);
         Ok(Self {
             id_field,
+            data_field: schema.get_field(DATA_FIELD).unwrap(),
             writer,
             schema,
+            path: path.to_path_buf(),
+            metadata,
+            reindex_queue,
         })
     }
 
@@ -97,6 +222,11 @@ impl BotIndex {
         self.writer.remove_docs(term).await?;
 
         remove_bot_from_live(bot_id);
+        autocomplete::remove_bots(&[bot_id]);
+        super::packs::writer().queue_reindex(models::packs::packs_containing(bot_id));
+        self.writer
+            .notify_on_commit(IndexEvent::BotRemoved { id: bot_id })
+            .await;
 
         Ok(())
     }
@@ -106,38 +236,303 @@ impl BotIndex {
             .await?
             .ok_or_else(|| anyhow!("Bot does not exist!"))?;
 
-        let term = Term::from_field_i64(self.id_field, bot_id);
-        let doc = bot.as_tantivy_doc(&self.schema);
-        self.writer.add_and_replace_document(term, doc).await?;
+        self.import_bot(bot).await?;
+        super::packs::writer().queue_reindex(models::packs::packs_containing(bot_id));
+        self.writer
+            .notify_on_commit(IndexEvent::BotUpserted { id: bot_id })
+            .await;
 
-        update_live_data(bot);
+        Ok(())
+    }
+
+    /// Applies the latest state of the bot's row, removing it if the row
+    /// was deleted or the bot is now hidden.
+    pub async fn apply_change(&self, bot_id: i64) -> Result<()> {
+        match Bot::fetch(bot_id).await? {
+            Some(bot) if !(bot.is_hidden || bot.is_forced_into_hiding) => {
+                self.import_bot(bot).await?;
+                self.writer
+                    .notify_on_commit(IndexEvent::BotUpserted { id: bot_id })
+                    .await;
+            },
+            _ => {
+                let term = Term::from_field_i64(self.id_field, bot_id);
+                self.writer.remove_docs(term).await?;
+                remove_bot_from_live(bot_id);
+                autocomplete::remove_bots(&[bot_id]);
+                self.writer
+                    .notify_on_commit(IndexEvent::BotRemoved { id: bot_id })
+                    .await;
+            },
+        }
+
+        super::packs::writer().queue_reindex(models::packs::packs_containing(bot_id));
 
         Ok(())
     }
 
-    pub async fn full_refresh(&self) -> Result<()> {
-        self.writer.clear_all_docs().await?;
-        models::bots::refresh_latest_data().await?;
+    /// Applies the latest state of every given bot's row like
+    /// [Self::apply_change], updating the live data once for the batch.
+    ///
+    /// Returns the bots whose rows could not be fetched, these are left as is.
+    pub async fn apply_changes(
+        &self,
+        bot_ids: Vec<i64>,
+    ) -> Result<Vec<(i64, anyhow::Error)>> {
+        let mut failed = vec![];
+        let mut visible = vec![];
+        let mut removed = vec![];
+        for bot_id in bot_ids {
+            match Bot::fetch(bot_id).await {
+                Ok(Some(bot)) if !(bot.is_hidden || bot.is_forced_into_hiding) => {
+                    visible.push(bot)
+                },
+                Ok(_) => removed.push(bot_id),
+                Err(e) => failed.push((bot_id, e)),
+            }
+        }
+
+        for bot_id in removed.iter() {
+            let term = Term::from_field_i64(self.id_field, *bot_id);
+            self.writer.remove_docs(term).await?;
+        }
+        autocomplete::remove_bots(&removed);
+        remove_many_from_live(removed.clone());
+
+        let upserted = visible.iter().map(|bot| *bot.id).collect::<Vec<_>>();
+        self.import_bots(visible).await?;
 
-        for bot in models::bots::all_bots() {
+        for id in upserted.iter().copied() {
             self.writer
-                .add_document(bot.as_tantivy_doc(&self.schema))
-                .await?;
+                .notify_on_commit(IndexEvent::BotUpserted { id })
+                .await;
+        }
+        for id in removed.iter().copied() {
+            self.writer
+                .notify_on_commit(IndexEvent::BotRemoved { id })
+                .await;
+        }
+        for bot_id in upserted.into_iter().chain(removed) {
+            super::packs::writer()
+                .queue_reindex(models::packs::packs_containing(bot_id));
         }
 
+        Ok(failed)
+    }
+
+    /// Indexes the given bot directly without pulling it from the database.
+    pub async fn import_bot(&self, bot: Bot) -> Result<()> {
+        let term = Term::from_field_i64(self.id_field, *bot.id);
+        let doc = bot.as_tantivy_doc(&self.schema);
+        self.writer
+            .add_and_replace_document_acked(term, doc)
+            .await?;
+
+        autocomplete::update_bots(std::slice::from_ref(&bot));
+        update_live_data(bot);
+
         Ok(())
     }
+
+    /// Indexes a batch of bots directly without pulling them from the database.
+    pub async fn import_bots(&self, bots: Vec<Bot>) -> Result<()> {
+        let docs = bots
+            .par_iter()
+            .map(|bot| {
+                let term = Term::from_field_i64(self.id_field, *bot.id);
+                (term, bot.as_tantivy_doc(&self.schema))
+            })
+            .collect();
+        self.writer.add_and_replace_documents(docs).await?;
+
+        autocomplete::update_bots(&bots);
+        update_live_data_many(bots);
+
+        Ok(())
+    }
+
+    /// Re-indexes the bot from its live data without pulling it from the database.
+    pub async fn reindex_live(&self, bot_id: i64) -> Result<()> {
+        let bot = match models::bots::get_bot_data(bot_id) {
+            Some(bot) => bot,
+            None => return Ok(()),
+        };
+
+        let term = Term::from_field_i64(self.id_field, bot_id);
+        let doc = bot.as_tantivy_doc(&self.schema);
+        self.writer.add_and_replace_document(term, doc).await
+    }
+
+    pub async fn full_refresh(&self) -> Result<RefreshSummary> {
+        let started = Instant::now();
+        let mut summary = models::bots::refresh_latest_data().await?;
+
+        let docs = models::bots::all_bots()
+            .par_iter()
+            .map(|bot| bot.as_tantivy_doc(&self.schema))
+            .collect::<Vec<_>>();
+        summary.indexed = docs.len();
+
+        for e in self.writer.replace_all_docs(docs).await? {
+            summary.indexed -= 1;
+            summary.record_error(e);
+        }
+        bots::reader().reload()?;
+        autocomplete::rebuild();
+        notify(IndexEvent::RefreshCompleted { index: "bots" });
+
+        self.metadata
+            .record_refresh(summary.total_rows(), started)?;
+        info!(
+            "Bot index refreshed, indexed: {}, skipped: {}, errored: {}",
+            summary.indexed, summary.skipped, summary.errored,
+        );
+
+        Ok(summary)
+    }
+
+    /// Re-indexes only the bots updated after the given unix timestamp in seconds.
+    ///
+    /// Bots which are now hidden are removed, every other bot is left as is.
+    /// Updates are only tracked for 30 days, so the whole index is rebuilt
+    /// instead when `since` is older than that.
+    pub async fn delta_refresh(&self, since: i64) -> Result<RefreshSummary> {
+        let since_millis = since.saturating_mul(1000);
+        if !models::bots::is_update_tracked(since_millis) {
+            info!(
+                "Bot updates since {} are no longer tracked, fully refreshing instead",
+                since
+            );
+            return self.full_refresh().await;
+        }
+
+        let (bots, mut summary) =
+            models::bots::fetch_updated_since(since_millis).await?;
+
+        let (hidden, visible): (Vec<_>, Vec<_>) = bots
+            .into_iter()
+            .partition(|bot| bot.is_hidden || bot.is_forced_into_hiding);
+
+        let mut changed = HashSet::new();
+        for bot in hidden.iter() {
+            let term = Term::from_field_i64(self.id_field, *bot.id);
+            self.writer.remove_docs(term).await?;
+
+            changed.insert(*bot.id);
+            summary.skipped += 1;
+        }
+        let hidden_ids = hidden.iter().map(|bot| *bot.id).collect::<Vec<_>>();
+        autocomplete::remove_bots(&hidden_ids);
+        remove_many_from_live(hidden_ids);
+
+        changed.extend(visible.iter().map(|bot| *bot.id));
+        summary.indexed += visible.len();
+        self.import_bots(visible).await?;
+
+        for bot_id in changed {
+            super::packs::writer()
+                .queue_reindex(models::packs::packs_containing(bot_id));
+        }
+
+        info!(
+            "Bot index delta refreshed, indexed: {}, skipped: {}, errored: {}",
+            summary.indexed, summary.skipped, summary.errored,
+        );
+
+        Ok(summary)
+    }
+
+    /// Commits any pending changes, making them searchable once the reader reloads.
+    pub async fn commit(&self) -> Result<()> {
+        self.writer.commit().await
+    }
+
+    /// Merges the smallest segments until at most `target_segments` remain.
+    pub async fn merge(&self, target_segments: usize) -> Result<MergeSummary> {
+        self.writer.merge(target_segments).await
+    }
+
+    pub fn writer_stats(&self) -> WriterStats {
+        self.writer.stats()
+    }
+
+    pub fn metadata(&self) -> BuildMetadata {
+        self.metadata.get()
+    }
+
+    /// If the index on disk was fully built with the current schema.
+    pub fn is_current(&self) -> bool {
+        self.metadata.is_current()
+    }
+
+    /// The directory the index files are stored in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 }
 
-fn default_schema() -> Schema {
+async fn run_reindex_queue(queue: flume::Receiver<i64>) {
+    while let Ok(bot_id) = queue.recv_async().await {
+        tokio::time::sleep(REINDEX_DEBOUNCE).await;
+
+        let mut pending = HashSet::from([bot_id]);
+        pending.extend(queue.drain());
+
+        debug!("Re-indexing {} queued bots", pending.len());
+        for bot_id in pending {
+            if let Err(e) = writer().reindex_live(bot_id).await {
+                error!("Failed to re-index bot {} due to error: {}", bot_id, e);
+            }
+        }
+    }
+}
+
+pub(crate) fn default_schema() -> Schema {
     let mut builder = SchemaBuilder::new();
 
     builder.add_i64_field(ID_FIELD, INDEXED | FAST | STORED);
+    builder.add_i64_field(CREATED_ON_FIELD, INDEXED | FAST);
+    builder.add_i64_field(USERNAME_SORT_FIELD, FAST);
     builder.add_u64_field(FEATURES_FIELD, INDEXED | FAST);
     builder.add_u64_field(PREMIUM_FIELD, INDEXED | FAST);
+    builder.add_u64_field(NSFW_FIELD, INDEXED);
+    builder.add_u64_field(HIDDEN_FIELD, INDEXED);
+    builder.add_u64_field(CERTIFIED_FIELD, INDEXED | FAST);
+    builder.add_i64_field(VOTES_FIELD, INDEXED | FAST);
+    builder.add_i64_field(TRENDING_FIELD, INDEXED | FAST);
+    builder.add_i64_field(RATING_FIELD, INDEXED | FAST);
+    builder.add_i64_field(GROWTH_FIELD, INDEXED | FAST);
+    builder.add_u64_field(GUILD_COUNT_FIELD, INDEXED | FAST);
+    builder.add_i64_field(OWNER_ID_FIELD, INDEXED);
+    builder.add_i64_field(CO_OWNER_IDS_FIELD, INDEXED);
+    builder.add_u64_field(FEATURED_FIELD, INDEXED | FAST);
     builder.add_text_field(USERNAME_FIELD, TEXT);
-    builder.add_text_field(DESCRIPTION_FIELD, TEXT);
+    builder.add_text_field(
+        DESCRIPTION_FIELD,
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer("stemmed"),
+        ),
+    );
+    builder.add_text_field(
+        LONG_DESCRIPTION_FIELD,
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer("stemmed"),
+        ),
+    );
     builder.add_text_field(TAGS_FIELD, TEXT | FAST);
+    builder.add_text_field(DATA_FIELD, STORED);
+    builder.add_text_field(
+        PREFIX_FIELD,
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::Basic)
+                .set_tokenizer("raw"),
+        ),
+    );
     builder.add_text_field(
         TAGS_AGG_FIELD,
         TextOptions::default().set_fast().set_indexing_options(
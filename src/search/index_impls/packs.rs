@@ -1,8 +1,11 @@
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use once_cell::sync::OnceCell;
+use rayon::prelude::*;
 use tantivy::schema::{
     Field,
     IndexRecordOption,
@@ -16,26 +19,37 @@ use tantivy::schema::{
     TEXT,
 };
 use tantivy::Term;
-use tokio::sync::Semaphore;
 
 use crate::models;
 use crate::models::packs::{remove_pack_from_live, update_live_data, Pack};
-use crate::search::index;
+use crate::models::RefreshSummary;
+use crate::notify::{notify, IndexEvent};
+use crate::search::limiter::AdaptiveLimiter;
+use crate::search::metadata::{BuildMetadata, MetadataStore};
+use crate::search::queries::SearchField;
 use crate::search::readers::packs;
 use crate::search::readers::packs::FieldContext;
 use crate::search::writer::Writer;
+use crate::search::{index, MergeSummary, WriterStats};
 
 pub static ID_FIELD: &str = "id";
+pub static CREATED_ON_FIELD: &str = "created_on";
+pub static NAME_SORT_FIELD: &str = "name_sort";
 pub static NAME_FIELD: &str = "name";
 pub static DESCRIPTION_FIELD: &str = "description";
 pub static TAG_FIELD: &str = "tag";
 pub static TAG_AGG_FIELD: &str = "tag_agg";
+pub static PREMIUM_FIELD: &str = "premium";
+pub static LIKES_FIELD: &str = "likes";
+
+/// How long to collect queued pack re-indexes for before applying them.
+const REINDEX_DEBOUNCE: Duration = Duration::from_secs(2);
 
 static PACK_INDEX: OnceCell<PackIndex> = OnceCell::new();
 
 pub async fn init_index(
     path: &Path,
-    limiter: Arc<Semaphore>,
+    limiter: Arc<AdaptiveLimiter>,
     max_concurrency: usize,
 ) -> Result<()> {
     let index = PackIndex::create(path, limiter, max_concurrency).await?;
@@ -52,45 +66,80 @@ pub struct PackIndex {
     id_field: Field,
     writer: Writer,
     schema: Schema,
+    path: PathBuf,
+    metadata: MetadataStore,
+    reindex_queue: flume::Sender<i64>,
 }
 
 impl PackIndex {
     pub async fn create(
         path: &Path,
-        limiter: Arc<Semaphore>,
+        limiter: Arc<AdaptiveLimiter>,
         max_concurrency: usize,
     ) -> Result<Self> {
         let (reader, schema, writer) =
             index::open_or_create(path, default_schema(), max_concurrency).await?;
 
         let id_field = schema.get_field(ID_FIELD).unwrap();
+        let created_on_field = schema.get_field(CREATED_ON_FIELD).unwrap();
+        let name_sort_field = schema.get_field(NAME_SORT_FIELD).unwrap();
         let tag_field = schema.get_field(TAG_FIELD).unwrap();
         let tag_agg_field = schema.get_field(TAG_AGG_FIELD).unwrap();
+        let premium_field = schema.get_field(PREMIUM_FIELD).unwrap();
+        let likes_field = schema.get_field(LIKES_FIELD).unwrap();
+        let name_field = schema.get_field(NAME_FIELD).unwrap();
+        let description_field = schema.get_field(DESCRIPTION_FIELD).unwrap();
         let search_fields = vec![
-            schema.get_field(NAME_FIELD).unwrap(),
-            schema.get_field(DESCRIPTION_FIELD).unwrap(),
-            tag_field,
+            SearchField::plain(name_field),
+            SearchField::stemmed(description_field),
+            SearchField::plain(tag_field),
         ];
 
         let ctx = FieldContext {
             id_field,
+            created_on_field,
+            name_sort_field,
             tag_agg_field,
+            premium_field,
+            likes_field,
+            highlight_fields: [("name", name_field), ("description", description_field)],
+            suggestion_fields: [name_field, tag_field],
         };
 
         packs::init(ctx, search_fields, reader, limiter);
 
+        let (reindex_queue, rx) = flume::unbounded();
+        tokio::spawn(run_reindex_queue(rx));
+
+        let metadata = MetadataStore::load(path, &schema);
         Ok(Self {
             id_field,
             writer,
             schema,
+            path: path.to_path_buf(),
+            metadata,
+            reindex_queue,
         })
     }
 
+    /// Queues the given packs to be re-indexed in the background.
+    ///
+    /// This is used when a bot changes so any packs containing
+    /// it do not keep stale data.
+    pub fn queue_reindex(&self, pack_ids: impl IntoIterator<Item = i64>) {
+        for pack_id in pack_ids {
+            let _ = self.reindex_queue.send(pack_id);
+        }
+    }
+
     pub async fn remove_pack(&self, pack_id: i64) -> Result<()> {
         let term = Term::from_field_i64(self.id_field, pack_id);
         self.writer.remove_docs(term).await?;
 
         remove_pack_from_live(pack_id);
+        self.writer
+            .notify_on_commit(IndexEvent::PackRemoved { id: pack_id })
+            .await;
 
         Ok(())
     }
@@ -100,41 +149,177 @@ impl PackIndex {
             .await?
             .ok_or_else(|| anyhow!("Bot does not exist!"))?;
 
-        if pack.bots.len() > 1 {
-            let term = Term::from_field_i64(self.id_field, pack_id);
-            let doc = pack.as_tantivy_doc(&self.schema);
-            self.writer.add_and_replace_document(term, doc).await?;
-
-            update_live_data(pack);
+        if self.import_pack(pack).await? {
+            self.writer
+                .notify_on_commit(IndexEvent::PackUpserted { id: pack_id })
+                .await;
         } else {
-            remove_pack_from_live(pack_id);
+            self.remove_pack(pack_id).await?;
         }
 
         Ok(())
     }
 
-    pub async fn full_refresh(&self) -> Result<()> {
-        self.writer.clear_all_docs().await?;
-        models::packs::refresh_latest_data().await?;
+    /// Applies the latest state of the pack's row, removing it if the row
+    /// was deleted or the pack is now hidden.
+    pub async fn apply_change(&self, pack_id: i64) -> Result<()> {
+        let indexed = match Pack::fetch(pack_id).await? {
+            Some(pack) => self.import_pack(pack).await?,
+            None => false,
+        };
 
-        for pack in models::packs::all_packs() {
-            if pack.bots.len() > 1 {
-                self.writer
-                    .add_document(pack.as_tantivy_doc(&self.schema))
-                    .await?;
-            }
+        if indexed {
+            self.writer
+                .notify_on_commit(IndexEvent::PackUpserted { id: pack_id })
+                .await;
+        } else {
+            self.remove_pack(pack_id).await?;
         }
 
         Ok(())
     }
+
+    /// Indexes the given pack directly without pulling it from the database.
+    ///
+    /// Returns `false` if the pack is hidden or has too few bots and was not
+    /// indexed.
+    pub async fn import_pack(&self, pack: Pack) -> Result<bool> {
+        if !is_searchable(&pack) {
+            return Ok(false);
+        }
+
+        let term = Term::from_field_i64(self.id_field, *pack.id);
+        let doc = pack.as_tantivy_doc(&self.schema);
+        self.writer
+            .add_and_replace_document_acked(term, doc)
+            .await?;
+
+        update_live_data(pack);
+
+        Ok(true)
+    }
+
+    /// Indexes a batch of packs directly without pulling them from the database.
+    ///
+    /// Returns the number of packs indexed, hidden packs and packs with one or
+    /// no bots are skipped.
+    pub async fn import_packs(&self, packs: Vec<Pack>) -> Result<usize> {
+        let packs = packs.into_iter().filter(is_searchable).collect::<Vec<_>>();
+
+        let docs = packs
+            .par_iter()
+            .map(|pack| {
+                let term = Term::from_field_i64(self.id_field, *pack.id);
+                (term, pack.as_tantivy_doc(&self.schema))
+            })
+            .collect();
+        self.writer.add_and_replace_documents(docs).await?;
+
+        let indexed = packs.len();
+        for pack in packs {
+            update_live_data(pack);
+        }
+
+        Ok(indexed)
+    }
+
+    pub async fn full_refresh(&self) -> Result<RefreshSummary> {
+        let started = Instant::now();
+        let mut summary = models::packs::refresh_latest_data().await?;
+
+        let packs = models::packs::all_packs();
+        let docs = packs
+            .par_iter()
+            .filter(|pack| is_searchable(pack))
+            .map(|pack| pack.as_tantivy_doc(&self.schema))
+            .collect::<Vec<_>>();
+        summary.skipped += packs.len() - docs.len();
+        summary.indexed += docs.len();
+
+        for e in self.writer.replace_all_docs(docs).await? {
+            summary.indexed -= 1;
+            summary.record_error(e);
+        }
+        packs::reader().reload()?;
+        notify(IndexEvent::RefreshCompleted { index: "packs" });
+
+        self.metadata
+            .record_refresh(summary.total_rows(), started)?;
+        info!(
+            "Pack index refreshed, indexed: {}, skipped: {}, errored: {}",
+            summary.indexed, summary.skipped, summary.errored,
+        );
+
+        Ok(summary)
+    }
+
+    /// Commits any pending changes, making them searchable once the reader reloads.
+    pub async fn commit(&self) -> Result<()> {
+        self.writer.commit().await
+    }
+
+    /// Merges the smallest segments until at most `target_segments` remain.
+    pub async fn merge(&self, target_segments: usize) -> Result<MergeSummary> {
+        self.writer.merge(target_segments).await
+    }
+
+    pub fn writer_stats(&self) -> WriterStats {
+        self.writer.stats()
+    }
+
+    pub fn metadata(&self) -> BuildMetadata {
+        self.metadata.get()
+    }
+
+    /// If the index on disk was fully built with the current schema.
+    pub fn is_current(&self) -> bool {
+        self.metadata.is_current()
+    }
+
+    /// The directory the index files are stored in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+async fn run_reindex_queue(queue: flume::Receiver<i64>) {
+    while let Ok(pack_id) = queue.recv_async().await {
+        tokio::time::sleep(REINDEX_DEBOUNCE).await;
+
+        let mut pending = HashSet::from([pack_id]);
+        pending.extend(queue.drain());
+
+        debug!("Re-indexing {} queued packs", pending.len());
+        for pack_id in pending {
+            if let Err(e) = writer().upsert_pack(pack_id).await {
+                error!("Failed to re-index pack {} due to error: {}", pack_id, e);
+            }
+        }
+    }
+}
+
+/// If the pack is visible and has enough bots to be indexed.
+fn is_searchable(pack: &Pack) -> bool {
+    !pack.is_hidden_from_search() && pack.bots.len() > 1
 }
 
 fn default_schema() -> Schema {
     let mut builder = SchemaBuilder::new();
 
     builder.add_i64_field(ID_FIELD, INDEXED | FAST | STORED);
+    builder.add_i64_field(CREATED_ON_FIELD, INDEXED | FAST);
+    builder.add_i64_field(NAME_SORT_FIELD, FAST);
+    builder.add_u64_field(PREMIUM_FIELD, INDEXED | FAST);
+    builder.add_i64_field(LIKES_FIELD, INDEXED | FAST);
     builder.add_text_field(NAME_FIELD, TEXT);
-    builder.add_text_field(DESCRIPTION_FIELD, TEXT);
+    builder.add_text_field(
+        DESCRIPTION_FIELD,
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer("stemmed"),
+        ),
+    );
     builder.add_text_field(TAG_FIELD, TEXT | FAST);
     builder.add_text_field(
         TAG_AGG_FIELD,
@@ -0,0 +1,198 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// How quickly the short term latency follows new samples.
+const SHORT_SMOOTHING: f64 = 0.2;
+
+/// How quickly the baseline latency follows new samples.
+const BASELINE_SMOOTHING: f64 = 0.01;
+
+/// How much slower than the baseline searches can get before backing off.
+const LATENCY_TOLERANCE: f64 = 2.0;
+
+/// The factor the limit is multiplied by when backing off.
+const BACKOFF_RATIO: f64 = 0.9;
+
+/// Limits the number of concurrent searches using AIMD.
+///
+/// The limit grows by one every time a full window of searches completes
+/// within the tolerated latency and shrinks multiplicatively when the
+/// observed latency drifts too far from the baseline, it never leaves
+/// the configured `floor..=ceiling` range.
+pub struct AdaptiveLimiter {
+    semaphore: Semaphore,
+    floor: usize,
+    ceiling: usize,
+
+    /// The limit the semaphore is converging towards.
+    limit: AtomicUsize,
+
+    /// The number of permits the semaphore currently manages.
+    permits: AtomicUsize,
+
+    state: Mutex<LatencyState>,
+}
+
+#[derive(Default)]
+struct LatencyState {
+    short: Option<f64>,
+    baseline: Option<f64>,
+    window_successes: usize,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(floor: usize, ceiling: usize) -> Self {
+        let floor = floor.max(1);
+        let ceiling = ceiling.max(floor);
+
+        Self {
+            semaphore: Semaphore::new(ceiling),
+            floor,
+            ceiling,
+            limit: AtomicUsize::new(ceiling),
+            permits: AtomicUsize::new(ceiling),
+            state: Mutex::new(LatencyState::default()),
+        }
+    }
+
+    /// The current concurrency limit.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub async fn acquire(&self) -> Result<LimiterPermit<'_>> {
+        let permit = self.semaphore.acquire().await?;
+
+        Ok(LimiterPermit {
+            limiter: self,
+            permit: Some(permit),
+            started: Instant::now(),
+        })
+    }
+
+    fn record(&self, latency: Duration) {
+        let sample = latency.as_secs_f64();
+        let mut state = self.state.lock();
+
+        let short = smooth(state.short, sample, SHORT_SMOOTHING);
+        let baseline = smooth(state.baseline, sample, BASELINE_SMOOTHING);
+        state.short = Some(short);
+        state.baseline = Some(baseline);
+
+        let limit = self.limit();
+        if short > baseline * LATENCY_TOLERANCE {
+            let new_limit = ((limit as f64 * BACKOFF_RATIO) as usize).max(self.floor);
+            if new_limit != limit {
+                debug!(
+                    "Reducing search concurrency limit {} -> {}",
+                    limit, new_limit
+                );
+                self.limit.store(new_limit, Ordering::Relaxed);
+            }
+
+            state.window_successes = 0;
+            return;
+        }
+
+        state.window_successes += 1;
+        if state.window_successes >= limit && limit < self.ceiling {
+            state.window_successes = 0;
+            self.limit.store(limit + 1, Ordering::Relaxed);
+        }
+    }
+
+    fn release(&self, permit: SemaphorePermit) {
+        let limit = self.limit();
+
+        let shrunk = self
+            .permits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |permits| {
+                if permits > limit {
+                    Some(permits - 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+
+        if shrunk {
+            // Dropping the permit from the semaphore shrinks the pool.
+            permit.forget();
+            return;
+        }
+
+        drop(permit);
+
+        let grown = self
+            .permits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |permits| {
+                if permits < limit {
+                    Some(permits + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+
+        if grown {
+            self.semaphore.add_permits(1);
+        }
+    }
+}
+
+/// A permit to run a search, the search latency is recorded once dropped.
+pub struct LimiterPermit<'a> {
+    limiter: &'a AdaptiveLimiter,
+    permit: Option<SemaphorePermit<'a>>,
+    started: Instant,
+}
+
+impl<'a> Drop for LimiterPermit<'a> {
+    fn drop(&mut self) {
+        self.limiter.record(self.started.elapsed());
+
+        if let Some(permit) = self.permit.take() {
+            self.limiter.release(permit);
+        }
+    }
+}
+
+fn smooth(previous: Option<f64>, sample: f64, factor: f64) -> f64 {
+    match previous {
+        None => sample,
+        Some(previous) => previous + (sample - previous) * factor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backs_off_to_floor() {
+        let limiter = AdaptiveLimiter::new(4, 50);
+
+        limiter.record(Duration::from_millis(5));
+        for _ in 0..100 {
+            limiter.record(Duration::from_secs(1));
+        }
+
+        assert_eq!(limiter.limit(), 4);
+    }
+
+    #[test]
+    fn test_grows_to_ceiling() {
+        let limiter = AdaptiveLimiter::new(4, 8);
+        limiter.limit.store(4, Ordering::Relaxed);
+
+        for _ in 0..100 {
+            limiter.record(Duration::from_millis(5));
+        }
+
+        assert_eq!(limiter.limit(), 8);
+    }
+}
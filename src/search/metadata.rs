@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use parking_lot::RwLock;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tantivy::schema::Schema;
+
+static METADATA_FILE: &str = "build-meta.json";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct BuildMetadata {
+    /// The unix timestamp of when the last full refresh completed.
+    pub last_full_refresh: Option<i64>,
+
+    /// The number of rows loaded from the database during the last full refresh.
+    pub source_rows: usize,
+
+    /// How long the last full refresh took in milliseconds.
+    pub build_duration_ms: u64,
+
+    /// The version of Cronos which built the index.
+    pub builder_version: String,
+
+    /// A hash of the schema the index was built with.
+    #[serde(default)]
+    pub schema_hash: Option<String>,
+}
+
+/// The build metadata of an index, persisted alongside the index files.
+pub struct MetadataStore {
+    path: PathBuf,
+    schema_hash: String,
+    current: RwLock<BuildMetadata>,
+}
+
+impl MetadataStore {
+    pub fn load(index_path: &Path, schema: &Schema) -> Self {
+        let path = index_path.join(METADATA_FILE);
+        let current = fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            schema_hash: schema_hash(schema),
+            current: RwLock::new(current),
+        }
+    }
+
+    /// If the index has been fully built with the given schema.
+    ///
+    /// This is `false` for indexes which were never refreshed or which
+    /// were recreated as they had an outdated schema.
+    pub fn is_current(&self) -> bool {
+        let current = self.current.read();
+        current.last_full_refresh.is_some()
+            && current.schema_hash.as_deref() == Some(self.schema_hash.as_str())
+    }
+
+    pub fn get(&self) -> BuildMetadata {
+        self.current.read().clone()
+    }
+
+    /// Records a completed full refresh and persists it to disk.
+    pub fn record_refresh(&self, source_rows: usize, started: Instant) -> Result<()> {
+        let finished = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let metadata = BuildMetadata {
+            last_full_refresh: Some(finished as i64),
+            source_rows,
+            build_duration_ms: started.elapsed().as_millis() as u64,
+            builder_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_hash: Some(self.schema_hash.clone()),
+        };
+
+        fs::write(&self.path, serde_json::to_vec_pretty(&metadata)?)?;
+        (*self.current.write()) = metadata;
+
+        Ok(())
+    }
+}
+
+/// A stable FNV-1a hash of the schema's definition.
+pub fn schema_hash(schema: &Schema) -> String {
+    let definition = serde_json::to_vec(schema).unwrap_or_default();
+    let hash = definition.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{:016x}", hash)
+}
@@ -1,13 +1,35 @@
 use tantivy::schema::Field;
 use tantivy::Document;
 
+use crate::search::readers::Highlighter;
+
+pub mod autocomplete;
+pub mod es_query;
 mod index;
 pub mod index_impls;
+pub mod limiter;
+pub mod metadata;
 mod queries;
 pub mod readers;
-mod tokenizer;
+pub mod replication;
+mod scheduler;
+pub mod snapshots;
+mod suggestions;
+pub mod tokenizer;
 mod writer;
 
+pub use index::set_recreate_outdated_indexes;
+pub use writer::{
+    set_writer_queue_size,
+    set_writer_threads,
+    MergeSummary,
+    WriterSaturated,
+    WriterStats,
+};
+
 pub trait FromTantivyDoc: Sized {
     fn from_doc(id_field: Field, doc: Document) -> Option<Self>;
+
+    /// Attaches the matched fragments of the hit's text fields.
+    fn highlight(&mut self, _highlighter: &Highlighter) {}
 }
@@ -6,11 +6,15 @@ use tantivy::query::{
     FuzzyTermQuery,
     Occur,
     Query,
+    TermQuery,
 };
-use tantivy::schema::Field;
-use tantivy::Term;
+use tantivy::schema::{Field, IndexRecordOption};
+use tantivy::{Score, Term};
 
-use crate::search::tokenizer::{SimpleTokenStream, SimpleUnicodeTokenizer};
+use crate::search::tokenizer::{stem, SimpleUnicodeTokenizer};
+
+/// The maximum number of tokens a query can produce across all of its clauses.
+const MAX_QUERY_TOKENS: usize = 10;
 
 macro_rules! add_if_exists {
     ($collector:expr, $qry:expr) => {{
@@ -20,81 +24,447 @@ macro_rules! add_if_exists {
     }};
 }
 
-pub fn distribution_query(query: Option<&str>, fields: &[Field]) -> Box<dyn Query> {
+#[derive(Debug, Copy, Clone)]
+/// A field which is searched by the query builders.
+pub struct SearchField {
+    pub field: Field,
+
+    /// If the field is indexed with the `stemmed` analyzer.
+    pub stemmed: bool,
+}
+
+impl SearchField {
+    pub fn plain(field: Field) -> Self {
+        Self {
+            field,
+            stemmed: false,
+        }
+    }
+
+    pub fn stemmed(field: Field) -> Self {
+        Self {
+            field,
+            stemmed: true,
+        }
+    }
+
+    /// Produces a term for the given token matching how the field was indexed.
+    pub fn term(&self, text: &str) -> Term {
+        if self.stemmed {
+            Term::from_field_text(self.field, &stem(text))
+        } else {
+            Term::from_field_text(self.field, text)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ClauseKind {
+    /// No operator was given.
+    ///
+    /// The clause is optional for fuzzy queries and required for exact queries.
+    Default,
+
+    /// The clause was joined with `AND` and must match.
+    Must,
+
+    /// The clause was joined with `OR` and is optional.
+    Should,
+
+    /// The clause was prefixed with `-` and must not match.
+    MustNot,
+}
+
+#[derive(Debug)]
+struct QueryClause {
+    kind: ClauseKind,
+    tokens: Vec<String>,
+}
+
+pub fn distribution_query(
+    query: Option<&str>,
+    fields: &[SearchField],
+) -> Box<dyn Query> {
     let query = match query {
         None => return Box::new(AllQuery {}),
         Some("*") => return Box::new(AllQuery {}),
         Some(q) => q,
     };
 
-    let tokenizer = SimpleUnicodeTokenizer::with_limit(10);
-    let mut token_stream = tokenizer.token_stream(query);
+    let clauses = parse_clauses(query);
+    if is_negation_only(&clauses) {
+        return build_negation_only(fields, &clauses);
+    }
 
-    build_fuzzy_stage(2, 0, fields, &mut token_stream)
-        .unwrap_or_else(|| Box::new(EmptyQuery {}))
+    build_fuzzy_stage(2, 0, fields, &clauses).unwrap_or_else(|| Box::new(EmptyQuery {}))
 }
 
-pub fn parse_query(query: Option<&str>, fields: &[Field]) -> Vec<Box<dyn Query>> {
+pub fn parse_query(query: Option<&str>, fields: &[SearchField]) -> Vec<Box<dyn Query>> {
     let query = match query {
         None => return vec![Box::new(AllQuery {})],
         Some("*") => return vec![Box::new(AllQuery {})],
         Some(q) => q,
     };
 
-    let tokenizer = SimpleUnicodeTokenizer::with_limit(10);
-    let mut token_stream = tokenizer.token_stream(query);
+    let clauses = parse_clauses(query);
+    if is_negation_only(&clauses) {
+        return vec![build_negation_only(fields, &clauses)];
+    }
+
     let mut stages = vec![];
 
-    add_if_exists!(stages, build_fuzzy_stage(0, 0, fields, &mut token_stream));
-    add_if_exists!(stages, build_fuzzy_stage(1, 4, fields, &mut token_stream));
-    add_if_exists!(stages, build_fuzzy_stage(2, 8, fields, &mut token_stream));
+    add_if_exists!(stages, build_fuzzy_stage(0, 0, fields, &clauses));
+    add_if_exists!(stages, build_fuzzy_stage(1, 4, fields, &clauses));
+    add_if_exists!(stages, build_fuzzy_stage(2, 8, fields, &clauses));
 
     stages
 }
 
+/// Builds a query which only matches documents containing every token of the
+/// query verbatim, bypassing all of the fuzzy stages.
+pub fn exact_query(query: Option<&str>, fields: &[SearchField]) -> Box<dyn Query> {
+    let query = match query {
+        None => return Box::new(AllQuery {}),
+        Some("*") => return Box::new(AllQuery {}),
+        Some(q) => q,
+    };
+
+    let clauses = parse_clauses(query);
+    if is_negation_only(&clauses) {
+        return build_negation_only(fields, &clauses);
+    }
+
+    let parts = clauses
+        .iter()
+        .map(|clause| {
+            let occur = match clause.kind {
+                ClauseKind::Default | ClauseKind::Must => Occur::Must,
+                ClauseKind::Should => Occur::Should,
+                ClauseKind::MustNot => Occur::MustNot,
+            };
+
+            (occur, build_term_clause(fields, &clause.tokens))
+        })
+        .collect::<Vec<_>>();
+
+    if parts.is_empty() {
+        return Box::new(EmptyQuery {});
+    }
+
+    Box::new(BooleanQuery::new(parts))
+}
+
+/// Splits the query into clauses, handling the `AND`, `OR` and `-term` operators.
+fn parse_clauses(query: &str) -> Vec<QueryClause> {
+    let tokenizer = SimpleUnicodeTokenizer::default();
+
+    let mut clauses: Vec<QueryClause> = vec![];
+    let mut next_kind = ClauseKind::Default;
+    for word in query.split_whitespace() {
+        match word {
+            "AND" => {
+                if let Some(last) = clauses.last_mut() {
+                    if last.kind != ClauseKind::MustNot {
+                        last.kind = ClauseKind::Must;
+                    }
+                }
+
+                next_kind = ClauseKind::Must;
+                continue;
+            },
+            "OR" => {
+                if let Some(last) = clauses.last_mut() {
+                    if last.kind == ClauseKind::Default {
+                        last.kind = ClauseKind::Should;
+                    }
+                }
+
+                next_kind = ClauseKind::Should;
+                continue;
+            },
+            _ => {},
+        }
+
+        let (kind, text) = match word.strip_prefix('-') {
+            Some(text) if !text.is_empty() => (ClauseKind::MustNot, text),
+            _ => (next_kind, word),
+        };
+        next_kind = ClauseKind::Default;
+
+        let tokens = tokenizer
+            .produce_tokens(text)
+            .into_iter()
+            .map(|token| token.text)
+            .collect::<Vec<_>>();
+
+        if !tokens.is_empty() {
+            clauses.push(QueryClause { kind, tokens });
+        }
+    }
+
+    // Each word is tokenized on its own, so stopwords need to be removed
+    // relative to the whole query rather than the individual word.
+    let has_keywords = clauses
+        .iter()
+        .flat_map(|clause| clause.tokens.iter())
+        .any(|token| !tokenizer.is_stopword(token));
+
+    if has_keywords {
+        for clause in clauses.iter_mut() {
+            clause.tokens.retain(|token| !tokenizer.is_stopword(token));
+        }
+    }
+
+    let mut remaining = MAX_QUERY_TOKENS;
+    for clause in clauses.iter_mut() {
+        clause.tokens.truncate(remaining);
+        remaining -= clause.tokens.len();
+    }
+    clauses.retain(|clause| !clause.tokens.is_empty());
+
+    clauses
+}
+
+fn is_negation_only(clauses: &[QueryClause]) -> bool {
+    !clauses.is_empty() && clauses.iter().all(|c| c.kind == ClauseKind::MustNot)
+}
+
+/// Matches every document except the ones matching the negated clauses.
+fn build_negation_only(
+    fields: &[SearchField],
+    clauses: &[QueryClause],
+) -> Box<dyn Query> {
+    let mut parts = vec![(Occur::Must, Box::new(AllQuery {}) as Box<dyn Query>)];
+    for clause in clauses {
+        parts.push((Occur::MustNot, build_term_clause(fields, &clause.tokens)));
+    }
+
+    Box::new(BooleanQuery::new(parts))
+}
+
+/// Builds a query which requires every token to be present verbatim in any field.
+fn build_term_clause(fields: &[SearchField], tokens: &[String]) -> Box<dyn Query> {
+    let parts = tokens
+        .iter()
+        .map(|token| {
+            let field_parts = boosted_fields(fields)
+                .map(|(field, boost)| {
+                    let term = field.term(token);
+                    let query =
+                        Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs));
+
+                    (
+                        Occur::Should,
+                        Box::new(BoostQuery::new(query, boost)) as Box<dyn Query>,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            (
+                Occur::Must,
+                Box::new(BooleanQuery::new(field_parts)) as Box<dyn Query>,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Box::new(BooleanQuery::new(parts))
+}
+
 fn build_fuzzy_stage(
     dist: u8,
     length_cut_off: usize,
-    fields: &[Field],
-    token_stream: &mut SimpleTokenStream,
+    fields: &[SearchField],
+    clauses: &[QueryClause],
 ) -> Option<Box<dyn Query>> {
-    let mut stage = {
-        let mut inner = vec![];
-        for _ in 0..fields.len() {
-            inner.push(vec![]);
+    let synonyms = crate::models::synonyms::synonyms();
+
+    let mut has_fuzzy = false;
+    let mut parts = vec![];
+    for clause in clauses {
+        if clause.kind == ClauseKind::MustNot {
+            parts.push((Occur::MustNot, build_term_clause(fields, &clause.tokens)));
+            continue;
         }
 
-        inner
-    };
+        let mut clause_parts = vec![];
+        for token in clause.tokens.iter() {
+            if token.len() < length_cut_off {
+                continue;
+            }
 
-    while let Some(token) = token_stream.next() {
-        if token.text.len() < length_cut_off {
-            continue;
+            let expansions = synonyms
+                .get(token)
+                .map(|v| v.as_slice())
+                .unwrap_or_default();
+
+            for text in std::iter::once(token).chain(expansions) {
+                for (field, boost) in boosted_fields(fields) {
+                    let term = field.term(text);
+                    let query = Box::new(FuzzyTermQuery::new_prefix(term, dist, true));
+
+                    clause_parts.push((
+                        Occur::Should,
+                        Box::new(BoostQuery::new(query, boost)) as Box<dyn Query>,
+                    ));
+                }
+            }
         }
 
-        for (i, field) in fields.iter().copied().enumerate() {
-            let term = Term::from_field_text(field, token.text.as_str());
-            stage[i].push((
-                Occur::Should,
-                Box::new(FuzzyTermQuery::new_prefix(term, dist, true)) as Box<dyn Query>,
-            ));
+        if clause_parts.is_empty() {
+            // A required clause must still match when its tokens are too
+            // short to be fuzzy, otherwise the stage would drop the `AND`.
+            if clause.kind == ClauseKind::Must {
+                parts.push((Occur::Must, build_term_clause(fields, &clause.tokens)));
+            }
+            continue;
         }
+
+        let occur = match clause.kind {
+            ClauseKind::Must => Occur::Must,
+            _ => Occur::Should,
+        };
+
+        has_fuzzy = true;
+        parts.push((
+            occur,
+            Box::new(BooleanQuery::new(clause_parts)) as Box<dyn Query>,
+        ));
     }
-    token_stream.reset();
 
-    if stage[0].is_empty() {
+    // Without a fuzzy clause the stage only repeats an earlier one.
+    if !has_fuzzy {
         return None;
     }
 
-    let mut boost_factor = 1.0;
-    let mut built_queries = vec![];
-    for field_stage in stage {
-        let boolean = Box::new(BooleanQuery::new(field_stage));
-        let boosted = Box::new(BoostQuery::new(boolean, boost_factor)) as Box<dyn Query>;
+    Some(Box::new(BooleanQuery::new(parts)))
+}
+
+/// Pairs each field with its boost, earlier fields are considered more relevant.
+fn boosted_fields(
+    fields: &[SearchField],
+) -> impl Iterator<Item = (&SearchField, Score)> + '_ {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| (field, 1.0 - (i as Score * 0.10)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::collector::DocSetCollector;
+    use tantivy::schema::{SchemaBuilder, STORED, TEXT};
+    use tantivy::{doc, Index};
+
+    use super::*;
+
+    fn parse_and_compare(query: &str, expected: Vec<(ClauseKind, Vec<&str>)>) {
+        let clauses = parse_clauses(query)
+            .into_iter()
+            .map(|c| (c.kind, c.tokens))
+            .collect::<Vec<_>>();
 
-        built_queries.push((Occur::Should, boosted));
-        boost_factor -= 0.10;
+        let expected = expected
+            .into_iter()
+            .map(|(kind, tokens)| {
+                (
+                    kind,
+                    tokens.into_iter().map(String::from).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(clauses, expected);
+    }
+
+    #[test]
+    fn test_plain_words() {
+        parse_and_compare(
+            "music bot",
+            vec![
+                (ClauseKind::Default, vec!["music"]),
+                (ClauseKind::Default, vec!["bot"]),
+            ],
+        );
     }
 
-    Some(Box::new(BooleanQuery::new(built_queries)))
+    #[test]
+    fn test_and_operator() {
+        parse_and_compare(
+            "music AND dashboard",
+            vec![
+                (ClauseKind::Must, vec!["music"]),
+                (ClauseKind::Must, vec!["dashboard"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_or_operator() {
+        parse_and_compare(
+            "moderation music OR audio",
+            vec![
+                (ClauseKind::Default, vec!["moderation"]),
+                (ClauseKind::Should, vec!["music"]),
+                (ClauseKind::Should, vec!["audio"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_negation() {
+        parse_and_compare(
+            "music -economy -",
+            vec![
+                (ClauseKind::Default, vec!["music"]),
+                (ClauseKind::MustNot, vec!["economy"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_and_operator_keeps_short_tokens() {
+        let mut builder = SchemaBuilder::new();
+        let username = builder.add_text_field("username", TEXT | STORED);
+        let index = Index::create_in_ram(builder.build());
+
+        let mut writer = index.writer(15_000_000).unwrap();
+        for name in ["dj music", "dj", "music", "musics"] {
+            writer.add_document(doc!(username => name)).unwrap();
+        }
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let fields = [SearchField::plain(username)];
+        let stages = parse_query(Some("dj AND music"), &fields);
+        assert_eq!(stages.len(), 2);
+
+        for stage in stages {
+            let names = searcher
+                .search(stage.as_ref(), &DocSetCollector)
+                .unwrap()
+                .into_iter()
+                .map(|addr| {
+                    let doc = searcher.doc(addr).unwrap();
+                    doc.get_first(username)
+                        .and_then(|v| v.as_text())
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(names, vec!["dj music"]);
+        }
+    }
+
+    #[test]
+    fn test_lowercase_operators_are_words() {
+        parse_and_compare(
+            "rock and roll",
+            vec![
+                (ClauseKind::Default, vec!["rock"]),
+                (ClauseKind::Default, vec!["and"]),
+                (ClauseKind::Default, vec!["roll"]),
+            ],
+        );
+    }
 }
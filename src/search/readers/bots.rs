@@ -1,21 +1,112 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use backend_common::types::JsSafeBigInt;
 use once_cell::sync::OnceCell;
 use poem_openapi::{Enum, Object};
 use tantivy::collector::TopDocs;
-use tantivy::query::{BooleanQuery, Occur, Query, TermQuery};
+use tantivy::query::{
+    BooleanQuery,
+    BoostQuery,
+    ConstScoreQuery,
+    Occur,
+    Query,
+    RangeQuery,
+    TermQuery,
+};
 use tantivy::schema::{Field, IndexRecordOption};
-use tantivy::{DocAddress, IndexReader, Searcher, Term};
-use tokio::sync::{oneshot, Semaphore};
+use tantivy::{DocAddress, IndexReader, Score, Searcher, Term};
+use tokio::sync::oneshot;
 
 use crate::models::bots;
-use crate::search::index_impls::bots::TAGS_AGG_FIELD;
-use crate::search::readers::{extract_search_data, Order, SearchResult};
+use crate::search::es_query::{self, EsQuery};
+use crate::search::index_impls::bots::{GUILD_COUNT_FIELD, TAGS_AGG_FIELD};
+use crate::search::limiter::AdaptiveLimiter;
+use crate::search::queries::SearchField;
+use crate::search::readers::{
+    extract_search_data,
+    CountMode,
+    Facet,
+    FacetCounts,
+    FormulaRanking,
+    Highlighter,
+    IndexChecksum,
+    Order,
+    RankingStrategy,
+    RecencyDecay,
+    ScoreBoosts,
+    SearchResult,
+    SortKey,
+    SortSpec,
+};
+use crate::search::scheduler::{self, QueryCost};
+use crate::search::tokenizer::{prefix_key, SimpleUnicodeTokenizer};
 use crate::search::FromTantivyDoc;
 
+/// The maximum number of description terms used to find similar bots.
+const MAX_SIMILAR_DESCRIPTION_TERMS: usize = 10;
+
+/// How much more a shared tag counts than a shared description term.
+const SIMILAR_TAG_BOOST: Score = 2.0;
+
+/// The boost given to a bot created right now when recency decay is enabled.
+const RECENCY_WEIGHT: f64 = 0.1;
+
+/// The start of each guild count range, guild counts follow a power law so
+/// each range is ten times the size of the previous one.
+const GUILD_COUNT_RANGES: &[f64] =
+    &[0.0, 100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0];
+
 static BOT_READER: OnceCell<InnerReader> = OnceCell::new();
+static FEATURED_BOOST: OnceCell<Score> = OnceCell::new();
+static PREMIUM_BOOST: OnceCell<Score> = OnceCell::new();
+static RECENCY_HALF_LIFE: OnceCell<Duration> = OnceCell::new();
+
+/// Sets the score added to featured bots when sorting by relevancy.
+pub fn set_featured_boost(boost: Score) {
+    let _ = FEATURED_BOOST.set(boost);
+}
+
+/// Sets the factor the score of premium bots is multiplied by when
+/// sorting by relevancy.
+pub fn set_premium_boost(boost: Score) {
+    let _ = PREMIUM_BOOST.set(boost);
+}
+
+/// Sets how long it takes the boost newer bots get when sorting by
+/// relevancy to halve.
+pub fn set_recency_half_life(half_life: Duration) {
+    let _ = RECENCY_HALF_LIFE.set(half_life);
+}
+
+/// A ranking formula results can be sorted by instead of a sort field.
+#[derive(Enum, Debug, Copy, Clone)]
+#[oai(rename_all = "snake_case")]
+pub enum BotsRanking {
+    /// votes * ln(guild count) + trending score.
+    Composite,
+
+    /// relevance * ln(votes).
+    WeightedRelevancy,
+}
+
+impl BotsRanking {
+    pub fn strategy(self) -> Arc<dyn RankingStrategy> {
+        match self {
+            Self::Composite => Arc::new(FormulaRanking(|id, _| {
+                let votes = bots::get_bot_votes(id) as f64;
+                let guilds = bots::get_bot_guild_count(id) as f64;
+
+                votes * guilds.ln_1p() + bots::get_bot_trending_score(id)
+            })),
+            Self::WeightedRelevancy => Arc::new(FormulaRanking(|id, score| {
+                score as f64 * (1.0 + (bots::get_bot_votes(id) as f64).ln_1p())
+            })),
+        }
+    }
+}
 
 pub fn reader() -> &'static InnerReader {
     BOT_READER.get().unwrap()
@@ -23,9 +114,9 @@ pub fn reader() -> &'static InnerReader {
 
 pub fn init(
     ctx: FieldContext,
-    search_fields: Vec<Field>,
+    search_fields: Vec<SearchField>,
     reader: IndexReader,
-    concurrency_limiter: Arc<Semaphore>,
+    concurrency_limiter: Arc<AdaptiveLimiter>,
 ) {
     BOT_READER.get_or_init(|| {
         InnerReader::new(ctx, search_fields, reader, concurrency_limiter)
@@ -41,17 +132,35 @@ pub enum BotsSortBy {
     /// Sort by votes.
     Votes,
 
+    /// Sort by the votes of every month combined.
+    AllTimeVotes,
+
     /// Sort by age.
     Age,
 
+    /// Sort by the creation date.
+    Newest,
+
+    /// Sort alphabetically by name, `asc` orders from A to Z.
+    Name,
+
     /// Sort by the trending score.
     Trending,
 
+    /// Sort by the average rating.
+    Rating,
+
+    /// Sort by how much the guild count grew over the last 7 days.
+    Growth,
+
     /// How many servers the bot is in.
     Popularity,
 
     /// Premium Bots.
     Premium,
+
+    /// Certified Bots.
+    Certified,
 }
 
 impl Default for BotsSortBy {
@@ -60,7 +169,38 @@ impl Default for BotsSortBy {
     }
 }
 
-#[derive(Debug, Enum)]
+impl BotsSortBy {
+    /// Where the value of a bot to sort by is read from.
+    fn sort_key(self, ctx: FieldContext) -> SortKey {
+        match self {
+            Self::Relevancy => SortKey::Relevance,
+            Self::Votes => SortKey::I64(ctx.votes_field),
+            Self::AllTimeVotes => {
+                SortKey::Entity(|id| bots::get_bot_all_time_votes(id) as i64)
+            },
+            Self::Name => SortKey::I64(ctx.name_sort_field),
+            Self::Age | Self::Newest => SortKey::I64(ctx.created_on_field),
+            Self::Trending => SortKey::I64(ctx.trending_field),
+            Self::Rating => SortKey::I64(ctx.rating_field),
+            Self::Growth => SortKey::I64(ctx.growth_field),
+            Self::Popularity => SortKey::U64(ctx.guild_count_field),
+            Self::Premium => SortKey::U64(ctx.premium_field),
+            Self::Certified => SortKey::U64(ctx.certified_field),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Object)]
+pub struct BotsSortCriterion {
+    /// What to sort by.
+    by: BotsSortBy,
+
+    /// Order results Asc or Desc.
+    #[oai(default)]
+    order: Order,
+}
+
+#[derive(Debug, Copy, Clone, Enum)]
 #[oai(rename_all = "lowercase")]
 pub enum FilterMode {
     Union,
@@ -73,44 +213,149 @@ impl Default for FilterMode {
     }
 }
 
-#[derive(Default, Debug, Object)]
+#[derive(Debug, Copy, Clone, Enum)]
+#[oai(rename_all = "lowercase")]
+pub enum FeaturesMatch {
+    /// Match bots with any of the given features.
+    Any,
+
+    /// Match bots with all of the given features.
+    All,
+}
+
+impl Default for FeaturesMatch {
+    fn default() -> Self {
+        Self::Any
+    }
+}
+
+impl FeaturesMatch {
+    #[inline]
+    fn matches(self, value: u64, flags: u64) -> bool {
+        match self {
+            Self::Any => (value & flags) != 0,
+            Self::All => (value & flags) == flags,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Object)]
 #[oai(rename_all = "camelCase")]
 pub struct BotFilter {
     /// A set of tags to filter results by.
     #[oai(validator(max_items = 50, unique_items), default)]
     tags: Vec<String>,
 
+    /// A set of tags to remove from the results.
+    #[oai(validator(max_items = 50, unique_items), default)]
+    exclude_tags: Vec<String>,
+
     /// The set of features to filter by.
     features: Option<JsSafeBigInt>,
 
+    /// If bots must have any or all of the given features.
+    #[oai(default)]
+    features_match: FeaturesMatch,
+
     /// If the bot should be premium or not.
     premium: Option<bool>,
 
+    /// If the bot should be certified or not.
+    certified: Option<bool>,
+
+    /// Only return bots owned by this user.
+    owner_id: Option<JsSafeBigInt>,
+
+    /// Only return bots this user is a co-owner of.
+    co_owner_id: Option<JsSafeBigInt>,
+
+    /// Only return bots using this command prefix, ignoring case.
+    #[oai(validator(min_length = 1, max_length = 32))]
+    prefix: Option<String>,
+
+    /// Only return bots created within this many days.
+    #[oai(validator(minimum(value = "1"), maximum(value = "3650")))]
+    added_within_days: Option<u32>,
+
+    /// Include bots marked as NSFW in the results.
+    #[oai(default)]
+    include_nsfw: bool,
+
     #[oai(default)]
     filter_mode: FilterMode,
 }
 
+impl BotFilter {
+    /// The number of facets the search has to be filtered by.
+    fn num_facets(&self) -> usize {
+        self.tags.len()
+            + self.exclude_tags.len()
+            + self.features.is_some() as usize
+            + self.premium.is_some() as usize
+            + self.certified.is_some() as usize
+            + self.owner_id.is_some() as usize
+            + self.co_owner_id.is_some() as usize
+            + self.prefix.is_some() as usize
+            + self.added_within_days.is_some() as usize
+    }
+}
+
+/// The facets of a bot search besides the tag distribution.
+#[derive(Debug, Default)]
+pub struct BotFacets {
+    /// The number of matching bots which are premium.
+    pub premium_hits: usize,
+
+    /// The number of matching bots with each feature flag set.
+    pub feature_hits: HashMap<u64, usize>,
+
+    /// The number of matching bots per guild count range, along with the
+    /// start and end of the range.
+    pub guild_counts: Vec<(f64, Option<f64>, usize)>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct FieldContext {
     pub id_field: Field,
+    pub created_on_field: Field,
+    pub name_sort_field: Field,
     pub premium_field: Field,
+    pub nsfw_field: Field,
+    pub hidden_field: Field,
+    pub certified_field: Field,
+    pub votes_field: Field,
+    pub trending_field: Field,
+    pub rating_field: Field,
+    pub growth_field: Field,
+    pub guild_count_field: Field,
+    pub prefix_field: Field,
+    pub owner_id_field: Field,
+    pub co_owner_ids_field: Field,
     pub tags_agg_field: Field,
     pub features_field: Field,
+    pub featured_field: Field,
+    pub description_field: Field,
+
+    /// The text fields to produce highlights for and their name in the hit.
+    pub highlight_fields: [(&'static str, Field); 2],
+
+    /// The fields whose terms are used to correct typos in the query.
+    pub suggestion_fields: [Field; 2],
 }
 
 pub struct InnerReader {
     ctx: FieldContext,
     reader: IndexReader,
-    concurrency_limiter: Arc<Semaphore>,
-    search_fields: Arc<Vec<Field>>,
+    concurrency_limiter: Arc<AdaptiveLimiter>,
+    search_fields: Arc<Vec<SearchField>>,
 }
 
 impl InnerReader {
     fn new(
         ctx: FieldContext,
-        search_fields: Vec<Field>,
+        search_fields: Vec<SearchField>,
         reader: IndexReader,
-        concurrency_limiter: Arc<Semaphore>,
+        concurrency_limiter: Arc<AdaptiveLimiter>,
     ) -> Self {
         Self {
             ctx,
@@ -120,15 +365,48 @@ impl InnerReader {
         }
     }
 
+    /// Reloads the reader so the latest commit is searchable straight away.
+    pub fn reload(&self) -> Result<()> {
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    /// Get a searcher of the current commit, the files of its segments are
+    /// never garbage collected while it's held.
+    pub fn searcher(&self) -> Searcher {
+        self.reader.searcher()
+    }
+
+    /// Identifies the commit currently being searched, this changes
+    /// every time the reader reloads.
+    pub fn generation(&self) -> u64 {
+        self.reader.searcher().generation().generation_id()
+    }
+
+    /// The number of documents currently searchable.
+    pub fn num_docs(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    /// A checksum of the documents currently searchable.
+    pub fn checksum(&self) -> Result<IndexChecksum> {
+        super::compute_checksum(&self.reader.searcher(), self.ctx.id_field)
+    }
+
     pub async fn search<T>(
         &self,
         query: Option<String>,
         filter: BotFilter,
+        es_query: Option<EsQuery>,
         limit: usize,
         offset: usize,
         sort_by: BotsSortBy,
         order: Order,
-    ) -> Result<SearchResult<T>>
+        then_by: Vec<BotsSortCriterion>,
+        ranking: Option<Arc<dyn RankingStrategy>>,
+        exact: bool,
+        count_mode: CountMode,
+    ) -> Result<SearchResult<T, BotFacets>>
     where
         T: FromTantivyDoc + Sync + Send + 'static,
     {
@@ -138,11 +416,16 @@ impl InnerReader {
         let searcher = self.reader.searcher();
         let ctx = self.ctx;
         let fields = self.search_fields.clone();
+        let es_query = es_query.map(|q| q.build(searcher.index())).transpose()?;
+
+        let cost =
+            QueryCost::classify(query.as_deref(), limit, offset, filter.num_facets());
 
-        rayon::spawn(move || {
+        scheduler::spawn(cost, move || {
             let state = execute_search(
                 ctx,
                 filter,
+                es_query,
                 fields.as_ref(),
                 &searcher,
                 query,
@@ -150,6 +433,10 @@ impl InnerReader {
                 offset,
                 sort_by,
                 order,
+                then_by,
+                ranking,
+                exact,
+                count_mode,
             );
 
             let _ = waker.send(state);
@@ -157,29 +444,152 @@ impl InnerReader {
 
         rx.await?
     }
+
+    /// Finds the bots most similar to the given bot by its tags and description.
+    ///
+    /// Hidden bots are excluded by the query itself, so at most `limit` bots
+    /// are ever collected. Returns `None` if the bot does not exist.
+    pub async fn similar<T>(&self, bot_id: i64, limit: usize) -> Result<Option<Vec<T>>>
+    where
+        T: FromTantivyDoc + Sync + Send + 'static,
+    {
+        let bot = match bots::get_bot_data(bot_id) {
+            Some(bot) => bot,
+            None => return Ok(None),
+        };
+
+        let _permit = self.concurrency_limiter.acquire().await?;
+        let (waker, rx) = oneshot::channel();
+
+        let searcher = self.reader.searcher();
+        let ctx = self.ctx;
+
+        scheduler::spawn(QueryCost::Cheap, move || {
+            let state = execute_similar(ctx, &searcher, &bot, limit);
+            let _ = waker.send(state);
+        });
+
+        rx.await?.map(Some)
+    }
+}
+
+fn execute_similar<T>(
+    ctx: FieldContext,
+    searcher: &Searcher,
+    bot: &bots::Bot,
+    limit: usize,
+) -> Result<Vec<T>>
+where
+    T: FromTantivyDoc + Sync + Send + 'static,
+{
+    let mut parts = bot
+        .tags
+        .iter()
+        .map(|tag| {
+            let term = Term::from_field_text(ctx.tags_agg_field, tag);
+            let query = Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+
+            (
+                Occur::Should,
+                Box::new(BoostQuery::new(query, SIMILAR_TAG_BOOST)) as Box<dyn Query>,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let description = SearchField::stemmed(ctx.description_field);
+    let mut terms = vec![];
+    for token in SimpleUnicodeTokenizer::default().produce_tokens(&bot.brief_description)
+    {
+        let term = description.term(&token.text);
+        if terms.iter().any(|(_, existing)| existing == &term) {
+            continue;
+        }
+
+        // Terms only present in the bot itself can never match another bot.
+        let doc_freq = searcher.doc_freq(&term)?;
+        if doc_freq > 1 {
+            terms.push((doc_freq, term));
+        }
+    }
+
+    // Rarer terms describe the bot better than common ones.
+    terms.sort_by_key(|(doc_freq, _)| *doc_freq);
+    parts.extend(terms.into_iter().take(MAX_SIMILAR_DESCRIPTION_TERMS).map(
+        |(_, term)| {
+            (
+                Occur::Should,
+                Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs))
+                    as Box<dyn Query>,
+            )
+        },
+    ));
+
+    if parts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    parts.push((
+        Occur::MustNot,
+        Box::new(TermQuery::new(
+            Term::from_field_i64(ctx.id_field, *bot.id),
+            IndexRecordOption::Basic,
+        )),
+    ));
+
+    parts.push((Occur::MustNot, hidden_query(ctx)));
+
+    // NSFW bots are only recommended alongside other NSFW bots.
+    if (*bot.flags & bots::flags::NSFW) == 0 {
+        parts.push((Occur::MustNot, nsfw_query(ctx)));
+    }
+
+    let query = BooleanQuery::new(parts);
+    let docs = searcher
+        .search(&query, &TopDocs::with_limit(limit))?
+        .into_iter()
+        .map(|(_, addr)| addr);
+
+    extract_search_data(searcher, ctx.id_field, docs, &Highlighter::none())
 }
 
 #[allow(clippy::too_many_arguments)]
 fn execute_search<T>(
     ctx: FieldContext,
     filter: BotFilter,
-    search_fields: &[Field],
+    es_query: Option<Box<dyn Query>>,
+    search_fields: &[SearchField],
     searcher: &Searcher,
     query: Option<String>,
     limit: usize,
     offset: usize,
     sort_by: BotsSortBy,
     order: Order,
-) -> Result<SearchResult<T>>
+    then_by: Vec<BotsSortCriterion>,
+    ranking: Option<Arc<dyn RankingStrategy>>,
+    exact: bool,
+    count_mode: CountMode,
+) -> Result<SearchResult<T, BotFacets>>
 where
     T: FromTantivyDoc + Sync + Send + 'static,
 {
-    let query_stages =
-        crate::search::queries::parse_query(query.as_deref(), search_fields);
+    let query_stages = if exact {
+        vec![crate::search::queries::exact_query(
+            query.as_deref(),
+            search_fields,
+        )]
+    } else {
+        crate::search::queries::parse_query(query.as_deref(), search_fields)
+    };
     let mut result_addresses = vec![];
-    let features_filter = filter.features.map(|v| *v as u64);
+    let features_filter = filter.features.map(|v| (*v as u64, filter.features_match));
     for stage in query_stages {
         let stage = apply_filter(ctx, &filter, stage);
+        let stage = es_query::restrict(stage, es_query.as_deref());
+        let stage = if matches!(sort_by, BotsSortBy::Relevancy) {
+            apply_featured_boost(ctx, stage)
+        } else {
+            stage
+        };
 
         search_docs(
             ctx,
@@ -189,6 +599,8 @@ where
             limit + offset,
             sort_by,
             order,
+            &then_by,
+            ranking.clone(),
             features_filter,
         )?;
 
@@ -197,36 +609,74 @@ where
         }
     }
 
-    let query =
-        crate::search::queries::distribution_query(query.as_deref(), search_fields);
+    let raw_query = query.as_deref();
+    let highlight_query = crate::search::queries::exact_query(raw_query, search_fields);
+    let highlighter =
+        Highlighter::new(searcher, highlight_query.as_ref(), &ctx.highlight_fields)?;
+
+    let query = if exact {
+        crate::search::queries::exact_query(raw_query, search_fields)
+    } else {
+        crate::search::queries::distribution_query(raw_query, search_fields)
+    };
 
+    // Tag counts in union mode ignore the tags being filtered by so
+    // every other tag still shows how many results it would add.
     let query = if matches!(filter.filter_mode, FilterMode::Intersection) {
         apply_filter(ctx, &filter, query)
-    } else if let Some(premium) = filter.premium {
-        Box::new(BooleanQuery::new(vec![
-            (Occur::Must, query),
-            (
-                Occur::Must,
-                Box::new(TermQuery::new(
-                    Term::from_field_u64(ctx.premium_field, premium as u64),
-                    IndexRecordOption::Basic,
-                )),
-            ),
-        ]))
     } else {
-        query
+        apply_filter_with_tags(ctx, &filter, &[], query)
     };
+    let query = es_query::restrict(query, es_query.as_deref());
 
-    let filter =
-        features_filter.map(|flags| (ctx.features_field, move |v| (v & flags) == flags));
+    let filter = features_filter
+        .map(|(flags, mode)| (ctx.features_field, move |v| mode.matches(v, flags)));
 
-    let (count, dist) =
-        super::search_aggregate(query, TAGS_AGG_FIELD.to_string(), searcher, filter)?;
+    let (count, facets) = super::search_aggregate(
+        query,
+        searcher,
+        &[
+            Facet::Terms(TAGS_AGG_FIELD),
+            Facet::Value(ctx.premium_field, 1),
+            Facet::Bits(ctx.features_field),
+            Facet::Ranges(GUILD_COUNT_FIELD, GUILD_COUNT_RANGES),
+        ],
+        count_mode,
+        filter,
+    )?;
+
+    let mut facets = facets.into_iter();
+    let distribution = facets
+        .next()
+        .map(FacetCounts::into_terms)
+        .unwrap_or_default();
+    let bot_facets = BotFacets {
+        premium_hits: facets
+            .next()
+            .map(FacetCounts::into_value)
+            .unwrap_or_default(),
+        feature_hits: facets
+            .next()
+            .map(FacetCounts::into_bits)
+            .unwrap_or_default(),
+        guild_counts: facets
+            .next()
+            .map(FacetCounts::into_ranges)
+            .unwrap_or_default(),
+    };
+
+    let suggestion = maybe_suggest(searcher, &ctx.suggestion_fields, raw_query, count)?;
 
     let docs = result_addresses.into_iter().skip(offset);
-    let loaded = extract_search_data(searcher, ctx.id_field, docs)?;
+    let loaded = extract_search_data(searcher, ctx.id_field, docs, &highlighter)?;
 
-    Ok((count, dist, loaded))
+    Ok(SearchResult {
+        num_hits: count,
+        distribution,
+        facets: bot_facets,
+        hits: loaded,
+        suggestion,
+    })
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -238,15 +688,78 @@ fn search_docs(
     limit: usize,
     sort_by: BotsSortBy,
     order: Order,
-    features_filter: Option<u64>,
+    then_by: &[BotsSortCriterion],
+    ranking: Option<Arc<dyn RankingStrategy>>,
+    features_filter: Option<(u64, FeaturesMatch)>,
 ) -> Result<()> {
     let collector = TopDocs::with_limit(limit);
-    let filter =
-        features_filter.map(|flags| (ctx.features_field, move |v| (v & flags) != 0));
+    let filter = features_filter
+        .map(|(flags, mode)| (ctx.features_field, move |v| mode.matches(v, flags)));
+
+    if let Some(strategy) = ranking {
+        return super::execute_ranked_search(
+            searcher,
+            query,
+            results,
+            ctx.id_field,
+            collector,
+            strategy,
+            order,
+            filter,
+        );
+    }
+
+    if !then_by.is_empty() {
+        let specs = std::iter::once((sort_by, order))
+            .chain(then_by.iter().map(|c| (c.by, c.order)))
+            .map(|(by, order)| SortSpec {
+                key: by.sort_key(ctx),
+                order,
+            })
+            .collect();
+
+        return super::execute_multi_sort_search(
+            searcher,
+            query,
+            results,
+            ctx.id_field,
+            collector,
+            specs,
+            filter,
+        );
+    }
+
     match sort_by {
-        BotsSortBy::Relevancy => super::execute_basic_search(
-            searcher, query, results, collector, order, filter,
+        BotsSortBy::Name => super::execute_fast_field_search(
+            searcher,
+            query,
+            results,
+            ctx.name_sort_field,
+            collector,
+            order,
+            filter,
         ),
+        BotsSortBy::Newest => super::execute_fast_field_search(
+            searcher,
+            query,
+            results,
+            ctx.created_on_field,
+            collector,
+            order,
+            filter,
+        ),
+        BotsSortBy::Relevancy => {
+            let boosts = relevancy_boosts(ctx);
+            if boosts.is_empty() {
+                super::execute_basic_search(
+                    searcher, query, results, collector, order, filter,
+                )
+            } else {
+                super::execute_boosted_search(
+                    searcher, query, results, boosts, collector, order, filter,
+                )
+            }
+        },
         BotsSortBy::Popularity => super::execute_search(
             searcher,
             query,
@@ -267,23 +780,59 @@ fn search_docs(
             order,
             filter,
         ),
-        BotsSortBy::Trending => super::execute_search(
+        BotsSortBy::Certified => super::execute_search(
             searcher,
             query,
             results,
             ctx.id_field,
             collector,
-            bots::get_bot_trending_score,
+            bots::get_bot_certified,
+            order,
+            filter,
+        ),
+        BotsSortBy::Trending => super::execute_fast_field_search(
+            searcher,
+            query,
+            results,
+            ctx.trending_field,
+            collector,
+            order,
+            filter,
+        ),
+        BotsSortBy::Votes => super::execute_fast_field_search(
+            searcher,
+            query,
+            results,
+            ctx.votes_field,
+            collector,
+            order,
+            filter,
+        ),
+        BotsSortBy::Rating => super::execute_fast_field_search(
+            searcher,
+            query,
+            results,
+            ctx.rating_field,
+            collector,
             order,
             filter,
         ),
-        BotsSortBy::Votes => super::execute_search(
+        BotsSortBy::Growth => super::execute_fast_field_search(
+            searcher,
+            query,
+            results,
+            ctx.growth_field,
+            collector,
+            order,
+            filter,
+        ),
+        BotsSortBy::AllTimeVotes => super::execute_search(
             searcher,
             query,
             results,
             ctx.id_field,
             collector,
-            bots::get_bot_votes,
+            bots::get_bot_all_time_votes,
             order,
             filter,
         ),
@@ -302,18 +851,71 @@ fn search_docs(
     Ok(())
 }
 
+/// The premium and recency boosts applied when sorting by relevancy.
+fn relevancy_boosts(ctx: FieldContext) -> ScoreBoosts {
+    let premium = PREMIUM_BOOST.get().copied().unwrap_or(1.0);
+    let flag = (premium != 1.0).then_some((ctx.premium_field, premium));
+
+    let recency = RECENCY_HALF_LIFE
+        .get()
+        .filter(|half_life| !half_life.is_zero())
+        .map(|half_life| RecencyDecay {
+            field: ctx.created_on_field,
+            half_life: half_life.as_secs_f64(),
+            weight: RECENCY_WEIGHT,
+            now: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64,
+        });
+
+    ScoreBoosts { flag, recency }
+}
+
+/// Adds a constant score to featured bots so they rank above regular matches.
+fn apply_featured_boost(
+    ctx: FieldContext,
+    existing_query: Box<dyn Query>,
+) -> Box<dyn Query> {
+    let boost = FEATURED_BOOST.get().copied().unwrap_or_default();
+    if boost <= 0.0 {
+        return existing_query;
+    }
+
+    let featured = TermQuery::new(
+        Term::from_field_u64(ctx.featured_field, 1),
+        IndexRecordOption::Basic,
+    );
+
+    Box::new(BooleanQuery::new(vec![
+        (Occur::Must, existing_query),
+        (
+            Occur::Should,
+            Box::new(ConstScoreQuery::new(Box::new(featured), boost)),
+        ),
+    ]))
+}
+
 fn apply_filter(
     ctx: FieldContext,
     filter: &BotFilter,
     existing_query: Box<dyn Query>,
+) -> Box<dyn Query> {
+    apply_filter_with_tags(ctx, filter, &filter.tags, existing_query)
+}
+
+fn apply_filter_with_tags(
+    ctx: FieldContext,
+    filter: &BotFilter,
+    tags: &[String],
+    existing_query: Box<dyn Query>,
 ) -> Box<dyn Query> {
     let occur = match filter.filter_mode {
         FilterMode::Union => Occur::Should,
         FilterMode::Intersection => Occur::Must,
     };
 
-    let mut parts = filter
-        .tags
+    let tag_parts = tags
         .iter()
         .map(|v| {
             (
@@ -326,6 +928,13 @@ fn apply_filter(
         })
         .collect::<Vec<(Occur, Box<dyn Query>)>>();
 
+    // The tags are grouped on their own, as a should clause next to the
+    // required filters below would only affect the score.
+    let mut parts: Vec<(Occur, Box<dyn Query>)> = vec![];
+    if !tag_parts.is_empty() {
+        parts.push((Occur::Must, Box::new(BooleanQuery::new(tag_parts))));
+    }
+
     if let Some(premium) = filter.premium {
         parts.push((
             Occur::Must,
@@ -336,12 +945,209 @@ fn apply_filter(
         ));
     }
 
-    if parts.is_empty() {
-        existing_query
-    } else {
-        Box::new(BooleanQuery::new(vec![
-            (Occur::Must, existing_query),
-            (Occur::Must, Box::new(BooleanQuery::new(parts))),
-        ]))
+    if let Some(certified) = filter.certified {
+        parts.push((
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(ctx.certified_field, certified as u64),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    if let Some(owner_id) = filter.owner_id.as_deref() {
+        parts.push((
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_i64(ctx.owner_id_field, *owner_id),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    if let Some(co_owner_id) = filter.co_owner_id.as_deref() {
+        parts.push((
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_i64(ctx.co_owner_ids_field, *co_owner_id),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    if let Some(prefix) = filter.prefix.as_deref() {
+        parts.push((
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_text(ctx.prefix_field, &prefix_key(prefix)),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    if let Some(days) = filter.added_within_days {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let since = now - days as i64 * 24 * 60 * 60;
+
+        parts.push((
+            Occur::Must,
+            Box::new(RangeQuery::new_i64(ctx.created_on_field, since..i64::MAX)),
+        ));
+    }
+
+    let mut clauses = vec![
+        (Occur::Must, existing_query),
+        (Occur::MustNot, hidden_query(ctx)),
+    ];
+
+    if !parts.is_empty() {
+        clauses.push((Occur::Must, Box::new(BooleanQuery::new(parts))));
+    }
+
+    if !filter.include_nsfw {
+        clauses.push((Occur::MustNot, nsfw_query(ctx)));
+    }
+
+    for tag in filter.exclude_tags.iter() {
+        clauses.push((
+            Occur::MustNot,
+            Box::new(TermQuery::new(
+                Term::from_field_text(ctx.tags_agg_field, tag),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    Box::new(BooleanQuery::new(clauses))
+}
+
+fn hidden_query(ctx: FieldContext) -> Box<dyn Query> {
+    Box::new(TermQuery::new(
+        Term::from_field_u64(ctx.hidden_field, 1),
+        IndexRecordOption::Basic,
+    ))
+}
+
+fn nsfw_query(ctx: FieldContext) -> Box<dyn Query> {
+    Box::new(TermQuery::new(
+        Term::from_field_u64(ctx.nsfw_field, 1),
+        IndexRecordOption::Basic,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::collector::Count;
+    use tantivy::query::AllQuery;
+    use tantivy::{doc, Index};
+
+    use super::*;
+    use crate::search::index_impls::bots::{
+        default_schema,
+        CREATED_ON_FIELD,
+        HIDDEN_FIELD,
+        NSFW_FIELD,
+    };
+
+    fn test_ctx(index: &Index) -> FieldContext {
+        let schema = index.schema();
+        let field = |name| schema.get_field(name).unwrap();
+        let any = field(CREATED_ON_FIELD);
+
+        FieldContext {
+            id_field: any,
+            created_on_field: field(CREATED_ON_FIELD),
+            name_sort_field: any,
+            premium_field: any,
+            nsfw_field: field(NSFW_FIELD),
+            hidden_field: field(HIDDEN_FIELD),
+            certified_field: any,
+            votes_field: any,
+            trending_field: any,
+            rating_field: any,
+            growth_field: any,
+            guild_count_field: any,
+            prefix_field: any,
+            owner_id_field: any,
+            co_owner_ids_field: any,
+            tags_agg_field: field(TAGS_AGG_FIELD),
+            features_field: any,
+            featured_field: any,
+            description_field: any,
+            highlight_fields: [("username", any), ("description", any)],
+            suggestion_fields: [any, any],
+        }
+    }
+
+    #[test]
+    fn test_union_tags_with_required_filters() {
+        let index = Index::create_in_ram(default_schema());
+        let ctx = test_ctx(&index);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let rows = [
+            ("music", now),
+            ("music", now - 100 * 24 * 60 * 60),
+            ("games", now),
+        ];
+
+        let mut writer = index.writer(15_000_000).unwrap();
+        for (tag, created_on) in rows {
+            writer
+                .add_document(doc!(
+                    ctx.tags_agg_field => tag,
+                    ctx.created_on_field => created_on,
+                ))
+                .unwrap();
+        }
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let tags = vec!["music".to_string(), "moderation".to_string()];
+        let count = |filter: &BotFilter| {
+            let query =
+                apply_filter_with_tags(ctx, filter, &tags, Box::new(AllQuery {}));
+            searcher.search(query.as_ref(), &Count).unwrap()
+        };
+
+        let filter = BotFilter {
+            filter_mode: FilterMode::Union,
+            ..Default::default()
+        };
+        assert_eq!(count(&filter), 2);
+
+        let filter = BotFilter {
+            filter_mode: FilterMode::Union,
+            added_within_days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(count(&filter), 1);
+    }
+
+    #[test]
+    fn test_hidden_bots_are_excluded() {
+        let index = Index::create_in_ram(default_schema());
+        let ctx = test_ctx(&index);
+
+        let mut writer = index.writer(15_000_000).unwrap();
+        for hidden in [0u64, 1, 0] {
+            writer
+                .add_document(doc!(
+                    ctx.tags_agg_field => "music",
+                    ctx.hidden_field => hidden,
+                ))
+                .unwrap();
+        }
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let query = apply_filter(ctx, &BotFilter::default(), Box::new(AllQuery {}));
+        assert_eq!(searcher.search(query.as_ref(), &Count).unwrap(), 2);
     }
 }
@@ -1,7 +1,9 @@
 use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use poem_openapi::Enum;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
 use tantivy::aggregation::agg_req::{
     Aggregation,
     Aggregations,
@@ -9,20 +11,161 @@ use tantivy::aggregation::agg_req::{
     BucketAggregationType,
 };
 use tantivy::aggregation::agg_result::{AggregationResult, BucketResult};
-use tantivy::aggregation::bucket::TermsAggregation;
+use tantivy::aggregation::bucket::{
+    RangeAggregation,
+    RangeAggregationRange,
+    TermsAggregation,
+};
 use tantivy::aggregation::AggregationCollector;
-use tantivy::collector::{Collector, Count, FilterCollector, TopDocs};
-use tantivy::fastfield::FastFieldReader;
-use tantivy::query::Query;
+use tantivy::collector::{
+    Collector,
+    Count,
+    FilterCollector,
+    FruitHandle,
+    MultiCollector,
+    SegmentCollector,
+    TopDocs,
+};
+use tantivy::fastfield::{DynamicFastFieldReader, FastFieldReader};
+use tantivy::query::{Query, Scorer};
 use tantivy::schema::Field;
-use tantivy::{DocAddress, DocId, Score, Searcher, SegmentReader};
+use tantivy::{
+    DocAddress,
+    DocId,
+    DocSet,
+    Score,
+    Searcher,
+    SegmentOrdinal,
+    SegmentReader,
+    SnippetGenerator,
+    TERMINATED,
+};
 
+use crate::search::suggestions::{self, SUGGESTION_THRESHOLD};
 use crate::search::FromTantivyDoc;
 
 pub mod bots;
 pub mod packs;
 
-pub(crate) type SearchResult<T> = (usize, HashMap<String, usize>, Vec<T>);
+pub(crate) struct SearchResult<T, F = ()> {
+    /// The number of documents matching the query.
+    pub num_hits: usize,
+
+    /// The distribution of tags across the matching documents.
+    pub distribution: HashMap<String, usize>,
+
+    /// Any additional facets specific to the entity being searched.
+    pub facets: F,
+
+    /// The requested page of documents.
+    pub hits: Vec<T>,
+
+    /// A corrected version of the query if it produced very few hits.
+    pub suggestion: Option<String>,
+}
+
+/// A facet of the matching documents, computed alongside the hit count.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Facet {
+    /// The number of documents for each term of a text fast field.
+    Terms(&'static str),
+
+    /// The number of documents with the given value in a u64 fast field.
+    Value(Field, u64),
+
+    /// The number of documents with each bit of a u64 fast field set.
+    Bits(Field),
+
+    /// The number of documents in each range of a numeric fast field, the
+    /// ranges start at each bound and the last one is unbounded.
+    Ranges(&'static str, &'static [f64]),
+}
+
+#[derive(Debug)]
+pub(crate) enum FacetCounts {
+    Terms(HashMap<String, usize>),
+    Value(usize),
+
+    /// The counts keyed by the value of the bit, i.e `1 << n`.
+    Bits(HashMap<u64, usize>),
+
+    /// The counts keyed by the start and end of each range.
+    Ranges(Vec<(f64, Option<f64>, usize)>),
+}
+
+impl FacetCounts {
+    /// Extrapolates the counts by the given factor.
+    fn scale(self, factor: f64) -> Self {
+        let scale = |count: usize| (count as f64 * factor).round() as usize;
+
+        match self {
+            Self::Terms(counts) => {
+                Self::Terms(counts.into_iter().map(|(k, v)| (k, scale(v))).collect())
+            },
+            Self::Value(count) => Self::Value(scale(count)),
+            Self::Bits(counts) => {
+                Self::Bits(counts.into_iter().map(|(k, v)| (k, scale(v))).collect())
+            },
+            Self::Ranges(counts) => Self::Ranges(
+                counts
+                    .into_iter()
+                    .map(|(start, end, v)| (start, end, scale(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn into_terms(self) -> HashMap<String, usize> {
+        match self {
+            Self::Terms(counts) => counts,
+            _ => HashMap::new(),
+        }
+    }
+
+    pub fn into_value(self) -> usize {
+        match self {
+            Self::Value(count) => count,
+            _ => 0,
+        }
+    }
+
+    pub fn into_bits(self) -> HashMap<u64, usize> {
+        match self {
+            Self::Bits(counts) => counts,
+            _ => HashMap::new(),
+        }
+    }
+
+    pub fn into_ranges(self) -> Vec<(f64, Option<f64>, usize)> {
+        match self {
+            Self::Ranges(counts) => counts,
+            _ => vec![],
+        }
+    }
+}
+
+/// Estimated counts stop searching once this many documents have matched.
+const ESTIMATE_THRESHOLD: usize = 1000;
+
+/// Only ids which are a multiple of this are included in the checksum hash.
+///
+/// The sample is picked by id rather than by position so two replicas with
+/// different segment layouts still hash the same set of documents.
+const CHECKSUM_SAMPLE_RATE: i64 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Object)]
+#[serde(rename_all = "camelCase")]
+#[oai(rename_all = "camelCase")]
+pub struct IndexChecksum {
+    /// The number of documents currently searchable.
+    pub num_docs: u64,
+
+    /// The number of documents included in the hash.
+    pub sampled_docs: u64,
+
+    /// A hash of the sampled document ids.
+    pub hash: String,
+}
 
 #[derive(Enum, Debug, Copy, Clone)]
 #[oai(rename_all = "lowercase")]
@@ -40,6 +183,178 @@ impl Default for Order {
     }
 }
 
+#[derive(Enum, Debug, Copy, Clone)]
+#[oai(rename_all = "lowercase")]
+pub enum CountMode {
+    /// Count every matching document.
+    Exact,
+
+    /// Stop counting once enough documents have matched and extrapolate
+    /// the counts from the part of the index searched so far.
+    Estimate,
+}
+
+impl Default for CountMode {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+
+/// A formula combining the data of an entity into the value results are sorted by.
+///
+/// Strategies are looked up by name when searching, so new formulas can be
+/// tried without adding a new sort option.
+pub trait RankingStrategy: Send + Sync {
+    /// The value of the entity, `score` is its relevance to the query.
+    fn rank(&self, entity_id: i64, score: Score) -> f64;
+}
+
+/// A ranking strategy backed by a plain function.
+pub struct FormulaRanking(pub fn(i64, Score) -> f64);
+
+impl RankingStrategy for FormulaRanking {
+    #[inline]
+    fn rank(&self, entity_id: i64, score: Score) -> f64 {
+        (self.0)(entity_id, score)
+    }
+}
+
+/// Sorts the results by the value produced by the given ranking strategy.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn execute_ranked_search<CB>(
+    searcher: &Searcher,
+    query: Box<dyn Query>,
+    results: &mut Vec<DocAddress>,
+    field: Field,
+    collector: TopDocs,
+    strategy: Arc<dyn RankingStrategy>,
+    order: Order,
+    filter: Option<(Field, CB)>,
+) -> anyhow::Result<()>
+where
+    CB: Fn(u64) -> bool + Sync + Send + Clone + 'static,
+{
+    let collector = collector.tweak_score(move |segment_reader: &SegmentReader| {
+        let reader = segment_reader.fast_fields().i64(field).unwrap();
+        let strategy = strategy.clone();
+
+        move |doc: DocId, original_score: Score| {
+            let value = strategy.rank(reader.get(doc), original_score);
+
+            match order {
+                Order::Desc => value,
+                Order::Asc => -value,
+            }
+        }
+    });
+
+    let docs = apply_filter_and_collect(searcher, query, collector, filter)?;
+    filter_down_addresses(docs, results);
+
+    Ok(())
+}
+
+/// Where the value of a single sort criterion is read from.
+#[derive(Copy, Clone)]
+pub(crate) enum SortKey {
+    /// The relevance of the document to the query.
+    Relevance,
+
+    /// A signed fast field of the document.
+    I64(Field),
+
+    /// An unsigned fast field of the document.
+    U64(Field),
+
+    /// Looks up the value by the entity ID, for values which are not indexed.
+    Entity(fn(i64) -> i64),
+}
+
+/// A single criterion of a multi-criteria sort.
+#[derive(Copy, Clone)]
+pub(crate) struct SortSpec {
+    pub key: SortKey,
+    pub order: Order,
+}
+
+/// A [SortKey] opened for a single segment.
+enum SegmentSortKey {
+    Relevance,
+    I64(DynamicFastFieldReader<i64>),
+    U64(DynamicFastFieldReader<u64>),
+    Entity(fn(i64) -> i64),
+}
+
+/// Maps a float to an integer with the same ordering.
+pub(crate) fn f64_sort_key(value: f64) -> i64 {
+    let bits = value.to_bits() as i64;
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+/// Sorts the results by each criterion in turn, later criteria only
+/// break ties of the ones before them.
+pub(crate) fn execute_multi_sort_search<CB>(
+    searcher: &Searcher,
+    query: Box<dyn Query>,
+    results: &mut Vec<DocAddress>,
+    field: Field,
+    collector: TopDocs,
+    specs: Vec<SortSpec>,
+    filter: Option<(Field, CB)>,
+) -> anyhow::Result<()>
+where
+    CB: Fn(u64) -> bool + Sync + Send + Clone + 'static,
+{
+    let collector = collector.tweak_score(move |segment_reader: &SegmentReader| {
+        let fast_fields = segment_reader.fast_fields();
+        let reader = fast_fields.i64(field).unwrap();
+        let keys = specs
+            .iter()
+            .map(|spec| {
+                let key = match spec.key {
+                    SortKey::Relevance => SegmentSortKey::Relevance,
+                    SortKey::I64(field) => {
+                        SegmentSortKey::I64(fast_fields.i64(field).unwrap())
+                    },
+                    SortKey::U64(field) => {
+                        SegmentSortKey::U64(fast_fields.u64(field).unwrap())
+                    },
+                    SortKey::Entity(cb) => SegmentSortKey::Entity(cb),
+                };
+
+                (key, spec.order)
+            })
+            .collect::<Vec<_>>();
+
+        move |doc: DocId, original_score: Score| {
+            // Vectors compare lexicographically which gives us the tie breaking,
+            // negating the value flips the order of a single criterion.
+            keys.iter()
+                .map(|(key, order)| {
+                    let value = match key {
+                        SegmentSortKey::Relevance => (0, original_score),
+                        SegmentSortKey::I64(values) => (values.get(doc), 0.0),
+                        SegmentSortKey::U64(values) => {
+                            (i64::try_from(values.get(doc)).unwrap_or(i64::MAX), 0.0)
+                        },
+                        SegmentSortKey::Entity(cb) => (cb(reader.get(doc)), 0.0),
+                    };
+
+                    match order {
+                        Order::Desc => value,
+                        Order::Asc => (value.0.saturating_neg(), -value.1),
+                    }
+                })
+                .collect::<Vec<(i64, Score)>>()
+        }
+    });
+
+    let docs = apply_filter_and_collect(searcher, query, collector, filter)?;
+    filter_down_addresses(docs, results);
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn execute_search<T, CB>(
     searcher: &Searcher,
@@ -95,6 +410,95 @@ where
     Ok(())
 }
 
+/// Adjustments made to the score of each document when sorting by relevancy.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct ScoreBoosts {
+    /// Multiplies the score of any document with the u64 fast field set.
+    pub flag: Option<(Field, Score)>,
+
+    /// Boosts newer documents using an i64 timestamp fast field.
+    pub recency: Option<RecencyDecay>,
+}
+
+impl ScoreBoosts {
+    pub fn is_empty(&self) -> bool {
+        self.flag.is_none() && self.recency.is_none()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct RecencyDecay {
+    /// The timestamp field in seconds.
+    pub field: Field,
+
+    /// The number of seconds it takes for the boost to halve.
+    pub half_life: f64,
+
+    /// The boost given to a document created right now, i.e `0.1` is 10%.
+    pub weight: f64,
+
+    /// The timestamp ages are measured from.
+    pub now: i64,
+}
+
+impl RecencyDecay {
+    #[inline]
+    fn factor(&self, created_on: i64) -> f64 {
+        let age = (self.now - created_on).max(0) as f64;
+        1.0 + self.weight * 0.5f64.powf(age / self.half_life)
+    }
+}
+
+/// Sorts the results by score after applying the given boosts.
+pub(crate) fn execute_boosted_search<CB>(
+    searcher: &Searcher,
+    query: Box<dyn Query>,
+    results: &mut Vec<DocAddress>,
+    boosts: ScoreBoosts,
+    collector: TopDocs,
+    order: Order,
+    filter: Option<(Field, CB)>,
+) -> anyhow::Result<()>
+where
+    CB: Fn(u64) -> bool + Sync + Send + Clone + 'static,
+{
+    let collector = collector.tweak_score(move |segment_reader: &SegmentReader| {
+        let flag = boosts.flag.map(|(field, boost)| {
+            (segment_reader.fast_fields().u64(field).unwrap(), boost)
+        });
+        let recency = boosts.recency.map(|decay| {
+            (
+                segment_reader.fast_fields().i64(decay.field).unwrap(),
+                decay,
+            )
+        });
+
+        move |doc: DocId, original_score: Score| {
+            let mut score = original_score;
+
+            if let Some((reader, boost)) = flag.as_ref() {
+                if reader.get(doc) != 0 {
+                    score *= *boost;
+                }
+            }
+
+            if let Some((reader, decay)) = recency.as_ref() {
+                score *= decay.factor(reader.get(doc)) as Score;
+            }
+
+            match order {
+                Order::Desc => score,
+                Order::Asc => -score,
+            }
+        }
+    });
+
+    let docs = apply_filter_and_collect(searcher, query, collector, filter)?;
+    filter_down_addresses(docs, results);
+
+    Ok(())
+}
+
 pub(crate) fn collector_for_id_desc<T, CB>(
     searcher: &Searcher,
     query: Box<dyn Query>,
@@ -155,10 +559,144 @@ where
     Ok(())
 }
 
+/// Sorts the results by an i64 fast field stored in the index itself
+/// rather than the live data.
+pub(crate) fn execute_fast_field_search<CB>(
+    searcher: &Searcher,
+    query: Box<dyn Query>,
+    results: &mut Vec<DocAddress>,
+    sort_field: Field,
+    collector: TopDocs,
+    order: Order,
+    filter: Option<(Field, CB)>,
+) -> anyhow::Result<()>
+where
+    CB: Fn(u64) -> bool + Sync + Send + Clone + 'static,
+{
+    let collector = collector.tweak_score(move |segment_reader: &SegmentReader| {
+        let reader = segment_reader.fast_fields().i64(sort_field).unwrap();
+
+        move |doc: DocId, original_score: Score| {
+            let value: i64 = reader.get(doc);
+
+            match order {
+                Order::Desc => (value, original_score),
+                Order::Asc => (value.saturating_neg(), original_score),
+            }
+        }
+    });
+
+    let docs = apply_filter_and_collect(searcher, query, collector, filter)?;
+    filter_down_addresses(docs, results);
+
+    Ok(())
+}
+
+/// Computes a cheap checksum of the index used to compare replicas.
+pub(crate) fn compute_checksum(
+    searcher: &Searcher,
+    id_field: Field,
+) -> anyhow::Result<IndexChecksum> {
+    let mut sampled = vec![];
+    for segment_reader in searcher.segment_readers() {
+        let reader = segment_reader.fast_fields().i64(id_field)?;
+
+        for doc in segment_reader.doc_ids_alive() {
+            let entity_id: i64 = reader.get(doc);
+
+            if entity_id.rem_euclid(CHECKSUM_SAMPLE_RATE) == 0 {
+                sampled.push(entity_id);
+            }
+        }
+    }
+
+    sampled.sort_unstable();
+
+    // FNV-1a, this needs to be stable across builds and instances.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for entity_id in sampled.iter() {
+        for byte in entity_id.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    Ok(IndexChecksum {
+        num_docs: searcher.num_docs(),
+        sampled_docs: sampled.len() as u64,
+        hash: format!("{:016x}", hash),
+    })
+}
+
+/// The maximum length of a highlighted fragment.
+const MAX_FRAGMENT_CHARS: usize = 150;
+
+/// Produces the matched fragments of a hit's text fields.
+///
+/// Only terms which match verbatim are highlighted, typo tolerant matches
+/// are not picked up by the snippet generator.
+pub struct Highlighter {
+    generators: HashMap<&'static str, SnippetGenerator>,
+}
+
+impl Highlighter {
+    pub(crate) fn new(
+        searcher: &Searcher,
+        query: &dyn Query,
+        fields: &[(&'static str, Field)],
+    ) -> anyhow::Result<Self> {
+        let mut generators = HashMap::with_capacity(fields.len());
+        for (name, field) in fields {
+            let mut generator = SnippetGenerator::create(searcher, query, *field)?;
+            generator.set_max_num_chars(MAX_FRAGMENT_CHARS);
+
+            generators.insert(*name, generator);
+        }
+
+        Ok(Self { generators })
+    }
+
+    /// A highlighter which never produces any fragments.
+    pub(crate) fn none() -> Self {
+        Self {
+            generators: HashMap::new(),
+        }
+    }
+
+    /// Highlights the given text of a field, matches are wrapped in `<b>` tags.
+    ///
+    /// Returns `None` if the field has no matches.
+    pub fn fragment(&self, field_name: &str, text: &str) -> Option<String> {
+        let snippet = self.generators.get(field_name)?.snippet(text);
+
+        if snippet.highlighted().is_empty() {
+            None
+        } else {
+            Some(snippet.to_html())
+        }
+    }
+}
+
+/// Suggests a corrected query if the search produced too few hits.
+pub(crate) fn maybe_suggest(
+    searcher: &Searcher,
+    fields: &[Field],
+    query: Option<&str>,
+    num_hits: usize,
+) -> anyhow::Result<Option<String>> {
+    match query {
+        Some(query) if query != "*" && num_hits < SUGGESTION_THRESHOLD => {
+            suggestions::suggest(searcher, fields, query)
+        },
+        _ => Ok(None),
+    }
+}
+
 pub(crate) fn extract_search_data<T>(
     searcher: &Searcher,
     id_field: Field,
     address: impl Iterator<Item = DocAddress>,
+    highlighter: &Highlighter,
 ) -> anyhow::Result<Vec<T>>
 where
     T: FromTantivyDoc + Sync + Send + 'static,
@@ -166,7 +704,8 @@ where
     let mut loaded = vec![];
     for doc in address {
         let doc = searcher.doc(doc)?;
-        if let Some(doc) = T::from_doc(id_field, doc) {
+        if let Some(mut doc) = T::from_doc(id_field, doc) {
+            doc.highlight(highlighter);
             loaded.push(doc);
         }
     }
@@ -174,52 +713,264 @@ where
     Ok(loaded)
 }
 
+/// Counts the matching documents and computes each of the given facets.
+///
+/// Everything is collected in a single pass over the matching documents,
+/// the counts are returned in the same order as the facets.
 fn search_aggregate<CB>(
     query: Box<dyn Query>,
-    field_name: String,
     searcher: &Searcher,
+    facets: &[Facet],
+    count_mode: CountMode,
     filter_field: Option<(Field, CB)>,
-) -> anyhow::Result<(usize, HashMap<String, usize>)>
+) -> anyhow::Result<(usize, Vec<FacetCounts>)>
 where
     CB: Fn(u64) -> bool + Sync + Send + Clone + 'static,
 {
-    let terms = TermsAggregation {
-        field: field_name,
-        size: Some(1000),
-        ..Default::default()
+    enum Pending {
+        Aggregation(String),
+        Value(FruitHandle<usize>),
+        Bits(FruitHandle<Vec<usize>>),
+    }
+
+    let mut collector = MultiCollector::new();
+    let count_handle = collector.add_collector(Count);
+
+    let mut aggs = Aggregations::default();
+    let mut pending = Vec::with_capacity(facets.len());
+    for (i, facet) in facets.iter().enumerate() {
+        let bucket_agg = match *facet {
+            Facet::Value(field, value) => {
+                let handle = collector.add_collector(FilterCollector::new(
+                    field,
+                    move |v: u64| v == value,
+                    Count,
+                ));
+                pending.push(Pending::Value(handle));
+                continue;
+            },
+            Facet::Bits(field) => {
+                let handle = collector.add_collector(BitsCollector { field });
+                pending.push(Pending::Bits(handle));
+                continue;
+            },
+            Facet::Terms(field_name) => BucketAggregationType::Terms(TermsAggregation {
+                field: field_name.to_string(),
+                size: Some(1000),
+                ..Default::default()
+            }),
+            Facet::Ranges(field_name, bounds) => {
+                let ranges = bounds
+                    .iter()
+                    .enumerate()
+                    .map(|(i, from)| RangeAggregationRange {
+                        from: Some(*from),
+                        to: bounds.get(i + 1).copied(),
+                    })
+                    .collect();
+
+                BucketAggregationType::Range(RangeAggregation {
+                    field: field_name.to_string(),
+                    ranges,
+                })
+            },
+        };
+
+        let name = i.to_string();
+        aggs.insert(
+            name.clone(),
+            Aggregation::Bucket(BucketAggregation {
+                bucket_agg,
+                sub_aggregation: Aggregations::default(),
+            }),
+        );
+        pending.push(Pending::Aggregation(name));
+    }
+
+    let aggs_handle = collector.add_collector(AggregationCollector::from_aggs(aggs));
+
+    let (mut fruits, factor) = match count_mode {
+        CountMode::Exact => (
+            apply_filter_and_collect(searcher, query, collector, filter_field)?,
+            1.0,
+        ),
+        CountMode::Estimate => match filter_field {
+            None => collect_sampled(searcher, query.as_ref(), collector)?,
+            Some((field, pred)) => collect_sampled(
+                searcher,
+                query.as_ref(),
+                FilterCollector::new(field, pred, collector),
+            )?,
+        },
     };
 
-    let aggs: Aggregations = vec![(
-        "tags".to_string(),
-        Aggregation::Bucket(BucketAggregation {
-            bucket_agg: BucketAggregationType::Terms(terms),
-            sub_aggregation: Aggregations::default(),
-        }),
-    )]
-    .into_iter()
-    .collect();
-    let collector = AggregationCollector::from_aggs(aggs);
-
-    let (count, terms) = if let Some((field, cb)) = filter_field {
-        let collector = FilterCollector::new(field, cb, (Count, collector));
-        searcher.search(&query, &collector)?
+    let count = (count_handle.extract(&mut fruits) as f64 * factor).round() as usize;
+    let mut results = aggs_handle.extract(&mut fruits).0;
+
+    let counts = pending
+        .into_iter()
+        .map(|pending| match pending {
+            Pending::Value(handle) => FacetCounts::Value(handle.extract(&mut fruits)),
+            Pending::Bits(handle) => {
+                let counts = handle
+                    .extract(&mut fruits)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(bit, count)| (1 << bit, count))
+                    .collect();
+
+                FacetCounts::Bits(counts)
+            },
+            Pending::Aggregation(name) => match results.remove(&name) {
+                Some(AggregationResult::BucketResult(BucketResult::Terms {
+                    buckets,
+                    ..
+                })) => FacetCounts::Terms(
+                    buckets
+                        .into_iter()
+                        .map(|v| (v.key.to_string(), v.doc_count as usize))
+                        .collect(),
+                ),
+                // The range below the first bound is always added so that
+                // bucket is dropped.
+                Some(AggregationResult::BucketResult(BucketResult::Range {
+                    buckets,
+                })) => FacetCounts::Ranges(
+                    buckets
+                        .into_iter()
+                        .filter_map(|v| Some((v.from?, v.to, v.doc_count as usize)))
+                        .collect(),
+                ),
+                _ => FacetCounts::Terms(HashMap::new()),
+            },
+        })
+        .map(|counts| counts.scale(factor))
+        .collect();
+
+    Ok((count, counts))
+}
+
+/// Collects documents until enough have matched, stopping part way through
+/// a segment if needed.
+///
+/// Returns the fruit of the searched documents and the factor needed to
+/// extrapolate its counts to the whole index, segments are only searched
+/// up to the doc ID collecting stopped at so the factor is based on how
+/// far through the last segment it got.
+fn collect_sampled<C>(
+    searcher: &Searcher,
+    query: &dyn Query,
+    collector: C,
+) -> anyhow::Result<(C::Fruit, f64)>
+where
+    C: Collector,
+{
+    let scoring = collector.requires_scoring();
+    let weight = query.weight(searcher, scoring)?;
+
+    let mut fruits = vec![];
+    let mut matched = 0;
+    let mut searched_docs = 0.0;
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let mut segment_collector =
+            collector.for_segment(segment_ord as SegmentOrdinal, segment_reader)?;
+        let mut scorer = weight.scorer(segment_reader, 1.0)?;
+        let alive_bitset = segment_reader.alive_bitset();
+
+        let mut stopped_at = None;
+        let mut doc = scorer.doc();
+        while doc != TERMINATED {
+            if alive_bitset.map_or(true, |alive| alive.is_alive(doc)) {
+                let score = if scoring { scorer.score() } else { 0.0 };
+                segment_collector.collect(doc, score);
+
+                matched += 1;
+                if matched >= ESTIMATE_THRESHOLD {
+                    stopped_at = Some(doc + 1);
+                    break;
+                }
+            }
+
+            doc = scorer.advance();
+        }
+        fruits.push(segment_collector.harvest());
+
+        let num_docs = segment_reader.num_docs() as f64;
+        match stopped_at {
+            Some(stopped_at) => {
+                let max_doc = segment_reader.max_doc().max(1) as f64;
+                searched_docs += num_docs * (stopped_at as f64 / max_doc);
+                break;
+            },
+            None => searched_docs += num_docs,
+        }
+    }
+
+    let factor = if searched_docs == 0.0 {
+        1.0
     } else {
-        searcher.search(&query, &(Count, collector))?
+        searcher.num_docs() as f64 / searched_docs
     };
 
-    let (_, first_agg) = terms.0.into_iter().next().unwrap();
-    let mut distributions = HashMap::new();
-    if let AggregationResult::BucketResult(BucketResult::Terms { buckets, .. }) =
-        first_agg
-    {
-        distributions.extend(
-            buckets
-                .into_iter()
-                .map(|v| (v.key.to_string(), v.doc_count as usize)),
-        );
+    Ok((collector.merge_fruits(fruits)?, factor))
+}
+
+/// Counts how many documents have each bit of a u64 fast field set.
+struct BitsCollector {
+    field: Field,
+}
+
+impl Collector for BitsCollector {
+    type Fruit = Vec<usize>;
+    type Child = BitsSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(BitsSegmentCollector {
+            reader: segment_reader.fast_fields().u64(self.field)?,
+            counts: vec![0; u64::BITS as usize],
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, fruits: Vec<Vec<usize>>) -> tantivy::Result<Vec<usize>> {
+        let mut merged = vec![0; u64::BITS as usize];
+        for fruit in fruits {
+            for (total, count) in merged.iter_mut().zip(fruit) {
+                *total += count;
+            }
+        }
+
+        Ok(merged)
     }
+}
+
+struct BitsSegmentCollector {
+    reader: DynamicFastFieldReader<u64>,
+    counts: Vec<usize>,
+}
+
+impl SegmentCollector for BitsSegmentCollector {
+    type Fruit = Vec<usize>;
 
-    Ok((count, distributions))
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        let mut value = self.reader.get(doc);
+        while value != 0 {
+            self.counts[value.trailing_zeros() as usize] += 1;
+            value &= value - 1;
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        self.counts
+    }
 }
 
 fn apply_filter_and_collect<C, CB>(
@@ -7,11 +7,26 @@ use tantivy::collector::TopDocs;
 use tantivy::query::{BooleanQuery, Occur, Query, TermQuery};
 use tantivy::schema::{Field, IndexRecordOption};
 use tantivy::{DocAddress, IndexReader, Searcher, Term};
-use tokio::sync::{oneshot, Semaphore};
+use tokio::sync::oneshot;
 
 use crate::models::packs;
+use crate::search::es_query::{self, EsQuery};
 use crate::search::index_impls::packs::TAG_AGG_FIELD;
-use crate::search::readers::{extract_search_data, Order, SearchResult};
+use crate::search::limiter::AdaptiveLimiter;
+use crate::search::queries::SearchField;
+use crate::search::readers::{
+    extract_search_data,
+    f64_sort_key,
+    CountMode,
+    Facet,
+    Highlighter,
+    IndexChecksum,
+    Order,
+    SearchResult,
+    SortKey,
+    SortSpec,
+};
+use crate::search::scheduler::{self, QueryCost};
 use crate::search::FromTantivyDoc;
 
 static PACK_READER: OnceCell<InnerReader> = OnceCell::new();
@@ -22,9 +37,9 @@ pub fn reader() -> &'static InnerReader {
 
 pub fn init(
     ctx: FieldContext,
-    search_fields: Vec<Field>,
+    search_fields: Vec<SearchField>,
     reader: IndexReader,
-    concurrency_limiter: Arc<Semaphore>,
+    concurrency_limiter: Arc<AdaptiveLimiter>,
 ) {
     PACK_READER.get_or_init(|| {
         InnerReader::new(ctx, search_fields, reader, concurrency_limiter)
@@ -37,17 +52,29 @@ pub enum PacksSortBy {
     /// Sort by relevance.
     Relevancy,
 
-    /// Sort by votes.
+    /// Sort by likes.
+    Likes,
+
+    /// The same as `likes`, kept for existing clients.
     Votes,
 
     /// Sort by age.
     Age,
 
+    /// Sort by the creation date.
+    Newest,
+
+    /// Sort alphabetically by name, `asc` orders from A to Z.
+    Name,
+
     /// Sort by the trending score.
     Trending,
 
     /// How many bots the pack is in.
     NumBots,
+
+    /// Premium Packs.
+    Premium,
 }
 
 impl Default for PacksSortBy {
@@ -56,32 +83,79 @@ impl Default for PacksSortBy {
     }
 }
 
-#[derive(Default, Debug, Object)]
+impl PacksSortBy {
+    /// Where the value of a pack to sort by is read from.
+    fn sort_key(self, ctx: FieldContext) -> SortKey {
+        match self {
+            Self::Relevancy => SortKey::Relevance,
+            Self::Likes | Self::Votes => SortKey::I64(ctx.likes_field),
+            Self::Name => SortKey::I64(ctx.name_sort_field),
+            Self::Age | Self::Newest => SortKey::I64(ctx.created_on_field),
+            Self::Trending => {
+                SortKey::Entity(|id| f64_sort_key(packs::get_pack_trending_score(id)))
+            },
+            Self::NumBots => SortKey::Entity(|id| packs::get_pack_bot_count(id) as i64),
+            Self::Premium => SortKey::U64(ctx.premium_field),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Object)]
+pub struct PacksSortCriterion {
+    /// What to sort by.
+    by: PacksSortBy,
+
+    /// Order results Asc or Desc.
+    #[oai(default)]
+    order: Order,
+}
+
+#[derive(Default, Debug, Clone, Object)]
 pub struct PackFilter {
     /// A specific category to filter out results.
     #[oai(validator(max_items = 10, unique_items), default)]
     categories: Vec<String>,
+
+    /// If the pack should be premium or not.
+    premium: Option<bool>,
+}
+
+impl PackFilter {
+    /// The number of facets the search has to be filtered by.
+    fn num_facets(&self) -> usize {
+        self.categories.len() + self.premium.is_some() as usize
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct FieldContext {
     pub id_field: Field,
+    pub created_on_field: Field,
+    pub name_sort_field: Field,
     pub tag_agg_field: Field,
+    pub premium_field: Field,
+    pub likes_field: Field,
+
+    /// The text fields to produce highlights for and their name in the hit.
+    pub highlight_fields: [(&'static str, Field); 2],
+
+    /// The fields whose terms are used to correct typos in the query.
+    pub suggestion_fields: [Field; 2],
 }
 
 pub struct InnerReader {
     ctx: FieldContext,
     reader: IndexReader,
-    concurrency_limiter: Arc<Semaphore>,
-    search_fields: Arc<Vec<Field>>,
+    concurrency_limiter: Arc<AdaptiveLimiter>,
+    search_fields: Arc<Vec<SearchField>>,
 }
 
 impl InnerReader {
     fn new(
         ctx: FieldContext,
-        search_fields: Vec<Field>,
+        search_fields: Vec<SearchField>,
         reader: IndexReader,
-        concurrency_limiter: Arc<Semaphore>,
+        concurrency_limiter: Arc<AdaptiveLimiter>,
     ) -> Self {
         Self {
             ctx,
@@ -91,14 +165,40 @@ impl InnerReader {
         }
     }
 
+    /// Reloads the reader so the latest commit is searchable straight away.
+    pub fn reload(&self) -> Result<()> {
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    /// Get a searcher of the current commit, the files of its segments are
+    /// never garbage collected while it's held.
+    pub fn searcher(&self) -> Searcher {
+        self.reader.searcher()
+    }
+
+    /// The number of documents currently searchable.
+    pub fn num_docs(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    /// A checksum of the documents currently searchable.
+    pub fn checksum(&self) -> Result<IndexChecksum> {
+        super::compute_checksum(&self.reader.searcher(), self.ctx.id_field)
+    }
+
     pub async fn search<T>(
         &self,
         query: Option<String>,
         filter: PackFilter,
+        es_query: Option<EsQuery>,
         limit: usize,
         offset: usize,
         sort_by: PacksSortBy,
         order: Order,
+        then_by: Vec<PacksSortCriterion>,
+        exact: bool,
+        count_mode: CountMode,
     ) -> Result<SearchResult<T>>
     where
         T: FromTantivyDoc + Sync + Send + 'static,
@@ -109,11 +209,16 @@ impl InnerReader {
         let searcher = self.reader.searcher();
         let fields = self.search_fields.clone();
         let ctx = self.ctx;
+        let es_query = es_query.map(|q| q.build(searcher.index())).transpose()?;
+
+        let cost =
+            QueryCost::classify(query.as_deref(), limit, offset, filter.num_facets());
 
-        rayon::spawn(move || {
+        scheduler::spawn(cost, move || {
             let state = execute_search(
                 ctx,
                 filter,
+                es_query,
                 fields.as_ref(),
                 &searcher,
                 query,
@@ -121,6 +226,9 @@ impl InnerReader {
                 offset,
                 sort_by,
                 order,
+                then_by,
+                exact,
+                count_mode,
             );
 
             let _ = waker.send(state);
@@ -134,23 +242,34 @@ impl InnerReader {
 fn execute_search<T>(
     ctx: FieldContext,
     filter: PackFilter,
-    search_fields: &[Field],
+    es_query: Option<Box<dyn Query>>,
+    search_fields: &[SearchField],
     searcher: &Searcher,
     query: Option<String>,
     limit: usize,
     offset: usize,
     sort_by: PacksSortBy,
     order: Order,
+    then_by: Vec<PacksSortCriterion>,
+    exact: bool,
+    count_mode: CountMode,
 ) -> Result<SearchResult<T>>
 where
     T: FromTantivyDoc + Sync + Send + 'static,
 {
-    let query_stages =
-        crate::search::queries::parse_query(query.as_deref(), search_fields);
+    let query_stages = if exact {
+        vec![crate::search::queries::exact_query(
+            query.as_deref(),
+            search_fields,
+        )]
+    } else {
+        crate::search::queries::parse_query(query.as_deref(), search_fields)
+    };
     let mut result_addresses = vec![];
 
     for stage in query_stages {
-        let stage = apply_filter(ctx.tag_agg_field, &filter, stage);
+        let stage = apply_filter(ctx, &filter, stage);
+        let stage = es_query::restrict(stage, es_query.as_deref());
 
         search_docs(
             ctx,
@@ -160,6 +279,7 @@ where
             limit + offset,
             sort_by,
             order,
+            &then_by,
         )?;
 
         if result_addresses.len() == (limit + offset) {
@@ -167,22 +287,41 @@ where
         }
     }
 
-    let query =
-        crate::search::queries::distribution_query(query.as_deref(), search_fields);
+    let raw_query = query.as_deref();
+    let highlight_query = crate::search::queries::exact_query(raw_query, search_fields);
+    let highlighter =
+        Highlighter::new(searcher, highlight_query.as_ref(), &ctx.highlight_fields)?;
+
+    let query = if exact {
+        crate::search::queries::exact_query(raw_query, search_fields)
+    } else {
+        crate::search::queries::distribution_query(raw_query, search_fields)
+    };
+    let query = es_query::restrict(query, es_query.as_deref());
 
-    let (count, dist) = super::search_aggregate::<fn(u64) -> bool>(
+    let (count, mut facets) = super::search_aggregate::<fn(u64) -> bool>(
         query,
-        TAG_AGG_FIELD.to_string(),
         searcher,
+        &[Facet::Terms(TAG_AGG_FIELD)],
+        count_mode,
         None,
     )?;
 
-    let docs = result_addresses.into_iter().skip(offset);
-    let loaded = extract_search_data(searcher, ctx.id_field, docs)?;
+    let suggestion = maybe_suggest(searcher, &ctx.suggestion_fields, raw_query, count)?;
 
-    Ok((count, dist, loaded))
+    let docs = result_addresses.into_iter().skip(offset);
+    let loaded = extract_search_data(searcher, ctx.id_field, docs, &highlighter)?;
+
+    Ok(SearchResult {
+        num_hits: count,
+        distribution: facets.remove(0).into_terms(),
+        facets: (),
+        hits: loaded,
+        suggestion,
+    })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn search_docs(
     ctx: FieldContext,
     results: &mut Vec<DocAddress>,
@@ -191,10 +330,49 @@ fn search_docs(
     limit: usize,
     sort_by: PacksSortBy,
     order: Order,
+    then_by: &[PacksSortCriterion],
 ) -> Result<()> {
     let collector = TopDocs::with_limit(limit);
 
+    if !then_by.is_empty() {
+        let specs = std::iter::once((sort_by, order))
+            .chain(then_by.iter().map(|c| (c.by, c.order)))
+            .map(|(by, order)| SortSpec {
+                key: by.sort_key(ctx),
+                order,
+            })
+            .collect();
+
+        return super::execute_multi_sort_search::<fn(u64) -> bool>(
+            searcher,
+            query,
+            results,
+            ctx.id_field,
+            collector,
+            specs,
+            None,
+        );
+    }
+
     match sort_by {
+        PacksSortBy::Name => super::execute_fast_field_search::<fn(u64) -> bool>(
+            searcher,
+            query,
+            results,
+            ctx.name_sort_field,
+            collector,
+            order,
+            None,
+        ),
+        PacksSortBy::Newest => super::execute_fast_field_search::<fn(u64) -> bool>(
+            searcher,
+            query,
+            results,
+            ctx.created_on_field,
+            collector,
+            order,
+            None,
+        ),
         PacksSortBy::Relevancy => super::execute_basic_search::<fn(u64) -> bool>(
             searcher, query, results, collector, order, None,
         ),
@@ -218,13 +396,24 @@ fn search_docs(
             order,
             None,
         ),
-        PacksSortBy::Votes => super::execute_search::<_, fn(u64) -> bool>(
+        PacksSortBy::Likes | PacksSortBy::Votes => {
+            super::execute_fast_field_search::<fn(u64) -> bool>(
+                searcher,
+                query,
+                results,
+                ctx.likes_field,
+                collector,
+                order,
+                None,
+            )
+        },
+        PacksSortBy::Premium => super::execute_search::<_, fn(u64) -> bool>(
             searcher,
             query,
             results,
             ctx.id_field,
             collector,
-            packs::get_pack_likes,
+            packs::get_pack_premium,
             order,
             None,
         ),
@@ -244,30 +433,43 @@ fn search_docs(
 }
 
 fn apply_filter(
-    tag_field: Field,
+    ctx: FieldContext,
     filter: &PackFilter,
     existing_query: Box<dyn Query>,
 ) -> Box<dyn Query> {
-    let parts = filter
+    let categories = filter
         .categories
         .iter()
         .map(|v| {
             (
                 Occur::Should,
                 Box::new(TermQuery::new(
-                    Term::from_field_text(tag_field, v),
+                    Term::from_field_text(ctx.tag_agg_field, v),
                     IndexRecordOption::Basic,
                 )) as Box<dyn Query>,
             )
         })
         .collect::<Vec<_>>();
 
-    if parts.is_empty() {
-        existing_query
+    let mut parts = vec![(Occur::Must, existing_query)];
+
+    if !categories.is_empty() {
+        parts.push((Occur::Must, Box::new(BooleanQuery::new(categories))));
+    }
+
+    if let Some(premium) = filter.premium {
+        parts.push((
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(ctx.premium_field, premium as u64),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    if parts.len() == 1 {
+        parts.remove(0).1
     } else {
-        Box::new(BooleanQuery::new(vec![
-            (Occur::Must, existing_query),
-            (Occur::Must, Box::new(BooleanQuery::new(parts))),
-        ]))
+        Box::new(BooleanQuery::new(parts))
     }
 }
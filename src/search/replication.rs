@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use tantivy::Searcher;
+
+use crate::routes;
+use crate::search::{index_impls, readers};
+
+/// The index meta, this references every segment which is part of the index.
+pub(crate) static META_FILE: &str = "meta.json";
+
+/// Files which change in place and must be shipped on every sync.
+///
+/// Every other file in a tantivy index is written once and never changed,
+/// so comparing name and size is enough to know if a replica has it.
+pub(crate) static MUTABLE_FILES: &[&str] =
+    &["meta.json", ".managed.json", "build-meta.json"];
+
+/// Files which are local to an instance and are never shipped.
+static LOCAL_FILES: &[&str] = &[".tantivy-meta.lock", ".tantivy-writer.lock"];
+
+/// The suffix of files which are still being downloaded.
+static PARTIAL_SUFFIX: &str = ".part";
+
+/// How long to wait for the primary to send a single file.
+const FILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many times the primary's files are listed before giving up when
+/// it commits in between every listing.
+const MAX_LISTING_ATTEMPTS: usize = 5;
+
+static IS_REPLICA: OnceCell<bool> = OnceCell::new();
+
+/// Marks this instance as a read replica which must never write to its indexes.
+pub fn set_replica() {
+    let _ = IS_REPLICA.set(true);
+}
+
+#[inline]
+pub fn is_replica() -> bool {
+    IS_REPLICA.get().copied().unwrap_or_default()
+}
+
+#[derive(Enum, Debug, Copy, Clone, Serialize, Deserialize)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum IndexName {
+    Bots,
+    Packs,
+}
+
+impl IndexName {
+    pub const ALL: [IndexName; 2] = [Self::Bots, Self::Packs];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bots => "bots",
+            Self::Packs => "packs",
+        }
+    }
+
+    pub fn path(&self) -> &'static Path {
+        match self {
+            Self::Bots => index_impls::bots::writer().path(),
+            Self::Packs => index_impls::packs::writer().path(),
+        }
+    }
+
+    /// Reloads the index's reader and gets a searcher of its latest commit.
+    pub fn searcher(&self) -> Result<Searcher> {
+        match self {
+            Self::Bots => {
+                readers::bots::reader().reload()?;
+                Ok(readers::bots::reader().searcher())
+            },
+            Self::Packs => {
+                readers::packs::reader().reload()?;
+                Ok(readers::packs::reader().searcher())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct ManifestEntry {
+    /// The name of the file within the index directory.
+    pub name: String,
+
+    /// The size of the file in bytes.
+    pub size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub downloaded: usize,
+    pub removed: usize,
+}
+
+/// Lists the files of the index which can be shipped to replicas.
+pub fn manifest(index_path: &Path) -> Result<Vec<ManifestEntry>> {
+    let mut entries = vec![];
+    for entry in std::fs::read_dir(index_path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        if is_shippable(&name) {
+            entries.push(ManifestEntry {
+                name,
+                size: metadata.len(),
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(entries)
+}
+
+/// Reads a single index file, returning `None` if the file cannot be shipped.
+pub async fn read_file(index_path: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+    if !is_shippable(name)
+        || name.contains('/')
+        || name.contains('\\')
+        || name.starts_with("..")
+    {
+        return Ok(None);
+    }
+
+    match tokio::fs::read(index_path.join(name)).await {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Pulls every new or changed file of the index from the primary.
+///
+/// The index meta is written last so readers only pick up the new
+/// segments once all of them are present.
+pub async fn sync_from(primary: &str, index: IndexName) -> Result<SyncSummary> {
+    let client = reqwest::Client::new();
+    let base = format!(
+        "{}/admin/replication/{}",
+        primary.trim_end_matches('/'),
+        index.as_str()
+    );
+
+    let path = index.path();
+    let local_meta = tokio::fs::read(path.join(META_FILE)).await.ok();
+    let (remote_meta, remote) = match remote_listing(&client, &base, local_meta).await? {
+        Some(listing) => listing,
+        None => return Ok(SyncSummary::default()),
+    };
+
+    let local = manifest(path)?
+        .into_iter()
+        .map(|entry| (entry.name, entry.size))
+        .collect::<HashMap<_, _>>();
+
+    let mut summary = SyncSummary::default();
+    for entry in remote.iter() {
+        let is_mutable = MUTABLE_FILES.contains(&entry.name.as_str());
+        if entry.name == META_FILE
+            || (!is_mutable && local.get(&entry.name) == Some(&entry.size))
+        {
+            continue;
+        }
+
+        let data = fetch_file(&client, &base, &entry.name).await?;
+        if !is_mutable && data.len() as u64 != entry.size {
+            return Err(anyhow!(
+                "file {:?} changed size while being shipped, expected {} got {}",
+                entry.name,
+                entry.size,
+                data.len(),
+            ));
+        }
+
+        write_file(path, &entry.name, &data).await?;
+
+        if !is_mutable {
+            summary.downloaded += 1;
+        }
+    }
+
+    // `meta.json` references the other files so it must always come last.
+    write_file(path, META_FILE, &remote_meta).await?;
+
+    // Files the primary has garbage collected are no longer referenced.
+    for name in local.keys() {
+        if MUTABLE_FILES.contains(&name.as_str()) {
+            continue;
+        }
+
+        if !remote.iter().any(|entry| &entry.name == name) {
+            tokio::fs::remove_file(path.join(name)).await?;
+            summary.removed += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Lists the primary's files along with the meta they belong to, or `None`
+/// if its meta matches the local one.
+///
+/// The meta is fetched on both sides of the listing and the listing is only
+/// used once they match, otherwise a commit in between could have merged
+/// away files the meta still references.
+async fn remote_listing(
+    client: &reqwest::Client,
+    base: &str,
+    local_meta: Option<Vec<u8>>,
+) -> Result<Option<(Vec<u8>, Vec<ManifestEntry>)>> {
+    let mut meta = fetch_file(client, base, META_FILE).await?;
+    for _ in 0..MAX_LISTING_ATTEMPTS {
+        if local_meta.as_ref() == Some(&meta) {
+            return Ok(None);
+        }
+
+        let entries = authorized(client.get(format!("{}/manifest", base)))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<ManifestEntry>>()
+            .await?;
+
+        let after = fetch_file(client, base, META_FILE).await?;
+        if after == meta {
+            return Ok(Some((meta, entries)));
+        }
+
+        meta = after;
+    }
+
+    Err(anyhow!(
+        "the primary kept committing after {} attempts to list its files",
+        MAX_LISTING_ATTEMPTS,
+    ))
+}
+
+async fn fetch_file(
+    client: &reqwest::Client,
+    base: &str,
+    name: &str,
+) -> Result<Vec<u8>> {
+    let data = authorized(client.get(format!("{}/files/{}", base, name)))
+        .timeout(FILE_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    Ok(data.to_vec())
+}
+
+/// Sends the admin token the primary's replication routes require.
+fn authorized(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match routes::admin_token() {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Writes the file to a temporary path first so it's never read half written.
+pub(crate) async fn write_file(
+    index_path: &Path,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let partial = index_path.join(format!("{}{}", name, PARTIAL_SUFFIX));
+    tokio::fs::write(&partial, data).await?;
+    tokio::fs::rename(&partial, index_path.join(name)).await?;
+
+    Ok(())
+}
+
+fn is_shippable(name: &str) -> bool {
+    !LOCAL_FILES.contains(&name) && !name.ends_with(PARTIAL_SUFFIX)
+}
@@ -0,0 +1,93 @@
+use once_cell::sync::Lazy;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// Searches with a cost above this are scheduled on the expensive lane.
+const EXPENSIVE_COST_THRESHOLD: usize = 8;
+
+/// The number of documents a search can collect for each unit of cost.
+const DOCS_PER_COST_UNIT: usize = 100;
+
+static CHEAP_LANE: Lazy<ThreadPool> = Lazy::new(|| build_lane("search-cheap", 1));
+static EXPENSIVE_LANE: Lazy<ThreadPool> =
+    Lazy::new(|| build_lane("search-expensive", 2));
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueryCost {
+    /// Short queries near the start of the results, i.e autocomplete.
+    Cheap,
+
+    /// Long queries, deep offsets or queries with many facets.
+    Expensive,
+}
+
+impl QueryCost {
+    /// Estimates the cost of a search before it is executed.
+    pub fn classify(
+        query: Option<&str>,
+        limit: usize,
+        offset: usize,
+        num_facets: usize,
+    ) -> Self {
+        let num_tokens = match query {
+            None | Some("*") => 0,
+            Some(query) => query.split_whitespace().count(),
+        };
+
+        let cost = num_tokens + num_facets + ((limit + offset) / DOCS_PER_COST_UNIT);
+
+        if cost > EXPENSIVE_COST_THRESHOLD {
+            Self::Expensive
+        } else {
+            Self::Cheap
+        }
+    }
+}
+
+/// Runs the given search on the lane matching its cost.
+///
+/// Each lane has its own set of threads so cheap searches are never
+/// queued behind expensive ones, the expensive lane only gets half the
+/// threads to leave headroom for the cheap lane.
+pub fn spawn<F>(cost: QueryCost, func: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    match cost {
+        QueryCost::Cheap => CHEAP_LANE.spawn(func),
+        QueryCost::Expensive => EXPENSIVE_LANE.spawn(func),
+    }
+}
+
+fn build_lane(name: &'static str, divisor: usize) -> ThreadPool {
+    let num_threads = std::thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(1);
+
+    ThreadPoolBuilder::new()
+        .num_threads((num_threads / divisor).max(1))
+        .thread_name(move |i| format!("{}-{}", name, i))
+        .build()
+        .expect("failed to build search thread pool")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            QueryCost::classify(Some("music"), 20, 0, 0),
+            QueryCost::Cheap
+        );
+        assert_eq!(QueryCost::classify(None, 20, 0, 1), QueryCost::Cheap);
+        assert_eq!(
+            QueryCost::classify(Some("music"), 50, 2000, 0),
+            QueryCost::Expensive,
+        );
+        assert_eq!(
+            QueryCost::classify(Some("music bot"), 20, 0, 10),
+            QueryCost::Expensive,
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use serde::{Deserialize, Serialize};
+use tantivy::Searcher;
+
+use crate::search::replication::{
+    self,
+    IndexName,
+    ManifestEntry,
+    META_FILE,
+    MUTABLE_FILES,
+};
+
+/// The list of files making up the latest snapshot of an index.
+///
+/// This is the only key which is ever overwritten, every other file is
+/// either immutable or uploaded under the generation of its snapshot.
+static SNAPSHOT_MANIFEST: &str = "manifest.json";
+
+/// How many times the searcher is reloaded before giving up on reading
+/// the meta of the commit it searches.
+const MAX_LISTING_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotManifest {
+    /// When the snapshot was uploaded in milliseconds, the mutable files
+    /// are uploaded under it.
+    generation: i64,
+
+    files: Vec<ManifestEntry>,
+}
+
+impl SnapshotManifest {
+    fn keys(&self, index: IndexName) -> impl Iterator<Item = String> + '_ {
+        self.files
+            .iter()
+            .map(move |entry| file_key(index, self.generation, &entry.name))
+    }
+}
+
+static STORE: OnceCell<SnapshotStore> = OnceCell::new();
+
+struct SnapshotStore {
+    bucket: Bucket,
+
+    /// The immutable files of each index which have already been uploaded.
+    uploaded: Mutex<HashMap<&'static str, HashSet<String>>>,
+}
+
+impl SnapshotStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let resp = self.bucket.put_object(key, data).await?;
+        if !(200..300).contains(&resp.status_code()) {
+            return Err(anyhow!(
+                "failed to upload {:?}, bucket responded with status {}",
+                key,
+                resp.status_code(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let resp = self.bucket.get_object(key).await?;
+        match resp.status_code() {
+            200..=299 => Ok(Some(resp.bytes().to_vec())),
+            404 => Ok(None),
+            status => Err(anyhow!(
+                "failed to download {:?}, bucket responded with status {}",
+                key,
+                status,
+            )),
+        }
+    }
+
+    fn is_uploaded(&self, index: IndexName, name: &str) -> bool {
+        self.uploaded
+            .lock()
+            .get(index.as_str())
+            .map(|names| names.contains(name))
+            .unwrap_or_default()
+    }
+
+    fn mark_uploaded(&self, index: IndexName, name: &str) {
+        self.uploaded
+            .lock()
+            .entry(index.as_str())
+            .or_default()
+            .insert(name.to_string());
+    }
+
+    async fn manifest(&self, index: IndexName) -> Result<Option<SnapshotManifest>> {
+        match self.get(&key(index, SNAPSHOT_MANIFEST)).await? {
+            Some(manifest) => Ok(Some(serde_json::from_slice(&manifest)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes every file of the index in the bucket which is not one of
+    /// the given keys, returning the number of files deleted.
+    async fn delete_unlisted(
+        &self,
+        index: IndexName,
+        listed: &HashSet<String>,
+    ) -> Result<usize> {
+        let prefix = format!("{}/", index.as_str());
+        let mut deleted = 0;
+        for page in self.bucket.list(prefix.clone(), None).await? {
+            for object in page.contents {
+                if listed.contains(&object.key) {
+                    continue;
+                }
+
+                let resp = self.bucket.delete_object(&object.key).await?;
+                if !(200..300).contains(&resp.status_code()) {
+                    return Err(anyhow!(
+                        "failed to delete {:?}, bucket responded with status {}",
+                        object.key,
+                        resp.status_code(),
+                    ));
+                }
+
+                if let Some(names) = self.uploaded.lock().get_mut(index.as_str()) {
+                    names.remove(object.key.trim_start_matches(&prefix));
+                }
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// Enables uploading snapshots to, and restoring them from, the given bucket.
+///
+/// Credentials are loaded from the standard AWS environment variables or profile.
+pub fn init(bucket: &str, region: &str, endpoint: Option<String>) -> Result<()> {
+    let region = match endpoint {
+        Some(endpoint) => Region::Custom {
+            region: region.to_string(),
+            endpoint,
+        },
+        None => region.parse()?,
+    };
+
+    let bucket = Bucket::new(bucket, region, Credentials::default()?)?;
+    let _ = STORE.set(SnapshotStore {
+        bucket,
+        uploaded: Default::default(),
+    });
+
+    Ok(())
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    STORE.get().is_some()
+}
+
+/// Uploads a snapshot of the index, returning the number of files uploaded.
+///
+/// Segment files never change, so only new segments are uploaded each time.
+/// The mutable files are uploaded under the generation of the snapshot and
+/// the manifest pointing at them is written last, so a restore only ever
+/// sees a complete snapshot.
+pub async fn upload(index: IndexName) -> Result<usize> {
+    let store = match STORE.get() {
+        Some(store) => store,
+        None => return Ok(0),
+    };
+
+    // The searcher is held until the upload is done so the writer can't
+    // garbage collect the segments being uploaded if it merges them away.
+    let (searcher, meta) = searched_commit(index).await?;
+    let segments = searcher
+        .segment_readers()
+        .iter()
+        .map(|reader| reader.segment_id().uuid_string())
+        .collect::<HashSet<_>>();
+
+    let path = index.path();
+    let mut files = replication::manifest(path)?
+        .into_iter()
+        .filter(|entry| {
+            MUTABLE_FILES.contains(&entry.name.as_str())
+                || segments.contains(segment_of(&entry.name))
+        })
+        .collect::<Vec<_>>();
+
+    let generation = Utc::now().timestamp_millis();
+    let mut uploaded = 0;
+    for entry in files.iter_mut() {
+        if entry.name == META_FILE {
+            entry.size = meta.len() as u64;
+            store
+                .put(&file_key(index, generation, META_FILE), &meta)
+                .await?;
+            continue;
+        }
+
+        let is_mutable = MUTABLE_FILES.contains(&entry.name.as_str());
+        if !is_mutable && store.is_uploaded(index, &entry.name) {
+            continue;
+        }
+
+        let data = tokio::fs::read(path.join(&entry.name)).await?;
+        store
+            .put(&file_key(index, generation, &entry.name), &data)
+            .await?;
+
+        if !is_mutable {
+            store.mark_uploaded(index, &entry.name);
+            uploaded += 1;
+        }
+    }
+
+    let previous = store.manifest(index).await?;
+    let manifest = SnapshotManifest { generation, files };
+    store
+        .put(
+            &key(index, SNAPSHOT_MANIFEST),
+            &serde_json::to_vec(&manifest)?,
+        )
+        .await?;
+
+    // The previous snapshot is kept as a restore may still be reading it,
+    // anything older is no longer referenced.
+    let listed = manifest
+        .keys(index)
+        .chain(previous.iter().flat_map(|previous| previous.keys(index)))
+        .chain([key(index, SNAPSHOT_MANIFEST)])
+        .collect::<HashSet<_>>();
+    let deleted = store.delete_unlisted(index, &listed).await?;
+    if deleted > 0 {
+        debug!(
+            "Deleted {} unused snapshot files of {}",
+            deleted,
+            index.as_str()
+        );
+    }
+
+    drop(searcher);
+
+    Ok(uploaded)
+}
+
+/// Gets a searcher of the latest commit along with the meta of that commit,
+/// retrying until no commit happens between reloading and reading the meta.
+async fn searched_commit(index: IndexName) -> Result<(Searcher, Vec<u8>)> {
+    for _ in 0..MAX_LISTING_ATTEMPTS {
+        let searcher = index.searcher()?;
+        let meta = tokio::fs::read(index.path().join(META_FILE)).await?;
+
+        let searched = searcher
+            .segment_readers()
+            .iter()
+            .map(|reader| reader.segment_id().uuid_string())
+            .collect::<HashSet<_>>();
+
+        if meta_segments(&meta)? == searched {
+            return Ok((searcher, meta));
+        }
+    }
+
+    Err(anyhow!(
+        "the index kept changing after {} attempts to read its meta",
+        MAX_LISTING_ATTEMPTS,
+    ))
+}
+
+/// The IDs of the segments the meta references, in the same format as
+/// they appear in the segment file names.
+fn meta_segments(meta: &[u8]) -> Result<HashSet<String>> {
+    let meta = serde_json::from_slice::<serde_json::Value>(meta)?;
+
+    Ok(meta["segments"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|segment| segment["segment_id"].as_str())
+        .map(|id| id.replace('-', ""))
+        .collect())
+}
+
+/// The ID of the segment a file belongs to, segment files are named
+/// after their segment followed by the component, i.e `<id>.idx`.
+fn segment_of(name: &str) -> &str {
+    name.split('.').next().unwrap_or_default()
+}
+
+/// Downloads the latest snapshot of the index into `path` if there is
+/// no index there yet.
+///
+/// Returns `false` if nothing was restored.
+pub async fn restore_if_empty(index: IndexName, path: &Path) -> Result<bool> {
+    let store = match STORE.get() {
+        Some(store) => store,
+        None => return Ok(false),
+    };
+
+    if path.join(META_FILE).exists() {
+        return Ok(false);
+    }
+
+    let manifest = match store.manifest(index).await? {
+        Some(manifest) => manifest,
+        None => return Ok(false),
+    };
+
+    tokio::fs::create_dir_all(path).await?;
+
+    // `meta.json` references the other files so it must always come last.
+    let names = manifest
+        .files
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .filter(|name| *name != META_FILE)
+        .chain(std::iter::once(META_FILE));
+
+    for name in names {
+        let data = store
+            .get(&file_key(index, manifest.generation, name))
+            .await?
+            .ok_or_else(|| anyhow!("snapshot file {:?} is missing", name))?;
+
+        replication::write_file(path, name, &data).await?;
+    }
+
+    Ok(true)
+}
+
+fn key(index: IndexName, name: &str) -> String {
+    format!("{}/{}", index.as_str(), name)
+}
+
+/// The key a file of the snapshot is uploaded to, mutable files are kept
+/// apart for each generation so uploading one never overwrites another.
+fn file_key(index: IndexName, generation: i64, name: &str) -> String {
+    if MUTABLE_FILES.contains(&name) {
+        format!("{}/{}/{}", index.as_str(), generation, name)
+    } else {
+        key(index, name)
+    }
+}
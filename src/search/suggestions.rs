@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA, SINK_STATE};
+use once_cell::sync::Lazy;
+use tantivy::schema::Field;
+use tantivy::Searcher;
+
+use crate::search::tokenizer::SimpleUnicodeTokenizer;
+
+/// Searches returning fewer hits than this will produce a suggestion.
+pub const SUGGESTION_THRESHOLD: usize = 3;
+
+/// Tokens shorter than this are never corrected.
+const MIN_CORRECTION_LENGTH: usize = 3;
+
+/// The maximum number of tokens to correct in a single query.
+const MAX_QUERY_TOKENS: usize = 10;
+
+/// The automaton builders for an edit distance of 1 and 2, these are
+/// expensive to create so they're only built once.
+static AUTOMATON_BUILDERS: Lazy<[LevenshteinAutomatonBuilder; 2]> = Lazy::new(|| {
+    [
+        LevenshteinAutomatonBuilder::new(1, false),
+        LevenshteinAutomatonBuilder::new(2, false),
+    ]
+});
+
+/// Lets the term dictionaries be searched with a levenshtein automaton.
+struct LevenshteinDfa(DFA);
+
+impl tantivy_fst::Automaton for LevenshteinDfa {
+    type State = u32;
+
+    fn start(&self) -> u32 {
+        self.0.initial_state()
+    }
+
+    fn is_match(&self, state: &u32) -> bool {
+        matches!(self.0.distance(*state), Distance::Exact(_))
+    }
+
+    fn can_match(&self, state: &u32) -> bool {
+        *state != SINK_STATE
+    }
+
+    fn accept(&self, state: &u32, byte: u8) -> u32 {
+        self.0.transition(*state, byte)
+    }
+}
+
+/// Produces a corrected version of the query using the term dictionaries
+/// of the given fields.
+///
+/// Each token unknown to the index is replaced with the closest term
+/// sharing its first character, ties are broken by the term's document
+/// frequency. Returns `None` if nothing in the query could be corrected.
+pub fn suggest(
+    searcher: &Searcher,
+    fields: &[Field],
+    query: &str,
+) -> Result<Option<String>> {
+    let tokens = SimpleUnicodeTokenizer::with_limit(MAX_QUERY_TOKENS)
+        .with_stopwords(&[])
+        .produce_tokens(query);
+
+    let mut corrected = false;
+    let mut suggestion = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        match correct_token(searcher, fields, &token.text)? {
+            Some(correction) => {
+                corrected = true;
+                suggestion.push(correction);
+            },
+            None => suggestion.push(token.text),
+        }
+    }
+
+    if !corrected {
+        return Ok(None);
+    }
+
+    Ok(Some(suggestion.join(" ")))
+}
+
+fn correct_token(
+    searcher: &Searcher,
+    fields: &[Field],
+    token: &str,
+) -> Result<Option<String>> {
+    let length = token.chars().count();
+    if length < MIN_CORRECTION_LENGTH {
+        return Ok(None);
+    }
+
+    let builder = &AUTOMATON_BUILDERS[if length <= 4 { 0 } else { 1 }];
+    let (lower, upper) = match prefix_range(token) {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    // Only the terms within the edit distance are streamed, the automaton
+    // skips every other part of the dictionary.
+    let distances = builder.build_dfa(token);
+    let mut candidates: HashMap<String, (u8, u32)> = HashMap::new();
+    for segment_reader in searcher.segment_readers() {
+        for field in fields {
+            let inverted_index = segment_reader.inverted_index(*field)?;
+            let dfa = LevenshteinDfa(builder.build_dfa(token));
+            let mut stream = inverted_index
+                .terms()
+                .search(dfa)
+                .ge(&lower)
+                .lt(&upper)
+                .into_stream()?;
+
+            while stream.advance() {
+                let term = match std::str::from_utf8(stream.key()) {
+                    Ok(term) => term,
+                    Err(_) => continue,
+                };
+
+                // The token already exists so there is nothing to correct.
+                if term == token {
+                    return Ok(None);
+                }
+
+                let distance = match edit_distance(&distances, term) {
+                    Some(distance) => distance,
+                    None => continue,
+                };
+
+                let entry = candidates.entry(term.to_string()).or_insert((distance, 0));
+                entry.1 += stream.value().doc_freq;
+            }
+        }
+    }
+
+    let best = candidates
+        .into_iter()
+        .min_by(|(a_term, (a_dist, a_freq)), (b_term, (b_dist, b_freq))| {
+            a_dist
+                .cmp(b_dist)
+                .then(b_freq.cmp(a_freq))
+                .then(a_term.cmp(b_term))
+        })
+        .map(|(term, _)| term);
+
+    Ok(best)
+}
+
+/// The byte range of all terms starting with the same character as the token.
+fn prefix_range(token: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let first = token.chars().next()?;
+
+    let mut buffer = [0; 4];
+    let lower = first.encode_utf8(&mut buffer).as_bytes().to_vec();
+
+    // The last byte of a UTF-8 character is never `0xFF`.
+    let mut upper = lower.clone();
+    *upper.last_mut()? += 1;
+
+    Some((lower, upper))
+}
+
+/// The edit distance of the term from the token the automaton was built
+/// for, if it's within the automaton's maximum distance.
+fn edit_distance(dfa: &DFA, term: &str) -> Option<u8> {
+    match dfa.eval(term) {
+        Distance::Exact(distance) => Some(distance),
+        Distance::AtLeast(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        let builder = &AUTOMATON_BUILDERS[1];
+        let music = builder.build_dfa("music");
+        assert_eq!(edit_distance(&music, "music"), Some(0));
+        assert_eq!(edit_distance(&music, "musci"), Some(2));
+        assert_eq!(edit_distance(&music, "musi"), Some(1));
+        assert_eq!(edit_distance(&music, "bot"), None);
+    }
+
+    #[test]
+    fn test_prefix_range() {
+        assert_eq!(prefix_range("music"), Some((b"m".to_vec(), b"n".to_vec())));
+        assert_eq!(prefix_range(""), None);
+    }
+}
@@ -1,4 +1,10 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use clap::ValueEnum;
 use deunicode::deunicode_char;
+use once_cell::sync::{Lazy, OnceCell};
+use rust_stemmers::{Algorithm, Stemmer};
 use tantivy::tokenizer::{
     BoxTokenStream,
     SimpleTokenizer,
@@ -7,39 +13,185 @@ use tantivy::tokenizer::{
     Tokenizer,
 };
 
+static SYMBOL_MODE: OnceCell<SymbolMode> = OnceCell::new();
+static STOPWORDS: OnceCell<Arc<HashSet<String>>> = OnceCell::new();
+static NO_STOPWORDS: Lazy<Arc<HashSet<String>>> = Lazy::new(Default::default);
+static STEMMER: Lazy<Stemmer> = Lazy::new(|| Stemmer::create(Algorithm::English));
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolMode {
+    /// Symbols and emojis are converted to their ascii names, i.e `🦄 -> unicorn`.
+    Transliterate,
+
+    /// Any non-alphanumeric symbols are treated as word separators.
+    Strip,
+}
+
+impl Default for SymbolMode {
+    fn default() -> Self {
+        Self::Transliterate
+    }
+}
+
+/// Sets how the tokenizer should handle decorative symbols.
+///
+/// This must be set before any indexes are opened as it applies
+/// at both index and query time.
+pub fn set_symbol_mode(mode: SymbolMode) {
+    let _ = SYMBOL_MODE.set(mode);
+}
+
+#[inline]
+fn symbol_mode() -> SymbolMode {
+    SYMBOL_MODE.get().copied().unwrap_or_default()
+}
+
+/// Sets the words which are dropped when tokenizing text.
+///
+/// Like the symbol mode, this must be set before any indexes are opened.
+pub fn set_stopwords(words: impl IntoIterator<Item = impl AsRef<str>>) {
+    let words = words
+        .into_iter()
+        .map(|v| v.as_ref().trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    let _ = STOPWORDS.set(Arc::new(words));
+}
+
+#[inline]
+fn stopwords() -> Arc<HashSet<String>> {
+    STOPWORDS.get().unwrap_or(&*NO_STOPWORDS).clone()
+}
+
+#[inline]
+/// Stems a single token in the same way the `stemmed` analyzer does at index time.
+pub fn stem(token: &str) -> String {
+    STEMMER.stem(token).into_owned()
+}
+
+/// Produces a key which orders names alphabetically once normalized.
+///
+/// Only the first 8 bytes of the normalized name are used, the sign bit
+/// is flipped so the keys order the same way when stored as an `i64`.
+pub fn sort_key(name: &str) -> i64 {
+    let normalized = SimpleUnicodeTokenizer::default()
+        .with_stopwords(&[])
+        .produce_tokens(name)
+        .into_iter()
+        .map(|token| token.text)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut key = [0; 8];
+    for (slot, byte) in key.iter_mut().zip(normalized.bytes()) {
+        *slot = byte;
+    }
+
+    (u64::from_be_bytes(key) ^ (1 << 63)) as i64
+}
+
+/// Normalizes a bot's command prefix so they match regardless of case or
+/// surrounding whitespace.
+pub fn prefix_key(prefix: &str) -> String {
+    prefix.trim().to_lowercase()
+}
+
 #[derive(Clone)]
 pub struct SimpleUnicodeTokenizer {
     limit: usize,
+    symbols: SymbolMode,
+    stopwords: Arc<HashSet<String>>,
 }
 
 impl Default for SimpleUnicodeTokenizer {
     fn default() -> Self {
-        Self { limit: usize::MAX }
+        Self::with_limit(usize::MAX)
     }
 }
 
 impl SimpleUnicodeTokenizer {
     pub fn with_limit(num_words: usize) -> Self {
-        Self { limit: num_words }
+        Self {
+            limit: num_words,
+            symbols: symbol_mode(),
+            stopwords: stopwords(),
+        }
+    }
+
+    pub fn with_symbol_mode(mut self, mode: SymbolMode) -> Self {
+        self.symbols = mode;
+        self
+    }
+
+    pub fn with_stopwords(mut self, words: &[&str]) -> Self {
+        self.stopwords = Arc::new(words.iter().map(|v| v.to_string()).collect());
+        self
+    }
+
+    #[inline]
+    pub fn is_stopword(&self, token: &str) -> bool {
+        self.stopwords.contains(token)
     }
 
     pub fn token_stream(&self, text: &str) -> SimpleTokenStream {
-        let tokens = produce_tokens(text, self.limit);
+        let tokens = self.produce_tokens(text);
 
         SimpleTokenStream { tokens, pointer: 0 }
     }
+
+    pub fn produce_tokens(&self, text: &str) -> Vec<Token> {
+        let characters = normalize_text(text, self.symbols);
+
+        let simple = SimpleTokenizer {};
+        let mut stream = simple.token_stream(&characters);
+
+        let mut tokens = vec![];
+        let mut dropped = vec![];
+        while let Some(token) = stream.next() {
+            if tokens.len() >= self.limit {
+                break;
+            }
+
+            if self.stopwords.contains(&token.text) {
+                dropped.push(token.clone());
+                continue;
+            }
+
+            tokens.push(token.clone());
+        }
+
+        // Text made up entirely of stopwords would otherwise never be
+        // searchable, so we keep them as is.
+        if tokens.is_empty() {
+            dropped.truncate(self.limit);
+            return dropped;
+        }
+
+        tokens
+    }
 }
 
 impl Tokenizer for SimpleUnicodeTokenizer {
     fn token_stream<'a>(&self, text: &'a str) -> BoxTokenStream<'a> {
-        let tokens = produce_tokens(text, self.limit);
+        let tokens = self.produce_tokens(text);
         BoxTokenStream::from(SimpleTokenStream { tokens, pointer: 0 })
     }
 }
 
-pub fn produce_tokens(text: &str, num_tokens: usize) -> Vec<Token> {
+fn normalize_text(text: &str, symbols: SymbolMode) -> String {
     let mut characters = String::with_capacity(text.len());
     for char in text.chars() {
+        if is_decorative(char, symbols) {
+            // Marks such as combining accents produce no ascii and should
+            // not split the word they belong to.
+            if deunicode_char(char) != Some("") {
+                characters.push(' ');
+            }
+
+            continue;
+        }
+
         if let Some(ascii) = deunicode_char(char) {
             if ascii.len() > 1 {
                 characters.push(' ');
@@ -48,19 +200,12 @@ pub fn produce_tokens(text: &str, num_tokens: usize) -> Vec<Token> {
         }
     }
 
-    let simple = SimpleTokenizer {};
-    let mut stream = simple.token_stream(&characters);
-
-    let mut tokens = vec![];
-    while let Some(token) = stream.next() {
-        if tokens.len() >= num_tokens {
-            break;
-        }
-
-        tokens.push(token.clone());
-    }
+    characters
+}
 
-    tokens
+#[inline]
+fn is_decorative(char: char, symbols: SymbolMode) -> bool {
+    symbols == SymbolMode::Strip && !char.is_ascii() && !char.is_alphanumeric()
 }
 
 pub struct SimpleTokenStream {
@@ -107,8 +252,29 @@ impl TokenStream for SimpleTokenStream {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sort_key() {
+        assert!(sort_key("Apple") < sort_key("banana"));
+        assert!(sort_key("Music") < sort_key("music bot"));
+        assert_eq!(sort_key("Ærial"), sort_key("aerial"));
+        assert!(sort_key("") < sort_key("a"));
+    }
+
+    #[test]
+    fn test_prefix_key() {
+        assert_eq!(prefix_key(" PLS "), "pls");
+        assert_eq!(prefix_key("!"), "!");
+    }
+
     fn parse_and_compare(text: &str, expected: Vec<&str>) {
-        let tokenizer = SimpleUnicodeTokenizer::default();
+        parse_and_compare_with(SimpleUnicodeTokenizer::default(), text, expected)
+    }
+
+    fn parse_and_compare_with(
+        tokenizer: SimpleUnicodeTokenizer,
+        text: &str,
+        expected: Vec<&str>,
+    ) {
         let mut stream = tokenizer.token_stream(text);
 
         let mut tokens = vec![];
@@ -203,4 +369,44 @@ mod tests {
         ];
         parse_and_compare(text, tokens);
     }
+
+    #[test]
+    fn test_strip_decorative_symbols() {
+        let tokenizer =
+            SimpleUnicodeTokenizer::default().with_symbol_mode(SymbolMode::Strip);
+        let text = "꧁༺ Music ༻꧂";
+        let tokens = vec!["music"];
+        parse_and_compare_with(tokenizer, text, tokens);
+    }
+
+    #[test]
+    fn test_strip_emojis() {
+        let tokenizer =
+            SimpleUnicodeTokenizer::default().with_symbol_mode(SymbolMode::Strip);
+        let text = "🦄music🎵bot Ôóű";
+        let tokens = vec!["music", "bot", "oou"];
+        parse_and_compare_with(tokenizer, text, tokens);
+    }
+
+    #[test]
+    fn test_stopwords() {
+        let tokenizer = SimpleUnicodeTokenizer::default().with_stopwords(&["for", "a"]);
+        let text = "A bot for music";
+        let tokens = vec!["bot", "music"];
+        parse_and_compare_with(tokenizer, text, tokens);
+    }
+
+    #[test]
+    fn test_only_stopwords() {
+        let tokenizer = SimpleUnicodeTokenizer::default().with_stopwords(&["the"]);
+        let text = "The The";
+        let tokens = vec!["the", "the"];
+        parse_and_compare_with(tokenizer, text, tokens);
+    }
+
+    #[test]
+    fn test_stem() {
+        assert_eq!(stem("moderation"), stem("moderate"));
+        assert_eq!(stem("moderator"), stem("moderators"));
+    }
 }
@@ -1,20 +1,94 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use flume::RecvTimeoutError;
+use once_cell::sync::OnceCell;
+use poem_openapi::Object;
 use tantivy::{Document, Index, IndexWriter, Term};
 use tokio::sync::oneshot;
 
+use crate::notify::{self, IndexEvent};
+
 const MEMORY_ARENA: usize = 300 << 20;
 const AUTO_COMMIT_SECS: u64 = 5;
+const DEFAULT_QUEUE_SIZE: usize = 64;
+
+/// The most indexing threads tantivy will run for a single writer.
+const MAX_INDEXING_THREADS: usize = 8;
+
+/// How long an operation waits for space in a full queue before giving up.
+const SATURATED_TIMEOUT: Duration = Duration::from_secs(10);
+
+static QUEUE_SIZE: OnceCell<usize> = OnceCell::new();
+static INDEXING_THREADS: OnceCell<usize> = OnceCell::new();
+
+/// Sets the maximum number of operations queued for each writer.
+pub fn set_writer_queue_size(size: usize) {
+    let _ = QUEUE_SIZE.set(size.max(1));
+}
+
+/// Sets the number of threads each writer indexes documents with,
+/// `0` lets tantivy pick based on the number of cores.
+///
+/// The memory arena is split between the threads.
+pub fn set_writer_threads(threads: usize) {
+    let _ = INDEXING_THREADS.set(threads.min(MAX_INDEXING_THREADS));
+}
+
+/// The writer queue stayed full for longer than [SATURATED_TIMEOUT].
+#[derive(Debug)]
+pub struct WriterSaturated;
+
+impl Display for WriterSaturated {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The writer is saturated, try again later.")
+    }
+}
+
+impl std::error::Error for WriterSaturated {}
+
+#[derive(Debug, Copy, Clone, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct WriterStats {
+    /// The number of operations waiting to be processed.
+    pub queue_depth: usize,
+
+    /// The maximum number of operations which can be queued.
+    pub queue_capacity: usize,
+
+    /// How long the last processed operation waited in the queue in milliseconds.
+    pub last_queue_wait_ms: u64,
+
+    /// The number of operations rejected because the queue stayed full.
+    pub saturated_ops: u64,
+}
+
+#[derive(Default)]
+struct Metrics {
+    last_queue_wait_ms: AtomicU64,
+    saturated_ops: AtomicU64,
+}
 
 pub async fn start_writer(index: Index) -> Result<Writer> {
-    let (tx, rx) = flume::bounded(4);
-    let handle = thread::spawn(move || run_writer(index, rx));
+    let capacity = QUEUE_SIZE.get().copied().unwrap_or(DEFAULT_QUEUE_SIZE);
+    let metrics = Arc::new(Metrics::default());
+
+    let (tx, rx) = flume::bounded(capacity);
+    let handle = {
+        let metrics = metrics.clone();
+        thread::spawn(move || run_writer(index, rx, metrics))
+    };
 
     let (waker, ack) = oneshot::channel();
-    if (tx.send_async(WriterOp::__Ping(waker)).await).is_err() {
+    if (tx
+        .send_async((Instant::now(), WriterOp::__Ping(waker)))
+        .await)
+        .is_err()
+    {
         handle.join().expect("Join correctly")?;
 
         // Should never happen theoretically as our rx will only be
@@ -30,63 +104,197 @@ pub async fn start_writer(index: Index) -> Result<Writer> {
         return Err(anyhow!("Failed to start writer due to unknown error."));
     };
 
-    Ok(Writer { tx })
+    Ok(Writer {
+        tx,
+        capacity,
+        metrics,
+    })
+}
+
+#[derive(Debug, Copy, Clone, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct MergeSummary {
+    /// The number of segments before merging.
+    pub segments_before: usize,
+
+    /// The number of segments after merging.
+    pub segments_after: usize,
 }
 
 pub struct Writer {
-    tx: flume::Sender<WriterOp>,
+    tx: flume::Sender<(Instant, WriterOp)>,
+    capacity: usize,
+    metrics: Arc<Metrics>,
 }
 
 impl Writer {
+    /// Queues the operation, returning [WriterSaturated] if the queue
+    /// does not free up in time.
     async fn send_op(&self, op: WriterOp) -> Result<()> {
-        self.tx
-            .send_async(op)
-            .await
-            .map_err(|_| anyhow!("Writer actor has shutdown."))
+        let send = self.tx.send_async((Instant::now(), op));
+        match tokio::time::timeout(SATURATED_TIMEOUT, send).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(anyhow!("Writer actor has shutdown.")),
+            Err(_) => {
+                self.metrics.saturated_ops.fetch_add(1, Ordering::Relaxed);
+                Err(WriterSaturated.into())
+            },
+        }
+    }
+
+    pub fn stats(&self) -> WriterStats {
+        WriterStats {
+            queue_depth: self.tx.len(),
+            queue_capacity: self.capacity,
+            last_queue_wait_ms: self.metrics.last_queue_wait_ms.load(Ordering::Relaxed),
+            saturated_ops: self.metrics.saturated_ops.load(Ordering::Relaxed),
+        }
     }
 
+    /// Queues the document to replace any matching the term.
+    ///
+    /// This returns once the operation is queued, indexing errors are only logged.
     pub async fn add_and_replace_document(
         &self,
         term: Term,
         doc: Document,
     ) -> Result<()> {
-        self.send_op(WriterOp::AddAndReplaceDocument(term, doc))
+        self.send_op(WriterOp::AddAndReplaceDocument(term, doc, None))
             .await
     }
 
-    pub async fn add_document(&self, doc: Document) -> Result<()> {
-        self.send_op(WriterOp::AddDocument(doc)).await
+    /// Replaces any documents matching the term, waiting until the writer
+    /// has applied it so indexing errors are returned.
+    pub async fn add_and_replace_document_acked(
+        &self,
+        term: Term,
+        doc: Document,
+    ) -> Result<()> {
+        let (waker, ack) = oneshot::channel();
+        self.send_op(WriterOp::AddAndReplaceDocument(term, doc, Some(waker)))
+            .await?;
+
+        ack.await
+            .map_err(|_| anyhow!("Writer actor has shutdown."))?
     }
 
+    /// Adds a batch of documents, each replacing any documents matching its term,
+    /// waiting until the writer has applied them all.
+    ///
+    /// The documents are spread across the writer's indexing threads, which is
+    /// far quicker than queuing them one at a time. The batch is applied
+    /// atomically, if any document fails none of them are applied.
+    pub async fn add_and_replace_documents(
+        &self,
+        docs: Vec<(Term, Document)>,
+    ) -> Result<()> {
+        let (waker, ack) = oneshot::channel();
+        self.send_op(WriterOp::AddDocuments(docs, waker)).await?;
+
+        ack.await
+            .map_err(|_| anyhow!("Writer actor has shutdown."))?
+    }
+
+    /// Removes any documents matching the term, waiting until the writer
+    /// has applied it.
     pub async fn remove_docs(&self, term: Term) -> Result<()> {
-        self.send_op(WriterOp::RemoveDocuments(term)).await
+        let (waker, ack) = oneshot::channel();
+        self.send_op(WriterOp::RemoveDocuments(term, waker)).await?;
+
+        ack.await
+            .map_err(|_| anyhow!("Writer actor has shutdown."))?
+    }
+
+    /// Replaces every document in the index with the given documents.
+    ///
+    /// This is done in a single commit, so searches keep seeing the old
+    /// documents until the new ones are all searchable.
+    ///
+    /// Documents which fail to be added are skipped, the error of each
+    /// of them is returned.
+    pub async fn replace_all_docs(&self, docs: Vec<Document>) -> Result<Vec<String>> {
+        let (waker, ack) = oneshot::channel();
+        self.send_op(WriterOp::ReplaceAll(docs, waker)).await?;
+
+        ack.await
+            .map_err(|_| anyhow!("Writer actor has shutdown."))?
+    }
+
+    /// Commits all pending operations and merges the smallest segments
+    /// until at most `target_segments` remain.
+    ///
+    /// No other operations are processed while the merge is running.
+    pub async fn merge(&self, target_segments: usize) -> Result<MergeSummary> {
+        let (waker, ack) = oneshot::channel();
+        self.send_op(WriterOp::Merge(target_segments, waker))
+            .await?;
+
+        ack.await
+            .map_err(|_| anyhow!("Writer actor has shutdown."))?
+    }
+
+    /// Queues the event to be sent once every operation queued before it
+    /// has been committed, so receivers never see a change before it's searchable.
+    ///
+    /// The event is dropped if the writer is saturated.
+    pub async fn notify_on_commit(&self, event: IndexEvent) {
+        if let Err(e) = self.send_op(WriterOp::Notify(event)).await {
+            warn!("Dropping index event, it could not be queued: {}", e);
+        }
     }
 
-    pub async fn clear_all_docs(&self) -> Result<()> {
-        self.send_op(WriterOp::ClearAll).await
+    /// Commits all pending operations without waiting for the auto commit.
+    pub async fn commit(&self) -> Result<()> {
+        let (waker, ack) = oneshot::channel();
+        self.send_op(WriterOp::Commit(waker)).await?;
+
+        ack.await
+            .map_err(|_| anyhow!("Writer actor has shutdown."))?
     }
 }
 
+/// Notifies the sender once an operation has been applied.
+type Ack = oneshot::Sender<Result<()>>;
+
 enum WriterOp {
-    AddAndReplaceDocument(Term, Document),
-    AddDocument(Document),
-    RemoveDocuments(Term),
-    ClearAll,
+    AddAndReplaceDocument(Term, Document, Option<Ack>),
+    AddDocuments(Vec<(Term, Document)>, Ack),
+    RemoveDocuments(Term, Ack),
+    ReplaceAll(Vec<Document>, oneshot::Sender<Result<Vec<String>>>),
+    Commit(Ack),
+    Merge(usize, oneshot::Sender<Result<MergeSummary>>),
+    Notify(IndexEvent),
 
     /// A simple Ping to check if the worker is alive still after creation.
     __Ping(oneshot::Sender<()>),
 }
 
-fn run_writer(index: Index, tasks: flume::Receiver<WriterOp>) -> anyhow::Result<()> {
-    let mut writer = index.writer(MEMORY_ARENA)?;
+fn run_writer(
+    index: Index,
+    tasks: flume::Receiver<(Instant, WriterOp)>,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<()> {
+    let mut writer = match INDEXING_THREADS.get().copied() {
+        None | Some(0) => index.writer(MEMORY_ARENA)?,
+        Some(threads) => index.writer_with_num_threads(threads, MEMORY_ARENA)?,
+    };
     let mut op_since_last_commit = false;
 
+    // Events are only sent once the changes they describe are committed.
+    let mut pending_events = vec![];
+
+    let record_wait = |queued: Instant| {
+        let waited = queued.elapsed().as_millis() as u64;
+        metrics.last_queue_wait_ms.store(waited, Ordering::Relaxed);
+    };
+
     loop {
         if !op_since_last_commit {
             info!("parking writer until new events present");
-            if let Ok(op) = tasks.recv() {
+            if let Ok((queued, op)) = tasks.recv() {
+                record_wait(queued);
                 op_since_last_commit = true;
-                handle_message(op, &mut writer)?;
+                handle_message(op, &mut writer, &mut pending_events)?;
             } else {
                 info!("writer actor channel dropped, shutting down...");
                 break;
@@ -99,48 +307,171 @@ fn run_writer(index: Index, tasks: flume::Receiver<WriterOp>) -> anyhow::Result<
             Err(RecvTimeoutError::Timeout) => {
                 info!("running auto commit");
 
-                writer.commit()?;
+                commit(&mut writer, &mut pending_events)?;
                 op_since_last_commit = false;
             },
             Err(RecvTimeoutError::Disconnected) => {
                 info!("writer actor channel dropped, shutting down...");
                 break;
             },
-            Ok(op) => {
-                handle_message(op, &mut writer)?;
+            Ok((queued, op)) => {
+                record_wait(queued);
+                handle_message(op, &mut writer, &mut pending_events)?;
             },
         }
     }
 
-    writer.commit()?;
+    commit(&mut writer, &mut pending_events)?;
     writer.wait_merging_threads()?;
 
     Ok(())
 }
 
-fn handle_message(op: WriterOp, writer: &mut IndexWriter) -> anyhow::Result<()> {
+/// Commits the writer, sending the pending events once it succeeds.
+fn commit(
+    writer: &mut IndexWriter,
+    pending_events: &mut Vec<IndexEvent>,
+) -> tantivy::Result<()> {
+    writer.commit()?;
+
+    for event in pending_events.drain(..) {
+        notify::notify(event);
+    }
+
+    Ok(())
+}
+
+fn handle_message(
+    op: WriterOp,
+    writer: &mut IndexWriter,
+    pending_events: &mut Vec<IndexEvent>,
+) -> anyhow::Result<()> {
     match op {
         WriterOp::__Ping(waker) => {
             let _ = waker.send(());
         },
-        WriterOp::AddAndReplaceDocument(term, doc) => {
+        WriterOp::AddAndReplaceDocument(term, doc, ack) => {
             debug!("Adding document: {:?}", doc);
             writer.delete_term(term);
-            writer.add_document(doc)?;
+            let result = writer.add_document(doc).map(|_| ());
+
+            // Acknowledged operations report the error to the caller
+            // rather than stopping the writer.
+            match ack {
+                Some(ack) => {
+                    let _ = ack.send(result.map_err(anyhow::Error::from));
+                },
+                None => result?,
+            }
         },
-        WriterOp::AddDocument(doc) => {
-            debug!("Adding document: {:?}", doc);
-            writer.add_document(doc)?;
+        WriterOp::AddDocuments(docs, ack) => {
+            debug!("Adding {} documents", docs.len());
+            let _ = ack.send(add_documents(writer, pending_events, docs));
         },
-        WriterOp::RemoveDocuments(term) => {
+        WriterOp::RemoveDocuments(term, ack) => {
             debug!("Removing document: {:?}", term);
             writer.delete_term(term);
+            let _ = ack.send(Ok(()));
         },
-        WriterOp::ClearAll => {
-            debug!("Clearing all documents");
-            writer.delete_all_documents()?;
+        WriterOp::ReplaceAll(docs, waker) => {
+            debug!("Replacing all documents with {} new documents", docs.len());
+            let _ = waker.send(replace_all(writer, pending_events, docs));
         },
+        WriterOp::Commit(waker) => {
+            debug!("Running requested commit");
+            let result = commit(writer, pending_events).map_err(anyhow::Error::from);
+            let _ = waker.send(result);
+        },
+        WriterOp::Merge(target_segments, waker) => {
+            debug!("Merging segments down to {}", target_segments);
+            let _ = waker.send(merge_segments(writer, pending_events, target_segments));
+        },
+        WriterOp::Notify(event) => pending_events.push(event),
     };
 
     Ok(())
 }
+
+fn merge_segments(
+    writer: &mut IndexWriter,
+    pending_events: &mut Vec<IndexEvent>,
+    target_segments: usize,
+) -> anyhow::Result<MergeSummary> {
+    commit(writer, pending_events)?;
+
+    let mut segments = writer.index().searchable_segment_metas()?;
+    let segments_before = segments.len();
+    let target_segments = target_segments.max(1);
+
+    if segments_before <= target_segments {
+        return Ok(MergeSummary {
+            segments_before,
+            segments_after: segments_before,
+        });
+    }
+
+    // Merging the smallest segments into one keeps the amount of data
+    // re-written as low as possible.
+    segments.sort_by_key(|meta| meta.num_docs());
+    let segment_ids = segments
+        .iter()
+        .take(segments_before - target_segments + 1)
+        .map(|meta| meta.id())
+        .collect::<Vec<_>>();
+
+    writer.merge(&segment_ids).wait()?;
+
+    Ok(MergeSummary {
+        segments_before,
+        segments_after: writer.index().searchable_segment_metas()?.len(),
+    })
+}
+
+fn add_documents(
+    writer: &mut IndexWriter,
+    pending_events: &mut Vec<IndexEvent>,
+    docs: Vec<(Term, Document)>,
+) -> anyhow::Result<()> {
+    // Any pending operations are committed first so the batch can be
+    // rolled back on its own if a document fails.
+    commit(writer, pending_events)?;
+
+    let result = docs.into_iter().try_for_each(|(term, doc)| {
+        writer.delete_term(term);
+        writer.add_document(doc).map(|_| ())
+    });
+
+    if let Err(e) = result {
+        writer.rollback()?;
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+fn replace_all(
+    writer: &mut IndexWriter,
+    pending_events: &mut Vec<IndexEvent>,
+    docs: Vec<Document>,
+) -> anyhow::Result<Vec<String>> {
+    // Any pending operations are committed first so a failure part way
+    // through the rebuild can be rolled back without losing them.
+    commit(writer, pending_events)?;
+
+    writer.delete_all_documents()?;
+
+    let mut errors = vec![];
+    for doc in docs {
+        if let Err(e) = writer.add_document(doc) {
+            warn!("Skipping document which failed to be indexed: {}", e);
+            errors.push(e.to_string());
+        }
+    }
+
+    if let Err(e) = writer.commit() {
+        writer.rollback()?;
+        return Err(e.into());
+    }
+
+    Ok(errors)
+}
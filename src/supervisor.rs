@@ -0,0 +1,247 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use poem_openapi::Object;
+
+/// How long to wait before the first restart of a panicked task.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest a panicked task will wait before being restarted.
+///
+/// Tasks which ran for longer than this before panicking restart
+/// with the initial backoff again.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// The fraction of a task's period which is randomly added to each tick.
+const JITTER_FRACTION: f64 = 0.1;
+
+/// If this instance runs the leader only tasks, every instance is the
+/// leader unless leader election is enabled.
+static IS_LEADER: AtomicBool = AtomicBool::new(true);
+
+static STATUSES: Lazy<Mutex<BTreeMap<&'static str, TaskStatus>>> =
+    Lazy::new(Default::default);
+
+#[derive(Debug, Default, Clone, Object)]
+#[oai(rename_all = "camelCase")]
+pub struct TaskStatus {
+    /// The name of the task.
+    pub name: String,
+
+    /// If the task is currently running.
+    pub running: bool,
+
+    /// The number of times the task has been restarted after panicking.
+    pub restarts: u32,
+
+    /// The unix timestamp the task last completed a run without any errors.
+    pub last_success: Option<i64>,
+
+    /// The last error the task ran into.
+    pub last_error: Option<String>,
+
+    /// The unix timestamp of the last error.
+    pub last_error_at: Option<i64>,
+}
+
+#[inline]
+pub fn is_leader() -> bool {
+    IS_LEADER.load(Ordering::Relaxed)
+}
+
+pub fn set_leader(is_leader: bool) {
+    let was_leader = IS_LEADER.swap(is_leader, Ordering::Relaxed);
+    if was_leader != is_leader {
+        info!("This instance is now the leader: {}", is_leader);
+    }
+}
+
+/// Get the status of every supervised task.
+pub fn statuses() -> Vec<TaskStatus> {
+    STATUSES.lock().values().cloned().collect()
+}
+
+/// Get the status of the task if it has been started.
+pub fn status(name: &str) -> Option<TaskStatus> {
+    STATUSES.lock().get(name).cloned()
+}
+
+/// Spawns the task, restarting it with an exponential backoff if it panics.
+///
+/// The task is not restarted if it returns normally.
+pub fn spawn<F, Fut>(name: &'static str, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    update(name, |_| {});
+
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            let started = Instant::now();
+            let err = match tokio::spawn(task()).await {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = INITIAL_BACKOFF;
+            }
+
+            error!(
+                "Task {} stopped unexpectedly, restarting in {:?}: {}",
+                name, backoff, err
+            );
+            update(name, |status| {
+                status.restarts += 1;
+                status.last_error = Some(format!("task stopped unexpectedly: {}", err));
+                status.last_error_at = Some(unix_secs());
+            });
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        info!("Task {} has finished", name);
+    });
+}
+
+/// The task was already running so it was not started again.
+#[derive(Debug)]
+pub struct TaskRunning;
+
+impl Display for TaskRunning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The task is already running.")
+    }
+}
+
+impl std::error::Error for TaskRunning {}
+
+/// Ticks every period plus a random jitter, so tasks started together
+/// drift apart rather than all hitting the database at once.
+///
+/// The first tick completes immediately and ticks missed while a run
+/// takes longer than the period are skipped rather than run back to back.
+pub struct Ticker {
+    period: Duration,
+    next: Option<tokio::time::Instant>,
+}
+
+impl Ticker {
+    pub fn new(period: Duration) -> Self {
+        Self { period, next: None }
+    }
+
+    pub async fn tick(&mut self) {
+        let now = tokio::time::Instant::now();
+        if let Some(next) = self.next {
+            tokio::time::sleep_until(next.max(now)).await;
+        }
+
+        let jitter = self.period.mul_f64(JITTER_FRACTION * random_fraction());
+        self.next = Some(tokio::time::Instant::now() + self.period + jitter);
+    }
+}
+
+/// A single run of a supervised task, errors are logged and recorded
+/// as the task's last error.
+///
+/// Only one run of each task can exist at a time.
+pub struct TaskRun {
+    name: &'static str,
+    started: Instant,
+    errored: bool,
+}
+
+impl TaskRun {
+    /// Starts a run of the task, returning `None` if it is already running.
+    pub fn try_start(name: &'static str) -> Option<Self> {
+        let mut already_running = false;
+        update(name, |status| {
+            already_running = status.running;
+            status.running = true;
+        });
+
+        if already_running {
+            debug!("Skipping run of task {} as it is already running", name);
+            return None;
+        }
+
+        Some(Self {
+            name,
+            started: Instant::now(),
+            errored: false,
+        })
+    }
+
+    /// Starts a run of the task if this instance is the leader and the
+    /// task is not already running.
+    pub fn try_start_leader(name: &'static str) -> Option<Self> {
+        if !is_leader() {
+            debug!(
+                "Skipping run of task {} as this instance is not the leader",
+                name
+            );
+            return None;
+        }
+
+        Self::try_start(name)
+    }
+
+    pub fn error(&mut self, error: impl Display) {
+        error!("{}", error);
+
+        self.errored = true;
+        update(self.name, |status| {
+            status.last_error = Some(error.to_string());
+            status.last_error_at = Some(unix_secs());
+        });
+    }
+
+    /// Records the run as a success if it had no errors.
+    pub fn finish(self) {
+        if !self.errored {
+            update(self.name, |status| status.last_success = Some(unix_secs()));
+        }
+    }
+}
+
+impl Drop for TaskRun {
+    fn drop(&mut self) {
+        debug!("Task {} ran in {:?}", self.name, self.started.elapsed());
+        update(self.name, |status| status.running = false);
+    }
+}
+
+fn update(name: &'static str, f: impl FnOnce(&mut TaskStatus)) {
+    let mut statuses = STATUSES.lock();
+    let status = statuses.entry(name).or_insert_with(|| TaskStatus {
+        name: name.to_string(),
+        ..Default::default()
+    });
+
+    f(status);
+}
+
+/// A random number between `0` and `1`.
+fn random_fraction() -> f64 {
+    let value = RandomState::new().build_hasher().finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn unix_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
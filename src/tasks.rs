@@ -1,102 +1,677 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tokio::time::interval;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use cron::Schedule;
+use once_cell::sync::OnceCell;
+use poem_openapi::Enum;
 
-pub fn start_vote_update_tasks() {
-    tokio::spawn(check_votes_loop());
+use crate::ingest::nats::NatsIngest;
+use crate::models::cdc::{self, ChangedTable};
+use crate::models::connection;
+use crate::scores::ScoresProvider;
+use crate::search::replication::{self, IndexName};
+use crate::supervisor::{self, TaskRun, TaskRunning, Ticker};
+
+/// The provider trending scores are fetched from, set once the live data tasks start.
+static SCORES_PROVIDER: OnceCell<Arc<dyn ScoresProvider>> = OnceCell::new();
+
+/// The tasks which can be run on demand rather than waiting for their next tick.
+#[derive(Enum, Debug, Copy, Clone)]
+#[oai(rename_all = "lowercase")]
+pub enum ManualTask {
+    Votes,
+    Trending,
+    Featured,
+}
+
+impl ManualTask {
+    /// The name the task is supervised under.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Votes => "votes",
+            Self::Trending => "trending",
+            Self::Featured => "featured",
+        }
+    }
 }
 
-async fn check_votes_loop() {
-    let mut interval = interval(Duration::from_secs(300));
+/// Runs a single run of the task immediately, its errors are recorded in
+/// the task's status the same as a scheduled run.
+///
+/// Returns [TaskRunning] if the task is already running.
+/// This must only be called once the indexes are initialised.
+pub async fn run_now(task: ManualTask) -> Result<()> {
+    let run = TaskRun::try_start(task.as_str()).ok_or(TaskRunning)?;
+
+    match task {
+        ManualTask::Votes => check_votes(run, !replication::is_replica()).await,
+        ManualTask::Trending => {
+            let provider = SCORES_PROVIDER
+                .get()
+                .ok_or_else(|| anyhow!("Trending scores are not being refreshed."))?;
+            refresh_trending(run, provider.as_ref()).await;
+        },
+        ManualTask::Featured => refresh_featured(run).await,
+    }
+
+    Ok(())
+}
+
+/// Keeps the vote counts up to date.
+///
+/// If `reindex` is set the bots and packs whose votes changed are re-indexed, so this
+/// must only be started once the indexes are initialised.
+pub fn start_vote_update_tasks(reindex: bool) {
+    supervisor::spawn("votes", move || check_votes_loop(reindex));
+}
+
+async fn check_votes_loop(reindex: bool) {
+    let mut ticker = Ticker::new(Duration::from_secs(300));
 
     loop {
-        interval.tick().await;
+        ticker.tick().await;
+        if let Some(run) = TaskRun::try_start("votes") {
+            check_votes(run, reindex).await;
+        }
+    }
+}
+
+async fn check_votes(mut run: TaskRun, reindex: bool) {
+    match crate::models::bots::refresh_latest_votes().await {
+        Ok(changed) if reindex => {
+            crate::search::index_impls::bots::queue_reindex(changed)
+        },
+        Ok(_) => {},
+        Err(e) => run.error(format!("Failed to update bot votes due to error: {}", e)),
+    }
+
+    match crate::models::ratings::refresh_latest_ratings().await {
+        Ok(changed) if reindex => {
+            crate::search::index_impls::bots::queue_reindex(changed)
+        },
+        Ok(_) => {},
+        Err(e) => run.error(format!("Failed to update bot ratings due to error: {}", e)),
+    }
 
-        if let Err(e) = crate::models::bots::refresh_latest_votes().await {
-            error!("Failed to update bot votes due to error: {}", e);
+    match crate::models::packs::refresh_latest_votes().await {
+        Ok(changed) if reindex => {
+            crate::search::index_impls::packs::writer().queue_reindex(changed);
+        },
+        Ok(_) => {},
+        Err(e) => run.error(format!("Failed to update pack votes due to error: {}", e)),
+    }
+
+    run.finish();
+}
+
+/// How far each read of the CDC log overlaps the previous one, as changes
+/// can become visible slightly after the time they were made.
+const CDC_OVERLAP: Duration = Duration::from_secs(30);
+
+/// Keeps the trending scores up to date.
+///
+/// If `reload_live_data` is set the bots and packs are also reloaded from
+/// the database every 20 minutes, this can be left off when the changes
+/// are being applied from the CDC log instead.
+pub fn start_live_data_tasks(provider: Arc<dyn ScoresProvider>, reload_live_data: bool) {
+    let _ = SCORES_PROVIDER.set(provider.clone());
+    supervisor::spawn("trending", move || {
+        refresh_trending_scores(provider.clone())
+    });
+
+    if reload_live_data {
+        supervisor::spawn("live_data", refresh_live_data_loop);
+    }
+}
+
+/// Applies changes made to the bots and packs tables since the given time
+/// as they are read from the CDC log.
+///
+/// This must only be started once the indexes are initialised.
+pub fn start_cdc_tasks(since: SystemTime, every: Duration) {
+    supervisor::spawn("cdc", move || apply_changes_loop(since, every));
+}
+
+async fn apply_changes_loop(since: SystemTime, every: Duration) {
+    let mut ticker = Ticker::new(every);
+    let mut since = unix_millis(since);
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("cdc") else {
+            continue;
+        };
+
+        // Rows are re-fetched rather than read from the log, so a change
+        // being applied more than once due to the overlap is harmless.
+        let polled_at = unix_millis(SystemTime::now());
+        let cursor = since - CDC_OVERLAP.as_millis() as i64;
+
+        let bots = apply_changes(&mut run, ChangedTable::Bots, cursor).await;
+        let packs = apply_changes(&mut run, ChangedTable::Packs, cursor).await;
+        if bots && packs {
+            since = polled_at;
         }
 
-        if let Err(e) = crate::models::packs::refresh_latest_votes().await {
-            error!("Failed to update pack votes due to error: {}", e);
+        run.finish();
+    }
+}
+
+/// Applies the changes made to the table since the cursor, returning
+/// `false` if the log could not be read.
+async fn apply_changes(run: &mut TaskRun, table: ChangedTable, cursor: i64) -> bool {
+    let changed = match cdc::changed_since(table, cursor).await {
+        Ok(changed) => changed,
+        Err(e) => {
+            run.error(format!(
+                "Failed to read {:?} changes due to error: {}",
+                table, e
+            ));
+            return false;
+        },
+    };
+
+    if changed.is_empty() {
+        return true;
+    }
+
+    debug!("Applying {} changed {:?} rows", changed.len(), table);
+    if matches!(table, ChangedTable::Bots) {
+        // Bots are applied as one batch so the live data is only copied once.
+        match crate::search::index_impls::bots::writer()
+            .apply_changes(changed.into_iter().collect())
+            .await
+        {
+            Ok(failed) => {
+                for (id, e) in failed {
+                    run.error(format!(
+                        "Failed to apply change to {:?} row {} due to error: {}",
+                        table, id, e
+                    ));
+                }
+            },
+            Err(e) => {
+                run.error(format!(
+                    "Failed to apply {:?} changes due to error: {}",
+                    table, e
+                ));
+            },
+        }
+
+        return true;
+    }
+
+    for id in changed {
+        let result = crate::search::index_impls::packs::writer()
+            .apply_change(id)
+            .await;
+
+        if let Err(e) = result {
+            run.error(format!(
+                "Failed to apply change to {:?} row {} due to error: {}",
+                table, id, e
+            ));
+        }
+    }
+
+    true
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// Keeps the featured flag of indexed bots up to date.
+///
+/// This must only be started once the indexes are initialised.
+pub fn start_featured_tasks() {
+    supervisor::spawn("featured", refresh_featured_loop);
+}
+
+async fn refresh_featured_loop() {
+    let mut ticker = Ticker::new(Duration::from_secs(60));
+
+    loop {
+        ticker.tick().await;
+        if let Some(run) = TaskRun::try_start("featured") {
+            refresh_featured(run).await;
         }
     }
 }
 
-pub fn start_live_data_tasks(a7s_uri: String, a7s_auth: String) {
-    tokio::spawn(refresh_trending_scores(a7s_uri, a7s_auth));
-    tokio::spawn(refresh_live_data_loop());
+async fn refresh_featured(mut run: TaskRun) {
+    let changed = match crate::models::featured::refresh_featured_bots().await {
+        Ok(changed) => changed,
+        Err(e) => {
+            run.error(format!(
+                "Failed to update featured bots due to error: {}",
+                e
+            ));
+            return;
+        },
+    };
+
+    crate::search::index_impls::bots::queue_reindex(changed);
+
+    run.finish();
 }
 
 async fn refresh_live_data_loop() {
-    let mut interval = interval(Duration::from_secs(1200));
+    let mut ticker = Ticker::new(Duration::from_secs(1200));
 
     loop {
-        interval.tick().await;
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("live_data") else {
+            continue;
+        };
 
-        if let Err(e) = crate::models::bots::refresh_latest_data().await {
-            error!("Failed to update bot data due to error: {}", e);
+        match crate::models::bots::refresh_latest_data().await {
+            Ok(_) => crate::search::autocomplete::rebuild(),
+            Err(e) => {
+                run.error(format!("Failed to update bot data due to error: {}", e))
+            },
         }
 
         if let Err(e) = crate::models::packs::refresh_latest_data().await {
-            error!("Failed to update pack data due to error: {}", e);
+            run.error(format!("Failed to update pack data due to error: {}", e));
+        }
+
+        run.finish();
+    }
+}
+
+/// Snapshots the monthly bot votes into the vote history and resets them
+/// once each month ends, only the leader rolls the votes over.
+pub fn start_vote_rollover_tasks() {
+    supervisor::spawn("vote_rollover", vote_rollover_loop);
+}
+
+async fn vote_rollover_loop() {
+    let mut ticker = Ticker::new(Duration::from_secs(600));
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start_leader("vote_rollover") else {
+            continue;
+        };
+
+        match crate::models::vote_history::rollover_if_due(Utc::now()).await {
+            Ok(0) => {},
+            Ok(rolled_over) => {
+                info!("Rolled over the votes of {} bots", rolled_over);
+                if let Err(e) = run_now(ManualTask::Votes).await {
+                    warn!("Failed to reload votes after the rollover: {}", e);
+                }
+            },
+            Err(e) => {
+                run.error(format!("Failed to roll over votes due to error: {}", e))
+            },
         }
+
+        run.finish();
     }
 }
 
-async fn refresh_trending_scores(a7s_uri: String, a7s_auth: String) {
-    let mut interval = interval(Duration::from_secs(300));
+/// Keeps the guild count growth of each bot up to date.
+///
+/// If `record` is set the leader also records the daily guild count
+/// snapshots the growth is measured from.
+pub fn start_growth_tasks(record: bool) {
+    supervisor::spawn("growth", move || growth_loop(record));
+}
+
+async fn growth_loop(record: bool) {
+    let mut ticker = Ticker::new(Duration::from_secs(3600));
 
     loop {
-        interval.tick().await;
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("growth") else {
+            continue;
+        };
 
-        if let Err(e) = fetch_pack_trending(&a7s_uri, &a7s_auth).await {
-            error!("Failed to update pack trending data due to error: {}", e);
+        let now = Utc::now();
+        if record && supervisor::is_leader() {
+            if let Err(e) = crate::models::growth::record_snapshot_if_due(now).await {
+                run.error(format!("Failed to record guild counts due to error: {}", e));
+            }
         }
 
-        if let Err(e) = fetch_bot_trending(&a7s_uri, &a7s_auth).await {
-            error!("Failed to update bot trending data due to error: {}", e);
+        match crate::models::growth::refresh_growth(now).await {
+            Ok(changed) => crate::search::index_impls::bots::queue_reindex(changed),
+            Err(e) => run.error(format!("Failed to update growth due to error: {}", e)),
         }
 
-        info!("Refreshed trending scores for entities!");
+        run.finish();
     }
 }
 
-async fn fetch_bot_trending(a7s_uri: &str, a7s_auth: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
-    let data = client
-        .get(format!("{}/bots/trending-scores", a7s_uri))
-        .bearer_auth(a7s_auth)
-        .send()
-        .await?
-        .json::<HashMap<i64, String>>()
-        .await?;
+/// How often the database is pinged.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
-    let data = data
-        .into_iter()
-        .filter_map(|(k, v)| Some((k, v.parse::<f64>().ok()?)))
-        .collect();
+/// The number of pings in a row which must fail before the session is
+/// re-established, this is retried every time as many pings fail again.
+const RECONNECT_AFTER_FAILURES: u32 = 6;
 
-    crate::models::bots::set_bot_trending_data(data);
+/// Pings the database, re-establishing the session when it stays unreachable.
+pub fn start_db_health_tasks() {
+    supervisor::spawn("db_health", db_health_loop);
+}
 
-    Ok(())
+async fn db_health_loop() {
+    let mut ticker = Ticker::new(HEALTH_CHECK_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("db_health") else {
+            continue;
+        };
+
+        if let Err(e) = connection::ping().await {
+            run.error(format!("Failed to ping the database due to error: {}", e));
+
+            let failures = connection::health().consecutive_failures;
+            if failures % RECONNECT_AFTER_FAILURES == 0 {
+                warn!(
+                    "Database has been unreachable for {} pings, reconnecting",
+                    failures
+                );
+
+                match connection::reconnect().await {
+                    Ok(()) => info!("Re-established the database session"),
+                    Err(e) => run.error(format!(
+                        "Failed to reconnect to the database due to error: {}",
+                        e
+                    )),
+                }
+            }
+        }
+
+        run.finish();
+    }
+}
+
+/// Periodically writes the buffered search analytics.
+pub fn start_analytics_tasks() {
+    crate::models::analytics::init();
+    supervisor::spawn("analytics", flush_analytics_loop);
 }
 
-async fn fetch_pack_trending(a7s_uri: &str, a7s_auth: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
-    let data = client
-        .get(format!("{}/packs/trending-scores", a7s_uri))
-        .bearer_auth(a7s_auth)
-        .send()
-        .await?
-        .json::<HashMap<i64, String>>()
-        .await?;
+/// Keeps the popular searches up to date from the recorded analytics.
+///
+/// Only the leader recounts them, every other instance loads what it saved.
+pub fn start_popular_searches_tasks() {
+    supervisor::spawn("popular_searches", refresh_popular_searches_loop);
+}
 
-    let data = data
-        .into_iter()
-        .filter_map(|(k, v)| Some((k, v.parse::<f64>().ok()?)))
-        .collect();
+async fn refresh_popular_searches_loop() {
+    let mut ticker = Ticker::new(Duration::from_secs(300));
 
-    crate::models::packs::set_pack_trending_data(data);
-    Ok(())
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("popular_searches") else {
+            continue;
+        };
+
+        let result = if supervisor::is_leader() {
+            crate::models::analytics::refresh_popular_searches().await
+        } else {
+            crate::models::analytics::load_popular_searches().await
+        };
+
+        if let Err(e) = result {
+            run.error(format!(
+                "Failed to update popular searches due to error: {}",
+                e
+            ));
+        }
+
+        run.finish();
+    }
+}
+
+async fn flush_analytics_loop() {
+    let mut ticker = Ticker::new(Duration::from_secs(10));
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("analytics") else {
+            continue;
+        };
+
+        match crate::models::analytics::flush_searches().await {
+            Ok(written) if written > 0 => {
+                debug!("Wrote {} search analytics records", written)
+            },
+            Ok(_) => {},
+            Err(e) => run.error(format!(
+                "Failed to write search analytics due to error: {}",
+                e
+            )),
+        }
+
+        run.finish();
+    }
+}
+
+/// Periodically uploads a snapshot of each index.
+pub fn start_snapshot_tasks(every: Duration) {
+    supervisor::spawn("snapshots", move || upload_snapshots_loop(every));
+}
+
+async fn upload_snapshots_loop(every: Duration) {
+    let mut ticker = Ticker::new(every);
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start_leader("snapshots") else {
+            continue;
+        };
+
+        for index in IndexName::ALL {
+            match crate::search::snapshots::upload(index).await {
+                Ok(uploaded) => info!(
+                    "Uploaded {} index snapshot, new files: {}",
+                    index.as_str(),
+                    uploaded,
+                ),
+                Err(e) => run.error(format!(
+                    "Failed to upload {} index snapshot due to error: {}",
+                    index.as_str(),
+                    e
+                )),
+            }
+        }
+
+        run.finish();
+    }
+}
+
+/// Rebuilds the indexes from the database without blocking startup.
+///
+/// This is used when the indexes were restored from a snapshot, which
+/// can be searched while it is brought up to date.
+pub fn start_background_refresh() {
+    tokio::spawn(async {
+        if let Err(e) = crate::search::index_impls::packs::writer()
+            .full_refresh()
+            .await
+        {
+            error!("Failed to refresh restored pack index due to error: {}", e);
+        }
+
+        if let Err(e) = crate::search::index_impls::bots::writer()
+            .full_refresh()
+            .await
+        {
+            error!("Failed to refresh restored bot index due to error: {}", e);
+        }
+    });
+}
+
+/// Runs a full refresh of every index each time the schedule fires.
+///
+/// This must only be started once the indexes are initialised.
+pub fn start_scheduled_refresh_tasks(schedule: Schedule) {
+    supervisor::spawn("scheduled_refresh", move || {
+        scheduled_refresh_loop(schedule.clone())
+    });
+}
+
+async fn scheduled_refresh_loop(schedule: Schedule) {
+    while let Some(next) = schedule.upcoming(Utc).next() {
+        let until = (next - Utc::now()).to_std().unwrap_or_default();
+        info!("Next scheduled full refresh is at {}", next);
+        tokio::time::sleep(until).await;
+        let Some(mut run) = TaskRun::try_start_leader("scheduled_refresh") else {
+            continue;
+        };
+
+        if let Err(e) = crate::search::index_impls::packs::writer()
+            .full_refresh()
+            .await
+        {
+            run.error(format!(
+                "Failed to run scheduled pack refresh due to error: {}",
+                e
+            ));
+        }
+
+        if let Err(e) = crate::search::index_impls::bots::writer()
+            .full_refresh()
+            .await
+        {
+            run.error(format!(
+                "Failed to run scheduled bot refresh due to error: {}",
+                e
+            ));
+        }
+
+        run.finish();
+    }
+
+    warn!("Full refresh schedule has no upcoming times, stopping scheduled refreshes");
+}
+
+/// Applies the entity changes published to Redis by the main backend.
+///
+/// This must only be started once the indexes are initialised.
+pub fn start_redis_tasks(url: String) {
+    supervisor::spawn("redis", move || {
+        crate::ingest::redis::subscribe_loop(url.clone())
+    });
+}
+
+/// Applies the entity changes consumed from a NATS JetStream stream.
+///
+/// This must only be started once the indexes are initialised.
+pub fn start_nats_tasks(ingest: NatsIngest) {
+    let ingest = Arc::new(ingest);
+    supervisor::spawn("nats", move || {
+        let ingest = ingest.clone();
+        async move { ingest.consume_loop().await }
+    });
+}
+
+/// The name of the lease held by the leader instance.
+const LEADER_LEASE: &str = "leader";
+
+/// How long the leader keeps the lease without renewing it.
+const LEADER_LEASE_TTL: Duration = Duration::from_secs(30);
+
+/// Elects a single instance to run the leader only tasks, which are the
+/// snapshot uploads and scheduled full refreshes.
+///
+/// Every instance takes part in the election with a unique `instance_id`.
+pub fn start_leader_election(instance_id: String) {
+    supervisor::set_leader(false);
+    supervisor::spawn("leader_election", move || {
+        leader_election_loop(instance_id.clone())
+    });
+}
+
+async fn leader_election_loop(instance_id: String) {
+    let mut ticker = Ticker::new(LEADER_LEASE_TTL / 3);
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("leader_election") else {
+            continue;
+        };
+
+        match crate::models::leases::try_acquire(
+            LEADER_LEASE,
+            &instance_id,
+            LEADER_LEASE_TTL,
+        )
+        .await
+        {
+            Ok(is_leader) => supervisor::set_leader(is_leader),
+            Err(e) => {
+                // Another instance may take over if the lease can't be renewed.
+                supervisor::set_leader(false);
+                run.error(format!("Failed to renew leader lease due to error: {}", e));
+            },
+        }
+
+        run.finish();
+    }
+}
+
+/// Keeps the local indexes in sync with the primary writer instance.
+pub fn start_replication_tasks(primary: String, every: Duration) {
+    supervisor::spawn("replication", move || {
+        replicate_loop(primary.clone(), every)
+    });
+}
+
+async fn replicate_loop(primary: String, every: Duration) {
+    let mut ticker = Ticker::new(every);
+
+    loop {
+        ticker.tick().await;
+        let Some(mut run) = TaskRun::try_start("replication") else {
+            continue;
+        };
+
+        for index in IndexName::ALL {
+            match replication::sync_from(&primary, index).await {
+                Ok(summary) if summary.downloaded > 0 || summary.removed > 0 => info!(
+                    "Synced {} index from primary, downloaded: {}, removed: {}",
+                    index.as_str(),
+                    summary.downloaded,
+                    summary.removed,
+                ),
+                Ok(_) => {},
+                Err(e) => run.error(format!(
+                    "Failed to sync {} index from primary due to error: {}",
+                    index.as_str(),
+                    e
+                )),
+            }
+        }
+
+        run.finish();
+    }
+}
+
+async fn refresh_trending_scores(provider: Arc<dyn ScoresProvider>) {
+    let mut ticker = Ticker::new(Duration::from_secs(300));
+
+    loop {
+        ticker.tick().await;
+        if let Some(run) = TaskRun::try_start("trending") {
+            refresh_trending(run, provider.as_ref()).await;
+        }
+    }
+}
+
+async fn refresh_trending(run: TaskRun, provider: &dyn ScoresProvider) {
+    crate::scores::refresh_scores(provider).await;
+
+    info!("Refreshed trending scores for entities!");
+    run.finish();
 }